@@ -31,7 +31,7 @@ mod paging_bootstrap;
 
 global_asm!(include_str!("start.s"));
 
-unsafe extern "C" {fn print(s:u64, len:u64);}
+unsafe extern "C" {fn print(s:u64, len:u64); fn record(b:u64, len:u64);}
 
 
 /// Stage 1 Initialize of the system architechture.
@@ -57,6 +57,14 @@ pub fn console_output(s:&str) {
         print(s.as_ptr() as u64, s.len() as u64);
     };    
 }
+
+// one linux_proxy::record::Record to the monitor's --record file, which is
+// what a record::Sink in here would call
+pub fn record_output(b:&[u8]) {
+    unsafe {
+        record(b.as_ptr() as u64, b.len() as u64);
+    };
+}
     
 #[unsafe(no_mangle)]
 fn arch_init_stage1(
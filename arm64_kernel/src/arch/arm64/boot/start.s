@@ -89,4 +89,9 @@ _start:
 print:
         hvc #5
         ret
+
+.globl record
+record:
+        hvc #6
+        ret
         
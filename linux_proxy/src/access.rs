@@ -1,13 +1,54 @@
-use core::ffi::c_char;
 use crate::{
-    Fd,
-    KernelError,
+    AccessMode, AddressSpace, AtFlags, Fd, Lockable, PATH_MAX, Runtime, Task,
+    copy_in_cstr, linuxerr, load,
+    attr::FileAttr,
+    namei::{resolve, start},
 };
+use protocol::{Error, Oid};
 
-pub async fn sys_faccessat(dirfd: Fd, path: TUA<c_char>, mode: i32) -> Result<usize, KernelError> {
-    sys_faccessat2(dirfd, path, mode, 0).await
+// whether the caller may do mode to oid, as themselves. the check open,
+// exec and chdir make, and anything else about to use the entry. gives
+// back the attributes, since they were needed for it
+pub async fn permission<R:Runtime>(t: &Task<R>, oid: Oid, mode: AccessMode) -> Result<FileAttr, Error> {
+    let attr: FileAttr = load(&t.process.kernel.runtime, oid).await?;
+    let creds = t.process.creds.lock().clone();
+    attr.check_access(&creds, mode)?;
+    Ok(attr)
 }
 
-pub async fn sys_faccessat2(_dirfd: Fd, _path: TUA<c_char>, _mode: i32, _flags: i32) -> Result<usize, KernelError> {
+pub async fn sys_faccessat<R:Runtime>(t: Task<R>, dirfd: Fd, path: AddressSpace, mode: u32) -> Result<usize, Error> {
+    sys_faccessat2(t, dirfd, path, mode, AtFlags::empty()).await
+}
+
+// mode 0 is F_OK, just whether it's there. without AT_EACCESS the question
+// is about the real ids, so a setuid program can ask about whoever ran it
+pub async fn sys_faccessat2<R:Runtime>(
+    t: Task<R>,
+    dirfd: Fd,
+    path: AddressSpace,
+    mode: u32,
+    flags: AtFlags,
+) -> Result<usize, Error> {
+    let mode = AccessMode::from_bits(mode as i32).ok_or(linuxerr!(EINVAL))?;
+    if !(flags - (AtFlags::AT_EACCESS | AtFlags::AT_SYMLINK_NOFOLLOW | AtFlags::AT_EMPTY_PATH)).is_empty() {
+        return Err(linuxerr!(EINVAL));
+    }
+    let path = copy_in_cstr(&t.process.kernel.runtime, path, PATH_MAX - 1)?;
+    let oid = if path.is_empty() && flags.contains(AtFlags::AT_EMPTY_PATH) {
+        start(&t, dirfd, &path)?
+    } else {
+        let r = resolve(&t, dirfd, &path, !flags.contains(AtFlags::AT_SYMLINK_NOFOLLOW)).await?;
+        if mode.contains(AccessMode::W_OK) {
+            r.writable()?;
+        }
+        r.oid.ok_or(linuxerr!(ENOENT))?
+    };
+    if mode.is_empty() {
+        return Ok(0);
+    }
+    let attr: FileAttr = load(&t.process.kernel.runtime, oid).await?;
+    let creds = t.process.creds.lock().clone();
+    let creds = if flags.contains(AtFlags::AT_EACCESS) { creds } else { creds.real() };
+    attr.check_access(&creds, mode)?;
     Ok(0)
 }
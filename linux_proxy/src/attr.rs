@@ -1,5 +1,5 @@
-use crate::{FileType, Gid, Uid, linuxerr, AccessMode};
-use protocol::Error;
+use crate::{Capabilities, Credentials, FileType, Gid, Uid, linuxerr, AccessMode};
+use protocol::{Entity, Error, FromValue, Value};
 
 use bitflags::bitflags;
 use core::time::Duration;
@@ -30,99 +30,107 @@ bitflags! {
     }
 }
 
+impl From<FilePermissions> for Value {
+    fn from(value: FilePermissions) -> Self {
+        Value::Unsigned(value.bits() as u64)
+    }
+}
+
+impl FromValue for FilePermissions {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        Ok(FilePermissions::from_bits_truncate(u64::from_value(v)? as u16))
+    }
+}
+
 /// Represents file metadata, similar to `stat`.
 // this isn't the user one - we're going to have issues with id == 8 bytes
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, Entity)]
 pub struct FileAttr {
+    #[entity(skip)]
     pub id: u64,
-    pub size: u64,
+    // directories don't have one, and the times are missing on anything
+    // made before they were kept. so is everything else on an entity that
+    // wasn't made as a file, which reads like namei reads it, see the
+    // accessors below
+    pub size: Option<u64>,
+    #[entity(skip)]
     pub block_size: u32,
+    #[entity(skip)]
     pub blocks: u64,
-    pub atime: Duration, // Access time (e.g., seconds since epoch)
-    pub mtime: Duration, // Modification time
-    pub ctime: Duration, // Change time
-    pub file_type: FileType,
-    pub mode: FilePermissions,
-    pub nlinks: u32,
-    pub uid: Uid,
-    pub gid: Gid,
+    pub atime: Option<Duration>, // Access time (e.g., seconds since epoch)
+    pub mtime: Option<Duration>, // Modification time
+    pub ctime: Option<Duration>, // Change time
+    #[entity(rename = "type")]
+    pub file_type: Option<FileType>,
+    pub mode: Option<FilePermissions>,
+    #[entity(rename = "nlink")]
+    pub nlinks: Option<u32>,
+    pub uid: Option<Uid>,
+    pub gid: Option<Gid>,
 }
 
-impl Default for FileAttr {
-    fn default() -> Self {
-        Self {
-            id: 0,
-            size: 0,
-            block_size: 0,
-            blocks: 0,
-            atime: Duration::new(0, 0),
-            mtime: Duration::new(0, 0),
-            ctime: Duration::new(0, 0),
-            file_type: FileType::File,
-            mode: FilePermissions::empty(),
-            nlinks: 1,
-            uid: Uid::new_root(),
-            gid: Gid::new_root_group(),
-        }
+impl FileAttr {
+    pub fn file_type(&self) -> FileType {
+        self.file_type.unwrap_or(FileType::File)
+    }
+
+    pub fn mode(&self) -> FilePermissions {
+        self.mode.unwrap_or(FilePermissions::empty())
+    }
+
+    pub fn nlinks(&self) -> u32 {
+        self.nlinks.unwrap_or(1)
+    }
+
+    // root's, like anything else nobody owns
+    pub fn uid(&self) -> Uid {
+        self.uid.unwrap_or(Uid::new_root())
+    }
+
+    pub fn gid(&self) -> Gid {
+        self.gid.unwrap_or(Gid::new_root_group())
     }
-}
 
-impl FileAttr {
     /// Checks if a given set of credentials has the requested access permissions for this file.
     ///
+    /// The owner gets the owner bits, anyone in the file's group, by their
+    /// effective gid or a supplementary one, the group bits, and everyone
+    /// else the other bits. Only the one class is looked at, so an owner
+    /// without a permission doesn't get it from the group or other bits.
+    /// CAP_DAC_OVERRIDE reads and writes anything and searches any directory,
+    /// but only executes a file if some execute bit is set. CAP_DAC_READ_SEARCH
+    /// reads anything and searches directories.
+    ///
     /// # Arguments
-    /// * `uid` - The user-ID that will be checked against this file's uid field.
-    /// * `gid` - The group-ID that will be checked against this file's uid field.
+    /// * `creds` - Whose access this is. The fs ids are the ones used, and
+    ///   the file's owner and group are seen through its id maps.
     /// * `requested_mode` - A bitmask of `AccessMode` flags (`R_OK`, `W_OK`, `X_OK`) to check.
-    pub fn check_access(
-        &self,
-        uid: Uid,
-        gid: Gid,
-        requested_mode: AccessMode,
-    ) -> Result<(), Error> {
-        // root (UID 0) bypasses most permission checks. For execute, at
-        // least one execute bit must be set.
-        if uid.is_root() {
-            if requested_mode.contains(AccessMode::X_OK) {
-                // Root still needs at least one execute bit to be set for X_OK
-                if self.mode.intersects(
-                    FilePermissions::S_IXUSR | FilePermissions::S_IXGRP | FilePermissions::S_IXOTH,
-                ) {
-                    return Ok(());
-                }
-            } else {
-                return Ok(());
-            }
-        }
-
-        // Determine which set of permission bits to use (owner, group, or other)
-        let perms_to_check = if self.uid == uid {
-            // User is the owner
-            self.mode
-        } else if self.gid == gid {
-            // User is in the file's group. Shift group bits to align with owner bits for easier checking.
-            FilePermissions::from_bits_truncate(self.mode.bits() << 3)
+    pub fn check_access(&self, creds: &Credentials, requested_mode: AccessMode) -> Result<(), Error> {
+        let (uid, gid) = (self.uid(), self.gid());
+        let bits = self.mode().bits();
+        let class = if creds.owns(uid) {
+            bits >> 6
+        } else if creds.guest_gid(gid).is_some_and(|g| creds.in_group(g)) {
+            bits >> 3
         } else {
-            // Others. Shift other bits to align with owner bits.
-            FilePermissions::from_bits_truncate(self.mode.bits() << 6)
+            bits
         };
-
-        if requested_mode.contains(AccessMode::R_OK)
-            && !perms_to_check.contains(FilePermissions::S_IRUSR)
-        {
-            return Err(linuxerr!(EPERM));
+        let wanted = requested_mode.bits() as u16 & 0o7;
+        if class & wanted == wanted {
+            return Ok(());
         }
-        if requested_mode.contains(AccessMode::W_OK)
-            && !perms_to_check.contains(FilePermissions::S_IWUSR)
-        {
-            return Err(linuxerr!(EPERM));
+
+        let directory = self.file_type() == FileType::Directory;
+        if creds.capable_on(Capabilities::CAP_DAC_READ_SEARCH, uid, gid) && !requested_mode.contains(AccessMode::W_OK)
+            && (directory || !requested_mode.contains(AccessMode::X_OK)) {
+            return Ok(());
         }
-        if requested_mode.contains(AccessMode::X_OK)
-            && !perms_to_check.contains(FilePermissions::S_IXUSR)
-        {
-            return Err(linuxerr!(EPERM));
+        if creds.capable_on(Capabilities::CAP_DAC_OVERRIDE, uid, gid) {
+            let any_exec = FilePermissions::S_IXUSR | FilePermissions::S_IXGRP | FilePermissions::S_IXOTH;
+            if !requested_mode.contains(AccessMode::X_OK) || directory || self.mode().intersects(any_exec) {
+                return Ok(());
+            }
         }
-
-        Ok(())
+        Err(linuxerr!(EACCES))
     }
 }
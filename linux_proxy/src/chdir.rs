@@ -1,29 +1,93 @@
+use protocol::{Error, Oid};
 use crate::{
-    memory::uaccess::{copy_to_user_slice},
-    current_task,
-};
-use alloc::{ffi::CString, string::ToString};
-use core::{ffi::c_char, str::FromStr};
-use crate::{
-    error::KernelError,
-    memory::address::{TUA, UA},
+    AT_FDCWD, AccessMode, AddressSpace, Capabilities, Fd, FileType, Lockable, PATH_MAX, Path, Runtime, Task,
+    copy_in_cstr, copy_out, linuxerr,
+    access::permission,
+    namei::{names_of, path_of, resolve, type_of},
 };
 
-pub async fn sys_getcwd(buf: UA, len: usize) -> Result<usize, KernelError> {
-    let task = current_task();
-    let path = task.cwd.lock_save_irq().1.as_str().to_string();
-    let cstr = CString::from_str(&path).map_err(|_| KernelError::InvalidValue)?;
-    let slice = cstr.as_bytes_with_nul();
+// the cwd keeps its path from the root next to it, worked out here once
+// rather than every time someone asks. a cwd left outside the root by a
+// chroot keeps a relative one. in a view the graph can't say, the path it
+// was reached by is the only one there is
+async fn set_cwd<R:Runtime>(t: &Task<R>, dir: Oid, known: Option<Path>) -> Result<(), Error> {
+    let path = match known {
+        Some(path) => path,
+        None => {
+            let (absolute, names) = names_of(t, dir).await?;
+            Path::from_elements(absolute, names)
+        }
+    };
+    *t.process.cwd.lock() = (dir, path);
+    Ok(())
+}
 
-    if slice.len() > len {
-        return Err(KernelError::TooLarge);
+// somewhere to go, it has to be a directory we can search
+async fn directory<R:Runtime>(t: &Task<R>, path: AddressSpace) -> Result<(Oid, Option<Path>), Error> {
+    let path = copy_in_cstr(&t.process.kernel.runtime, path, PATH_MAX - 1)?;
+    let r = resolve(t, Fd(AT_FDCWD), &path, true).await?;
+    let oid = r.oid.ok_or(linuxerr!(ENOENT))?;
+    if r.file_type != FileType::Directory {
+        return Err(linuxerr!(ENOTDIR));
     }
+    permission(t, oid, AccessMode::X_OK).await?;
+    Ok((oid, r.path))
+}
 
-    copy_to_user_slice(slice, buf).await?;
+pub async fn sys_chdir<R:Runtime>(t: Task<R>, path: AddressSpace) -> Result<usize, Error> {
+    let (dir, known) = directory(&t, path).await?;
+    set_cwd(&t, dir, known).await?;
+    Ok(0)
+}
 
-    Ok(buf.value())
+pub async fn sys_fchdir<R:Runtime>(t: Task<R>, fd: Fd) -> Result<usize, Error> {
+    let file = t.process.get_fd(fd)?;
+    let dir = file.obj;
+    if type_of(&t, dir).await? != FileType::Directory {
+        return Err(linuxerr!(ENOTDIR));
+    }
+    permission(&t, dir, AccessMode::X_OK).await?;
+    set_cwd(&t, dir, file.path).await?;
+    Ok(0)
 }
 
-pub async fn sys_chdir(_path: TUA<c_char>) -> Result<usize, KernelError> {
+// the cwd stays where it is, like linux, and absolute paths and '..' now
+// stop at the new root. only the cwd's path is looked at again
+pub async fn sys_chroot<R:Runtime>(t: Task<R>, path: AddressSpace) -> Result<usize, Error> {
+    if !t.process.creds.lock().capable(Capabilities::CAP_SYS_CHROOT) {
+        return Err(linuxerr!(EPERM));
+    }
+    let (dir, _) = directory(&t, path).await?;
+    *t.process.root.lock() = dir;
+    let cwd = t.process.cwd.lock().0;
+    set_cwd(&t, cwd, None).await?;
     Ok(0)
 }
+
+// returns the length with the nul. a cwd outside the root is marked the
+// way linux does it. the kept path is checked first, a directory above
+// may have been renamed since
+pub async fn sys_getcwd<R:Runtime>(t: Task<R>, buf: AddressSpace, size: usize) -> Result<usize, Error> {
+    let (dir, known) = t.process.cwd.lock().clone();
+    let path = path_of(&t, dir, known.is_absolute().then_some(known)).await?;
+    {
+        let mut cwd = t.process.cwd.lock();
+        if cwd.0 == dir {
+            cwd.1 = path.clone();
+        }
+    }
+    let mut bytes = if path.is_absolute() {
+        path.to_bytes()
+    } else {
+        [&b"(unreachable)/"[..], &path.to_bytes()].concat()
+    };
+    bytes.push(0);
+    if bytes.len() > PATH_MAX {
+        return Err(linuxerr!(ENAMETOOLONG));
+    }
+    if bytes.len() > size {
+        return Err(linuxerr!(ERANGE));
+    }
+    copy_out(&t.process.kernel.runtime, buf, &bytes)?;
+    Ok(bytes.len())
+}
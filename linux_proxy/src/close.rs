@@ -1,11 +1,61 @@
-use crate::{linux::Fd, current_task};
-use alloc::sync::Arc;
-use crate::error::KernelError;
-
-pub async fn sys_close(fd: Fd) -> Result<usize, KernelError> {
-    let file = current_task()
-        .fd_table
-        .lock_save_irq()
-        .remove(fd)
-        .ok_or(KernelError::BadFd)?;
+use alloc::{vec, vec::Vec};
+use core::sync::atomic::Ordering;
+use crate::{Fd, FdFlags, Lockable, Runtime, Task, linuxerr, rw::drain};
+use protocol::{Command, Error, Oid, Predicate, Value, attribute};
+
+// an entry goes away once it has neither names nor descriptors. unlink
+// takes care of the first happening last, this is the other way round
+pub async fn sys_close<R:Runtime>(t: Task<R>, fd: Fd) -> Result<usize, Error> {
+    let file = t.process.remove_fd(fd).ok_or(linuxerr!(EBADF))?;
+    // like linux, the descriptor is gone even if what follows fails
+    if t.process.kernel.closed(file.obj) {
+        reclaim(&t, file.obj).await?;
+    }
+    Ok(0)
+}
+
+// destroy it if nothing names it. an entry that was never counted, the
+// root or something from elsewhere, has no nlink and stays
+pub async fn reclaim<R:Runtime>(t: &Task<R>, oid: Oid) -> Result<(), Error> {
+    let block = vec![
+        Command::Get(Value::Oid(oid), attribute!("nlink"), Value::Variable(0)),
+        Command::Guard(Value::Variable(0), Predicate::Equal, Value::Unsigned(0)),
+        Command::Destroy(Value::Oid(oid)),
+    ];
+    match drain(t, block).await {
+        Err(e) if e.is_guard_failure() => Ok(()),
+        r => r,
+    }
+}
+
+// the descriptors marked close on exec, at exec
+pub async fn close_on_exec<R:Runtime>(t: &Task<R>) -> Result<(), Error> {
+    let mut closing = Vec::new();
+    {
+        let mut fds = t.process.fd_table.lock();
+        for (i, slot) in fds.iter_mut().enumerate() {
+            if slot.as_ref().is_some_and(|f| f.flags.contains(FdFlags::CLOEXEC)) {
+                closing.extend(slot.take());
+                t.process.next_fd_hint.fetch_min(i, Ordering::Relaxed);
+            }
+        }
+    }
+    for file in closing {
+        if t.process.kernel.closed(file.obj) {
+            reclaim(t, file.obj).await?;
+        }
+    }
+    Ok(())
+}
+
+// every descriptor, at exit
+pub async fn close_all<R:Runtime>(t: &Task<R>) -> Result<(), Error> {
+    let fds = core::mem::take(&mut *t.process.fd_table.lock());
+    t.process.next_fd_hint.store(0, Ordering::Relaxed);
+    for file in fds.into_iter().flatten() {
+        if t.process.kernel.closed(file.obj) {
+            reclaim(t, file.obj).await?;
+        }
+    }
+    Ok(())
 }
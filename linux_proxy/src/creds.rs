@@ -1,16 +1,101 @@
+use alloc::{vec, vec::Vec};
 use core::convert::Infallible;
-use protocol::Error;
+use protocol::{Command, Entity, Error, FromValue, Oid, Value, attribute};
 
-use crate::{Gid, Uid, Task, Runtime, Lockable};
+use crate::{AddressSpace, Gid, Uid, Task, Runtime, Lockable, copy_in, copy_out, linuxerr, idmap::IdMap};
 
-#[derive(Clone, PartialEq, Eq)]
+// the most supplementary groups a process can have, NGROUPS_MAX
+const NGROUPS_MAX: usize = 65536;
+
+// bit n is capability n, the numbering is linux's
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct Capabilities: u64 {
+        const CAP_CHOWN = 1 << 0;
+        const CAP_DAC_OVERRIDE = 1 << 1;
+        const CAP_DAC_READ_SEARCH = 1 << 2;
+        const CAP_FOWNER = 1 << 3;
+        const CAP_FSETID = 1 << 4;
+        const CAP_KILL = 1 << 5;
+        const CAP_SETGID = 1 << 6;
+        const CAP_SETUID = 1 << 7;
+        const CAP_SETPCAP = 1 << 8;
+        const CAP_LINUX_IMMUTABLE = 1 << 9;
+        const CAP_NET_BIND_SERVICE = 1 << 10;
+        const CAP_NET_BROADCAST = 1 << 11;
+        const CAP_NET_ADMIN = 1 << 12;
+        const CAP_NET_RAW = 1 << 13;
+        const CAP_IPC_LOCK = 1 << 14;
+        const CAP_IPC_OWNER = 1 << 15;
+        const CAP_SYS_MODULE = 1 << 16;
+        const CAP_SYS_RAWIO = 1 << 17;
+        const CAP_SYS_CHROOT = 1 << 18;
+        const CAP_SYS_PTRACE = 1 << 19;
+        const CAP_SYS_PACCT = 1 << 20;
+        const CAP_SYS_ADMIN = 1 << 21;
+        const CAP_SYS_BOOT = 1 << 22;
+        const CAP_SYS_NICE = 1 << 23;
+        const CAP_SYS_RESOURCE = 1 << 24;
+        const CAP_SYS_TIME = 1 << 25;
+        const CAP_SYS_TTY_CONFIG = 1 << 26;
+        const CAP_MKNOD = 1 << 27;
+        const CAP_LEASE = 1 << 28;
+        const CAP_AUDIT_WRITE = 1 << 29;
+        const CAP_AUDIT_CONTROL = 1 << 30;
+        const CAP_SETFCAP = 1 << 31;
+        const CAP_MAC_OVERRIDE = 1 << 32;
+        const CAP_MAC_ADMIN = 1 << 33;
+        const CAP_SYSLOG = 1 << 34;
+        const CAP_WAKE_ALARM = 1 << 35;
+        const CAP_BLOCK_SUSPEND = 1 << 36;
+        const CAP_AUDIT_READ = 1 << 37;
+        const CAP_PERFMON = 1 << 38;
+        const CAP_BPF = 1 << 39;
+        const CAP_CHECKPOINT_RESTORE = 1 << 40;
+
+        // the ones that go with the fsuid, CAP_FS_MASK
+        const FS = Self::CAP_CHOWN.bits() | Self::CAP_DAC_OVERRIDE.bits()
+            | Self::CAP_DAC_READ_SEARCH.bits() | Self::CAP_FOWNER.bits()
+            | Self::CAP_FSETID.bits() | Self::CAP_LINUX_IMMUTABLE.bits()
+            | Self::CAP_MAC_OVERRIDE.bits() | Self::CAP_MKNOD.bits();
+    }
+}
+
+impl From<Capabilities> for Value {
+    fn from(value: Capabilities) -> Self {
+        Value::Unsigned(value.bits())
+    }
+}
+
+impl FromValue for Capabilities {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        Ok(Capabilities::from_bits_truncate(u64::from_value(v)?))
+    }
+}
+
+// files are checked against the fs ids, which follow the effective ones
+// unless setfsuid/setfsgid moved them. what a process can do beyond that
+// is its effective capabilities, and the permitted ones are what it can get
+// back into effective. all of these are the ids the process sees, the
+// maps say what they are outside, where entries are owned. no map is the
+// identity, which is where everything starts
+#[derive(Clone, PartialEq, Eq, Entity)]
 pub struct Credentials {
     uid: Uid,
     euid: Uid,
     suid: Uid,
+    fsuid: Uid,
     gid: Gid,
     egid: Gid,
     sgid: Gid,
+    fsgid: Gid,
+    permitted: Capabilities,
+    effective: Capabilities,
+    uid_map: Option<IdMap>,
+    gid_map: Option<IdMap>,
+    // supplementary groups
+    #[entity(skip)]
+    groups: Vec<Gid>,
 }
 
 impl Credentials {
@@ -19,12 +104,122 @@ impl Credentials {
             uid: Uid::new_root(),
             euid: Uid::new_root(),
             suid: Uid::new_root(),
+            fsuid: Uid::new_root(),
             gid: Gid::new_root_group(),
             egid: Gid::new_root_group(),
             sgid: Gid::new_root_group(),
+            fsgid: Gid::new_root_group(),
+            permitted: Capabilities::all(),
+            effective: Capabilities::all(),
+            uid_map: None,
+            gid_map: None,
+            groups: Vec::new(),
+        }
+    }
+
+    // someone without privilege, all of the ids the same and no capabilities
+    pub fn new_user(uid: Uid, gid: Gid, groups: Vec<Gid>) -> Self {
+        Self {
+            uid,
+            euid: uid,
+            suid: uid,
+            fsuid: uid,
+            gid,
+            egid: gid,
+            sgid: gid,
+            fsgid: gid,
+            permitted: Capabilities::empty(),
+            effective: Capabilities::empty(),
+            uid_map: None,
+            gid_map: None,
+            groups,
+        }
+    }
+
+    // the ids mapped to others outside, from then on. a guest root that is
+    // someone else outside keeps its capabilities, but they only count on
+    // entries owned by someone it can see
+    pub fn mapped(self, uid_map: IdMap, gid_map: IdMap) -> Self {
+        Self { uid_map: Some(uid_map), gid_map: Some(gid_map), ..self }
+    }
+
+    pub fn uid_map(&self) -> Option<&IdMap> {
+        self.uid_map.as_ref()
+    }
+
+    pub fn gid_map(&self) -> Option<&IdMap> {
+        self.gid_map.as_ref()
+    }
+
+    pub fn host_uid(&self, uid: Uid) -> Option<Uid> {
+        match &self.uid_map {
+            Some(map) => map.to_host(uid.into()).map(Uid::new),
+            None => Some(uid),
+        }
+    }
+
+    pub fn host_gid(&self, gid: Gid) -> Option<Gid> {
+        match &self.gid_map {
+            Some(map) => map.to_host(gid.into()).map(Gid::new),
+            None => Some(gid),
+        }
+    }
+
+    pub fn guest_uid(&self, uid: Uid) -> Option<Uid> {
+        match &self.uid_map {
+            Some(map) => map.to_guest(uid.into()).map(Uid::new),
+            None => Some(uid),
         }
     }
 
+    pub fn guest_gid(&self, gid: Gid) -> Option<Gid> {
+        match &self.gid_map {
+            Some(map) => map.to_guest(gid.into()).map(Gid::new),
+            None => Some(gid),
+        }
+    }
+
+    // who something new is owned by, outside. linux refuses to make it
+    // if the fs ids don't map to anyone
+    pub fn owner(&self) -> Result<(Uid, Gid), Error> {
+        match (self.host_uid(self.fsuid), self.host_gid(self.fsgid)) {
+            (Some(uid), Some(gid)) => Ok((uid, gid)),
+            _ => Err(linuxerr!(EOVERFLOW)),
+        }
+    }
+
+    // whether an entry owned by uid outside is ours
+    pub fn owns(&self, uid: Uid) -> bool {
+        self.guest_uid(uid) == Some(self.fsuid)
+    }
+
+    // a capability over an entry only counts if its owner and group are
+    // visible, capable_wrt_inode_uidgid
+    pub fn capable_on(&self, cap: Capabilities, uid: Uid, gid: Gid) -> bool {
+        self.capable(cap) && self.guest_uid(uid).is_some() && self.guest_gid(gid).is_some()
+    }
+
+    // the same with the real ids standing in for the fs ones, which is who
+    // access and faccessat ask about. like linux the capabilities go with
+    // them, a real root keeps everything it's permitted and anyone else
+    // has nothing
+    pub fn real(&self) -> Self {
+        let effective = if self.uid.is_root() { self.permitted } else { Capabilities::empty() };
+        Self { fsuid: self.uid, fsgid: self.gid, effective, ..self.clone() }
+    }
+
+    pub fn groups(&self) -> &[Gid] {
+        &self.groups
+    }
+
+    pub fn in_group(&self, gid: Gid) -> bool {
+        self.fsgid == gid || self.groups.contains(&gid)
+    }
+
+    pub fn capable(&self, cap: Capabilities) -> bool {
+        self.effective.contains(cap)
+    }
+
     pub fn uid(&self) -> Uid {
         self.uid
     }
@@ -37,6 +232,10 @@ impl Credentials {
         self.suid
     }
 
+    pub fn fsuid(&self) -> Uid {
+        self.fsuid
+    }
+
     pub fn gid(&self) -> Gid {
         self.gid
     }
@@ -48,6 +247,241 @@ impl Credentials {
     pub fn sgid(&self) -> Gid {
         self.sgid
     }
+
+    pub fn fsgid(&self) -> Gid {
+        self.fsgid
+    }
+
+    pub fn permitted(&self) -> Capabilities {
+        self.permitted
+    }
+
+    pub fn effective(&self) -> Capabilities {
+        self.effective
+    }
+
+    // what changing the uids does to the capabilities, cap_emulate_setxuid.
+    // dropping root everywhere loses them for good, dropping it from the
+    // effective uid only until it comes back
+    fn fix_caps(&mut self, old: &Credentials) {
+        let was_root = old.uid.is_root() || old.euid.is_root() || old.suid.is_root();
+        let is_root = self.uid.is_root() || self.euid.is_root() || self.suid.is_root();
+        if was_root && !is_root {
+            self.permitted = Capabilities::empty();
+            self.effective = Capabilities::empty();
+        }
+        if old.euid.is_root() && !self.euid.is_root() {
+            self.effective = Capabilities::empty();
+        }
+        if !old.euid.is_root() && self.euid.is_root() {
+            self.effective = self.permitted;
+        }
+    }
+}
+
+// the first process's, root inside with whatever id maps the monitor left
+// on its entity as uid_map and gid_map. either one missing is the host's
+// ids as they are
+pub async fn launched<R:Runtime>(runtime: &R, process: Oid) -> Result<Credentials, Error> {
+    let block = vec![
+        Command::Get(Value::Oid(process), attribute!("uid_map"), Value::Union(0)),
+        Command::Get(Value::Oid(process), attribute!("gid_map"), Value::Union(1)),
+    ];
+    let row = runtime.execute(block)?.next().await?.ok_or(linuxerr!(ENOENT))?;
+    let map = |v: &Value| match v {
+        Value::Empty() => Ok(None),
+        v => IdMap::from_value(v).map(Some),
+    };
+    Ok(Credentials { uid_map: map(&row[0])?, gid_map: map(&row[1])?, ..Credentials::new_root() })
+}
+
+// -1 leaves an id alone
+fn given(id: u32) -> Option<u32> {
+    (id != u32::MAX).then_some(id)
+}
+
+// the new uids, if an unprivileged process could have them. each has to
+// be one of the ones it already has
+fn set_uids<R:Runtime>(t: &Task<R>, uid: Option<u32>, euid: Option<u32>, suid: Option<u32>,
+                       allowed: impl Fn(&Credentials, Uid, usize) -> bool) -> Result<usize, Error> {
+    let mut creds = t.process.creds.lock();
+    let old = creds.clone();
+    let privileged = old.capable(Capabilities::CAP_SETUID);
+    for (i, id) in [uid, euid, suid].into_iter().enumerate() {
+        if let Some(id) = id
+            && !privileged
+            && !allowed(&old, Uid::new(id), i) {
+            return Err(linuxerr!(EPERM));
+        }
+    }
+    if [uid, euid, suid].into_iter().flatten().any(|id| old.host_uid(Uid::new(id)).is_none()) {
+        return Err(linuxerr!(EINVAL));
+    }
+    let mut new = old.clone();
+    new.uid = uid.map(Uid::new).unwrap_or(old.uid);
+    new.euid = euid.map(Uid::new).unwrap_or(old.euid);
+    new.suid = suid.map(Uid::new).unwrap_or(old.suid);
+    new.fsuid = new.euid;
+    new.fix_caps(&old);
+    *creds = new;
+    Ok(0)
+}
+
+fn set_gids<R:Runtime>(t: &Task<R>, gid: Option<u32>, egid: Option<u32>, sgid: Option<u32>,
+                       allowed: impl Fn(&Credentials, Gid, usize) -> bool) -> Result<usize, Error> {
+    let mut creds = t.process.creds.lock();
+    let privileged = creds.capable(Capabilities::CAP_SETGID);
+    for (i, id) in [gid, egid, sgid].into_iter().enumerate() {
+        if let Some(id) = id
+            && !privileged
+            && !allowed(&creds, Gid::new(id), i) {
+            return Err(linuxerr!(EPERM));
+        }
+    }
+    if [gid, egid, sgid].into_iter().flatten().any(|id| creds.host_gid(Gid::new(id)).is_none()) {
+        return Err(linuxerr!(EINVAL));
+    }
+    creds.gid = gid.map(Gid::new).unwrap_or(creds.gid);
+    creds.egid = egid.map(Gid::new).unwrap_or(creds.egid);
+    creds.sgid = sgid.map(Gid::new).unwrap_or(creds.sgid);
+    creds.fsgid = creds.egid;
+    Ok(0)
+}
+
+// with CAP_SETUID all three, otherwise just the effective one and only to
+// the real or saved uid
+pub fn sys_setuid<R:Runtime>(t: Task<R>, uid: u32) -> Result<usize, Error> {
+    let uid = given(uid).ok_or(linuxerr!(EINVAL))?;
+    if t.process.creds.lock().capable(Capabilities::CAP_SETUID) {
+        set_uids(&t, Some(uid), Some(uid), Some(uid), |_, _, _| true)
+    } else {
+        set_uids(&t, None, Some(uid), None, |c, id, _| id == c.uid || id == c.suid)
+    }
+}
+
+pub fn sys_setgid<R:Runtime>(t: Task<R>, gid: u32) -> Result<usize, Error> {
+    let gid = given(gid).ok_or(linuxerr!(EINVAL))?;
+    if t.process.creds.lock().capable(Capabilities::CAP_SETGID) {
+        set_gids(&t, Some(gid), Some(gid), Some(gid), |_, _, _| true)
+    } else {
+        set_gids(&t, None, Some(gid), None, |c, id, _| id == c.gid || id == c.sgid)
+    }
+}
+
+// the real uid can become the effective one, the effective uid any of the
+// three. setting the real uid, or an effective one other than the real,
+// saves the new effective uid too
+pub fn sys_setreuid<R:Runtime>(t: Task<R>, uid: u32, euid: u32) -> Result<usize, Error> {
+    let (uid, euid) = (given(uid), given(euid));
+    let real = t.process.creds.lock().uid;
+    let suid = if uid.is_some() || euid.is_some_and(|e| Uid::new(e) != real) {
+        Some(euid.unwrap_or(u32::from(t.process.creds.lock().euid)))
+    } else {
+        None
+    };
+    set_uids(&t, uid, euid, suid, |c, id, i| match i {
+        0 => id == c.uid || id == c.euid,
+        1 => id == c.uid || id == c.euid || id == c.suid,
+        // the saved uid follows the effective one, which was checked
+        _ => true,
+    })
+}
+
+pub fn sys_setregid<R:Runtime>(t: Task<R>, gid: u32, egid: u32) -> Result<usize, Error> {
+    let (gid, egid) = (given(gid), given(egid));
+    let real = t.process.creds.lock().gid;
+    let sgid = if gid.is_some() || egid.is_some_and(|e| Gid::new(e) != real) {
+        Some(egid.unwrap_or(u32::from(t.process.creds.lock().egid)))
+    } else {
+        None
+    };
+    set_gids(&t, gid, egid, sgid, |c, id, i| match i {
+        0 => id == c.gid || id == c.egid,
+        1 => id == c.gid || id == c.egid || id == c.sgid,
+        _ => true,
+    })
+}
+
+pub fn sys_setresuid<R:Runtime>(t: Task<R>, uid: u32, euid: u32, suid: u32) -> Result<usize, Error> {
+    set_uids(&t, given(uid), given(euid), given(suid), |c, id, _| id == c.uid || id == c.euid || id == c.suid)
+}
+
+pub fn sys_setresgid<R:Runtime>(t: Task<R>, gid: u32, egid: u32, sgid: u32) -> Result<usize, Error> {
+    set_gids(&t, given(gid), given(egid), given(sgid), |c, id, _| id == c.gid || id == c.egid || id == c.sgid)
+}
+
+// always answers with the old fsuid, whether or not it changed. going to
+// or from root takes the file capabilities with it
+pub fn sys_setfsuid<R:Runtime>(t: Task<R>, fsuid: u32) -> core::result::Result<usize, Infallible> {
+    let mut creds = t.process.creds.lock();
+    let old = creds.fsuid;
+    let fsuid = Uid::new(fsuid);
+    let allowed = creds.capable(Capabilities::CAP_SETUID)
+        || [creds.uid, creds.euid, creds.suid, creds.fsuid].contains(&fsuid);
+    if allowed && creds.host_uid(fsuid).is_some() {
+        creds.fsuid = fsuid;
+        if old.is_root() && !fsuid.is_root() {
+            creds.effective -= Capabilities::FS;
+        }
+        if !old.is_root() && fsuid.is_root() {
+            let permitted = creds.permitted;
+            creds.effective |= permitted & Capabilities::FS;
+        }
+    }
+    Ok(u32::from(old) as usize)
+}
+
+pub fn sys_setfsgid<R:Runtime>(t: Task<R>, fsgid: u32) -> core::result::Result<usize, Infallible> {
+    let mut creds = t.process.creds.lock();
+    let old = creds.fsgid;
+    let fsgid = Gid::new(fsgid);
+    let allowed = creds.capable(Capabilities::CAP_SETGID)
+        || [creds.gid, creds.egid, creds.sgid, creds.fsgid].contains(&fsgid);
+    if allowed && creds.host_gid(fsgid).is_some() {
+        creds.fsgid = fsgid;
+    }
+    Ok(u32::from(old) as usize)
+}
+
+// with size 0 just how many there are. size is an int, so a negative one
+// is an error rather than a very large buffer
+pub fn sys_getgroups<R:Runtime>(t: Task<R>, size: i32, list: AddressSpace) -> Result<usize, Error> {
+    let groups = t.process.creds.lock().groups.clone();
+    if size < 0 {
+        return Err(linuxerr!(EINVAL));
+    }
+    if size == 0 {
+        return Ok(groups.len());
+    }
+    if (size as usize) < groups.len() {
+        return Err(linuxerr!(EINVAL));
+    }
+    let bytes: Vec<u8> = groups.iter().flat_map(|g| u32::from(*g).to_ne_bytes()).collect();
+    copy_out(&t.process.kernel.runtime, list, &bytes)?;
+    Ok(groups.len())
+}
+
+pub fn sys_setgroups<R:Runtime>(t: Task<R>, size: usize, list: AddressSpace) -> Result<usize, Error> {
+    if !t.process.creds.lock().capable(Capabilities::CAP_SETGID) {
+        return Err(linuxerr!(EPERM));
+    }
+    if size > NGROUPS_MAX {
+        return Err(linuxerr!(EINVAL));
+    }
+    let mut bytes = vec![0u8; size * 4];
+    if size > 0 {
+        copy_in(&t.process.kernel.runtime, list, &mut bytes)?;
+    }
+    let mut groups: Vec<Gid> = bytes.chunks(4)
+        .map(|b| Gid::new(u32::from_ne_bytes([b[0], b[1], b[2], b[3]])))
+        .collect();
+    if groups.iter().any(|g| t.process.creds.lock().host_gid(*g).is_none()) {
+        return Err(linuxerr!(EINVAL));
+    }
+    groups.sort_by_key(|g| u32::from(*g));
+    groups.dedup();
+    t.process.creds.lock().groups = groups;
+    Ok(0)
 }
 
 pub fn sys_getuid<R:Runtime>(t: Task<R>) -> core::result::Result<usize, Infallible> {
@@ -75,34 +509,20 @@ pub fn sys_gettid<R:Runtime>(t:Task<R>) -> core::result::Result<usize, Infallibl
     Ok(tid as _)
 }
 
-pub async fn sys_getresuid<R:Runtime>(
-    t: Task<R>,
-    ruid: *mut Uid,
-    euid: *mut Uid,
-    suid: *mut Uid,
-) -> Result<usize, Error> {
-    let creds = t.process.creds.lock().clone();
-
-    unsafe {
-        *ruid = creds.uid;
-        *euid = creds.euid;
-        *suid = creds.suid;
+// three u32s, each to its own address
+fn copy_out_ids<R:Runtime>(t: &Task<R>, ids: [(AddressSpace, u32); 3]) -> Result<usize, Error> {
+    for (to, id) in ids {
+        copy_out(&t.process.kernel.runtime, to, &id.to_ne_bytes())?;
     }
-    
     Ok(0)
 }
 
-pub async fn sys_getresgid<R:Runtime>(
-    t:Task<R>,
-    rgid: *mut Gid,
-    egid: *mut Gid,
-    sgid: *mut Gid
-) -> Result<usize, Error> {
+pub fn sys_getresuid<R:Runtime>(t: Task<R>, ruid: AddressSpace, euid: AddressSpace, suid: AddressSpace) -> Result<usize, Error> {
     let creds = t.process.creds.lock().clone();
-    unsafe {
-        *rgid = creds.gid;
-        *egid = creds.egid;
-        *sgid = creds.sgid;
-    }
-    Ok(0)
+    copy_out_ids(&t, [(ruid, creds.uid.into()), (euid, creds.euid.into()), (suid, creds.suid.into())])
+}
+
+pub fn sys_getresgid<R:Runtime>(t: Task<R>, rgid: AddressSpace, egid: AddressSpace, sgid: AddressSpace) -> Result<usize, Error> {
+    let creds = t.process.creds.lock().clone();
+    copy_out_ids(&t, [(rgid, creds.gid.into()), (egid, creds.egid.into()), (sgid, creds.sgid.into())])
 }
@@ -1,17 +1,17 @@
-use alloc::{vec};
-use crate::{Fd, FileType, AddressSpace, linuxerr, Task, Runtime};
-use protocol::{Buffer,
-               Command,
-               Value,
-               Error,
-               Attribute,
-               attribute};
+use alloc::{string::String, vec::Vec};
+use core::time::Duration;
+use crate::{Fd, FileType, AddressSpace, copy_out, linuxerr, Task, Runtime, stat::ino,
+            rw::drain, times::{Times, accessed}};
+use protocol::{Error,
+               Oid,
+               attribute,
+               execute};
 
 // merge with file type
 #[repr(u8)]
 #[derive(Clone, Copy, Debug)]
 enum DirentFileType {
-    _Unknown = 0,
+    Unknown = 0,
     Fifo = 1,
     Char = 2,
     Dir = 4,
@@ -54,52 +54,104 @@ fn pad(x: usize, to: usize) -> usize {
     (((x - 1) / to) + 1) * to
 }
 
-macro_rules! write {
-    ($dest:expr, $source:expr) => {
-        $dest.write($source).map_err(|_| linuxerr!(EINVAL))
+// the position of a directory is a cookie, d_off of an entry is the one to
+// come back with to carry on after it. they have to survive entries coming
+// and going around them, so rather than counting we hash the name, and list
+// in cookie order. the first few are ours
+const COOKIE_DOT: u64 = 1;
+const COOKIE_DOTDOT: u64 = 2;
+const COOKIE_FIRST: u64 = 3;
+
+// fnv-1a, kept positive since telldir hands it back as an off_t. two names
+// that hash the same would lose one of them across a resume, with 63 bits
+// we live with that
+fn cookie(name: &str) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325;
+    for b in name.bytes() {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x100000001b3);
     }
+    (h >> 1).max(COOKIE_FIRST)
 }
 
-macro_rules! var {
-    ($num:expr) => {
-        Value::Variable($num)
-    }
+struct Entry {
+    cookie: u64,
+    ino: u64,
+    kind: DirentFileType,
+    name: String,
 }
 
-macro_rules! union {
-    ($num:expr) => {
-        Value::Union($num)
+// one record, or false if it doesn't fit
+fn emit(b: &mut Vec<u8>, size: usize, e: &Entry) -> bool {
+    let header_len = core::mem::size_of::<Dirent64Hdr>();
+    let reclen = pad(header_len + e.name.len() + 1, 8);
+    if b.len() + reclen > size {
+        return false;
     }
+    b.extend_from_slice(&e.ino.to_le_bytes());
+    b.extend_from_slice(&e.cookie.to_le_bytes());
+    b.extend_from_slice(&(reclen as u16).to_le_bytes());
+    b.push(e.kind as u8);
+    b.extend_from_slice(e.name.as_bytes());
+    b.resize(b.len() + reclen - header_len - e.name.len(), 0);
+    true
 }
 
-// i would like to check if it has a contents, but not
-// to pull the whole thing. we also need to demux special files and links
+// every entry, in cookie order, with "." and ".." at the front, and the
+// times of the directory
+async fn entries<R:Runtime>(t: &Task<R>, dir: Oid) -> Result<(Vec<Entry>, Times), Error> {
+    let mut here = None;
+    execute!(t.process.kernel.runtime,
+             [Get(dir, attribute!("children"), names:Oid),
+              Get(dir, attribute!("parent"), parent:Option<Oid>),
+              Get(dir, attribute!("atime"), atime:Option<Duration>),
+              Get(dir, attribute!("mtime"), mtime:Option<Duration>),
+              Get(dir, attribute!("ctime"), ctime:Option<Duration>)] {
+                  // the root is its own parent
+                  here = Some((names, parent.unwrap_or(dir), Times { atime, mtime, ctime }));
+              });
+    // no row means no children, so it isn't a directory
+    let Some((names, parent, times)) = here else {
+        return Err(linuxerr!(ENOTDIR));
+    };
 
-pub async fn sys_getdents64<R:Runtime>(t: Task<R>, fd: Fd, mut ubuf: AddressSpace, size: u32) -> Result<usize, Error> {
-    let file = t.process.get_fd(fd)?;
-    let b = Buffer::new();
-    let st = t.process.kernel.runtime.execute(
-        vec!(Command::Get(file.obj, attribute!("children"), var!(0), union!(4)),
-             Command::Get(var!(0), var!(1), var!(2), union!(4)),
-             Command::Get(var!(2), attribute!("children"), union!(3), union!(4))))?;
+    let mut out = Vec::from([
+        Entry { cookie: COOKIE_DOT, ino: ino(dir), kind: DirentFileType::Dir, name: ".".into() },
+        Entry { cookie: COOKIE_DOTDOT, ino: ino(parent), kind: DirentFileType::Dir, name: "..".into() },
+    ]);
+    let mut children = Vec::new();
+    execute!(t.process.kernel.runtime,
+             [Get(names, name:String, child:Oid),
+              Get(child:Oid, attribute!("type"), kind:Option<FileType>)] {
+                  let kind = kind.map_or(DirentFileType::Unknown, DirentFileType::from);
+                  children.push(Entry { cookie: cookie(&name), ino: ino(child), kind, name });
+              });
+    children.sort_by(|a, b| (a.cookie, &a.name).cmp(&(b.cookie, &b.name)));
+    out.extend(children);
+    Ok((out, times))
+}
 
-    while let Some(v) = st.next() {
-        if let Value::String(name) = v[1] && 
-            let Value::Oid(oid) = v[2] &&
-            let Value::Set(children) = v[3] {
-                let header_len = core::mem::size_of::<Dirent64Hdr>();
-                let reclen : u16 = header_len + name.len() + 1;
-                write!(b, &oid.to_le_64())?;
-                write!(b, &(pad(reclen as u64, 8).to_le_u64()))?;
-                write!(b, &reclen.to_le_64())?;
-                let kind = if children.len() > 0 {
-                    &[DirentFileType::Dir as u8]
-                } else {
-                    &[DirentFileType::Reg as u8]
-                };
-                write!(b, kind)?;
-                write!(b, &name.into_bytes())?;
+pub async fn sys_getdents64<R:Runtime>(t: Task<R>, fd: Fd, ubuf: AddressSpace, size: u32) -> Result<usize, Error> {
+    let file = t.process.get_fd(fd)?;
+    let size = size as usize;
+    let mut b = Vec::new();
+    let mut pos = file.pos;
+    let (entries, times) = entries(&t, file.obj).await?;
+    for e in entries.iter().filter(|e| e.cookie > file.pos) {
+        if !emit(&mut b, size, e) {
+            // not even one record fits
+            if b.is_empty() {
+                return Err(linuxerr!(EINVAL));
             }
+            break;
+        }
+        pos = e.cookie;
+    }
+    // at the end we hand back nothing, and pos stays where it is
+    copy_out(&t.process.kernel.runtime, ubuf, &b)?;
+    if let Some(set) = accessed(&t, file.obj, &times) {
+        drain(&t, Vec::from([set])).await?;
     }
+    t.process.set_pos(fd, pos)?;
     Ok(b.len())
 }
@@ -1,317 +1,88 @@
+use alloc::vec::Vec;
 use crate::{
-    arch::{Arch, ArchImpl},
-    fs::VFS,
-    memory::{
-        page::ClaimedPage,
-        uaccess::{copy_from_user, cstr::UserCStr},
-    },
-    process::{TaskState, ctx::Context, thread_group::signal::SignalState},
-    sched::current_task,
+    AT_FDCWD, AccessMode, AddressSpace, Fd, FileType, LinuxError, Lockable, PATH_MAX, RlimitId, Runtime, Task,
+    copy_in, copy_in_cstr, linuxerr,
+    access::permission,
+    close::close_on_exec,
+    namei::resolve,
+    view,
 };
-use alloc::{string::String, vec};
-use alloc::{string::ToString, sync::Arc, vec::Vec};
-use auxv::{AT_NULL, AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_RANDOM};
-use core::{ffi::c_char, mem, slice};
-use libkernel::{
-    UserAddressSpace, VirtualMemory,
-    error::{ExecError, KernelError, Result},
-    fs::{Inode, path::Path},
-    memory::{
-        PAGE_SIZE,
-        address::{TUA, VA},
-        permissions::PtePermissions,
-        proc_vm::{
-            ProcessVM,
-            memory_map::MemoryMap,
-            vmarea::{VMAPermissions, VMArea, VMAreaKind},
-        },
-        region::VirtMemoryRegion,
-    },
-};
-use object::{
-    LittleEndian,
-    elf::{self, PT_LOAD},
-    read::elf::{FileHeader, ProgramHeader},
-};
-
-mod auxv;
-
-const STACK_END: usize = 0x0000_8000_0000_0000;
-const STACK_SZ: usize = 0x2000 * 0x400;
-const STACK_START: usize = STACK_END - STACK_SZ;
-
-
-pub fn load_from_pheader<E: Endian>(
-    executable: Oid,
-    hdr: ProgramHeader64<E>,
-    endian: E,
-) {
-    let mut permissions = VMAPermissions {
-        read: false,
-        write: false,
-        execute: false,
+use protocol::{Error, Oid};
+
+// linux's limits on what can be passed, one string can't be more than 32
+// pages and all of them with their pointers no more than a quarter of the
+// stack
+pub const MAX_ARG_STRLEN: usize = 32 * 4096;
+pub const MAX_ARG_STRINGS: usize = 0x7fff_ffff;
+
+// a NULL terminated array of pointers to strings, argv or envp. a NULL
+// array is no strings at all
+fn strings<R:Runtime>(t: &Task<R>, array: AddressSpace, total: &mut usize) -> Result<Vec<Vec<u8>>, Error> {
+    let AddressSpace::User(mut at) = array else {
+        return Err(linuxerr!(EFAULT));
     };
-    
-    if hdr.p_flags(endian) & PF_X != 0 {
-        permissions.execute = true;
-    }
-    
-    if hdr.p_flags(endian) & PF_R != 0 {
-        permissions.read = true;
-    }
-
-    if hdr.p_flags(endian) & PF_W != 0 {
-        permissions.write = true;
+    let mut out = Vec::new();
+    if at == 0 {
+        return Ok(out);
     }
-    
-    let mappable_region = VirtMemoryRegion::new(
-        VA::from_value(hdr.p_vaddr(endian) as usize),
-        hdr.p_memsz(endian) as usize,
-    )
-        .to_mappable_region();
-    
-    Self {
-        region: mappable_region.region(),
-        kind: VMAreaKind::File(VMFileMapping {
-            file: f,
-            offset: hdr.p_offset(endian) - mappable_region.offset() as u64,
-            len: hdr.p_filesz(endian) + mappable_region.offset() as u64,
-        }),
-        permissions,
-    }
-}
-
-
-pub async fn kernel_exec(
-    executable: Oid,
-    argv: Vec<String>,
-    envp: Vec<String>,
-) -> Result<()> {
-    let mut auxv = Vec::new();
-
-    let buf = object_read(executable, "contents", 0, core::mem::size_of::<elf::FileHeader64<LittleEndian>>());
-
-    let elf = elf::FileHeader64::<LittleEndian>::parse(buf.as_slice())
-        .map_err(|_| ExecError::InvalidElfFormat)?;
-    let endian = elf.endian().unwrap();
-
-    // Push program header params.
-    auxv.push(AT_PHNUM);
-    auxv.push(elf.e_phnum.get(endian) as _);
-    auxv.push(AT_PHENT);
-    auxv.push(elf.e_phentsize(endian) as _);
-
-    let ph_buf = object_read(executable, "contents", 0,
-                             elf.e_phnum.get(endian) as usize * elf.e_phentsize.get(endian) as usize
-                             + elf.e_phoff.get(endian) as usize);
-
-    let hdrs = elf
-        .program_headers(endian, ph_buf.as_slice())
-        .map_err(|_| ExecError::InvalidPHdrFormat)?;
-
-    let mut vmas = Vec::new();
-    let mut highest_addr = 0;
-
-    for hdr in hdrs {
-        let kind = hdr.p_type(endian);
-
-        if kind == PT_LOAD {
-            // backing store, lets just read ahead
-            vmas.push(VMArea::from_pheader(inode.clone(), *hdr, endian));
-
-            if hdr.p_offset.get(endian) == 0 {
-                // TODO: poteintally more validation that this VA will contain
-                // the program headers.
-                auxv.push(AT_PHDR);
-                auxv.push(hdr.p_vaddr.get(endian) + elf.e_phoff.get(endian));
-            }
-
-            let mapping_end = hdr.p_vaddr(endian) + hdr.p_memsz(endian);
-
-            if mapping_end > highest_addr {
-                highest_addr = mapping_end;
-            }
+    loop {
+        let mut p = [0u8; 8];
+        copy_in(&t.process.kernel.runtime, AddressSpace::User(at), &mut p)?;
+        let p = u64::from_ne_bytes(p);
+        if p == 0 {
+            return Ok(out);
+        }
+        if out.len() == MAX_ARG_STRINGS {
+            return Err(linuxerr!(E2BIG));
         }
+        let s = copy_in_cstr(&t.process.kernel.runtime, AddressSpace::User(p), MAX_ARG_STRLEN - 1)
+            .map_err(|e| if e.syserr == Some(LinuxError::ENAMETOOLONG as u8) { linuxerr!(E2BIG) } else { e })?;
+        *total += s.len() + 1 + 8;
+        out.push(s);
+        at += 8;
     }
-
-    vmas.push(VMArea::new(
-        VirtMemoryRegion::new(VA::from_value(STACK_START), STACK_SZ),
-        VMAreaKind::Anon,
-        VMAPermissions::rw(),
-    ));
-
-    let mut mem_map = MemoryMap::from_vmas(vmas)?;
-
-    let stack_ptr = setup_user_stack(&mut mem_map, &argv, &envp, auxv)?;
-
-    let user_ctx =
-        ArchImpl::new_user_context(VA::from_value(elf.e_entry(endian) as usize), stack_ptr);
-    let mut vm = ProcessVM::from_map(mem_map, VA::from_value(highest_addr as usize));
-
-    // We don't have to worry about actually calling for a full context switch
-    // here. Parts of the old process that are replaced will go out of scope and
-    // be cleaned up (open files, etc); We don't need to preseve any extra
-    // state. Simply activate the new process's address space.
-    vm.mm_mut().address_space_mut().activate();
-
-    let current_task = current_task();
-
-    *current_task.ctx.lock_save_irq() = Context::from_user_ctx(user_ctx);
-    *current_task.state.lock_save_irq() = TaskState::Runnable;
-    *current_task.vm.lock_save_irq() = vm;
-    *current_task.process.signals.lock_save_irq() = SignalState::new_default();
-
-    Ok(())
 }
 
-// Sets up the user stack according to the System V ABI.
-//
-// The stack layout from high addresses to low addresses is:
-// - Argument and Environment strings
-// - Padding to 16-byte boundary
-// - Auxiliary Vector (auxv)
-// - Environment pointers (envp)
-// - Argument pointers (argv)
-// - Argument count (argc)
-//
-// The final stack pointer will point to `argc`.
-fn setup_user_stack(
-    mm: &mut MemoryMap<<ArchImpl as VirtualMemory>::ProcessAddressSpace>,
-    argv: &[String],
-    envp: &[String],
-    mut auxv: Vec<u64>,
-) -> Result<VA> {
-    // Calculate the space needed and the virtual addresses for all strings and
-    // pointers.
-    let mut string_addrs = Vec::new();
-    let mut total_string_size = 0;
-
-    // We add strings to the stack from top-down.
-    for s in envp.iter().chain(argv.iter()) {
-        let len = s.len() + 1; // +1 for null terminator
-        total_string_size += len;
-        string_addrs.push(len); // Temporarily store length
-    }
-
-    let mut current_va = STACK_END;
-    for len in string_addrs.iter_mut().rev() {
-        // Now calculate the final virtual address of each string.
-        current_va -= *len;
-        *len = current_va; // Replace length with the VA
-    }
-
-    let (envp_addrs, argv_addrs) = string_addrs.split_at(envp.len());
-
-    let mut info_block = Vec::<u64>::new();
-    info_block.push(argv.len() as u64); // argc
-    info_block.extend(argv_addrs.iter().map(|&addr| addr as u64));
-    info_block.push(0); // Null terminator for argv
-    info_block.extend(envp_addrs.iter().map(|&addr| addr as u64));
-    info_block.push(0); // Null terminator for envp
-
-    // Add auxiliary vectors
-    auxv.push(AT_PAGESZ);
-    auxv.push(PAGE_SIZE as u64);
-    auxv.push(AT_RANDOM);
-    // TODO: SECURITY: Actually make this a random value.
-    auxv.push(STACK_END as u64 - 0x10);
-    auxv.push(AT_NULL);
-    auxv.push(0);
-
-    info_block.append(&mut auxv);
-
-    let info_block_size = info_block.len() * mem::size_of::<u64>();
-
-    // The top of the info block must be 16-byte aligned. The stack pointer on
-    // entry to the new process must also be 16-byte aligned.
-    let strings_base_va = STACK_END - total_string_size;
-    let final_sp_unaligned = strings_base_va - info_block_size;
-    let final_sp_val = final_sp_unaligned & !0xF; // Align down to 16 bytes
-
-    let total_stack_size = STACK_END - final_sp_val;
-    if total_stack_size > STACK_SZ {
-        return Err(KernelError::TooLarge);
-    }
-
-    let mut stack_image = vec![0u8; total_stack_size];
-
-    // Write strings into the image
-    let mut string_cursor = STACK_END;
-    for s in envp.iter().chain(argv.iter()).rev() {
-        string_cursor -= s.len() + 1;
-        let offset = total_stack_size - (STACK_END - string_cursor);
-        stack_image[offset..offset + s.len()].copy_from_slice(s.as_bytes());
-        // Null terminator is already there from vec![0;...].
-    }
-
-    // Write info block into the image
-    let info_block_bytes: &[u8] =
-        unsafe { slice::from_raw_parts(info_block.as_ptr().cast(), info_block_size) };
-    let info_block_offset = total_stack_size - (STACK_END - final_sp_val);
-    stack_image[info_block_offset..info_block_offset + info_block_size]
-        .copy_from_slice(info_block_bytes);
-
-    // Allocate pages, copy image, and map into user space
-    let num_pages = total_stack_size.div_ceil(PAGE_SIZE);
-
-    for i in 0..num_pages {
-        let mut page = ClaimedPage::alloc_zeroed()?;
-
-        // Calculate the slice of the stack image that corresponds to this page
-        let image_end = total_stack_size - i * PAGE_SIZE;
-        let image_start = image_end.saturating_sub(PAGE_SIZE);
-        let image_slice = &stack_image[image_start..image_end];
-
-        // Copy the data
-        let page_slice = page.as_slice_mut();
-        page_slice[PAGE_SIZE - image_slice.len()..].copy_from_slice(image_slice);
-
-        // Map the page to the correct virtual address
-        let page_va = VA::from_value(STACK_END - (i + 1) * PAGE_SIZE);
-        mm.address_space_mut()
-            .map_page(page.leak(), page_va, PtePermissions::rw(true))?;
+// what has to hold before anything changes. only a regular file can be
+// run, and only if the caller could execute it, which for root still means
+// one of the execute bits is set
+pub async fn executable<R:Runtime>(t: &Task<R>, path: &[u8]) -> Result<Oid, Error> {
+    let r = resolve(t, Fd(AT_FDCWD), path, true).await?;
+    let oid = r.oid.ok_or(linuxerr!(ENOENT))?;
+    match r.file_type {
+        FileType::File if r.trailing_slash => return Err(linuxerr!(ENOTDIR)),
+        FileType::File => {}
+        _ => return Err(linuxerr!(EACCES)),
     }
-
-    Ok(VA::from_value(final_sp_val))
+    permission(t, oid, AccessMode::X_OK).await?;
+    Ok(oid)
 }
 
-pub async fn sys_execve(
-    path: TUA<c_char>,
-    mut usr_argv: TUA<TUA<c_char>>,
-    mut usr_env: TUA<TUA<c_char>>,
-) -> Result<usize> {
-    let mut buf = [0; 1024];
-    let mut argv = Vec::new();
-    let mut envp = Vec::new();
-
-    loop {
-        let ptr = copy_from_user(usr_argv).await?;
-
-        if ptr.is_null() {
-            break;
-        }
-
-        let str = UserCStr::from_ptr(ptr).copy_from_user(&mut buf).await?;
-        argv.push(str.to_string());
-        usr_argv = usr_argv.add_objs(1);
+// the process side of it, the runtime does the program. past the checks
+// the process is the new program's, the view it brings and its descriptors
+// without the close on exec ones, whether or not the runtime manages to
+// start it
+pub async fn sys_execve<R:Runtime>(
+    t: Task<R>,
+    path: AddressSpace,
+    argv: AddressSpace,
+    envp: AddressSpace,
+) -> Result<usize, Error> {
+    let path = copy_in_cstr(&t.process.kernel.runtime, path, PATH_MAX - 1)?;
+    if path.is_empty() {
+        return Err(linuxerr!(ENOENT));
     }
-
-    loop {
-        let ptr = copy_from_user(usr_env).await?;
-
-        if ptr.is_null() {
-            break;
-        }
-
-        let str = UserCStr::from_ptr(ptr).copy_from_user(&mut buf).await?;
-        envp.push(str.to_string());
-        usr_env = usr_env.add_objs(1);
+    let exe = executable(&t, &path).await?;
+    let mut total = 0;
+    let argv = strings(&t, argv, &mut total)?;
+    let envp = strings(&t, envp, &mut total)?;
+    let stack = t.process.rlimits.lock()[RlimitId::STACK.as_usize()].rlim_cur;
+    if total as u64 > stack / 4 {
+        return Err(linuxerr!(E2BIG));
     }
 
-    let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    let inode = VFS.resolve_path(path, VFS.root_inode()).await?;
-
-    kernel_exec(inode, argv, envp).await?;
-
+    view::exec(&t, exe).await?;
+    close_on_exec(&t).await?;
+    t.process.kernel.runtime.exec(t.process.myself, exe, argv, envp)?;
     Ok(0)
 }
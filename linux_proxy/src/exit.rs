@@ -1,38 +1,36 @@
-use crate::sched::current_task;
 use alloc::vec::Vec;
-use ringbuf::Arc;
+use crate::{Lockable, Pid, ProcessState, Runtime, Task, TaskState, close::close_all};
+use protocol::Error;
 
-use crate::{
-    KernelError,
-    process::TaskState,
-    process::thread_group::{ProcessState, Tgid, ThreadGroup},
-};
+// SigSet's bit for it, see signal
+pub const SIGCHLD: u64 = 1 << 16;
 
-pub fn do_exit_group(exit_code: ChildState) {
-    let task = current_task();
-    let process = Arc::clone(&task.process);
+// what a parent is told about a child that's gone, see wait
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChildState {
+    NormalExit { code: u32 },
+    SignalExit { signal: u32, core: bool },
+}
+
+pub async fn do_exit_group<R:Runtime>(task: &Task<R>, exit_code: ChildState) -> Result<(), Error> {
+    let process = task.process.clone();
 
-    if process.tgid.is_init() {
+    if process.pid.is_init() {
         panic!("Attempted to kill init");
     }
 
-    let parent = process
-        .parent
-        .lock_save_irq()
-        .as_ref()
-        .and_then(|x| x.upgrade())
-        .unwrap();
+    let parent = process.kernel.get_process(*process.parent.lock());
 
     {
-        let mut process_state = process.state.lock_save_irq();
+        let mut process_state = process.state.lock();
 
         // Check if we're already exiting (e.g., two threads call exit_group at
         // once)
         if *process_state != ProcessState::Running {
             // We're already on our way out. Just kill this thread.
             drop(process_state);
-            *task.state.lock_save_irq() = TaskState::Finished;
-            return;
+            *task.state.lock() = TaskState::Finished;
+            return Ok(());
         }
 
         // It's our job to tear it all down. Mark the process as exiting.
@@ -41,14 +39,14 @@ pub fn do_exit_group(exit_code: ChildState) {
 
     // Signal all other threads in the group to terminate. We iterate over Weak
     // pointers and upgrade them.
-    for thread_weak in process.threads.lock_save_irq().values() {
+    for thread_weak in process.threads.lock().values() {
         if let Some(other_thread) = thread_weak.upgrade() {
             // Don't signal ourselves
             if other_thread.tid != task.tid {
                 // TODO: Send an IPI/Signal to halt execution now. For now, just
                 // wait for the scheduler to never schdule any of it's tasks
                 // again.
-                *other_thread.state.lock_save_irq() = TaskState::Finished;
+                *other_thread.state.lock() = TaskState::Finished;
             }
         }
     }
@@ -57,52 +55,59 @@ pub fn do_exit_group(exit_code: ChildState) {
     // to wait for all the processes to have stopped execution before tearing
     // down the address-space, etc.
 
+    // the descriptors go before the parent hears about it, like linux. the
+    // rest of the teardown happens even if letting go of one fails
+    let closed = close_all(task).await;
+
     // Reparent children to `init`
     {
-        let mut our_children = process.children.lock_save_irq();
+        let mut our_children = process.children.lock();
 
-        let init = ThreadGroup::get(Tgid::init()).expect("Could not find init process");
+        let init = process.kernel.get_process(Pid::init()).expect("Could not find init process");
 
-        let mut init_children = init.children.lock_save_irq();
+        let mut init_children = init.children.lock();
 
         let mut our_children: Vec<_> = core::mem::take(&mut *our_children).into_iter().collect();
 
-        for (tgid, our_child) in our_children.drain(..) {
-            *our_child.parent.lock_save_irq() = Some(Arc::downgrade(&init));
+        for (pid, our_child) in our_children.drain(..) {
+            *our_child.parent.lock() = init.pid;
 
-            init_children.insert(tgid, our_child);
+            init_children.insert(pid, our_child);
         }
     }
 
-    parent.children.lock_save_irq().remove(&process.tgid);
+    // a process nothing here started has nobody to tell
+    if let Some(parent) = parent {
+        parent.children.lock().remove(&process.pid);
 
-    parent.child_notifiers.child_update(process.tgid, exit_code);
+        parent.child_states.lock().insert(process.pid, exit_code);
 
-    parent.signals.lock_save_irq().set_pending(SigId::SIGCHLD);
+        *parent.pending.lock() |= SIGCHLD;
+    }
 
     // 5. This thread is now finished.
-    *task.state.lock_save_irq() = TaskState::Finished;
+    *task.state.lock() = TaskState::Finished;
 
     // NOTE: that the scheduler will never execute the task again since it's
     // state is set to Finished.
+    closed
 }
 
-pub fn kernel_exit_with_signal(signal: SigId, core: bool) {
-    do_exit_group(ChildState::SignalExit { signal, core });
+pub async fn kernel_exit_with_signal<R:Runtime>(task: &Task<R>, signal: u32, core: bool) -> Result<(), Error> {
+    do_exit_group(task, ChildState::SignalExit { signal, core }).await
 }
 
-pub fn sys_exit_group(exit_code: usize) -> Result<usize, KernelError> {
-    do_exit_group(ChildState::NormalExit {
+pub async fn sys_exit_group<R:Runtime>(t: Task<R>, exit_code: usize) -> Result<usize, Error> {
+    do_exit_group(&t, ChildState::NormalExit {
         code: exit_code as _,
-    });
+    }).await?;
 
     Ok(0)
 }
 
-pub fn sys_exit(exit_code: usize) -> Result<usize, KernelError> {
-    let task = current_task();
-    let process = Arc::clone(&task.process);
-    let mut thread_lock = process.threads.lock_save_irq();
+pub async fn sys_exit<R:Runtime>(t: Task<R>, exit_code: usize) -> Result<usize, Error> {
+    let process = t.process.clone();
+    let mut thread_lock = process.threads.lock();
 
     // How many threads are left? We must count live ones.
     let live_threads = thread_lock
@@ -119,17 +124,17 @@ pub fn sys_exit(exit_code: usize) -> Result<usize, KernelError> {
         // we've established we're the only thread and we're executing a
         // sys_exit, there can absolutely be no way that a new thread can be
         // spawned on this process while the thread_lock is released.
-        do_exit_group(ChildState::NormalExit {
+        do_exit_group(&t, ChildState::NormalExit {
             code: exit_code as _,
-        });
+        }).await?;
 
         Ok(0)
     } else {
         // Mark our own state as finished.
-        *task.state.lock_save_irq() = TaskState::Finished;
+        *t.state.lock() = TaskState::Finished;
 
         // Remove ourself from the process's thread list.
-        thread_lock.remove(&task.tid);
+        thread_lock.remove(&t.tid);
 
         // 3. This thread stops executing forever. The task struct will be
         // deallocated when the last Arc<Task> is dropped (e.g., by the
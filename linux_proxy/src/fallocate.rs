@@ -0,0 +1,136 @@
+use alloc::{vec, vec::Vec};
+use crate::{
+    Fd, FileType, Runtime, Task, linuxerr,
+    namei::type_of,
+    rw::{Current, can_write, current, drain},
+    seek::extents,
+    times::{modified, now},
+    truncate::resize,
+};
+use protocol::{Command, Error, FromValue, Oid, Value, attribute};
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct FallocFlags: u32 {
+        const FALLOC_FL_KEEP_SIZE = 0x01;
+        const FALLOC_FL_PUNCH_HOLE = 0x02;
+        const FALLOC_FL_NO_HIDE_STALE = 0x04;
+        const FALLOC_FL_COLLAPSE_RANGE = 0x08;
+        const FALLOC_FL_ZERO_RANGE = 0x10;
+        const FALLOC_FL_INSERT_RANGE = 0x20;
+        const FALLOC_FL_UNSHARE_RANGE = 0x40;
+    }
+}
+
+// what's left of the extents with start..end taken out
+fn without(extents: &[(u64, u64)], start: u64, end: u64) -> Vec<(u64, u64)> {
+    let mut out = Vec::new();
+    for &(s, l) in extents {
+        let e = s + l;
+        if s < start {
+            out.push((s, e.min(start) - s));
+        }
+        if e > end {
+            let s = s.max(end);
+            out.push((s, e - s));
+        }
+    }
+    out
+}
+
+// zeros start..end and takes it out of the extents, so seek sees the hole.
+// the contents are cleared and what's either side copied back, which
+// leaves zeros between, like shrinking. the extents that run into the hole
+// are cut where they are, each with a compare and set so two punches can't
+// both cut the same one. a file without extents gets them, with the hole
+// the only thing missing
+async fn punch<R:Runtime>(t: &Task<R>, obj: Oid, start: u64, end: u64) -> Result<(), Error> {
+    loop {
+        let Current { stored, size, extents: ext, .. } = current(t, obj).await?;
+        let end = end.min(size);
+        if start >= end {
+            return Ok(());
+        }
+
+        // the size doesn't change, but it mustn't have either
+        let mut block = vec![
+            Command::CompareAndSet(Value::Oid(obj), attribute!("size"), stored.clone(), stored),
+        ];
+        match &ext {
+            Value::Empty() => {
+                block.push(Command::Create(Value::Variable(0)));
+                for (s, l) in without(&[(0, size)], start, end) {
+                    block.push(Command::Set(Value::Variable(0), Value::Unsigned(s), Value::Unsigned(l)));
+                }
+                block.push(Command::CompareAndSet(Value::Oid(obj), attribute!("extents"), Value::Empty(), Value::Variable(0)));
+            }
+            _ => {
+                block.push(Command::CompareAndSet(Value::Oid(obj), attribute!("extents"), ext.clone(), ext.clone()));
+                for (s, l) in extents(t, Oid::from_value(&ext)?).await? {
+                    if s >= end || s + l <= start {
+                        continue;
+                    }
+                    let left = without(&[(s, l)], start, end);
+                    let kept = match left.first() {
+                        Some(&(ks, kl)) if ks == s => Value::Unsigned(kl),
+                        _ => Value::Empty(),
+                    };
+                    block.push(Command::CompareAndSet(ext.clone(), Value::Unsigned(s), Value::Unsigned(l), kept));
+                    for (ks, kl) in left.into_iter().filter(|(ks, _)| *ks != s) {
+                        block.push(Command::Set(ext.clone(), Value::Unsigned(ks), Value::Unsigned(kl)));
+                    }
+                }
+            }
+        }
+        block.push(Command::Set(Value::Oid(obj), attribute!("contents"), Value::Empty()));
+        block.push(Command::Copy(
+            Value::Oid(obj), attribute!("contents"), Value::Unsigned(0),
+            Value::Oid(obj), attribute!("contents"), Value::Unsigned(0),
+            Value::Unsigned(start),
+        ));
+        block.push(Command::Copy(
+            Value::Oid(obj), attribute!("contents"), Value::Unsigned(end),
+            Value::Oid(obj), attribute!("contents"), Value::Unsigned(end),
+            Value::Unsigned(size - end),
+        ));
+        block.extend(modified(Value::Oid(obj), now(t)));
+        match drain(t, block).await {
+            Ok(()) => return Ok(()),
+            Err(e) if e.is_guard_failure() => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// there is no space to reserve, so plain allocation only matters past the
+// end where it grows the file, and with KEEP_SIZE not even there. punching
+// a hole is the one that changes anything inside the file. the rest of the
+// modes move data around or depend on a filesystem underneath, and are
+// refused the way a filesystem without them does
+pub async fn sys_fallocate<R:Runtime>(t: Task<R>, fd: Fd, mode: u32, offset: i64, len: i64) -> Result<usize, Error> {
+    let mode = FallocFlags::from_bits(mode).ok_or(linuxerr!(EOPNOTSUPP))?;
+    let keep = FallocFlags::FALLOC_FL_KEEP_SIZE;
+    let punching = keep | FallocFlags::FALLOC_FL_PUNCH_HOLE;
+    if !(mode.is_empty() || mode == keep || mode == punching) {
+        return Err(linuxerr!(EOPNOTSUPP));
+    }
+    if offset < 0 || len <= 0 {
+        return Err(linuxerr!(EINVAL));
+    }
+    let end = offset.checked_add(len).ok_or(linuxerr!(EFBIG))? as u64;
+    let file = t.process.get_fd(fd)?;
+    can_write(&file)?;
+    match type_of(&t, file.obj).await? {
+        FileType::File => {}
+        FileType::Directory => return Err(linuxerr!(EISDIR)),
+        FileType::Fifo => return Err(linuxerr!(ESPIPE)),
+        _ => return Err(linuxerr!(ENODEV)),
+    }
+
+    if mode == punching {
+        punch(&t, file.obj, offset as u64, end).await?;
+    } else if mode.is_empty() {
+        resize(&t, file.obj, end, false).await?;
+    }
+    Ok(0)
+}
@@ -1,4 +1,5 @@
-use crate::{OpenFlags, perr, Process, Runtime, runtime::Lockable};
+use crate::{OpenFlags, linuxerr, Process, Runtime, runtime::Lockable};
+use core::sync::atomic::Ordering;
 use protocol::{Error, Oid};
 
 #[repr(C)]
@@ -38,69 +39,111 @@ pub struct FileDescriptorEntry {
     pub oflags: crate::OpenFlags,
     pub pos: u64,
     pub flags: FdFlags,
+    // where it was opened in the view, for the *at calls. see view
+    pub path: Option<crate::Path>,
 }
 
 const MAX_FDS: usize = 8192;
 
 impl<R:Runtime> Process<R> {
     /// Inserts a new file into the table, returning the new file descriptor.
-    pub fn insert_fd(&mut self, obj: Oid, oflags: OpenFlags) -> Result<Fd, Error> {
-        let fd = self.find_free_fd()?;
+    pub fn insert_fd(&self, obj: Oid, oflags: OpenFlags, flags: FdFlags, path: Option<crate::Path>) -> Result<Fd, Error> {
+        let mut fds = self.fd_table.lock();
+        let fd = self.find_free_fd(&fds)?;
+        let fd_idx = fd.0 as usize;
+
+        if fd_idx >= fds.len() {
+            // We need to resize the vector to accommodate the new FD.
+            fds.resize_with(fd_idx + 1, || None);
+        }
 
-        let entry = FileDescriptorEntry {
+        self.kernel.opened(obj);
+        fds[fd_idx] = Some(FileDescriptorEntry {
             obj,
             oflags,
             pos: 0,
-            flags: FdFlags::default(),
-        };
-
-        self.insert_at(fd, entry);
+            flags,
+            path,
+        });
 
         Ok(fd)
     }
 
-    // Insert the given etnry at the specified index. If there was an entry at
-    // that index `Some(entry)` is returned. Otherwise, `None` is returned.
-    fn insert_at(&mut self, fd: Fd, entry: FileDescriptorEntry) -> Option<FileDescriptorEntry> {
+    /// Removes a file descriptor from the table, returning the file if it
+    /// existed. see close, for what happens to it after
+    pub fn remove_fd(&self, fd: Fd) -> Option<FileDescriptorEntry> {
         let fd_idx = fd.0 as usize;
         let mut fds = self.fd_table.lock();
+        let entry = fds.get_mut(fd_idx)?.take()?;
+        // Update the hint to speed up the next search.
+        self.next_fd_hint.fetch_min(fd_idx, Ordering::Relaxed);
+        Some(entry)
+    }
 
-        if fd_idx >= fds.len() {
-            // We need to resize the vector to accommodate the new FD.
-            self.fd_table.lock().resize_with(fd_idx + 1, || None);
+    /// Moves the position of an open file. the descriptor may have been
+    /// closed since it was looked up, which is an EBADF like any other
+    pub fn set_pos(&self, fd: Fd, pos: u64) -> Result<(), Error> {
+        match self.fd_table.lock().get_mut(fd.0 as usize) {
+            Some(Some(entry)) => {
+                entry.pos = pos;
+                Ok(())
+            }
+            _ => Err(linuxerr!(EBADF)),
         }
+    }
 
-        fds[fd_idx].replace(entry)
+    /// Moves the position from where a read started to where it ended,
+    /// unless someone else moved it in the meantime, which is false
+    pub fn move_pos(&self, fd: Fd, from: u64, to: u64) -> Result<bool, Error> {
+        match self.fd_table.lock().get_mut(fd.0 as usize) {
+            Some(Some(entry)) if entry.pos == from => {
+                entry.pos = to;
+                Ok(true)
+            }
+            Some(Some(_)) => Ok(false),
+            _ => Err(linuxerr!(EBADF)),
+        }
     }
 
-    /// Removes a file descriptor from the table, not - returning the file if it
-    /// existed. do we..need to?
-    pub fn remove_fd(&mut self, fd: Fd) {
-        let fd_idx = fd.0 as usize;
-        let mut fds = self.fd_table.lock();
-        if let Some(_entry) = fds.get(fd_idx) {
-            // Update the hint to speed up the next search.
-            self.next_fd_hint = self.next_fd_hint.min(fd_idx);
+    /// Takes count bytes from the position for a write before it goes out,
+    /// returning where they start. so two writes never get the same range
+    pub fn claim_pos(&self, fd: Fd, count: u64) -> Result<u64, Error> {
+        match self.fd_table.lock().get_mut(fd.0 as usize) {
+            Some(Some(entry)) => {
+                let at = entry.pos;
+                entry.pos = at.saturating_add(count);
+                Ok(at)
+            }
+            _ => Err(linuxerr!(EBADF)),
         }
-        fds[fd_idx] = None;
     }
 
-    /// Finds the lowest-numbered available file descriptor.
-    fn find_free_fd(&mut self) -> Result<Fd, Error> {
-        let fds = self.fd_table.lock();
-        let next = fds.len();        
-        // Start searching from our hint.
-        for i in self.next_fd_hint..next {
-            if fds[i].is_none() {
-                self.next_fd_hint = i + 1;
-                return Ok(Fd(i as i32));
+    /// After an append, the end of whichever landed last
+    pub fn advance_pos(&self, fd: Fd, to: u64) -> Result<(), Error> {
+        match self.fd_table.lock().get_mut(fd.0 as usize) {
+            Some(Some(entry)) => {
+                entry.pos = entry.pos.max(to);
+                Ok(())
             }
+            _ => Err(linuxerr!(EBADF)),
+        }
+    }
+
+    /// Finds the lowest-numbered available file descriptor. called with the
+    /// table locked, so nobody else can take it before we fill it in
+    fn find_free_fd(&self, fds: &[Option<FileDescriptorEntry>]) -> Result<Fd, Error> {
+        let next = fds.len();
+        // Start searching from our hint.
+        let hint = self.next_fd_hint.load(Ordering::Relaxed);
+        if let Some(i) = fds.iter().skip(hint).position(|f| f.is_none()) {
+            self.next_fd_hint.store(hint + i + 1, Ordering::Relaxed);
+            return Ok(Fd((hint + i) as i32));
         }
         // We didn't find a free slot in the existing capacity
         if next >= MAX_FDS {
-            Err(perr!(self, "too many files"))
+            Err(linuxerr!(EMFILE))
         } else {
-            self.next_fd_hint = next + 1;
+            self.next_fd_hint.store(next + 1, Ordering::Relaxed);
             Ok(Fd(next as i32))
         }
     }
@@ -0,0 +1,91 @@
+use alloc::{format, string::String, vec::Vec};
+use protocol::{Error, FromValue, Value, err};
+
+// what a process's ids are outside of it, like a user namespace. the
+// guest sees its own uids and gids in credentials and stat, and entries
+// in the graph are owned by the principals posix_service knows, the host
+// ids. written the way /proc/pid/uid_map is, a line of
+// "inside outside count" for each range
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IdRange {
+    pub inside: u32,
+    pub outside: u32,
+    pub count: u32,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct IdMap(Vec<IdRange>);
+
+// what a host id nobody inside maps to looks like, /proc/sys/kernel/overflowuid
+pub const OVERFLOW_ID: u32 = 65534;
+
+impl IdMap {
+    // the ranges can't overlap on either side, and like linux there is a
+    // limit to how many
+    pub fn new(mut ranges: Vec<IdRange>) -> Result<Self, Error> {
+        const MAX_RANGES: usize = 340;
+        if ranges.len() > MAX_RANGES {
+            return Err(err!("too many id ranges, {}", ranges.len()));
+        }
+        for r in &ranges {
+            if r.count == 0
+                || r.inside.checked_add(r.count - 1).is_none()
+                || r.outside.checked_add(r.count - 1).is_none() {
+                return Err(err!("bad id range {} {} {}", r.inside, r.outside, r.count));
+            }
+        }
+        let overlaps = |key: fn(&IdRange) -> u32, ranges: &mut Vec<IdRange>| {
+            ranges.sort_by_key(key);
+            ranges.windows(2).any(|w| key(&w[0]) as u64 + w[0].count as u64 > key(&w[1]) as u64)
+        };
+        if overlaps(|r| r.outside, &mut ranges) || overlaps(|r| r.inside, &mut ranges) {
+            return Err(err!("overlapping id ranges"));
+        }
+        Ok(IdMap(ranges))
+    }
+
+    pub fn parse(s: &str) -> Result<Self, Error> {
+        let mut ranges = Vec::new();
+        for line in s.lines().filter(|l| !l.trim().is_empty()) {
+            let n: Vec<u32> = line.split_whitespace()
+                .map(|f| f.parse::<u32>().map_err(|_| err!("bad id map line {}", line)))
+                .collect::<Result<_, _>>()?;
+            let [inside, outside, count] = n[..] else {
+                return Err(err!("bad id map line {}", line));
+            };
+            ranges.push(IdRange { inside, outside, count });
+        }
+        IdMap::new(ranges)
+    }
+
+    pub fn ranges(&self) -> &[IdRange] {
+        &self.0
+    }
+
+    pub fn to_host(&self, id: u32) -> Option<u32> {
+        self.0.iter()
+            .find(|r| id >= r.inside && id - r.inside < r.count)
+            .map(|r| r.outside + (id - r.inside))
+    }
+
+    pub fn to_guest(&self, id: u32) -> Option<u32> {
+        self.0.iter()
+            .find(|r| id >= r.outside && id - r.outside < r.count)
+            .map(|r| r.inside + (id - r.outside))
+    }
+}
+
+impl From<IdMap> for Value {
+    fn from(value: IdMap) -> Self {
+        let lines: Vec<String> = value.0.iter()
+            .map(|r| format!("{} {} {}\n", r.inside, r.outside, r.count))
+            .collect();
+        Value::Utf8String(lines.concat())
+    }
+}
+
+impl FromValue for IdMap {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        IdMap::parse(&String::from_value(v)?)
+    }
+}
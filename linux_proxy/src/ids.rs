@@ -1,3 +1,5 @@
+use protocol::{Error, FromValue, Value};
+
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Uid(u32);
@@ -22,6 +24,18 @@ impl From<Uid> for u32 {
     }
 }
 
+impl From<Uid> for Value {
+    fn from(value: Uid) -> Self {
+        Value::Unsigned(value.0 as u64)
+    }
+}
+
+impl FromValue for Uid {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        Ok(Uid(u32::from_value(v)?))
+    }
+}
+
 #[repr(C)]
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Gid(u32);
@@ -41,3 +55,15 @@ impl From<Gid> for u32 {
         value.0
     }
 }
+
+impl From<Gid> for Value {
+    fn from(value: Gid) -> Self {
+        Value::Unsigned(value.0 as u64)
+    }
+}
+
+impl FromValue for Gid {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        Ok(Gid(u32::from_value(v)?))
+    }
+}
@@ -1,54 +1,117 @@
-use crate::{
-    memory::uaccess::{UserCopyable, copy_obj_array_from_user},
-    linux::Fd,
-    current_task,
-};
-use crate::{
-    error::KernelError,
-    memory::address::{TUA, UA},
-};
-
-#[derive(Clone, Copy)]
+use alloc::{vec, vec::Vec};
+use crate::{AddressSpace, Fd, OpenFlags, Runtime, Task, copy_in, linuxerr,
+            rw::{offset, read_at, read_from_pos, write_at, write_from_pos}};
+use protocol::Error;
+
+// the whole iovec goes out as one block of copies, each segment picking up
+// where the last left off in the file. a short read fills the segments in
+// order and stops partway through one, the rest are left alone
+
+#[derive(Clone, Copy, Debug)]
 #[repr(C)]
 pub struct IoVec {
-    pub iov_base: UA,
-    pub iov_len: usize,
+    pub iov_base: u64,
+    pub iov_len: u64,
 }
 
-// SAFETY: An IoVec is safe to copy to-and-from userspace.
-unsafe impl UserCopyable for IoVec {}
+pub const IOV_MAX: usize = 1024;
 
-pub fn iovec_commands(mut b:Buffer, v:IoVec, u64 offset) {
-    for i in v {
-        push_command(b,Operation::Copy());
-        }
-    
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct RwfFlags: u32 {
+        const RWF_HIPRI = 0x1;
+        const RWF_DSYNC = 0x2;
+        const RWF_SYNC = 0x4;
+        const RWF_NOWAIT = 0x8;
+        const RWF_APPEND = 0x10;
+        const RWF_NOAPPEND = 0x20;
+    }
 }
 
-pub async fn sys_writev(fd: Fd, iov_ptr: TUA<IoVec>, no_iov: usize) -> Result<usize, KernelError> {
-    let file = current_task()
-        .fd_table
-        .lock_save_irq()
-        .get(fd)
-        .ok_or(KernelError::BadFd)?;
+// there is no cache to sync and nothing to poll, so HIPRI, DSYNC, SYNC and
+// NOWAIT are already true of every transfer. anything we don't know about
+// is refused the way linux does
+fn rwf(flags: u64) -> Result<RwfFlags, Error> {
+    RwfFlags::from_bits(flags as u32)
+        .filter(|_| flags >> 32 == 0)
+        .ok_or(linuxerr!(EOPNOTSUPP))
+}
 
-    let iovs = copy_obj_array_from_user(iov_ptr, no_iov).await?;    
-    let (ops, state) = &mut *file.lock().await;
-    let mut b = Buffer::new(1024);
-    iovec_commands(b, iovs);
-    dispatch_block(b);
+fn iovecs<R:Runtime>(t: &Task<R>, iov: AddressSpace, iovcnt: usize) -> Result<Vec<IoVec>, Error> {
+    if iovcnt > IOV_MAX {
+        return Err(linuxerr!(EINVAL));
+    }
+    let mut raw = vec![0u8; iovcnt * core::mem::size_of::<IoVec>()];
+    copy_in(&t.process.kernel.runtime, iov, &mut raw)?;
+    let word = |b: &[u8]| u64::from_le_bytes(b.try_into().unwrap());
+    let v: Vec<IoVec> = raw.chunks(16)
+        .map(|c| IoVec { iov_base: word(&c[..8]), iov_len: word(&c[8..]) })
+        .collect();
+    // the total has to fit in the ssize_t we return
+    let mut total: u64 = 0;
+    for s in &v {
+        total = total.checked_add(s.iov_len).filter(|t| *t <= isize::MAX as u64).ok_or(linuxerr!(EINVAL))?;
+    }
+    Ok(v)
 }
 
-pub async fn sys_readv(fd: Fd, iov_ptr: TUA<IoVec>, no_iov: usize) -> Result<usize, KernelError> {
-    let file = current_task()
-        .fd_table
-        .lock_save_irq()
-        .get(fd)
-        .ok_or(KernelError::BadFd)?;
+// None is the file position, which moves
+async fn readv<R:Runtime>(t: Task<R>, fd: Fd, iov: AddressSpace, iovcnt: usize, pos: Option<u64>) -> Result<usize, Error> {
+    let file = t.process.get_fd(fd)?;
+    let segments = iovecs(&t, iov, iovcnt)?;
+    match pos {
+        Some(pos) => read_at(&t, &file, &segments, pos).await,
+        None => read_from_pos(&t, fd, &segments).await,
+    }
+}
 
-    let iovs = copy_obj_array_from_user(iov_ptr, no_iov).await?;
+async fn writev<R:Runtime>(t: Task<R>, fd: Fd, iov: AddressSpace, iovcnt: usize,
+                           pos: Option<u64>, flags: RwfFlags) -> Result<usize, Error> {
+    let file = t.process.get_fd(fd)?;
+    let segments = iovecs(&t, iov, iovcnt)?;
+    let append = (file.oflags.contains(OpenFlags::O_APPEND) || flags.contains(RwfFlags::RWF_APPEND))
+        && !flags.contains(RwfFlags::RWF_NOAPPEND);
+    match pos {
+        None => write_from_pos(&t, fd, &segments, append).await,
+        Some(_) if append => Ok(write_at(&t, &file, &segments, None).await?.1 as usize),
+        Some(pos) => Ok(write_at(&t, &file, &segments, Some(pos)).await?.1 as usize),
+    }
+}
+
+// -1 is the current position for the v2 calls, anything else negative is
+// an error
+fn position(pos: i64) -> Result<Option<u64>, Error> {
+    match pos {
+        -1 => Ok(None),
+        pos => Ok(Some(offset(pos)?)),
+    }
+}
+
+pub async fn sys_readv<R:Runtime>(t: Task<R>, fd: Fd, iov: AddressSpace, iovcnt: usize) -> Result<usize, Error> {
+    readv(t, fd, iov, iovcnt, None).await
+}
+
+pub async fn sys_writev<R:Runtime>(t: Task<R>, fd: Fd, iov: AddressSpace, iovcnt: usize) -> Result<usize, Error> {
+    writev(t, fd, iov, iovcnt, None, RwfFlags::empty()).await
+}
+
+pub async fn sys_preadv<R:Runtime>(t: Task<R>, fd: Fd, iov: AddressSpace, iovcnt: usize, pos: i64) -> Result<usize, Error> {
+    readv(t, fd, iov, iovcnt, Some(offset(pos)?)).await
+}
+
+pub async fn sys_pwritev<R:Runtime>(t: Task<R>, fd: Fd, iov: AddressSpace, iovcnt: usize, pos: i64) -> Result<usize, Error> {
+    writev(t, fd, iov, iovcnt, Some(offset(pos)?), RwfFlags::empty()).await
+}
+
+// the append flags only mean anything for writing
+pub async fn sys_preadv2<R:Runtime>(t: Task<R>, fd: Fd, iov: AddressSpace, iovcnt: usize,
+                                    pos: i64, flags: u64) -> Result<usize, Error> {
+    rwf(flags)?;
+    readv(t, fd, iov, iovcnt, position(pos)?).await
+}
 
-    let mut b = Buffer::new(1024);
-    iovec_commands(b, iovs);
-    dispatch_block(b);    
+pub async fn sys_pwritev2<R:Runtime>(t: Task<R>, fd: Fd, iov: AddressSpace, iovcnt: usize,
+                                     pos: i64, flags: u64) -> Result<usize, Error> {
+    let flags = rwf(flags)?;
+    writev(t, fd, iov, iovcnt, position(pos)?, flags).await
 }
@@ -1,29 +1,74 @@
-use crate::{Pid, Process, Runtime};
-use alloc::{sync::Arc, vec::Vec};
+use crate::{Lockable, Pid, Process, Runtime, times::Atime};
+use alloc::{collections::BTreeMap, sync::{Arc, Weak}};
 use core::sync::atomic::Ordering;
+use protocol::Oid;
 
 // this is a logical kernel instance, really just a place to stash all the
 // globals from moss-kernel in someplace that isn't so global
 
 pub struct Kernel<R:Runtime> {
     pub runtime: R,
-    processes: Vec<Arc<Process<R>>>,
+    // every process here by pid, for the calls that name one
+    processes: R::Lock<BTreeMap<Pid, Weak<Process<R>>>>,
     pid_count: core::sync::atomic::AtomicU32,
+    // atime policy by device, anything not in here gets the default
+    atime: R::Lock<BTreeMap<u64, Atime>>,
+    // how many descriptors refer to each entry, across every process here.
+    // an entry nobody names is only destroyed once this goes to zero.
+    // other kernels sharing the graph don't show up in it
+    open: R::Lock<BTreeMap<Oid, usize>>,
 }
 
 impl<R:Runtime> Kernel<R> {
     // might just do these lookups in eav space rather than implement
     // it as DynEntity here
     pub fn get_process(&self, id: Pid) -> Option<Arc<Process<R>>> {
-        self.processes.get(id.0 as usize).cloned()
+        self.processes.lock().get(&id)?.upgrade()
+    }
+
+    pub fn add_process(&self, p: &Arc<Process<R>>) {
+        self.processes.lock().insert(p.pid, Arc::downgrade(p));
     }
     pub fn next_pid(&self) -> Pid {
         Pid(self.pid_count.fetch_add(1, Ordering::SeqCst))
     }
 
+    pub fn atime(&self, dev: u64) -> Atime {
+        self.atime.lock().get(&dev).copied().unwrap_or_default()
+    }
+
+    pub fn set_atime(&self, dev: u64, policy: Atime) {
+        self.atime.lock().insert(dev, policy);
+    }
+
+    pub fn opened(&self, oid: Oid) {
+        *self.open.lock().entry(oid).or_default() += 1;
+    }
+
+    // true if that was the last one
+    pub fn closed(&self, oid: Oid) -> bool {
+        let mut open = self.open.lock();
+        match open.get_mut(&oid) {
+            Some(n) if *n > 1 => {
+                *n -= 1;
+                false
+            }
+            _ => {
+                open.remove(&oid);
+                true
+            }
+        }
+    }
+
+    pub fn is_open(&self, oid: Oid) -> bool {
+        self.open.lock().contains_key(&oid)
+    }
+
     pub fn new(runtime:R) -> Kernel<R>{
         Kernel{runtime,
-               processes:Vec::new(),
-               pid_count: core::sync::atomic::AtomicU32::new(1)}
+               processes: R::Lock::new(BTreeMap::new()),
+               pid_count: core::sync::atomic::AtomicU32::new(1),
+               atime: R::Lock::new(BTreeMap::new()),
+               open: R::Lock::new(BTreeMap::new())}
     }
 }
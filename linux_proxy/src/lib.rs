@@ -1,20 +1,48 @@
 #![no_std]
 extern crate alloc;
 
+use alloc::{format, string::ToString};
+use protocol::{Error, FromValue, Value, err};
+
+pub mod access;
 pub mod attr;
+pub mod chdir;
+pub mod close;
 pub mod creds;
 pub mod dir;
+pub mod exec;
+pub mod exit;
+pub mod fallocate;
 pub mod fd_table;
 pub mod ids;
+pub mod idmap;
+pub mod iov;
 pub mod kernel;
+pub mod link;
+pub mod mkdir;
+pub mod namei;
+pub mod open;
 pub mod path;
 pub mod pid;
 pub mod process;
+pub mod readlink;
+pub mod rename;
+pub mod record;
 //pub mod wait;
 pub mod rsrc_lim;
 pub mod rw;
+pub mod seek;
+pub mod stat;
 pub mod syserr;
+pub mod syscall;
 pub mod task;
+pub mod times;
+pub mod timespec;
+pub mod truncate;
+pub mod unlink;
+pub mod utimensat;
+pub mod view;
+pub mod xattr;
 pub mod runtime;
 
 pub use creds::*;
@@ -33,7 +61,7 @@ pub use runtime::*;
 #[macro_export]
 macro_rules! perr {
     ($k:expr, $($arg:tt)*) => {{
-        protocol::Error{cause:alloc::format!($($arg)*), location:Some($k.myself), syserr: None}
+        protocol::Error{cause:alloc::format!($($arg)*), location:Some($k.myself), syserr: None, status: None}
     }}
 }
 
@@ -55,6 +83,47 @@ pub enum FileType {
     Socket,
 }
 
+// stored as the 'type' attribute. devices carry their numbers along
+impl From<FileType> for Value {
+    fn from(value: FileType) -> Self {
+        Value::Utf8String(match value {
+            FileType::File => "file".to_string(),
+            FileType::Directory => "directory".to_string(),
+            FileType::Symlink => "symlink".to_string(),
+            FileType::BlockDevice(d) => format!("block:{}:{}", d.major, d.minor),
+            FileType::CharDevice(d) => format!("char:{}:{}", d.major, d.minor),
+            FileType::Fifo => "fifo".to_string(),
+            FileType::Socket => "socket".to_string(),
+        })
+    }
+}
+
+impl FromValue for FileType {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        let s = alloc::string::String::from_value(v)?;
+        let mut parts = s.split(':');
+        let kind = parts.next();
+        let mut device = || -> Result<CharDevDescriptor, Error> {
+            let major = parts.next().and_then(|n| n.parse::<u64>().ok());
+            let minor = parts.next().and_then(|n| n.parse::<u64>().ok());
+            match (major, minor) {
+                (Some(major), Some(minor)) => Ok(CharDevDescriptor { major, minor }),
+                _ => Err(err!("bad device number in file type {}", s)),
+            }
+        };
+        match kind {
+            Some("file") => Ok(FileType::File),
+            Some("directory") => Ok(FileType::Directory),
+            Some("symlink") => Ok(FileType::Symlink),
+            Some("block") => Ok(FileType::BlockDevice(device()?)),
+            Some("char") => Ok(FileType::CharDevice(device()?)),
+            Some("fifo") => Ok(FileType::Fifo),
+            Some("socket") => Ok(FileType::Socket),
+            _ => Err(err!("unknown file type {}", s)),
+        }
+    }
+}
+
 bitflags::bitflags! {
     #[derive(Clone, Copy, Debug, PartialEq, Eq)]
     pub struct OpenFlags: u32 {
@@ -65,13 +134,31 @@ bitflags::bitflags! {
         const O_CREAT     = 0o100;
         const O_EXCL      = 0o200;
         const O_TRUNC     = 0o1000;
-        const O_DIRECTORY = 0o200000;
+        // arm64 values, they differ from x86 for these
+        const O_DIRECTORY = 0o40000;
+        const O_NOFOLLOW  = 0o100000;
         const O_APPEND    = 0o2000;
         const O_NONBLOCK  = 0o4000;
         const O_CLOEXEC   = 0o2000000;
     }
 }
 
+// the flags argument of the *at calls
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct AtFlags: u32 {
+        const AT_SYMLINK_NOFOLLOW = 0x100;  // Do not follow symbolic links.
+        const AT_EACCESS = 0x200;           // Check effective IDs in faccessat*.
+        const AT_REMOVEDIR = 0x200;         // unlinkat only, the same bit.
+        const AT_SYMLINK_FOLLOW = 0x400;    // Follow symbolic links (linkat).
+        const AT_NO_AUTOMOUNT = 0x800;      // Suppress terminal automount traversal.
+        const AT_EMPTY_PATH = 0x1000;       // Allow empty relative pathname.
+        // statx only, and meaningless here since there is no cache to sync
+        const AT_STATX_FORCE_SYNC = 0x2000;
+        const AT_STATX_DONT_SYNC = 0x4000;
+    }
+}
+
 /// Specifies how to seek within a file, mirroring `std::io::SeekFrom`.
 #[derive(Debug, Copy, Clone)]
 pub enum SeekFrom {
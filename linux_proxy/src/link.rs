@@ -0,0 +1,69 @@
+use crate::{
+    AddressSpace, AtFlags, Fd, FileType, PATH_MAX, Runtime, Task,
+    copy_in_cstr, linuxerr,
+    namei::{Edit, nlinks, resolve, start, type_of},
+    times::now,
+};
+use protocol::{Error, Value};
+
+// another name for an existing entry, which counts the link
+pub async fn sys_linkat<R:Runtime>(
+    t: Task<R>,
+    olddirfd: Fd,
+    oldpath: AddressSpace,
+    newdirfd: Fd,
+    newpath: AddressSpace,
+    flags: AtFlags,
+) -> Result<usize, Error> {
+    if !(flags - (AtFlags::AT_SYMLINK_FOLLOW | AtFlags::AT_EMPTY_PATH)).is_empty() {
+        return Err(linuxerr!(EINVAL));
+    }
+    let oldpath = copy_in_cstr(&t.process.kernel.runtime, oldpath, PATH_MAX - 1)?;
+    let newpath = copy_in_cstr(&t.process.kernel.runtime, newpath, PATH_MAX - 1)?;
+    loop {
+        let (old, file_type) = if oldpath.is_empty() && flags.contains(AtFlags::AT_EMPTY_PATH) {
+            // the dirfd itself
+            let old = start(&t, olddirfd, &oldpath)?;
+            (old, type_of(&t, old).await?)
+        } else {
+            let r = resolve(&t, olddirfd, &oldpath, flags.contains(AtFlags::AT_SYMLINK_FOLLOW)).await?;
+            if r.trailing_slash && r.file_type != FileType::Directory {
+                return Err(linuxerr!(ENOTDIR));
+            }
+            (r.oid.ok_or(linuxerr!(ENOENT))?, r.file_type)
+        };
+        if file_type == FileType::Directory {
+            return Err(linuxerr!(EPERM));
+        }
+        let r = resolve(&t, newdirfd, &newpath, false).await?;
+        let (None, Some(name)) = (r.oid, r.name) else {
+            return Err(linuxerr!(EEXIST));
+        };
+        if r.read_only {
+            return Err(linuxerr!(EROFS));
+        }
+        if r.trailing_slash {
+            return Err(linuxerr!(ENOENT));
+        }
+
+        let (stored, n) = nlinks(&t, &[old]).await?.remove(0);
+        // the last link going away in between would bring it back from
+        // the dead, the compare and set on the count catches that
+        if n == 0 {
+            return Err(linuxerr!(ENOENT));
+        }
+        let mut e = Edit::default();
+        let names = e.names(r.parent);
+        e.absent(&names, &name);
+        e.link(&names, &name, Value::Oid(old));
+        e.nlink(old, &stored, n + 1);
+        let now = now(&t);
+        e.modified(r.parent, now);
+        e.changed(old, now);
+        match e.run(&t).await {
+            Ok(_) => return Ok(0),
+            Err(e) if e.is_guard_failure() => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use crate::{
+    AddressSpace, Fd, FileType, Lockable, PATH_MAX, Runtime, Task,
+    copy_in_cstr, linuxerr,
+    attr::FilePermissions,
+    namei::{Edit, nlinks, resolve},
+    times::{created, now},
+};
+use protocol::{Error, Value};
+
+// a directory is the entry, its names entity, and a parent pointer back up
+// for '..'. the parent gains a link from the new '..'
+pub async fn sys_mkdirat<R:Runtime>(t: Task<R>, dirfd: Fd, path: AddressSpace, mode: u32) -> Result<usize, Error> {
+    let path = copy_in_cstr(&t.process.kernel.runtime, path, PATH_MAX - 1)?;
+    let umask = *t.process.umask.lock();
+    let mode = FilePermissions::from_bits_truncate((mode & !umask & 0o7777) as u16);
+    loop {
+        let r = resolve(&t, dirfd, &path, false).await?;
+        let (None, Some(name)) = (r.oid, r.name) else {
+            return Err(linuxerr!(EEXIST));
+        };
+        if r.read_only {
+            return Err(linuxerr!(EROFS));
+        }
+        let (stored, n) = nlinks(&t, &[r.parent]).await?.remove(0);
+        let (uid, gid) = t.process.creds.lock().owner()?;
+        let now = now(&t);
+        let mut e = Edit::default();
+        let names = e.names(r.parent);
+        e.absent(&names, &name);
+        let dir = e.create();
+        let children = e.create();
+        e.link(&names, &name, dir.clone());
+        e.set(dir.clone(), "children", children);
+        e.set(dir.clone(), "parent", Value::Oid(r.parent));
+        e.set(dir.clone(), "type", FileType::Directory.into());
+        e.set(dir.clone(), "mode", mode.into());
+        e.set(dir.clone(), "uid", uid.into());
+        e.set(dir.clone(), "gid", gid.into());
+        e.set(dir.clone(), "nlink", Value::from(2u32));
+        e.block.extend(created(dir, now));
+        e.modified(r.parent, now);
+        e.nlink(r.parent, &stored, n + 1);
+        match e.run(&t).await {
+            Ok(_) => return Ok(0),
+            // someone got there first, or changed the parent, look again
+            Err(e) if e.is_guard_failure() => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,684 @@
+use alloc::{string::String, vec, vec::Vec};
+use core::time::Duration;
+use crate::{Fd, FileType, LinuxError, Lockable, Path, Runtime, Task, linuxerr, path, times, view::{Layer, View}};
+use protocol::{Command, Error, FromValue, Oid, Predicate, Status, Value, Variable, attribute};
+
+// path resolution over the directory graph. a directory has a children
+// entity whose attributes are the names in it, each pointing at the entry,
+// and a parent attribute for '..' that the root doesn't have. a symlink is
+// an entry with a target attribute holding the bytes of the link
+
+// same as linux, links followed in one lookup before giving up with ELOOP
+pub const MAXSYMLINKS: usize = 40;
+
+pub struct Resolved {
+    // the directory the last component was looked up in. for a path that
+    // has no last component ("/", ".") its the entry itself
+    pub parent: Oid,
+    pub name: Option<String>,
+    // None if the last component doesn't exist, everything before it does
+    pub oid: Option<Oid>,
+    pub file_type: FileType,
+    // "a/b/" has to be a directory
+    pub trailing_slash: bool,
+    // it, or where it would go, is somewhere the view says can't change
+    pub read_only: bool,
+    // where it is in the view, when there is one. see view
+    pub path: Option<Path>,
+}
+
+impl Resolved {
+    // EROFS for anything that would change what was found
+    pub fn writable(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Err(linuxerr!(EROFS));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+enum Component {
+    Parent,
+    Name(String),
+}
+
+// the '..' stay, they're followed through the graph as they come. names
+// are stored as strings, so one that isn't utf8 can't be there
+fn components(path: &Path) -> Result<Vec<Component>, Error> {
+    path.components().map(|c| match c {
+        path::Component::Parent => Ok(Component::Parent),
+        path::Component::Name(n) => String::from_utf8(n.to_vec())
+            .map(Component::Name)
+            .map_err(|_| linuxerr!(ENOENT)),
+    }).collect()
+}
+
+fn root<R:Runtime>(t: &Task<R>) -> Oid {
+    *t.process.root.lock()
+}
+
+// where a relative path starts
+pub fn start<R:Runtime>(t: &Task<R>, dirfd: Fd, path: &[u8]) -> Result<Oid, Error> {
+    if path.first() == Some(&b'/') {
+        Ok(root(t))
+    } else if dirfd.is_atcwd() {
+        Ok(t.process.cwd.lock().0)
+    } else {
+        Ok(t.process.get_fd(dirfd)?.obj)
+    }
+}
+
+async fn first_row<R:Runtime>(t: &Task<R>, block: Vec<Command>) -> Result<Option<Vec<Value>>, Error> {
+    t.process.kernel.runtime.execute(block)?.next().await
+}
+
+fn var(n: usize) -> Value {
+    Value::Variable(n as Variable)
+}
+
+// one component of the path, returns the variable it lands in
+fn step(block: &mut Vec<Command>, from: Value, c: &Component, next: &mut usize) -> Value {
+    match c {
+        Component::Parent => {
+            block.push(Command::Get(from, attribute!("parent"), var(*next)));
+            *next += 1;
+        }
+        Component::Name(name) => {
+            block.push(Command::Get(from, attribute!("children"), var(*next)));
+            block.push(Command::Get(var(*next), attribute!(name), var(*next + 1)));
+            *next += 2;
+        }
+    }
+    var(*next - 1)
+}
+
+fn file_type(v: &Value) -> Result<FileType, Error> {
+    match v {
+        // things made before types were recorded
+        Value::Empty() => Ok(FileType::File),
+        v => FileType::from_value(v),
+    }
+}
+
+fn target(v: &Value) -> Result<Vec<u8>, Error> {
+    match v {
+        Value::Utf8String(s) => Ok(s.as_bytes().to_vec()),
+        v => Vec::<u8>::from_value(v),
+    }
+}
+
+// the type of an entry, and its target if its a link
+fn describe(block: &mut Vec<Command>, of: Value, next: usize) -> (usize, usize) {
+    block.push(Command::Get(of.clone(), attribute!("type"), Value::Union(next as Variable)));
+    block.push(Command::Get(of, attribute!("target"), Value::Union(next as Variable + 1)));
+    (next, next + 1)
+}
+
+async fn describe_one<R:Runtime>(t: &Task<R>, oid: Oid) -> Result<(FileType, Value), Error> {
+    let mut block = Vec::new();
+    let (ty, tg) = describe(&mut block, Value::Oid(oid), 0);
+    let row = first_row(t, block).await?.ok_or(linuxerr!(ENOENT))?;
+    Ok((file_type(&row[ty])?, row[tg].clone()))
+}
+
+pub async fn type_of<R:Runtime>(t: &Task<R>, oid: Oid) -> Result<FileType, Error> {
+    Ok(describe_one(t, oid).await?.0)
+}
+
+// the contents of a link, as far as readlink is concerned
+pub async fn read_link<R:Runtime>(t: &Task<R>, oid: Oid) -> Result<Vec<u8>, Error> {
+    match describe_one(t, oid).await? {
+        (FileType::Symlink, tg) => target(&tg),
+        _ => Err(linuxerr!(EINVAL)),
+    }
+}
+
+enum Walk {
+    Done(Resolved),
+    // a link, to be looked up from dir with whatever was left of the path
+    // after it
+    Link { dir: Oid, target: Vec<u8>, rest: Vec<Component> },
+}
+
+fn last_name(comps: &[Component]) -> Option<String> {
+    match comps.last() {
+        Some(Component::Name(n)) => Some(n.clone()),
+        _ => None,
+    }
+}
+
+// as far as the first link, or the end
+async fn walk<R:Runtime>(t: &Task<R>, start: Oid, comps: &[Component],
+                         follow: bool, trailing_slash: bool) -> Result<Walk, Error> {
+    // a trailing slash means it has to be a directory, so a link at the
+    // end gets followed whatever was asked for
+    let follow = follow || trailing_slash;
+
+    // the whole path in one block, which works whenever everything exists,
+    // there are no links on the way and no '..' runs into the root. the
+    // process root may well have a parent, so a '..' is guarded on not
+    // being there, and going through it is left to the slow way
+    let root = root(t);
+    let mut block = Vec::new();
+    let mut next = 0;
+    let mut cur = Value::Oid(start);
+    let mut parent = cur.clone();
+    for c in comps {
+        parent = cur;
+        if let Component::Parent = c {
+            block.push(Command::Guard(parent.clone(), Predicate::NotEqual, Value::Oid(root)));
+        }
+        cur = step(&mut block, parent.clone(), c, &mut next);
+    }
+    let (ty, tg) = describe(&mut block, cur.clone(), next);
+    let row = match first_row(t, block).await {
+        Err(e) if e.is_guard_failure() => None,
+        row => row?,
+    };
+    if let Some(row) = row {
+        let get = |v: &Value| match v {
+            Value::Variable(n) => Oid::from_value(&row[*n as usize]),
+            v => Oid::from_value(v),
+        };
+        let file_type = file_type(&row[ty])?;
+        if file_type == FileType::Symlink && follow {
+            return Ok(Walk::Link { dir: get(&parent)?, target: target(&row[tg])?, rest: Vec::new() });
+        }
+        return Ok(Walk::Done(Resolved {
+            parent: get(&parent)?,
+            name: last_name(comps),
+            oid: Some(get(&cur)?),
+            file_type,
+            trailing_slash,
+            read_only: false,
+            path: None,
+        }));
+    }
+
+    // something is missing or in the way, go a step at a time to find out
+    let mut cur = start;
+    let mut parent = start;
+    for (i, c) in comps.iter().enumerate() {
+        let last = i + 1 == comps.len();
+        // '..' never gets out of the root
+        if let Component::Parent = c && cur == root {
+            continue;
+        }
+        let block = match c {
+            Component::Parent => vec![Command::Get(Value::Oid(cur), attribute!("parent"), Value::Union(0))],
+            Component::Name(name) => vec![
+                Command::Get(Value::Oid(cur), attribute!("children"), var(0)),
+                Command::Get(var(0), attribute!(name), Value::Union(1)),
+            ],
+        };
+        let Some(row) = first_row(t, block).await? else {
+            // cur isn't a directory, but it might be a link to one
+            if cur != start && let (FileType::Symlink, tg) = describe_one(t, cur).await? {
+                return Ok(Walk::Link { dir: parent, target: target(&tg)?, rest: comps[i..].to_vec() });
+            }
+            return Err(linuxerr!(ENOTDIR));
+        };
+        match (c, row.last()) {
+            // '..' from the top of the graph stays there, from anything
+            // without a parent that isn't a directory it's an error, unless
+            // it's a link to one
+            (Component::Parent, Some(Value::Empty())) => match describe_one(t, cur).await? {
+                (FileType::Directory, _) => {}
+                (FileType::Symlink, tg) if cur != start => {
+                    return Ok(Walk::Link { dir: parent, target: target(&tg)?, rest: comps[i..].to_vec() });
+                }
+                _ => return Err(linuxerr!(ENOTDIR)),
+            },
+            (Component::Name(name), Some(Value::Empty())) => {
+                if last {
+                    return Ok(Walk::Done(Resolved {
+                        parent: cur,
+                        name: Some(name.clone()),
+                        oid: None,
+                        file_type: FileType::File,
+                        trailing_slash,
+                        read_only: false,
+                        path: None,
+                    }));
+                }
+                return Err(linuxerr!(ENOENT));
+            }
+            (_, Some(v)) => {
+                parent = cur;
+                cur = Oid::from_value(v)?;
+            }
+            (_, None) => return Err(linuxerr!(ENOENT)),
+        }
+    }
+    // everything was there after all, either it was created since or the
+    // type attribute is missing
+    let (file_type, tg) = describe_one(t, cur).await?;
+    if file_type == FileType::Symlink && follow {
+        return Ok(Walk::Link { dir: parent, target: target(&tg)?, rest: Vec::new() });
+    }
+    Ok(Walk::Done(Resolved {
+        parent,
+        name: last_name(comps),
+        oid: Some(cur),
+        file_type,
+        trailing_slash,
+        read_only: false,
+        path: None,
+    }))
+}
+
+// follow is for a link as the last component, links before that are always
+// followed
+pub async fn resolve<R:Runtime>(t: &Task<R>, dirfd: Fd, path: &[u8], follow: bool) -> Result<Resolved, Error> {
+    let parsed = Path::from_bytes(path)?;
+    let view = {
+        let view = t.process.view.lock();
+        (!view.is_empty()).then(|| view.clone())
+    };
+    if let Some(view) = view {
+        return resolve_in(t, &view, dirfd, &parsed, follow).await;
+    }
+    let mut start = start(t, dirfd, path)?;
+    let trailing_slash = parsed.has_trailing_slash();
+    let mut comps = components(&parsed)?;
+    let mut hops = 0;
+    loop {
+        match walk(t, start, &comps, follow, trailing_slash).await? {
+            Walk::Done(r) => return Ok(r),
+            Walk::Link { dir, target, rest } => {
+                hops += 1;
+                if hops > MAXSYMLINKS {
+                    return Err(linuxerr!(ELOOP));
+                }
+                if target.is_empty() {
+                    return Err(linuxerr!(ENOENT));
+                }
+                start = if target[0] == b'/' { root(t) } else { dir };
+                comps = components(&Path::from_bytes(&target)?)?;
+                comps.extend(rest);
+            }
+        }
+    }
+}
+
+// resolution through a view. the graph alone can't say where anything is,
+// since what is at a path depends on the entries above it, so this goes a
+// step at a time knowing the path of each directory it passes through.
+// a place is a directory in the view, the layers it's made of, top first
+#[derive(Clone)]
+struct Place {
+    path: Option<Path>,
+    layers: Vec<Layer>,
+}
+
+impl Place {
+    fn top(&self) -> Layer {
+        self.layers[0]
+    }
+
+    fn child(&self, name: &str) -> Option<Path> {
+        self.path.as_ref().map(|p| {
+            let mut p = p.clone();
+            p.push(name.as_bytes());
+            p
+        })
+    }
+}
+
+// the directory at path, wherever it happens to be
+fn single(oid: Oid, path: Option<Path>) -> Place {
+    Place { path, layers: vec![Layer { oid, read_only: false }] }
+}
+
+fn root_place<R:Runtime>(t: &Task<R>, view: &View) -> Place {
+    let path = Path::root();
+    let layers = view.at(&path);
+    if layers.is_empty() {
+        single(root(t), Some(path))
+    } else {
+        Place { path: Some(path), layers }
+    }
+}
+
+struct Hit {
+    layer: Layer,
+    oid: Oid,
+    file_type: FileType,
+    target: Value,
+}
+
+// name in each layer of a place, in order
+async fn hits<R:Runtime>(t: &Task<R>, place: &Place, name: &str) -> Result<Vec<Hit>, Error> {
+    let mut out = Vec::new();
+    for layer in &place.layers {
+        let mut block = vec![
+            Command::Get(Value::Oid(layer.oid), attribute!("children"), var(0)),
+            Command::Get(var(0), attribute!(name), var(1)),
+        ];
+        let (ty, tg) = describe(&mut block, var(1), 2);
+        if let Some(row) = first_row(t, block).await? {
+            out.push(Hit { layer: *layer, oid: Oid::from_value(&row[1])?, file_type: file_type(&row[ty])?, target: row[tg].clone() });
+        }
+    }
+    Ok(out)
+}
+
+// a directory found in a place, along with the same name in any layers
+// below it as long as those are directories too
+fn merged(path: Option<Path>, hits: &[Hit]) -> Place {
+    let layers = hits.iter()
+        .take_while(|h| h.file_type == FileType::Directory)
+        .map(|h| Layer { oid: h.oid, read_only: h.layer.read_only })
+        .collect();
+    Place { path, layers }
+}
+
+// the place an absolute path without '..' or links names, a directory
+fn place_at<'a, R:Runtime>(t: &'a Task<R>, view: &'a View, path: &'a Path)
+                            -> core::pin::Pin<alloc::boxed::Box<dyn core::future::Future<Output = Result<Place, Error>> + 'a>> {
+    alloc::boxed::Box::pin(async move {
+        let mut place = root_place(t, view);
+        for c in components(path)? {
+            let Component::Name(name) = c else {
+                return Err(linuxerr!(ENOENT));
+            };
+            let at = place.child(&name);
+            let layers = at.as_ref().map(|p| view.at(p)).unwrap_or_default();
+            place = if layers.is_empty() {
+                let hits = hits(t, &place, &name).await?;
+                match hits.first() {
+                    Some(h) if h.file_type == FileType::Directory => merged(at, &hits),
+                    Some(_) => return Err(linuxerr!(ENOTDIR)),
+                    None => return Err(linuxerr!(ENOENT)),
+                }
+            } else {
+                Place { path: at, layers }
+            };
+        }
+        Ok(place)
+    })
+}
+
+// whether there is a directory at path in the view, an absolute one
+// without '..' or links
+pub async fn directory_in<R:Runtime>(t: &Task<R>, view: &View, path: &Path) -> Result<(), Error> {
+    place_at(t, view, path).await.map(|_| ())
+}
+
+// the place at path, if it's still dir. a path kept next to a directory
+// was right when the directory was reached, but a rename above it since
+// can leave it naming something else or nothing at all
+async fn still<R:Runtime>(t: &Task<R>, view: &View, dir: Oid, path: &Path) -> Result<Option<Place>, Error> {
+    match place_at(t, view, path).await {
+        Ok(place) => Ok((place.top().oid == dir).then_some(place)),
+        Err(e) if e.syserr == Some(LinuxError::ENOENT as u8) || e.syserr == Some(LinuxError::ENOTDIR as u8) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// the path dir is at now, the known one if it still leads there and
+// otherwise whatever the graph says. relative if it isn't under the root
+pub async fn path_of<R:Runtime>(t: &Task<R>, dir: Oid, known: Option<Path>) -> Result<Path, Error> {
+    if let Some(path) = known {
+        let view = t.process.view.lock().clone();
+        if still(t, &view, dir, &path).await?.is_some() {
+            return Ok(path);
+        }
+    }
+    let (absolute, names) = names_of(t, dir).await?;
+    Ok(Path::from_elements(absolute, names))
+}
+
+// the cwd and directory fds know the path they were reached by, anything
+// opened before there was a view is just itself. a path that went stale is
+// looked for again, and one the graph's path doesn't lead to in the view
+// either is just itself too
+async fn start_in<R:Runtime>(t: &Task<R>, view: &View, dirfd: Fd, path: &Path) -> Result<Place, Error> {
+    if path.is_absolute() {
+        return Ok(root_place(t, view));
+    }
+    let (oid, known) = if dirfd.is_atcwd() {
+        let cwd = t.process.cwd.lock();
+        (cwd.0, cwd.1.is_absolute().then(|| cwd.1.clone()))
+    } else {
+        let file = t.process.get_fd(dirfd)?;
+        (file.obj, file.path)
+    };
+    let Some(known) = known else {
+        return Ok(single(oid, None));
+    };
+    if let Some(place) = still(t, view, oid, &known).await? {
+        return Ok(place);
+    }
+    let (absolute, names) = names_of(t, oid).await?;
+    if absolute && let Some(place) = still(t, view, oid, &Path::from_elements(true, names)).await? {
+        return Ok(place);
+    }
+    Ok(single(oid, None))
+}
+
+async fn resolve_in<R:Runtime>(t: &Task<R>, view: &View, dirfd: Fd, path: &Path, follow: bool) -> Result<Resolved, Error> {
+    let trailing_slash = path.has_trailing_slash();
+    let follow = follow || trailing_slash;
+    // the places passed through, for '..'
+    let mut stack = vec![start_in(t, view, dirfd, path).await?];
+    let mut comps = components(path)?;
+    comps.reverse();
+    let mut hops = 0;
+    while let Some(c) = comps.pop() {
+        let last = comps.is_empty();
+        let place = stack.last().unwrap().clone();
+        let name = match c {
+            Component::Parent => {
+                if stack.len() > 1 {
+                    stack.pop();
+                } else if let Some(p) = &place.path {
+                    // '..' from the root stays there
+                    if let Some(up) = p.parent() {
+                        stack = vec![place_at(t, view, &up).await?];
+                    }
+                } else if place.top().oid != root(t) {
+                    let block = vec![Command::Get(Value::Oid(place.top().oid), attribute!("parent"), Value::Union(0))];
+                    let row = first_row(t, block).await?.ok_or(linuxerr!(ENOENT))?;
+                    if row[0] != Value::Empty() {
+                        stack = vec![single(Oid::from_value(&row[0])?, None)];
+                    }
+                }
+                continue;
+            }
+            Component::Name(name) => name,
+        };
+        let at = place.child(&name);
+        let layers = at.as_ref().map(|p| view.at(p)).unwrap_or_default();
+        if !layers.is_empty() {
+            stack.push(Place { path: at, layers });
+            continue;
+        }
+        let hits = hits(t, &place, &name).await?;
+        let Some(hit) = hits.first() else {
+            if !last {
+                return Err(linuxerr!(ENOENT));
+            }
+            return Ok(Resolved {
+                parent: place.top().oid,
+                name: Some(name),
+                oid: None,
+                file_type: FileType::File,
+                trailing_slash,
+                read_only: place.top().read_only,
+                path: at,
+            });
+        };
+        match hit.file_type {
+            FileType::Symlink if follow || !last => {
+                hops += 1;
+                if hops > MAXSYMLINKS {
+                    return Err(linuxerr!(ELOOP));
+                }
+                let target = target(&hit.target)?;
+                let target = Path::from_bytes(&target)?;
+                if target.is_absolute() {
+                    stack = vec![root_place(t, view)];
+                }
+                comps.extend(components(&target)?.into_iter().rev());
+            }
+            FileType::Directory if !last => stack.push(merged(at, &hits)),
+            _ if !last => return Err(linuxerr!(ENOTDIR)),
+            file_type => return Ok(Resolved {
+                parent: hit.layer.oid,
+                name: Some(name),
+                oid: Some(hit.oid),
+                file_type,
+                trailing_slash,
+                read_only: hit.layer.read_only,
+                path: at,
+            }),
+        }
+    }
+    // it ended on a directory, or on '..'
+    let place = stack.pop().unwrap();
+    Ok(Resolved {
+        parent: place.top().oid,
+        name: None,
+        oid: Some(place.top().oid),
+        file_type: FileType::Directory,
+        trailing_slash,
+        read_only: place.top().read_only,
+        path: place.path,
+    })
+}
+
+// the names from the root down to a directory, found by going up the
+// parents and looking for each one among its parent's children. false if
+// it isn't under the root at all, and then the names are from the top of
+// the graph
+pub async fn names_of<R:Runtime>(t: &Task<R>, dir: Oid) -> Result<(bool, Vec<String>), Error> {
+    let root = root(t);
+    let mut names = Vec::new();
+    let mut cur = dir;
+    while cur != root {
+        let block = vec![
+            Command::Get(Value::Oid(cur), attribute!("parent"), var(0)),
+            Command::Get(var(0), attribute!("children"), var(1)),
+            Command::Get(var(1), var(2), Value::Oid(cur)),
+        ];
+        let Some(row) = first_row(t, block).await? else {
+            names.reverse();
+            return Ok((false, names));
+        };
+        names.push(String::from_value(&row[2])?);
+        cur = Oid::from_value(&row[0])?;
+    }
+    names.reverse();
+    Ok((true, names))
+}
+
+// link counts as stored, to compare against when changing them, and as
+// numbers. anything made before counts were kept has the one link
+pub async fn nlinks<R:Runtime>(t: &Task<R>, oids: &[Oid]) -> Result<Vec<(Value, u32)>, Error> {
+    let block = oids.iter().enumerate()
+        .map(|(i, oid)| Command::Get(Value::Oid(*oid), attribute!("nlink"), Value::Union(i as Variable)))
+        .collect();
+    let row = first_row(t, block).await?.ok_or(linuxerr!(ENOENT))?;
+    row.into_iter().map(|v| match v {
+        Value::Empty() => Ok((Value::Empty(), 1)),
+        v => u32::from_value(&v).map(|n| (v, n)),
+    }).collect()
+}
+
+// a block that changes the namespace. each change is guarded on the names
+// it read being what they were at resolve time, so a block that lost a
+// race fails as a whole and the caller looks again
+#[derive(Default)]
+pub struct Edit {
+    pub block: Vec<Command>,
+    next: usize,
+}
+
+impl Edit {
+    fn fresh(&mut self) -> Value {
+        self.next += 1;
+        var(self.next - 1)
+    }
+
+    // the names entity of a directory. no row at all if it isn't one
+    pub fn names(&mut self, dir: Oid) -> Value {
+        let names = self.fresh();
+        self.block.push(Command::Get(Value::Oid(dir), attribute!("children"), names.clone()));
+        names
+    }
+
+    // name is still oid
+    pub fn expect(&mut self, names: &Value, name: &str, oid: Oid) {
+        let v = self.fresh();
+        self.block.push(Command::Get(names.clone(), attribute!(name), v.clone()));
+        self.block.push(Command::Guard(v, Predicate::Equal, Value::Oid(oid)));
+    }
+
+    pub fn absent(&mut self, names: &Value, name: &str) {
+        self.block.push(Command::Absent(names.clone(), attribute!(name)));
+    }
+
+    // dir has nothing in it. returns the index of the guard, to tell its
+    // failure apart from losing a race
+    pub fn empty(&mut self, dir: Oid) -> u32 {
+        let names = self.names(dir);
+        let any = self.fresh();
+        self.block.push(Command::Absent(names, any));
+        self.block.len() as u32 - 1
+    }
+
+    pub fn create(&mut self) -> Value {
+        let v = self.fresh();
+        self.block.push(Command::Create(v.clone()));
+        v
+    }
+
+    pub fn set(&mut self, e: Value, a: &str, v: Value) {
+        self.block.push(Command::Set(e, attribute!(a), v));
+    }
+
+    pub fn link(&mut self, names: &Value, name: &str, to: Value) {
+        self.set(names.clone(), name, to);
+    }
+
+    pub fn unlink(&mut self, names: &Value, name: &str) {
+        self.set(names.clone(), name, Value::Empty());
+    }
+
+    pub fn nlink(&mut self, oid: Oid, stored: &Value, n: u32) {
+        self.block.push(Command::CompareAndSet(Value::Oid(oid), attribute!("nlink"), stored.clone(), Value::from(n)));
+    }
+
+    // last, after anything else touching it. see close
+    pub fn destroy(&mut self, oid: Oid) {
+        self.block.push(Command::Destroy(Value::Oid(oid)));
+    }
+
+    // a directory had names come or go
+    pub fn modified(&mut self, dir: Oid, now: Duration) {
+        self.block.extend(times::modified(Value::Oid(dir), now));
+    }
+
+    pub fn changed(&mut self, oid: Oid, now: Duration) {
+        self.block.push(times::changed(Value::Oid(oid), now));
+    }
+
+    // the one row, or ENOTDIR if a names lookup found nothing. the stream
+    // is run to the end like drain does, so nothing is left half applied
+    pub async fn run<R:Runtime>(self, t: &Task<R>) -> Result<Vec<Value>, Error> {
+        let mut st = t.process.kernel.runtime.execute(self.block)?;
+        let row = st.next().await?.ok_or(linuxerr!(ENOTDIR))?;
+        while st.next().await?.is_some() {}
+        Ok(row)
+    }
+}
+
+// which guard failed, if that's what happened
+pub fn failed_guard(e: &Error) -> Option<u32> {
+    match e.status {
+        Some(Status::GuardFailed(i)) => Some(i),
+        _ => None,
+    }
+}
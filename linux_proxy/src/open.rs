@@ -1,22 +1,137 @@
-use core::ffi::c_char;
+use alloc::string::String;
 use crate::{
-    KernelError,
-    memory::uaccess::cstr::UserCStr,
-    linux::{Fd, OpenFlags, path::Path},
-    memory::address::TUA,
+    AccessMode, AddressSpace, Fd, FdFlags, FileType, Lockable, OpenFlags, PATH_MAX,
+    Runtime, Task, copy_in_cstr, linuxerr,
+    attr::FilePermissions,
+    access::permission,
+    close::sys_close,
+    namei::resolve,
+    times::now,
+    truncate::resize,
 };
+use protocol::{Error, Oid, attribute, execute};
 
-pub async fn sys_openat(
+// a new regular file, linked into parent under name. the Absent guard makes
+// this fail rather than replace anything that got there first
+async fn create<R:Runtime>(t: &Task<R>, parent: Oid, name: &String, mode: FilePermissions) -> Result<Oid, Error> {
+    let (uid, gid) = t.process.creds.lock().owner()?;
+    let now = now(t);
+    let mut created = None;
+    execute!(t.process.kernel.runtime,
+             [Get(parent, attribute!("children"), names:Oid),
+              Absent(names:Oid, attribute!(name)),
+              Create(file:Oid),
+              Set(names:Oid, attribute!(name), file:Oid),
+              Set(file:Oid, attribute!("type"), FileType::File),
+              Set(file:Oid, attribute!("mode"), mode),
+              Set(file:Oid, attribute!("uid"), uid),
+              Set(file:Oid, attribute!("gid"), gid),
+              Set(file:Oid, attribute!("nlink"), 1u32),
+              Set(file:Oid, attribute!("size"), 0u64),
+              Set(file:Oid, attribute!("atime"), now),
+              Set(file:Oid, attribute!("mtime"), now),
+              Set(file:Oid, attribute!("ctime"), now),
+              Set(parent, attribute!("mtime"), now),
+              Set(parent, attribute!("ctime"), now)] {
+                  created = Some(file);
+              });
+    // no row means parent had no children, so it isn't a directory
+    created.ok_or(linuxerr!(ENOTDIR))
+}
+
+pub async fn sys_openat<R:Runtime>(
+    t: Task<R>,
     dirfd: Fd,
-    path: TUA<c_char>,
+    path: AddressSpace,
     flags: u32,
-    _mode: u16, // Permissions for file creation
-) -> Result<usize, KernelError> {
-    let mut buf = [0; 1024];
-
+    mode: u32, // Permissions for file creation
+) -> Result<usize, Error> {
+    // unknown open flags are ignored, not refused
     let flags = OpenFlags::from_bits_truncate(flags);
-    let path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    
-    // allocate fd
-    Ok(fd)
+    // linux won't make a directory this way, and won't pretend to
+    if flags.contains(OpenFlags::O_CREAT | OpenFlags::O_DIRECTORY) {
+        return Err(linuxerr!(EINVAL));
+    }
+    let path = copy_in_cstr(&t.process.kernel.runtime, path, PATH_MAX - 1)?;
+    let writing = (flags & OpenFlags::O_ACCMODE) != OpenFlags::O_RDONLY;
+    let access = match flags & OpenFlags::O_ACCMODE {
+        OpenFlags::O_RDONLY => AccessMode::R_OK,
+        OpenFlags::O_WRONLY => AccessMode::W_OK,
+        _ => AccessMode::R_OK | AccessMode::W_OK,
+    };
+    // linux wants write permission for O_TRUNC even when read only, and
+    // then truncates anyway
+    let access = if flags.contains(OpenFlags::O_TRUNC) { access | AccessMode::W_OK } else { access };
+
+    // at most twice, if we lose a race to create the file we open theirs
+    // O_CREAT|O_EXCL doesn't go through a link at the end either, it fails
+    // because the link exists
+    let exclusive = flags.contains(OpenFlags::O_CREAT | OpenFlags::O_EXCL);
+    let follow = !(flags.contains(OpenFlags::O_NOFOLLOW) || exclusive);
+    let mut retried = false;
+    let (obj, opened, truncate) = loop {
+        let r = resolve(&t, dirfd, &path, follow).await?;
+        match r.oid {
+            Some(oid) => {
+                if exclusive {
+                    return Err(linuxerr!(EEXIST));
+                }
+                if r.file_type == FileType::Symlink {
+                    return Err(linuxerr!(ELOOP));
+                }
+                if r.file_type == FileType::Directory {
+                    if writing || flags.contains(OpenFlags::O_CREAT) {
+                        return Err(linuxerr!(EISDIR));
+                    }
+                } else if flags.contains(OpenFlags::O_DIRECTORY) || r.trailing_slash {
+                    return Err(linuxerr!(ENOTDIR));
+                }
+                permission(&t, oid, access).await?;
+                if writing || flags.contains(OpenFlags::O_TRUNC) {
+                    r.writable()?;
+                }
+                break (oid, r.path, flags.contains(OpenFlags::O_TRUNC) && r.file_type == FileType::File);
+            }
+            None => {
+                if !flags.contains(OpenFlags::O_CREAT) {
+                    return Err(linuxerr!(ENOENT));
+                }
+                if r.trailing_slash {
+                    return Err(linuxerr!(EISDIR));
+                }
+                let umask = *t.process.umask.lock();
+                let mode = FilePermissions::from_bits_truncate((mode & !umask & 0o7777) as u16);
+                r.writable()?;
+                let name = r.name.ok_or(linuxerr!(ENOENT))?;
+                // a new file can be opened however it was asked for, what
+                // matters is being allowed to add it to the directory
+                permission(&t, r.parent, AccessMode::W_OK | AccessMode::X_OK).await?;
+                match create(&t, r.parent, &name, mode).await {
+                    Ok(oid) => break (oid, r.path, false),
+                    Err(e) if e.is_guard_failure() => {
+                        if flags.contains(OpenFlags::O_EXCL) || retried {
+                            return Err(linuxerr!(EEXIST));
+                        }
+                        retried = true;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+    };
+
+    let fd_flags = if flags.contains(OpenFlags::O_CLOEXEC) {
+        FdFlags::CLOEXEC
+    } else {
+        FdFlags::empty()
+    };
+    // O_APPEND stays in the open flags, write looks at it
+    let fd = t.process.insert_fd(obj, flags, fd_flags, opened)?;
+    // only once there's a descriptor to show for it, so running out of
+    // them doesn't leave the file emptied
+    if truncate && let Err(e) = resize(&t, obj, 0, true).await {
+        sys_close(t, fd).await?;
+        return Err(e);
+    }
+    Ok(fd.0 as usize)
 }
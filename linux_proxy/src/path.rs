@@ -1,53 +1,409 @@
 use alloc::{
-    string::{String, ToString},
+    string::String,
     vec::Vec,
 };
+use core::fmt;
+use protocol::Error;
+use crate::linuxerr;
 
-#[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Hash)]
+// limits.h. PATH_MAX counts the terminating nul
+pub const PATH_MAX: usize = 4096;
+pub const NAME_MAX: usize = 255;
+
+// a path as linux sees one, bytes split on '/'. nothing says they have to
+// be utf8. "." and empty components are dropped on the way in since they
+// never mean anything, ".." is kept because what it means depends on what
+// the path runs through, see normalize
+#[derive(Debug, Clone, Eq, PartialEq, PartialOrd, Ord, Hash)]
 pub struct Path {
     absolute: bool,
-    elements: Vec<String>,
+    elements: Vec<Vec<u8>>,
+    // "a/", "a/." and the like, which only name a directory
+    trailing_slash: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Component<'a> {
+    Parent,
+    Name(&'a [u8]),
 }
 
 impl Path {
+    // the root
+    pub fn root() -> Self {
+        Path { absolute: true, elements: Vec::new(), trailing_slash: false }
+    }
+
+    // for paths that are known to be fine, no limits are checked
     pub fn new(s: &str) -> Self {
-        let mut start: usize = 0;
-        let mut end: usize = 0;
-        let mut absolute = false;
+        Self::split(s.as_bytes())
+    }
+
+    // a path from userspace, without the nul. ENAMETOOLONG if it or any
+    // component is longer than linux allows, ENOENT if it's empty
+    pub fn from_bytes(b: &[u8]) -> Result<Self, Error> {
+        if b.is_empty() {
+            return Err(linuxerr!(ENOENT));
+        }
+        if b.len() >= PATH_MAX {
+            return Err(linuxerr!(ENAMETOOLONG));
+        }
+        let path = Self::split(b);
+        if path.elements.iter().any(|e| e.len() > NAME_MAX) {
+            return Err(linuxerr!(ENAMETOOLONG));
+        }
+        Ok(path)
+    }
+
+    fn split(b: &[u8]) -> Self {
         let mut elements = Vec::new();
-        let sb = s.as_bytes();
-
-        while end < s.len() {
-            // we can use as_bytes here, since '/' is a single byte
-            if sb[end] == b'/' {
-                if end == 0 {
-                    absolute = true;
-                    end = end + 1
-                }
-                elements.push(s[start..end].to_string());
-                start = end;
+        let mut trailing_slash = false;
+        for c in b.split(|b| *b == b'/') {
+            // whatever follows the last name decides, "a/." is "a/" and
+            // "a/.." names a directory anyway
+            trailing_slash = matches!(c, b"" | b".");
+            if !trailing_slash {
+                elements.push(c.to_vec());
             }
-            end = end + 1;
         }
-        Path { absolute, elements }
+        let absolute = b.first() == Some(&b'/');
+        Path { absolute, trailing_slash: trailing_slash && !elements.is_empty(), elements }
     }
 
-    pub fn to_string(&self) -> String {
-        let mut res = self.elements.join("/");
-        if self.absolute {
-            res = "/".to_string() + &res;
+    // already split, names only
+    pub fn from_elements<S: Into<Vec<u8>>>(absolute: bool, elements: impl IntoIterator<Item = S>) -> Self {
+        Path { absolute, elements: elements.into_iter().map(Into::into).collect(), trailing_slash: false }
+    }
+
+    pub fn is_absolute(&self) -> bool {
+        self.absolute
+    }
+
+    pub fn has_trailing_slash(&self) -> bool {
+        self.trailing_slash
+    }
+
+    // "." or "/"
+    pub fn is_empty(&self) -> bool {
+        self.elements.is_empty()
+    }
+
+    pub fn components(&self) -> impl DoubleEndedIterator<Item = Component<'_>> + ExactSizeIterator + '_ {
+        self.elements.iter().map(|e| match e.as_slice() {
+            b".." => Component::Parent,
+            e => Component::Name(e),
+        })
+    }
+
+    // the last component if it's a name, like std
+    pub fn file_name(&self) -> Option<&[u8]> {
+        match self.components().next_back()? {
+            Component::Name(n) => Some(n),
+            Component::Parent => None,
         }
-        res
     }
 
+    // everything but the last component, None when there isn't one
+    pub fn parent(&self) -> Option<Path> {
+        let (_, rest) = self.elements.split_last()?;
+        Some(Path { absolute: self.absolute, elements: rest.to_vec(), trailing_slash: false })
+    }
+
+    pub fn push(&mut self, name: &[u8]) {
+        self.elements.push(name.to_vec());
+        self.trailing_slash = false;
+    }
+
+    // other from here. an absolute other starts over from the root, the
+    // same as handing it to openat with a dirfd
     pub fn join(&self, other: &Path) -> Path {
-        // we kinda .. really.. dont want other to be absolute?
-        let mut ret: Path = Path {
-            absolute: self.absolute,
-            elements: Vec::with_capacity(self.elements.len() + other.elements.len()),
-        };
-        ret.elements.extend(self.elements.clone());
-        ret.elements.extend(other.elements.clone());
-        ret
+        if other.absolute {
+            return other.clone();
+        }
+        let mut elements = self.elements.clone();
+        elements.extend(other.elements.iter().cloned());
+        let trailing_slash = if other.is_empty() { self.trailing_slash } else { other.trailing_slash };
+        let trailing_slash = trailing_slash && !elements.is_empty();
+        Path { absolute: self.absolute, elements, trailing_slash }
+    }
+
+    // ".." taken as removing the name before it, without looking at what
+    // is actually there. right when nothing on the way is a link, which
+    // resolution can't assume, so namei keeps them. at the root ".." stays
+    // at the root, a relative path keeps the ones it can't remove
+    pub fn normalize(&self) -> Path {
+        let mut elements: Vec<Vec<u8>> = Vec::new();
+        for c in self.components() {
+            match c {
+                Component::Parent => match elements.last() {
+                    Some(e) if e != b".." => {
+                        elements.pop();
+                    }
+                    _ if self.absolute => {}
+                    _ => elements.push(b"..".to_vec()),
+                },
+                Component::Name(n) => elements.push(n.to_vec()),
+            }
+        }
+        let trailing_slash = self.trailing_slash && !elements.is_empty();
+        Path { absolute: self.absolute, elements, trailing_slash }
+    }
+
+    // back to what userspace would have written, without a nul. the empty
+    // relative path is "."
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        if self.absolute {
+            out.push(b'/');
+        }
+        for (i, e) in self.elements.iter().enumerate() {
+            if i > 0 {
+                out.push(b'/');
+            }
+            out.extend_from_slice(e);
+        }
+        if self.trailing_slash {
+            out.push(b'/');
+        }
+        if out.is_empty() {
+            out.push(b'.');
+        }
+        out
+    }
+}
+
+// lossy where it isn't utf8
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.to_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{format, string::ToString, vec};
+    use crate::LinuxError;
+
+    fn names(p: &Path) -> Vec<&[u8]> {
+        p.components().map(|c| match c {
+            Component::Parent => &b".."[..],
+            Component::Name(n) => n,
+        }).collect()
+    }
+
+    fn errno(r: Result<Path, Error>) -> Option<u8> {
+        r.err().and_then(|e| e.syserr)
+    }
+
+    #[test]
+    fn splitting() {
+        let cases: &[(&str, bool, &[&str], bool)] = &[
+            ("/", true, &[], false),
+            ("//", true, &[], false),
+            ("/.", true, &[], false),
+            ("/./", true, &[], false),
+            (".", false, &[], false),
+            ("./", false, &[], false),
+            ("a", false, &["a"], false),
+            ("/a", true, &["a"], false),
+            ("/a/b", true, &["a", "b"], false),
+            ("a/b/c", false, &["a", "b", "c"], false),
+            ("a/", false, &["a"], true),
+            ("a//", false, &["a"], true),
+            ("a/.", false, &["a"], true),
+            ("a/./", false, &["a"], true),
+            ("//a//b//", true, &["a", "b"], true),
+            ("./a/./b", false, &["a", "b"], false),
+            ("..", false, &[".."], false),
+            ("/..", true, &[".."], false),
+            ("a/..", false, &["a", ".."], false),
+            ("a/../", false, &["a", ".."], true),
+            ("...", false, &["..."], false),
+            (".a/a.", false, &[".a", "a."], false),
+        ];
+        for (s, absolute, elements, trailing) in cases {
+            let p = Path::new(s);
+            assert_eq!(p.is_absolute(), *absolute, "{}", s);
+            let want: Vec<&[u8]> = elements.iter().map(|e| e.as_bytes()).collect();
+            assert_eq!(names(&p), want, "{}", s);
+            assert_eq!(p.has_trailing_slash(), *trailing, "{}", s);
+            assert_eq!(p.components().len(), elements.len(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn components() {
+        let p = Path::new("/a/../b");
+        let c: Vec<Component> = p.components().collect();
+        assert_eq!(c, vec![Component::Name(b"a"), Component::Parent, Component::Name(b"b")]);
+        assert_eq!(p.components().next_back(), Some(Component::Name(b"b")));
+        assert_eq!(Path::new("/").components().next(), None);
+    }
+
+    #[test]
+    fn bytes() {
+        let raw = b"/bin/\xff\xfe/x";
+        let p = Path::from_bytes(raw).unwrap();
+        assert_eq!(p.file_name(), Some(&b"x"[..]));
+        assert_eq!(names(&p)[1], &b"\xff\xfe"[..]);
+        assert_eq!(p.to_bytes(), raw.to_vec());
+        assert_eq!(p.to_string(), "/bin/\u{fffd}\u{fffd}/x");
+        for (s, canonical) in [
+            ("/", "/"), ("//", "/"), (".", "."), ("", "."), ("a", "a"), ("a/", "a/"),
+            ("a/.", "a/"), ("/a//b/", "/a/b/"), ("./a", "a"), ("a/..", "a/.."), ("/..", "/.."),
+        ] {
+            assert_eq!(Path::new(s).to_bytes(), canonical.as_bytes(), "{}", s);
+            assert_eq!(Path::new(s).to_string(), canonical, "{}", s);
+            // and it reads back the same
+            assert_eq!(Path::new(canonical), Path::new(s), "{}", s);
+        }
+    }
+
+    #[test]
+    fn limits() {
+        assert_eq!(errno(Path::from_bytes(b"")), Some(LinuxError::ENOENT as u8));
+        let name = "a".repeat(NAME_MAX);
+        assert!(Path::from_bytes(name.as_bytes()).is_ok());
+        assert!(Path::from_bytes(format!("/x/{}/y", name).as_bytes()).is_ok());
+        let long = "a".repeat(NAME_MAX + 1);
+        assert_eq!(errno(Path::from_bytes(long.as_bytes())), Some(LinuxError::ENAMETOOLONG as u8));
+        assert_eq!(errno(Path::from_bytes(format!("/x/{}/y", long).as_bytes())), Some(LinuxError::ENAMETOOLONG as u8));
+        // the whole path, PATH_MAX counts the nul
+        let path = "a/".repeat((PATH_MAX - 1) / 2) + "b";
+        assert_eq!(path.len(), PATH_MAX - 1);
+        assert!(Path::from_bytes(path.as_bytes()).is_ok());
+        let path = path + "b";
+        assert_eq!(errno(Path::from_bytes(path.as_bytes())), Some(LinuxError::ENAMETOOLONG as u8));
+        // slashes count too
+        let slashes = "/".repeat(PATH_MAX);
+        assert_eq!(errno(Path::from_bytes(slashes.as_bytes())), Some(LinuxError::ENAMETOOLONG as u8));
+    }
+
+    #[test]
+    fn file_name_and_parent() {
+        let cases: &[(&str, Option<&str>, Option<&str>)] = &[
+            ("/", None, None),
+            (".", None, None),
+            ("a", Some("a"), Some(".")),
+            ("/a", Some("a"), Some("/")),
+            ("/a/b", Some("b"), Some("/a")),
+            ("a/b/", Some("b"), Some("a")),
+            ("a/..", None, Some("a")),
+            ("..", None, Some(".")),
+            ("/..", None, Some("/")),
+        ];
+        for (s, file_name, parent) in cases {
+            let p = Path::new(s);
+            assert_eq!(p.file_name(), file_name.map(|n| n.as_bytes()), "{}", s);
+            assert_eq!(p.parent().map(|p| p.to_string()), parent.map(|p| p.to_string()), "{}", s);
+        }
+    }
+
+    #[test]
+    fn join_and_push() {
+        let cases: &[(&str, &str, &str)] = &[
+            ("/a", "b", "/a/b"),
+            ("/a/", "b", "/a/b"),
+            ("/a", "b/", "/a/b/"),
+            ("/a/", ".", "/a/"),
+            ("a", "../b", "a/../b"),
+            ("/", "a", "/a"),
+            (".", "a", "a"),
+            ("a", ".", "a"),
+            // an absolute path isn't stuck on the end
+            ("/a", "/b", "/b"),
+            ("a", "/", "/"),
+        ];
+        for (a, b, joined) in cases {
+            assert_eq!(Path::new(a).join(&Path::new(b)).to_string(), *joined, "{} {}", a, b);
+        }
+        let mut p = Path::root();
+        p.push(b"a");
+        p.push(b"\x80");
+        assert_eq!(p.to_bytes(), b"/a/\x80".to_vec());
+        let mut q = Path::new("a/");
+        q.push(b"b");
+        assert_eq!(q.to_string(), "a/b");
+        assert_eq!(Path::from_elements(true, ["x".to_string(), "y".to_string()]), Path::new("/x/y"));
+        assert_eq!(Path::from_elements(false, Vec::<Vec<u8>>::new()), Path::new("."));
+    }
+
+    #[test]
+    fn normalizing() {
+        let cases: &[(&str, &str)] = &[
+            ("/", "/"),
+            ("/..", "/"),
+            ("/../..", "/"),
+            ("/../a", "/a"),
+            ("/a/..", "/"),
+            ("/a/../b", "/b"),
+            ("/a/b/../../c", "/c"),
+            ("/a/b/../..", "/"),
+            ("/a/b/../../../c", "/c"),
+            ("/a/./b/.", "/a/b/"),
+            ("a/..", "."),
+            ("a/../..", ".."),
+            ("..", ".."),
+            ("../..", "../.."),
+            ("../a/..", ".."),
+            ("a/b/../c", "a/c"),
+            ("a/b/../../../c", "../c"),
+            ("a/../b/", "b/"),
+        ];
+        for (s, normal) in cases {
+            let n = Path::new(s).normalize();
+            assert_eq!(n.to_string(), *normal, "{}", s);
+            // normalizing again changes nothing
+            assert_eq!(n.normalize(), n, "{}", s);
+        }
+        // only '..' that can't be removed is left
+        let n = Path::new("a/b/../../..").normalize();
+        assert!(n.components().all(|c| c == Component::Parent));
+    }
+
+    // every path over a small alphabet up to a length, checked against
+    // what the parts say about each other
+    #[test]
+    fn exhaustive() {
+        let alphabet: &[u8] = b"/.a";
+        let mut paths: Vec<Vec<u8>> = vec![vec![]];
+        let mut all = Vec::new();
+        for _ in 0..6 {
+            paths = paths.iter()
+                .flat_map(|p| alphabet.iter().map(move |c| [p.as_slice(), &[*c]].concat()))
+                .collect();
+            all.extend(paths.iter().cloned());
+        }
+        for raw in all {
+            let s = core::str::from_utf8(&raw).unwrap();
+            let p = Path::from_bytes(&raw).unwrap();
+            assert_eq!(p.is_absolute(), raw[0] == b'/', "{}", s);
+            assert!(p.components().all(|c| match c {
+                Component::Name(n) => !n.is_empty() && n != b"." && !n.contains(&b'/'),
+                Component::Parent => true,
+            }), "{}", s);
+            // what it prints as means the same thing
+            assert_eq!(Path::from_bytes(&p.to_bytes()).unwrap(), p, "{}", s);
+            let n = p.normalize();
+            assert_eq!(n.is_absolute(), p.is_absolute(), "{}", s);
+            assert_eq!(n.normalize(), n, "{}", s);
+            // no name is ever followed by '..', and an absolute path has none
+            let c: Vec<Component> = n.components().collect();
+            assert!(c.windows(2).all(|w| !matches!(w, [Component::Name(_), Component::Parent])), "{}", s);
+            assert!(!n.is_absolute() || !c.contains(&Component::Parent), "{}", s);
+            if let Some(parent) = p.parent() {
+                assert_eq!(parent.components().len() + 1, p.components().len(), "{}", s);
+                let last = p.components().next_back().unwrap();
+                let mut again = parent.clone();
+                again.push(match last {
+                    Component::Name(n) => n,
+                    Component::Parent => b"..",
+                });
+                assert_eq!(again.components().collect::<Vec<_>>(), p.components().collect::<Vec<_>>(), "{}", s);
+            } else {
+                assert!(p.is_empty(), "{}", s);
+            }
+        }
     }
 }
@@ -14,6 +14,15 @@ impl Pid {
     pub fn is_idle(self) -> bool {
         self.0 == 0
     }
+
+    // the one orphans go to, and that never exits
+    pub fn init() -> Pid {
+        Pid(1)
+    }
+
+    pub fn is_init(self) -> bool {
+        self == Pid::init()
+    }
 }
 
 impl Display for Pid {
@@ -55,9 +64,9 @@ pub fn sys_getpgid<R:Runtime>(t: Task<R>, pid: Pid) -> Result<usize, Error> {
 
 pub fn sys_setpgid<R:Runtime>(t: Task<R>, pid: Pid, pgid: Pgid) -> Result<usize, Error> {
     if pid.0 == 0 {
-        **t.process.pgid.lock() = pgid;
+        *t.process.pgid.lock() = pgid;
     } else if let Some(p) = t.process.kernel.get_process(pid) {
-        **p.pgid.lock() = pgid;
+        *p.pgid.lock() = pgid;
     } else {
         return Err(linuxerr!(ESRCH));
     };
@@ -1,4 +1,5 @@
 use crate::{
+    AddressSpace,
     Runtime,
     Credentials, Kernel, linuxerr, Lockable,
     FileDescriptorEntry,
@@ -8,11 +9,14 @@ use crate::{
     Path,
     Task,
     Tid,
+    view::View,
+    exit::ChildState,
+    rsrc_lim::{DEFAULT_RLIMITS, RLimit, RlimitId},
 };
 use core::ffi::c_long;
 use protocol::{Error, Oid};
 
-use core::sync::atomic::AtomicU32;
+use core::sync::atomic::{AtomicU32, AtomicUsize};
 
 use alloc::{
     vec::Vec,    
@@ -49,20 +53,99 @@ pub struct Process<R:Runtime> {
     pub umask: R::Lock<u32>,
     pub parent: R::Lock<Pid>,
     pub children: R::Lock<BTreeMap<Pid, Arc<Process<R>>>>,
+    // children that exited and haven't been waited for, see exit
+    pub child_states: R::Lock<BTreeMap<Pid, ChildState>>,
+    // signals sent and not yet delivered, a SigSet's bits. nothing
+    // delivers them yet
+    pub pending: R::Lock<u64>,
     pub threads: R::Lock<BTreeMap<Tid, Weak<Task<R>>>>,
     pub fd_table: R::Lock<Vec<Option<FileDescriptorEntry>>>,
-    pub robust_list: R::Lock<Option<*mut RobustListHead>>,
+    // where the guest keeps it, we never look inside
+    pub robust_list: R::Lock<Option<AddressSpace>>,
+    pub rlimits: R::Lock<[RLimit; RlimitId::NLIMITS as usize]>,
     pub creds: R::Lock<Credentials>,
     // we keep the path used to traverse to this objet since its
     // not unique, valuable user context, and very costly to ennumerate
     pub cwd: R::Lock<(Oid, Path)>,
-    pub next_fd_hint: usize,
+    // where absolute paths start
+    pub root: R::Lock<Oid>,
+    // what the executable sees where, see view
+    pub view: R::Lock<View>,
+    pub next_fd_hint: AtomicUsize,
     pub next_tid: AtomicU32,
 }
 
 unsafe impl<R:Runtime> Send for Process<R> {}
 
 impl<R:Runtime> Process<R> {
+    // a process of its own, in its own group and session, with nothing open
+    // and both cwd and root at root
+    pub fn new(kernel: Arc<Kernel<R>>, myself: Oid, pid: Pid, creds: Credentials, root: Oid) -> Self {
+        Self {
+            kernel,
+            myself,
+            pid,
+            pgid: R::Lock::new(Pgid(pid.value())),
+            sid: R::Lock::new(Sid(pid.value())),
+            state: R::Lock::new(ProcessState::Running),
+            umask: R::Lock::new(0o022),
+            parent: R::Lock::new(Pid(0)),
+            children: R::Lock::new(BTreeMap::new()),
+            child_states: R::Lock::new(BTreeMap::new()),
+            pending: R::Lock::new(0),
+            threads: R::Lock::new(BTreeMap::new()),
+            fd_table: R::Lock::new(Vec::new()),
+            robust_list: R::Lock::new(None),
+            rlimits: R::Lock::new(DEFAULT_RLIMITS),
+            creds: R::Lock::new(creds),
+            cwd: R::Lock::new((root, Path::new("/"))),
+            root: R::Lock::new(root),
+            view: R::Lock::new(View::default()),
+            next_fd_hint: AtomicUsize::new(0),
+            next_tid: AtomicU32::new(1),
+        }
+    }
+
+    // a child the way fork leaves it, with copies of our credentials, cwd,
+    // root, view and descriptors, so an exec or chdir on either side is
+    // its own. the descriptors' positions aren't shared the way linux
+    // shares an open file between the two
+    #[allow(clippy::arc_with_non_send_sync)]
+    pub fn fork(self: &Arc<Self>, myself: Oid, pid: Pid) -> Arc<Self> {
+        // the child's copies hold the files open as much as ours do, so an
+        // unlinked one lives until both have closed it
+        let fds = self.fd_table.lock().clone();
+        for f in fds.iter().flatten() {
+            self.kernel.opened(f.obj);
+        }
+        let child = Arc::new(Self {
+            kernel: self.kernel.clone(),
+            myself,
+            pid,
+            pgid: R::Lock::new(*self.pgid.lock()),
+            sid: R::Lock::new(*self.sid.lock()),
+            state: R::Lock::new(ProcessState::Running),
+            umask: R::Lock::new(*self.umask.lock()),
+            parent: R::Lock::new(self.pid),
+            children: R::Lock::new(BTreeMap::new()),
+            child_states: R::Lock::new(BTreeMap::new()),
+            pending: R::Lock::new(0),
+            threads: R::Lock::new(BTreeMap::new()),
+            fd_table: R::Lock::new(fds),
+            robust_list: R::Lock::new(None),
+            rlimits: R::Lock::new(*self.rlimits.lock()),
+            creds: R::Lock::new(self.creds.lock().clone()),
+            cwd: R::Lock::new(self.cwd.lock().clone()),
+            root: R::Lock::new(*self.root.lock()),
+            view: R::Lock::new(self.view.lock().clone()),
+            next_fd_hint: AtomicUsize::new(self.next_fd_hint.load(Ordering::Relaxed)),
+            next_tid: AtomicU32::new(1),
+        });
+        self.children.lock().insert(pid, child.clone());
+        self.kernel.add_process(&child);
+        child
+    }
+
     // Return the next avilable thread id. Will never return a thread who's ID
     // == PID, since that is defined as the main, root thread.
     pub fn next_tid(&self) -> Tid {
@@ -104,7 +187,7 @@ pub struct RobustListHead {
     list_op_pending: RobustList,
 }
 
-pub async fn sys_set_robust_list<R:Runtime>(t:Task<R>, head: *mut RobustListHead, len: usize) -> Result<usize, Error> {
+pub async fn sys_set_robust_list<R:Runtime>(t:Task<R>, head: AddressSpace, len: usize) -> Result<usize, Error> {
     if len != size_of::<RobustListHead>() {
         return Err(linuxerr!(EINVAL));
     }
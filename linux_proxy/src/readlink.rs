@@ -1,15 +1,87 @@
-use crate::linux::Fd;
-use core::ffi::c_char;
-use crate::error::KernelError;
-use crate::memory::address::TUA;
+use alloc::vec::Vec;
+use crate::{
+    AddressSpace, Fd, FileType, Lockable, PATH_MAX, Runtime, Task,
+    copy_in_cstr, copy_out, linuxerr,
+    attr::FilePermissions,
+    namei::{read_link, resolve},
+    times::now,
+};
+use protocol::{Error, Oid, attribute, execute};
 
-pub async fn sys_readlinkat(
-    _dirfd: Fd,
-    _path: TUA<c_char>,
-    _statbuf: TUA<c_char>,
-    _size: usize,
-) -> Result<usize, KernelError> {
-    // TODO: This is safe for fat32, since it doesn't support symbolic links.
-    // However, we need to implement this for a real FS!
-    Err(KernelError::InvalidValue)
+pub async fn sys_readlinkat<R:Runtime>(
+    t: Task<R>,
+    dirfd: Fd,
+    path: AddressSpace,
+    buf: AddressSpace,
+    size: usize,
+) -> Result<usize, Error> {
+    // signed in the kernel, so anything that would be negative is refused too
+    if size == 0 || size > i32::MAX as usize {
+        return Err(linuxerr!(EINVAL));
+    }
+    let path = copy_in_cstr(&t.process.kernel.runtime, path, PATH_MAX - 1)?;
+    let r = resolve(&t, dirfd, &path, false).await?;
+    let oid = r.oid.ok_or(linuxerr!(ENOENT))?;
+    let target = read_link(&t, oid).await?;
+    // no terminating nul, and silently cut short
+    let n = target.len().min(size);
+    copy_out(&t.process.kernel.runtime, buf, &target[..n])?;
+    Ok(n)
+}
+
+// the target is kept as it was given, it isn't looked at until someone
+// goes through the link
+async fn create<R:Runtime>(t: &Task<R>, parent: Oid, name: &str, target: Vec<u8>) -> Result<Oid, Error> {
+    let (uid, gid) = t.process.creds.lock().owner()?;
+    let size = target.len() as u64;
+    let now = now(t);
+    let mut created = None;
+    execute!(t.process.kernel.runtime,
+             [Get(parent, attribute!("children"), names:Oid),
+              Absent(names:Oid, attribute!(name)),
+              Create(link:Oid),
+              Set(names:Oid, attribute!(name), link:Oid),
+              Set(link:Oid, attribute!("type"), FileType::Symlink),
+              Set(link:Oid, attribute!("target"), target.clone()),
+              Set(link:Oid, attribute!("mode"), FilePermissions::from_bits_truncate(0o777)),
+              Set(link:Oid, attribute!("uid"), uid),
+              Set(link:Oid, attribute!("gid"), gid),
+              Set(link:Oid, attribute!("nlink"), 1u32),
+              Set(link:Oid, attribute!("size"), size),
+              Set(link:Oid, attribute!("atime"), now),
+              Set(link:Oid, attribute!("mtime"), now),
+              Set(link:Oid, attribute!("ctime"), now),
+              Set(parent, attribute!("mtime"), now),
+              Set(parent, attribute!("ctime"), now)] {
+                  created = Some(link);
+              });
+    created.ok_or(linuxerr!(ENOTDIR))
+}
+
+pub async fn sys_symlinkat<R:Runtime>(
+    t: Task<R>,
+    target: AddressSpace,
+    newdirfd: Fd,
+    linkpath: AddressSpace,
+) -> Result<usize, Error> {
+    let target = copy_in_cstr(&t.process.kernel.runtime, target, PATH_MAX - 1)?;
+    if target.is_empty() {
+        return Err(linuxerr!(ENOENT));
+    }
+    let linkpath = copy_in_cstr(&t.process.kernel.runtime, linkpath, PATH_MAX - 1)?;
+    let r = resolve(&t, newdirfd, &linkpath, false).await?;
+    if r.oid.is_some() {
+        return Err(linuxerr!(EEXIST));
+    }
+    r.writable()?;
+    // "a/" can only name a directory
+    if r.trailing_slash {
+        return Err(linuxerr!(ENOENT));
+    }
+    let name = r.name.ok_or(linuxerr!(EEXIST))?;
+    match create(&t, r.parent, &name, target).await {
+        Ok(_) => Ok(0),
+        Err(e) if e.is_guard_failure() => Err(linuxerr!(EEXIST)),
+        Err(e) => Err(e),
+    }
 }
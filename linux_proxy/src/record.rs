@@ -0,0 +1,409 @@
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use async_trait::async_trait;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use core::time::Duration;
+use protocol::{Buffer, Cancel, Command, DynClock, DynStream, Encodable, Error, Oid, Status, Stream, Value, err};
+use crate::runtime::{AccessMode, AddressSpace, DynSyscall, Runtime};
+
+// capture at the Runtime::execute boundary, so that a run under the monitor
+// can be replayed on the linux_proxy side alone, without any backend. every
+// block going out and every row or error coming back is logged as it
+// happens. seq ties rows back to the block that produced them, since the
+// streams of different blocks can be read interleaved
+#[derive(Debug, Clone, PartialEq)]
+#[repr(u8)]
+pub enum Record {
+    Block { seq: u64, time: u64, block: Vec<Command> } = 1,
+    Row { seq: u64, time: u64, row: Vec<Value> } = 2,
+    // the stream ran out, or failed
+    End { seq: u64, time: u64, error: Option<Ended> } = 3,
+    // execute itself failed, there was never a stream
+    Refused { seq: u64, time: u64, error: Ended } = 4,
+    // a read of the runtime's clock, seq being the block it came before,
+    // so replay hands back the same times and the blocks that carry them
+    // come out the same
+    Now { seq: u64, time: u64, now: u64 } = 5,
+}
+
+// enough of an Error to give the same one back on replay
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ended {
+    pub cause: String,
+    pub syserr: Option<u8>,
+    pub status: Option<Status>,
+}
+
+impl Ended {
+    fn from_error(e: &Error) -> Ended {
+        Ended { cause: e.cause.clone(), syserr: e.syserr, status: e.status.clone() }
+    }
+
+    fn error(&self) -> Error {
+        Error { location: None, cause: self.cause.clone(), syserr: self.syserr, status: self.status.clone() }
+    }
+}
+
+impl Encodable for Ended {
+    fn decode(source: &mut Buffer) -> Result<Self, Error> {
+        let length = source.read_varint()?;
+        let cause = String::from_utf8(source.read(length as usize)?.to_vec())
+            .map_err(|_| err!("invalid utf8 contents"))?;
+        let syserr = match source.read(1)?[0] {
+            0 => None,
+            x => Some(x),
+        };
+        let status = match source.read(1)?[0] {
+            0 => None,
+            _ => Some(Status::decode(source)?),
+        };
+        Ok(Ended { cause, syserr, status })
+    }
+
+    fn encode(&self, b: &mut Buffer) -> Result<(), Error> {
+        b.write_varint(self.cause.len() as u64)?;
+        b.write(self.cause.as_bytes())?;
+        b.write(&[self.syserr.unwrap_or(0)])?;
+        match &self.status {
+            Some(s) => {
+                b.write(&[1])?;
+                s.encode(b)
+            }
+            None => b.write(&[0]),
+        }
+    }
+}
+
+impl Encodable for Record {
+    fn decode(source: &mut Buffer) -> Result<Self, Error> {
+        let code = source.read(1)?[0];
+        let seq = source.read_varint()?;
+        let time = source.read_varint()?;
+        match code {
+            1 => {
+                let count = source.read_varint()?;
+                let mut block = Vec::new();
+                for _ in 0..count {
+                    block.push(Command::decode(source)?);
+                }
+                Ok(Record::Block { seq, time, block })
+            }
+            2 => {
+                let count = source.read_varint()?;
+                let mut row = Vec::new();
+                for _ in 0..count {
+                    row.push(Value::decode(source)?);
+                }
+                Ok(Record::Row { seq, time, row })
+            }
+            3 => {
+                let error = match source.read(1)?[0] {
+                    0 => None,
+                    _ => Some(Ended::decode(source)?),
+                };
+                Ok(Record::End { seq, time, error })
+            }
+            4 => Ok(Record::Refused { seq, time, error: Ended::decode(source)? }),
+            5 => Ok(Record::Now { seq, time, now: source.read_varint()? }),
+            x => Err(err!("invalid record code {}", x)),
+        }
+    }
+
+    fn encode(&self, b: &mut Buffer) -> Result<(), Error> {
+        match self {
+            Record::Block { seq, time, block } => {
+                b.write(&[1])?;
+                b.write_varint(*seq)?;
+                b.write_varint(*time)?;
+                b.write_varint(block.len() as u64)?;
+                for c in block {
+                    c.encode(b)?;
+                }
+            }
+            Record::Row { seq, time, row } => {
+                b.write(&[2])?;
+                b.write_varint(*seq)?;
+                b.write_varint(*time)?;
+                b.write_varint(row.len() as u64)?;
+                for v in row {
+                    v.encode(b)?;
+                }
+            }
+            Record::End { seq, time, error } => {
+                b.write(&[3])?;
+                b.write_varint(*seq)?;
+                b.write_varint(*time)?;
+                match error {
+                    Some(e) => {
+                        b.write(&[1])?;
+                        e.encode(b)?;
+                    }
+                    None => b.write(&[0])?,
+                }
+            }
+            Record::Refused { seq, time, error } => {
+                b.write(&[4])?;
+                b.write_varint(*seq)?;
+                b.write_varint(*time)?;
+                error.encode(b)?;
+            }
+            Record::Now { seq, time, now } => {
+                b.write(&[5])?;
+                b.write_varint(*seq)?;
+                b.write_varint(*time)?;
+                b.write_varint(*now)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// where the recording goes. each call is one whole record so a run that
+// dies partway still leaves everything up to the last one readable. in
+// the guest this is a closure around hvc #6, which the monitor appends to
+// the file given by --record, and Replay::new takes that file's contents
+pub type DynSink = Arc<dyn Sink + Send + Sync>;
+pub trait Sink {
+    fn append(&self, b: &[u8]) -> Result<(), Error>;
+}
+
+impl<F: Fn(&[u8]) -> Result<(), Error>> Sink for F {
+    fn append(&self, b: &[u8]) -> Result<(), Error> {
+        self(b)
+    }
+}
+
+#[derive(Clone)]
+struct Log {
+    sink: DynSink,
+    clock: DynClock,
+}
+
+impl Log {
+    fn write(&self, r: Record) -> Result<(), Error> {
+        let mut b = Buffer::new();
+        r.encode(&mut b)?;
+        self.sink.append(b.bytes())
+    }
+
+    fn now(&self) -> u64 {
+        (self.clock)()
+    }
+}
+
+// wraps the real runtime, everything but execute passes straight through
+pub struct Recorder<R:Runtime> {
+    inner: R,
+    log: Log,
+    seq: AtomicU64,
+}
+
+impl<R:Runtime> Recorder<R> {
+    pub fn new(inner: R, sink: DynSink, clock: DynClock) -> Self {
+        Recorder { inner, log: Log { sink, clock }, seq: AtomicU64::new(0) }
+    }
+}
+
+struct Recording {
+    inner: DynStream<Vec<Value>>,
+    log: Log,
+    seq: u64,
+}
+
+#[async_trait]
+impl Stream<Vec<Value>> for Recording {
+    async fn next(&mut self) -> Result<Option<Vec<Value>>, Error> {
+        match self.inner.next().await {
+            Ok(Some(row)) => {
+                self.log.write(Record::Row { seq: self.seq, time: self.log.now(), row: row.clone() })?;
+                Ok(Some(row))
+            }
+            Ok(None) => {
+                self.log.write(Record::End { seq: self.seq, time: self.log.now(), error: None })?;
+                Ok(None)
+            }
+            Err(e) => {
+                self.log.write(Record::End { seq: self.seq, time: self.log.now(), error: Some(Ended::from_error(&e)) })?;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl<R:Runtime> Runtime for Recorder<R> {
+    type Lock<A> = R::Lock<A>;
+    type Thread = R::Thread;
+
+    fn create_thread(&self, entry:u64, arg:u64, syscalls:DynSyscall) -> Result<Self::Thread, Error> {
+        self.inner.create_thread(entry, arg, syscalls)
+    }
+
+    fn console(&self, s:String) {
+        self.inner.console(s)
+    }
+
+    fn map(&self, from:AddressSpace, to:AddressSpace, a:AccessMode, length:usize) -> Result<(), Error> {
+        self.inner.map(from, to, a, length)
+    }
+
+    fn unmap(&self, at:AddressSpace, length:usize) -> Result<(), Error> {
+        self.inner.unmap(at, length)
+    }
+
+    fn copy(&self, to:AddressSpace, from:AddressSpace, length:usize) {
+        self.inner.copy(to, from, length)
+    }
+
+    fn exec(&self, process:Oid, exe:Oid, argv:Vec<Vec<u8>>, envp:Vec<Vec<u8>>) -> Result<(), Error> {
+        self.inner.exec(process, exe, argv, envp)
+    }
+
+    // a clock that can't be written down is only going to cost a replay,
+    // so a failed write doesn't stop the time being used
+    fn now(&self) -> Duration {
+        let now = self.inner.now();
+        let seq = self.seq.load(Ordering::Relaxed);
+        let _ = self.log.write(Record::Now { seq, time: self.log.now(), now: now.as_nanos() as u64 });
+        now
+    }
+
+    fn execute_cancellable(&self, block:Vec<Command>, cancel:Cancel) -> Result<DynStream<Vec<Value>>, Error> {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed);
+        self.log.write(Record::Block { seq, time: self.log.now(), block: block.clone() })?;
+        match self.inner.execute_cancellable(block, cancel) {
+            Ok(inner) => Ok(Box::new(Recording { inner, log: self.log.clone(), seq })),
+            Err(e) => {
+                self.log.write(Record::Refused { seq, time: self.log.now(), error: Ended::from_error(&e) })?;
+                Err(e)
+            }
+        }
+    }
+}
+
+struct Answer {
+    block: Vec<Command>,
+    rows: Vec<Vec<Value>>,
+    // None if the recording stops before the stream did
+    end: Option<Option<Ended>>,
+    refused: Option<Ended>,
+}
+
+// answers blocks out of a recording instead of sending them anywhere. the
+// same program run on the same inputs sends the same blocks in the same
+// order, so they are matched up in sequence and anything different is a
+// divergence rather than something to guess at. inner is only there for
+// locks, threads and memory, it never sees a block
+pub struct Replay<R:Runtime> {
+    inner: R,
+    answers: Vec<Answer>,
+    next: AtomicUsize,
+    // the times read before each block, by its seq, and how many of them
+    // have been handed out. a read more or less in one place only throws
+    // out the times up to the next block, not every one after it
+    nows: BTreeMap<u64, (Vec<u64>, AtomicUsize)>,
+}
+
+impl<R:Runtime> Replay<R> {
+    pub fn new(inner: R, recording: &[u8]) -> Result<Self, Error> {
+        let mut b = Buffer::from_bytes(recording);
+        let mut answers = Vec::new();
+        let mut nows = BTreeMap::new();
+        // seq to position in answers
+        let mut index = BTreeMap::new();
+        while !b.is_empty() {
+            match Record::decode(&mut b)? {
+                Record::Block { seq, block, .. } => {
+                    index.insert(seq, answers.len());
+                    answers.push(Answer { block, rows: Vec::new(), end: None, refused: None });
+                }
+                Record::Row { seq, row, .. } => {
+                    let i = index.get(&seq).ok_or_else(|| err!("row for unknown block {}", seq))?;
+                    answers[*i].rows.push(row);
+                }
+                Record::End { seq, error, .. } => {
+                    let i = index.get(&seq).ok_or_else(|| err!("end of unknown block {}", seq))?;
+                    answers[*i].end = Some(error);
+                }
+                Record::Refused { seq, error, .. } => {
+                    let i = index.get(&seq).ok_or_else(|| err!("refusal of unknown block {}", seq))?;
+                    answers[*i].refused = Some(error);
+                }
+                Record::Now { seq, now, .. } => {
+                    nows.entry(seq).or_insert_with(|| (Vec::new(), AtomicUsize::new(0))).0.push(now)
+                }
+            }
+        }
+        Ok(Replay { inner, answers, next: AtomicUsize::new(0), nows })
+    }
+}
+
+struct Replaying {
+    rows: alloc::vec::IntoIter<Vec<Value>>,
+    end: Option<Option<Ended>>,
+}
+
+#[async_trait]
+impl Stream<Vec<Value>> for Replaying {
+    async fn next(&mut self) -> Result<Option<Vec<Value>>, Error> {
+        if let Some(row) = self.rows.next() {
+            return Ok(Some(row));
+        }
+        match &self.end {
+            Some(None) => Ok(None),
+            Some(Some(e)) => Err(e.error()),
+            None => Err(err!("recording ends before this stream does")),
+        }
+    }
+}
+
+impl<R:Runtime> Runtime for Replay<R> {
+    type Lock<A> = R::Lock<A>;
+    type Thread = R::Thread;
+
+    fn create_thread(&self, entry:u64, arg:u64, syscalls:DynSyscall) -> Result<Self::Thread, Error> {
+        self.inner.create_thread(entry, arg, syscalls)
+    }
+
+    fn console(&self, s:String) {
+        self.inner.console(s)
+    }
+
+    fn map(&self, from:AddressSpace, to:AddressSpace, a:AccessMode, length:usize) -> Result<(), Error> {
+        self.inner.map(from, to, a, length)
+    }
+
+    fn unmap(&self, at:AddressSpace, length:usize) -> Result<(), Error> {
+        self.inner.unmap(at, length)
+    }
+
+    fn copy(&self, to:AddressSpace, from:AddressSpace, length:usize) {
+        self.inner.copy(to, from, length)
+    }
+
+    fn exec(&self, process:Oid, exe:Oid, argv:Vec<Vec<u8>>, envp:Vec<Vec<u8>>) -> Result<(), Error> {
+        self.inner.exec(process, exe, argv, envp)
+    }
+
+    // the times as recorded before the block that's next. blocks go out
+    // in seq order, so that's the seq of the next one. past the ones
+    // recorded there is nothing sensible to give, and whatever block it
+    // ends up in will diverge and say so
+    fn now(&self) -> Duration {
+        let seq = self.next.load(Ordering::Relaxed) as u64;
+        let now = self.nows.get(&seq).and_then(|(times, next)| times.get(next.fetch_add(1, Ordering::Relaxed)).copied());
+        Duration::from_nanos(now.unwrap_or(0))
+    }
+
+    // cancellation isn't replayed, the recording already says how the
+    // block came out including if it was cancelled
+    fn execute_cancellable(&self, block:Vec<Command>, _cancel:Cancel) -> Result<DynStream<Vec<Value>>, Error> {
+        let i = self.next.fetch_add(1, Ordering::Relaxed);
+        let answer = self.answers.get(i).ok_or_else(|| err!("recording ran out at block {}", i))?;
+        if answer.block != block {
+            return Err(err!("replay diverged at block {}, recorded {:?} but got {:?}", i, answer.block, block));
+        }
+        if let Some(e) = &answer.refused {
+            return Err(e.error());
+        }
+        Ok(Box::new(Replaying { rows: answer.rows.clone().into_iter(), end: answer.end.clone() }))
+    }
+}
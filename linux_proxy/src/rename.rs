@@ -0,0 +1,199 @@
+use alloc::{collections::BTreeMap, vec::Vec};
+use crate::{
+    AddressSpace, Fd, FileType, Lockable, PATH_MAX, Runtime, Task,
+    copy_in_cstr, linuxerr,
+    namei::{Edit, Resolved, failed_guard, nlinks, resolve},
+    times::now,
+};
+use protocol::{Command, Error, FromValue, Oid, Value, attribute};
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct RenameFlags: u32 {
+        const RENAME_NOREPLACE = 1;  // Don't overwrite the target.
+        const RENAME_EXCHANGE = 2;   // Swap the two.
+        const RENAME_WHITEOUT = 4;   // Overlay filesystems only.
+    }
+}
+
+// whether dir is of or somewhere under it, going up the parent pointers
+async fn within<R:Runtime>(t: &Task<R>, mut dir: Oid, of: Oid) -> Result<bool, Error> {
+    let root = *t.process.root.lock();
+    loop {
+        if dir == of {
+            return Ok(true);
+        }
+        if dir == root {
+            return Ok(false);
+        }
+        let block = Vec::from([Command::Get(Value::Oid(dir), attribute!("parent"), Value::Union(0))]);
+        let row = t.process.kernel.runtime.execute(block)?.next().await?.ok_or(linuxerr!(ENOENT))?;
+        dir = match &row[0] {
+            Value::Empty() => return Ok(false),
+            p => Oid::from_value(p)?,
+        };
+    }
+}
+
+// the entry and its name, for something that has to have both
+fn named(r: &Resolved) -> Result<(Oid, &str), Error> {
+    match (r.oid, &r.name) {
+        (Some(oid), Some(name)) => Ok((oid, name)),
+        (None, _) => Err(linuxerr!(ENOENT)),
+        // ".", ".." or "/"
+        (Some(_), None) => Err(linuxerr!(EBUSY)),
+    }
+}
+
+// both names are guarded on still being what they resolved to, and the
+// link counts move with them. a directory moving to another parent takes
+// its '..' link along, and has its parent pointer changed
+pub async fn sys_renameat2<R:Runtime>(
+    t: Task<R>,
+    olddirfd: Fd,
+    oldpath: AddressSpace,
+    newdirfd: Fd,
+    newpath: AddressSpace,
+    flags: RenameFlags,
+) -> Result<usize, Error> {
+    let exchange = flags.contains(RenameFlags::RENAME_EXCHANGE);
+    if flags.contains(RenameFlags::RENAME_WHITEOUT)
+        || (exchange && flags.contains(RenameFlags::RENAME_NOREPLACE)) {
+        return Err(linuxerr!(EINVAL));
+    }
+    let oldpath = copy_in_cstr(&t.process.kernel.runtime, oldpath, PATH_MAX - 1)?;
+    let newpath = copy_in_cstr(&t.process.kernel.runtime, newpath, PATH_MAX - 1)?;
+    loop {
+        let old = resolve(&t, olddirfd, &oldpath, false).await?;
+        let new = resolve(&t, newdirfd, &newpath, false).await?;
+        old.writable()?;
+        new.writable()?;
+        let (from, from_name) = named(&old)?;
+        let from_dir = old.file_type == FileType::Directory;
+        let to_name = match (&new.name, new.oid) {
+            (Some(name), _) => name.as_str(),
+            (None, Some(_)) => return Err(linuxerr!(EBUSY)),
+            (None, None) => return Err(linuxerr!(ENOENT)),
+        };
+        let to = new.oid;
+        let to_dir = to.is_some() && new.file_type == FileType::Directory;
+        if (old.trailing_slash && !from_dir) || (new.trailing_slash && to.is_some() && !to_dir) {
+            return Err(linuxerr!(ENOTDIR));
+        }
+        match (exchange, to) {
+            (true, None) => return Err(linuxerr!(ENOENT)),
+            (false, Some(_)) if flags.contains(RenameFlags::RENAME_NOREPLACE) => return Err(linuxerr!(EEXIST)),
+            // two names for the same thing, nothing to do
+            (_, Some(to)) if to == from => return Ok(0),
+            (false, Some(_)) if from_dir && !to_dir => return Err(linuxerr!(ENOTDIR)),
+            (false, Some(_)) if !from_dir && to_dir => return Err(linuxerr!(EISDIR)),
+            (false, None) if new.trailing_slash && !from_dir => return Err(linuxerr!(ENOTDIR)),
+            _ => {}
+        }
+        // a directory can't go under itself, either way round
+        if from_dir && within(&t, new.parent, from).await? {
+            return Err(linuxerr!(EINVAL));
+        }
+        if let (true, true, Some(to)) = (exchange, to_dir, to)
+            && within(&t, old.parent, to).await? {
+            return Err(linuxerr!(EINVAL));
+        }
+
+        let mut e = Edit::default();
+        let old_names = e.names(old.parent);
+        let new_names = if new.parent == old.parent { old_names.clone() } else { e.names(new.parent) };
+        e.expect(&old_names, from_name, from);
+        match to {
+            Some(to) => e.expect(&new_names, to_name, to),
+            None => e.absent(&new_names, to_name),
+        }
+
+        // changes to the link counts of the parents, and what happens to
+        // whatever was replaced
+        let mut delta: BTreeMap<Oid, i64> = BTreeMap::new();
+        let moved = |e: &mut Edit, delta: &mut BTreeMap<Oid, i64>, dir: Oid, from: Oid, to: Oid| {
+            if from != to {
+                e.set(Value::Oid(dir), "parent", Value::Oid(to));
+                *delta.entry(from).or_default() -= 1;
+                *delta.entry(to).or_default() += 1;
+            }
+        };
+        let mut empty = None;
+        let mut replaced = None;
+        if exchange {
+            let to = to.ok_or(linuxerr!(ENOENT))?;
+            e.link(&old_names, from_name, Value::Oid(to));
+            e.link(&new_names, to_name, Value::Oid(from));
+            if from_dir {
+                moved(&mut e, &mut delta, from, old.parent, new.parent);
+            }
+            if to_dir {
+                moved(&mut e, &mut delta, to, new.parent, old.parent);
+            }
+        } else {
+            if let Some(to) = to {
+                if to_dir {
+                    // the replaced directory's '..' goes, and nothing is
+                    // left pointing at it
+                    empty = Some(e.empty(to));
+                    *delta.entry(new.parent).or_default() -= 1;
+                    replaced = Some((to, 0));
+                } else {
+                    replaced = Some((to, -1));
+                }
+            }
+            e.unlink(&old_names, from_name);
+            e.link(&new_names, to_name, Value::Oid(from));
+            if from_dir {
+                moved(&mut e, &mut delta, from, old.parent, new.parent);
+            }
+        }
+
+        delta.retain(|_, d| *d != 0);
+        let mut gone = false;
+        let oids: Vec<Oid> = delta.keys().copied().chain(replaced.map(|(oid, _)| oid)).collect();
+        if !oids.is_empty() {
+            let counts = nlinks(&t, &oids).await?;
+            for (oid, (stored, n)) in oids.iter().zip(counts) {
+                let n = match (delta.get(oid), replaced) {
+                    (Some(d), _) => n as i64 + d,
+                    (None, Some((_, 0))) => 0,
+                    (None, _) => n as i64 - 1,
+                };
+                e.nlink(*oid, &stored, n.max(0) as u32);
+                gone |= replaced.is_some_and(|(to, _)| to == *oid) && n <= 0;
+            }
+        }
+        let now = now(&t);
+        e.modified(old.parent, now);
+        if new.parent != old.parent {
+            e.modified(new.parent, now);
+        }
+        e.changed(from, now);
+        if let Some(to) = to {
+            e.changed(to, now);
+        }
+        // a replaced entry that lost its last name goes like an unlinked one
+        if let Some((to, _)) = replaced
+            && gone
+            && !t.process.kernel.is_open(to) {
+            e.destroy(to);
+        }
+        match e.run(&t).await {
+            Ok(_) => return Ok(0),
+            Err(e) if empty.is_some() && failed_guard(&e) == empty => return Err(linuxerr!(ENOTEMPTY)),
+            Err(e) if e.is_guard_failure() => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+pub async fn sys_renameat<R:Runtime>(
+    t: Task<R>,
+    olddirfd: Fd,
+    oldpath: AddressSpace,
+    newdirfd: Fd,
+    newpath: AddressSpace,
+) -> Result<usize, Error> {
+    sys_renameat2(t, olddirfd, oldpath, newdirfd, newpath, RenameFlags::empty()).await
+}
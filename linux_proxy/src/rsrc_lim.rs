@@ -1,4 +1,4 @@
-use crate::{Pid, Task, Runtime};
+use crate::{AddressSpace, Capabilities, Lockable, Pid, Task, Runtime, copy_in, copy_out, linuxerr};
 
 use protocol::Error;
 
@@ -33,39 +33,93 @@ impl RlimitId {
 
 pub const RLIM_INFINITY: u64 = u64::MAX;
 
+// the most NOFILE can be raised to, linux's default nr_open
+pub const NR_OPEN: u64 = 1 << 20;
+
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct RLimit {
     pub rlim_cur: u64, // The current (soft) limit
     pub rlim_max: u64, // The hard limit
 }
 
+impl RLimit {
+    const fn new(rlim_cur: u64, rlim_max: u64) -> Self {
+        RLimit { rlim_cur, rlim_max }
+    }
+
+    fn copy_in<R:Runtime>(runtime: &R, from: AddressSpace) -> Result<Self, Error> {
+        let mut b = [0u8; 16];
+        copy_in(runtime, from, &mut b)?;
+        let word = |b: &[u8]| u64::from_ne_bytes(b.try_into().unwrap());
+        Ok(RLimit::new(word(&b[..8]), word(&b[8..])))
+    }
+
+    fn copy_out<R:Runtime>(&self, runtime: &R, to: AddressSpace) -> Result<(), Error> {
+        let mut b = [0u8; 16];
+        b[..8].copy_from_slice(&self.rlim_cur.to_ne_bytes());
+        b[8..].copy_from_slice(&self.rlim_max.to_ne_bytes());
+        copy_out(runtime, to, &b)
+    }
+}
+
+// what init starts with on linux. nothing here enforces them yet, they
+// are only kept so a program gets back what it set
+pub const DEFAULT_RLIMITS: [RLimit; RlimitId::NLIMITS as usize] = {
+    let inf = RLimit::new(RLIM_INFINITY, RLIM_INFINITY);
+    let mut l = [inf; RlimitId::NLIMITS as usize];
+    l[RlimitId::STACK.as_usize()] = RLimit::new(8 << 20, RLIM_INFINITY);
+    l[RlimitId::CORE.as_usize()] = RLimit::new(0, RLIM_INFINITY);
+    l[RlimitId::NOFILE.as_usize()] = RLimit::new(1024, 4096);
+    l[RlimitId::MEMLOCK.as_usize()] = RLimit::new(8 << 20, 8 << 20);
+    l[RlimitId::MSGQUEUE.as_usize()] = RLimit::new(819200, 819200);
+    l[RlimitId::NICE.as_usize()] = RLimit::new(0, 0);
+    l[RlimitId::RPRIO.as_usize()] = RLimit::new(0, 0);
+    l
+};
+
+// only our own limits, we can't see into any other process
 pub async fn sys_prlimit64<R:Runtime>(
-    _t:Task<R>,
-    _pid: Pid,
-    _resource: u32,
-    _new_rlim: *const RLimit,
-    _old_rlim: *mut RLimit,
+    t:Task<R>,
+    pid: Pid,
+    resource: u32,
+    new_rlim: AddressSpace,
+    old_rlim: AddressSpace,
 ) -> Result<usize, Error> {
-    /*
-    tree me baby
-        let new_limit = if !new_rlim.is_null() {
-            Some(copy_from_user(new_rlim).await?)
-        } else {
-            None
-        };
-
-        let old_lim = if let Some(new_limit) = new_limit {
-            task.rsrc_lim
-                .lock_save_irq()
-                .set(resource, new_limit, true)?
-        } else {
-            task.rsrc_lim.lock_save_irq().get(resource)
-        };
+    if resource >= RlimitId::NLIMITS as u32 {
+        return Err(linuxerr!(EINVAL));
+    }
+    if pid.value() != 0 && pid != t.process.pid {
+        return Err(linuxerr!(ESRCH));
+    }
+    let runtime = &t.process.kernel.runtime;
+    let new_limit = match new_rlim {
+        AddressSpace::User(0) => None,
+        from => Some(RLimit::copy_in(runtime, from)?),
+    };
 
-        if !old_rlim.is_null() {
-            copy_to_user(old_rlim, old_lim).await?
+    let old_limit = {
+        let mut limits = t.process.rlimits.lock();
+        let old = limits[resource as usize];
+        if let Some(new) = new_limit {
+            if new.rlim_cur > new.rlim_max {
+                return Err(linuxerr!(EINVAL));
+            }
+            let raising = new.rlim_max > old.rlim_max;
+            if raising && !t.process.creds.lock().capable(Capabilities::CAP_SYS_RESOURCE) {
+                return Err(linuxerr!(EPERM));
+            }
+            if resource == RlimitId::NOFILE as u32 && new.rlim_max > NR_OPEN {
+                return Err(linuxerr!(EPERM));
+            }
+            limits[resource as usize] = new;
         }
-    */
+        old
+    };
+
+    match old_rlim {
+        AddressSpace::User(0) => {}
+        to => old_limit.copy_out(runtime, to)?,
+    }
     Ok(0)
 }
@@ -1,18 +1,23 @@
-use alloc::{sync::Arc, string::String, vec::Vec};
-use protocol::{Error, Command, Value, DynStream};
-use core::ops::{Deref, DerefMut};
+use alloc::{boxed::Box, sync::Arc, string::String, vec::Vec};
+use protocol::{Cancel, Error, Command, Value, DynStream, Oid, Schema};
+use crate::linuxerr;
+use async_trait::async_trait;
+use core::ops::DerefMut;
+use core::time::Duration;
 
 
-type DynSyscall = Arc<dyn Syscall>;
+// called by the thread for every svc, with x8 and x0-x5. what comes back
+// goes in x0. the future stays on the thread that took the trap
+pub type DynSyscall = Arc<dyn Syscall + Send + Sync>;
+#[async_trait(?Send)]
 pub trait Syscall {
-    fn on_syscall(&self, num:u32, args:[u64;6]);
+    async fn on_syscall(&self, num:u32, args:[u64;6]) -> u64;
 }
 
 pub trait Lockable<A> {
-    type Lock<C>;
-    type Guard<'a, B:'a> : Deref<Target = &'a B> + DerefMut<Target = &'a mut B> where Self: 'a;
-    fn new(item:A) -> Self::Lock<A>;
-    fn lock(&self) ->  Self::Guard<'_, A>;
+    type Guard<'a> : DerefMut<Target = A> where Self: 'a;
+    fn new(item:A) -> Self;
+    fn lock(&self) ->  Self::Guard<'_>;
 }
 
 pub trait Runnable {
@@ -20,6 +25,7 @@ pub trait Runnable {
 }
 
 // AddressSpace looks alot like protocol::Address?
+#[derive(Debug, Clone, Copy)]
 pub enum AddressSpace {
     User(u64),
     Kernel(u64),
@@ -48,5 +54,83 @@ pub trait Runtime {
     fn map(&self, from:AddressSpace, to:AddressSpace, a:AccessMode, length:usize) -> Result<(), Error>;
     fn unmap(&self, at:AddressSpace, length:usize) -> Result<(), Error>;
     fn copy(&self, to:AddressSpace, from:AddressSpace, length:usize);
-    fn execute(&self, block:Vec<Command>) -> Result<DynStream<Vec<Value>>, Error>;
+    // wall clock time since the epoch, for file timestamps
+    fn now(&self) -> Duration;
+    fn execute_cancellable(&self, block:Vec<Command>, cancel:Cancel) -> Result<DynStream<Vec<Value>>, Error>;
+
+    fn execute(&self, block:Vec<Command>) -> Result<DynStream<Vec<Value>>, Error> {
+        self.execute_cancellable(block, Cancel::new())
+    }
+
+    // start exe in the process in place of what it was running, see
+    // execve, which has done everything else by now. one that can't load
+    // programs says so
+    fn exec(&self, _process:Oid, _exe:Oid, _argv:Vec<Vec<u8>>, _envp:Vec<Vec<u8>>) -> Result<(), Error> {
+        Err(linuxerr!(ENOEXEC))
+    }
+}
+
+// read a whole Schema struct (FileAttr, Credentials..) out of the graph in one block
+pub async fn load<R:Runtime, S:Schema>(runtime:&R, oid:Oid) -> Result<S, Error> {
+    let mut st = runtime.execute(S::load(oid))?;
+    match st.next().await? {
+        Some(row) => S::from_row(&row),
+        None => Err(linuxerr!(ENOENT)),
+    }
+}
+
+pub async fn store<R:Runtime, S:Schema>(runtime:&R, oid:Oid, s:&S) -> Result<(), Error> {
+    let mut st = runtime.execute(s.store(oid))?;
+    while st.next().await?.is_some() {}
+    Ok(())
+}
+
+// user memory. Kernel is our own address space, so a local buffer is
+// named by its pointer
+pub fn copy_in<R:Runtime>(runtime:&R, from:AddressSpace, dest:&mut [u8]) -> Result<(), Error> {
+    match from {
+        AddressSpace::User(_) => {
+            runtime.copy(AddressSpace::Kernel(dest.as_mut_ptr() as u64), from, dest.len());
+            Ok(())
+        }
+        _ => Err(linuxerr!(EFAULT)),
+    }
+}
+
+pub fn copy_out<R:Runtime>(runtime:&R, to:AddressSpace, source:&[u8]) -> Result<(), Error> {
+    match to {
+        AddressSpace::User(_) => {
+            runtime.copy(to, AddressSpace::Kernel(source.as_ptr() as u64), source.len());
+            Ok(())
+        }
+        _ => Err(linuxerr!(EFAULT)),
+    }
+}
+
+// a nul terminated string of at most max bytes, not including the nul. read
+// in aligned chunks so we never touch a page past the one the string ends in
+pub fn copy_in_cstr<R:Runtime>(runtime:&R, from:AddressSpace, max:usize) -> Result<Vec<u8>, Error> {
+    const CHUNK: u64 = 256;
+    let AddressSpace::User(mut addr) = from else {
+        return Err(linuxerr!(EFAULT));
+    };
+    let mut out = Vec::new();
+    loop {
+        let mut chunk = [0u8; CHUNK as usize];
+        let length = (CHUNK - addr % CHUNK) as usize;
+        copy_in(runtime, AddressSpace::User(addr), &mut chunk[..length])?;
+        if let Some(end) = chunk[..length].iter().position(|b| *b == 0) {
+            out.extend_from_slice(&chunk[..end]);
+            break;
+        }
+        out.extend_from_slice(&chunk[..length]);
+        if out.len() > max {
+            return Err(linuxerr!(ENAMETOOLONG));
+        }
+        addr += length as u64;
+    }
+    if out.len() > max {
+        return Err(linuxerr!(ENAMETOOLONG));
+    }
+    Ok(out)
 }
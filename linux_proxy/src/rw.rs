@@ -1,50 +1,250 @@
-use alloc::{boxed::Box, vec, vec::Vec};
-use crate::{Fd, AddressSpace, Task, Runtime, linuxerr};
-use protocol::{Address, Attribute, Command, attribute, Error};
+use alloc::{vec, vec::Vec};
+use crate::{Fd, AddressSpace, FileDescriptorEntry, FileType, OpenFlags, Task, Runtime, linuxerr, interrupted, iov::IoVec,
+            times::{Times, accessed, modified, now}};
+use protocol::{Cancel, Command, Value, Variable, Predicate, attribute, Error, FromValue, Oid};
 
-// partial writes?
-// update pos
+// file contents are the contents attribute, and size says how much of it
+// there is. neither is there on a file that has never been written
 
-pub async fn sys_write<R:Runtime>(t: Task<R>, fd: Fd, user_buf: AddressSpace, count: usize) -> Result<usize, Error> {
+fn can_read(file: &FileDescriptorEntry) -> Result<(), Error> {
+    match file.oflags & OpenFlags::O_ACCMODE {
+        OpenFlags::O_RDONLY | OpenFlags::O_RDWR => Ok(()),
+        _ => Err(linuxerr!(EBADF)),
+    }
+}
+
+pub fn can_write(file: &FileDescriptorEntry) -> Result<(), Error> {
+    match file.oflags & OpenFlags::O_ACCMODE {
+        OpenFlags::O_WRONLY | OpenFlags::O_RDWR => Ok(()),
+        _ => Err(linuxerr!(EBADF)),
+    }
+}
+
+fn user_addr(buf: AddressSpace) -> Result<u64, Error> {
+    match buf {
+        AddressSpace::User(addr) => Ok(addr),
+        _ => Err(linuxerr!(EFAULT)),
+    }
+}
+
+pub async fn drain<R:Runtime>(t: &Task<R>, block: Vec<Command>) -> Result<(), Error> {
+    let mut st = t.process.kernel.runtime.execute(block)?;
+    while st.next().await?.is_some() {}
+    Ok(())
+}
+
+// the same, for a block that may wait on whatever is behind the entity. a
+// signal gets us out with EINTR, and nothing from the block is kept
+pub async fn drain_interruptible<R:Runtime>(t: &Task<R>, block: Vec<Command>) -> Result<(), Error> {
+    let mut st = t.execute_interruptible(block, Cancel::new()).map_err(interrupted)?;
+    while st.next().await.map_err(interrupted)?.is_some() {}
+    Ok(())
+}
+
+// what a read or write needs to know first. the size as stored, to compare
+// against later, and as a number, the extents entity if there is one, and
+// the times for deciding on atime
+pub struct Current {
+    pub stored: Value,
+    pub size: u64,
+    pub extents: Value,
+    pub times: Times,
+}
+
+pub async fn current<R:Runtime>(t: &Task<R>, obj: Oid) -> Result<Current, Error> {
+    let mut block = vec![
+        Command::Get(Value::Oid(obj), attribute!("size"), Value::Union(0)),
+        Command::Get(Value::Oid(obj), attribute!("type"), Value::Union(1)),
+        Command::Get(Value::Oid(obj), attribute!("extents"), Value::Union(2)),
+    ];
+    Times::get(&mut block, Value::Oid(obj), 3);
+    let row = t.process.kernel.runtime.execute(block)?.next().await?.ok_or(linuxerr!(EBADF))?;
+    if row[1] != Value::Empty() && FileType::from_value(&row[1])? == FileType::Directory {
+        return Err(linuxerr!(EISDIR));
+    }
+    let size = match &row[0] {
+        Value::Empty() => 0,
+        v => u64::from_value(v)?,
+    };
+    Ok(Current { stored: row[0].clone(), size, extents: row[2].clone(), times: Times::from_row(&row[3..])? })
+}
+
+// drops the extents that were there, see seek. nothing else points at them
+// so they go too
+pub fn forget_extents(obj: Oid, extents: &Value) -> Vec<Command> {
+    let mut block = vec![Command::CompareAndSet(Value::Oid(obj), attribute!("extents"), extents.clone(), Value::Empty())];
+    if let Value::Oid(_) = extents {
+        block.push(Command::Destroy(extents.clone()));
+    }
+    block
+}
+
+// the copies for count bytes spread over the segments in order, the last
+// one used may only be partly filled. copy makes the command for a piece of
+// memory at addr and the file at pos
+fn scatter(segments: &[IoVec], count: u64, mut copy: impl FnMut(u64, u64, u64) -> Command) -> Vec<Command> {
+    let mut out = Vec::new();
+    let mut done = 0;
+    for s in segments {
+        if done == count {
+            break;
+        }
+        let n = s.iov_len.min(count - done);
+        if n == 0 {
+            continue;
+        }
+        out.push(copy(s.iov_base, done, n));
+        done += n;
+    }
+    out
+}
+
+fn total(segments: &[IoVec]) -> u64 {
+    segments.iter().map(|s| s.iov_len).sum()
+}
+
+// reads up to the total of the segments from pos, fewer at the end of the
+// file, none past it. the size is checked again in the block doing the
+// copies, so a file that shrinks in between is read again rather than read
+// past
+pub async fn read_at<R:Runtime>(t: &Task<R>, file: &FileDescriptorEntry, segments: &[IoVec],
+                                pos: u64) -> Result<usize, Error> {
+    can_read(file)?;
+    loop {
+        let c = current(t, file.obj).await?;
+        let n = c.size.saturating_sub(pos).min(total(segments));
+        if n == 0 {
+            return Ok(0);
+        }
+        let mut block = vec![
+            Command::Get(Value::Oid(file.obj), attribute!("size"), Value::Union(0)),
+            Command::Guard(Value::Variable(0 as Variable), Predicate::Equal, c.stored),
+        ];
+        block.extend(accessed(t, file.obj, &c.times));
+        block.extend(scatter(segments, n, |addr, off, len| Command::Copy(
+            Value::Oid(file.obj), attribute!("contents"), Value::Unsigned(pos + off),
+            Value::Oid(t.process.myself), attribute!("vma"), Value::Unsigned(addr),
+            Value::Unsigned(len),
+        )));
+        match drain_interruptible(t, block).await {
+            Ok(()) => return Ok(n as usize),
+            Err(e) if e.is_guard_failure() => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// the most a file can hold, linux's MAX_LFS_FILESIZE
+pub const MAX_FILE_SIZE: u64 = i64::MAX as u64;
+
+// writes the segments at pos, or at the end of the file for None. the size
+// goes up in the same block with a compare and set, so two appends can't
+// land in the same place. anything that would go past MAX_FILE_SIZE is
+// left off, so it returns where it went and how much
+pub async fn write_at<R:Runtime>(t: &Task<R>, file: &FileDescriptorEntry, segments: &[IoVec],
+                                 pos: Option<u64>) -> Result<(u64, u64), Error> {
+    can_write(file)?;
+    loop {
+        let Current { stored, size, extents, .. } = current(t, file.obj).await?;
+        let at = pos.unwrap_or(size);
+        let count = total(segments).min(MAX_FILE_SIZE.saturating_sub(at));
+        if total(segments) == 0 {
+            return Ok((at, 0));
+        }
+        if count == 0 {
+            return Err(linuxerr!(EFBIG));
+        }
+        let end = at + count;
+        // whatever extents the file had may have just filled in a hole, and
+        // no extents is always right, see seek
+        let mut block = vec![
+            Command::CompareAndSet(Value::Oid(file.obj), attribute!("size"), stored, Value::Unsigned(size.max(end))),
+        ];
+        block.extend(forget_extents(file.obj, &extents));
+        block.extend(modified(Value::Oid(file.obj), now(t)));
+        block.extend(scatter(segments, count, |addr, off, len| Command::Copy(
+            Value::Oid(t.process.myself), attribute!("vma"), Value::Unsigned(addr),
+            Value::Oid(file.obj), attribute!("contents"), Value::Unsigned(at + off),
+            Value::Unsigned(len),
+        )));
+        match drain(t, block).await {
+            Ok(()) => return Ok((at, count)),
+            Err(e) if e.is_guard_failure() => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// a read from the file position, which only moves if nobody else moved it
+// while the read was out. if they did, it's read again from where it is now
+pub async fn read_from_pos<R:Runtime>(t: &Task<R>, fd: Fd, segments: &[IoVec]) -> Result<usize, Error> {
+    loop {
+        let file = t.process.get_fd(fd)?;
+        let n = read_at(t, &file, segments, file.pos).await?;
+        if t.process.move_pos(fd, file.pos, file.pos + n as u64)? {
+            return Ok(n);
+        }
+    }
+}
+
+// a write at the file position claims its range first, and gives back what
+// it didn't use if nobody has moved on past it since. appends go where the
+// end is, and leave the position after them
+pub async fn write_from_pos<R:Runtime>(t: &Task<R>, fd: Fd, segments: &[IoVec], append: bool) -> Result<usize, Error> {
     let file = t.process.get_fd(fd)?;
-    if let AddressSpace::User(addr) = user_buf {
-        let program = vec!(Command::Copy(
-            Address::Offset(
-                Box::new(Address::Entity(t.process.myself, attribute!("vma"))),
-                addr),
-            Address::Offset(
-                Box::new(Address::Entity(file.obj, attribute!("contents"))),
-                file.pos,
-            ),
-            count,
-            0
-        ));
-        t.process.kernel.runtime.execute(program);
+    if append {
+        let (at, n) = write_at(t, &file, segments, None).await?;
+        t.process.advance_pos(fd, at + n)?;
+        return Ok(n as usize);
     }
+    can_write(&file)?;
+    let count = total(segments);
+    let at = t.process.claim_pos(fd, count)?;
+    let claimed = at.saturating_add(count);
+    match write_at(t, &file, segments, Some(at)).await {
+        Ok((_, n)) => {
+            t.process.move_pos(fd, claimed, at + n)?;
+            Ok(n as usize)
+        }
+        Err(e) => {
+            let _ = t.process.move_pos(fd, claimed, at);
+            Err(e)
+        }
+    }
+}
+
+// a single buffer, as one segment
+fn one(buf: AddressSpace, count: usize) -> Result<[IoVec; 1], Error> {
+    Ok([IoVec { iov_base: user_addr(buf)?, iov_len: count as u64 }])
+}
+
+pub fn offset(offset: i64) -> Result<u64, Error> {
+    u64::try_from(offset).map_err(|_| linuxerr!(EINVAL))
+}
 
-    Ok(count)
+pub async fn sys_write<R:Runtime>(t: Task<R>, fd: Fd, user_buf: AddressSpace, count: usize) -> Result<usize, Error> {
+    let append = t.process.get_fd(fd)?.oflags.contains(OpenFlags::O_APPEND);
+    write_from_pos(&t, fd, &one(user_buf, count)?, append).await
 }
 
 pub async fn sys_read<R:Runtime>(t:Task<R>, fd: Fd, user_buf: AddressSpace, count: usize) -> Result<usize, Error> {
+    read_from_pos(&t, fd, &one(user_buf, count)?).await
+}
+
+pub async fn sys_pread64<R:Runtime>(t:Task<R>, fd: Fd, user_buf: AddressSpace, count: usize, pos: i64) -> Result<usize, Error> {
     let file = t.process.get_fd(fd)?;
+    read_at(&t, &file, &one(user_buf, count)?, offset(pos)?).await
+}
 
-    // translate user_buf to 'physical'
-    let mut block = Vec::new();
-    if let AddressSpace::User(addr) = user_buf {
-        block.push(Command::Copy(
-            Address::Offset(
-                Box::new(Address::Entity(file.obj, attribute!("contents"))),
-                file.pos),
-            Address::Offset(
-                Box::new(Address::Entity(t.process.myself, attribute!("vma"))),
-                addr as u64),
-            count,
-            0,
-        ));
-        // update pos
-        // partial reads?
-        Ok(count)
+// like linux, and unlike posix, O_APPEND wins over the offset
+pub async fn sys_pwrite64<R:Runtime>(t:Task<R>, fd: Fd, user_buf: AddressSpace, count: usize, pos: i64) -> Result<usize, Error> {
+    let file = t.process.get_fd(fd)?;
+    let pos = offset(pos)?;
+    let pos = if file.oflags.contains(OpenFlags::O_APPEND) {
+        None
     } else {
-        Err(linuxerr!(EFAULT))
-    }
+        Some(pos)
+    };
+    let (_, n) = write_at(&t, &file, &one(user_buf, count)?, pos).await?;
+    Ok(n as usize)
 }
@@ -1,27 +1,117 @@
-use crate::{
-    linux::Fd,
-    current_task,
-    error::{KernelError},
-    linux::SeekFrom,
-};
-
-const SEEK_SET: i32 = 0;
-const SEEK_CUR: i32 = 1;
-const SEEK_END: i32 = 2;
-
-pub async fn sys_lseek(fd: Fd, offset: isize, whence: i32) -> Result<usize, KernelError> {
-    let seek_from = match whence {
-        SEEK_SET => SeekFrom::Start(offset as _),
-        SEEK_CUR => SeekFrom::Current(offset as _),
-        SEEK_END => SeekFrom::End(offset as _),
-        _ => return Err(KernelError::InvalidValue),
+use alloc::{vec, vec::Vec};
+use crate::{Fd, FileType, Runtime, SeekFrom, Task, linuxerr};
+use protocol::{Command, Error, FromValue, Oid, Value, attribute};
+
+const SEEK_SET: u32 = 0;
+const SEEK_CUR: u32 = 1;
+const SEEK_END: u32 = 2;
+const SEEK_DATA: u32 = 3;
+const SEEK_HOLE: u32 = 4;
+
+// where a sparse file actually has something. the extents attribute of a
+// file is an entity from the start of each extent to its length, both
+// unsigned, filled in by whoever serves the file. without one the whole
+// file is data, which is what linux does for filesystems that don't know
+// better, and what a write from here falls back to since it drops the
+// attribute rather than keep it up to date
+pub async fn extents<R:Runtime>(t: &Task<R>, extents: Oid) -> Result<Vec<(u64, u64)>, Error> {
+    let block = vec![Command::Get(Value::Oid(extents), Value::Variable(0), Value::Variable(1))];
+    let mut st = t.process.kernel.runtime.execute(block)?;
+    let mut out = Vec::new();
+    while let Some(row) = st.next().await? {
+        out.push((u64::from_value(&row[0])?, u64::from_value(&row[1])?));
+    }
+    out.sort();
+    Ok(out)
+}
+
+// the first data at or after offset, the file is known to be longer
+fn data(extents: &[(u64, u64)], offset: u64) -> Option<u64> {
+    extents.iter()
+        .find(|(start, length)| start + length > offset)
+        .map(|(start, _)| offset.max(*start))
+}
+
+// the first hole at or after offset. there is always one at the end
+fn hole(extents: &[(u64, u64)], offset: u64, size: u64) -> u64 {
+    let mut at = offset;
+    for (start, length) in extents {
+        if *start > at {
+            break;
+        }
+        at = at.max(start + length);
+    }
+    at.min(size)
+}
+
+// the whences that move relative to something, and the two that look for
+// something from an offset
+enum Target {
+    From(SeekFrom),
+    Data(i64),
+    Hole(i64),
+}
+
+pub async fn sys_lseek<R:Runtime>(t: Task<R>, fd: Fd, offset: i64, whence: u32) -> Result<usize, Error> {
+    let file = t.process.get_fd(fd)?;
+    let target = match whence {
+        SEEK_SET => Target::From(SeekFrom::Start(u64::try_from(offset).map_err(|_| linuxerr!(EINVAL))?)),
+        SEEK_CUR => Target::From(SeekFrom::Current(offset as _)),
+        SEEK_END => Target::From(SeekFrom::End(offset as _)),
+        SEEK_DATA => Target::Data(offset),
+        SEEK_HOLE => Target::Hole(offset),
+        _ => return Err(linuxerr!(EINVAL)),
     };
 
-    let fd = current_task()
-        .fd_table
-        .lock_save_irq()
-        .get(fd)
-        .ok_or(KernelError::BadFd)?;
+    let block = vec![
+        Command::Get(Value::Oid(file.obj), attribute!("type"), Value::Union(0)),
+        Command::Get(Value::Oid(file.obj), attribute!("size"), Value::Union(1)),
+        Command::Get(Value::Oid(file.obj), attribute!("extents"), Value::Union(2)),
+    ];
+    let row = t.process.kernel.runtime.execute(block)?.next().await?.ok_or(linuxerr!(EBADF))?;
+    let file_type = match &row[0] {
+        Value::Empty() => FileType::File,
+        v => FileType::from_value(v)?,
+    };
+    let size = match &row[1] {
+        Value::Empty() => 0,
+        v => u64::from_value(v)?,
+    };
+    match file_type {
+        FileType::Fifo | FileType::Socket => return Err(linuxerr!(ESPIPE)),
+        // positions in a directory are the cookies getdents hands out, there
+        // is no end to seek from
+        FileType::Directory if !matches!(target, Target::From(SeekFrom::Start(_) | SeekFrom::Current(_))) => {
+            return Err(linuxerr!(EINVAL));
+        }
+        _ => {}
+    }
 
-    Ok(offset)
+    let to = |base: u64, offset: i64| {
+        base.checked_add_signed(offset).filter(|p| *p <= i64::MAX as u64).ok_or(linuxerr!(EINVAL))
+    };
+    let pos = match target {
+        Target::From(SeekFrom::Start(p)) => p,
+        Target::From(SeekFrom::Current(o)) => to(file.pos, o)?,
+        Target::From(SeekFrom::End(o)) => to(size, o)?,
+        // offset is where to start looking from, past the end there is
+        // neither data nor a hole
+        Target::Data(o) | Target::Hole(o) => {
+            let offset = u64::try_from(o).map_err(|_| linuxerr!(ENXIO))?;
+            if offset >= size {
+                return Err(linuxerr!(ENXIO));
+            }
+            let extents = match &row[2] {
+                Value::Empty() => vec![(0, size)],
+                v => extents(&t, Oid::from_value(v)?).await?,
+            };
+            if let Target::Data(_) = target {
+                data(&extents, offset).filter(|p| *p < size).ok_or(linuxerr!(ENXIO))?
+            } else {
+                hole(&extents, offset, size)
+            }
+        }
+    };
+    t.process.set_pos(fd, pos)?;
+    Ok(pos as usize)
 }
@@ -1,30 +1,67 @@
-use super::at::stat::Stat;
-use crate::memory::uaccess::copy_to_user;
-use crate::{linux::Fd, current_task};
-use crate::{error::KernelError, memory::address::TUA};
-
+use core::time::Duration;
 use crate::{
-    KernelError,
-    linux::{FileType, attr::FileAttr, path::Path},
-    memory::address::TUA,
-    memory::uaccess::{UserCopyable, cstr::UserCStr},
-    linux::Fd,
+    AddressSpace, AtFlags, Fd, FileType, Gid, Lockable, PATH_MAX, Runtime, Task, Uid,
+    copy_in_cstr, copy_out, linuxerr, load,
+    attr::FileAttr,
+    idmap::OVERFLOW_ID,
+    namei::{resolve, start},
 };
-use core::ffi::c_char;
+use protocol::{Error, Oid};
+
+// there is no real block device under any of this, these are just what
+// userspace expects to see
+const BLOCK_SIZE: u32 = 4096;
+
+// inode numbers and devices come out of the Oid, the low half is the inode
+// and the high half the device. the same object always gets the same pair,
+// and no two objects share one
+pub fn ino(oid: Oid) -> u64 {
+    oid.0 as u64
+}
+
+pub fn dev(oid: Oid) -> u64 {
+    (oid.0 >> 64) as u64
+}
+
+fn type_bits(t: FileType) -> u32 {
+    match t {
+        FileType::Directory => 0o040000,
+        FileType::CharDevice(_) => 0o020000,
+        FileType::BlockDevice(_) => 0o060000,
+        FileType::File => 0o100000,
+        FileType::Fifo => 0o010000,
+        FileType::Symlink => 0o120000,
+        FileType::Socket => 0o140000,
+    }
+}
+
+fn rdev(t: FileType) -> (u32, u32) {
+    match t {
+        FileType::CharDevice(d) | FileType::BlockDevice(d) => (d.major as u32, d.minor as u32),
+        _ => (0, 0),
+    }
+}
 
+// new_encode_dev, so glibc's major() and minor() take it apart again
+fn encode_dev((major, minor): (u32, u32)) -> u64 {
+    (minor as u64 & 0xff) | ((major as u64) << 8) | ((minor as u64 & !0xff) << 12)
+}
 
-bitflags::bitflags! {
-    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
-    pub struct AtFlags: i32 {
-        const AT_SYMLINK_NOFOLLOW = 0x100; // Do not follow symbolic links.
-        const AT_EACCESS = 0x200;          // Check effective IDs in faccessat*.
-        const AT_NO_AUTOMOUNT = 0x800;     // Suppress terminal automount traversal.
-        const AT_EMPTY_PATH = 0x1000;      // Allow empty relative pathname.
+fn size(a: &FileAttr) -> u64 {
+    match (a.size, a.file_type()) {
+        (Some(size), _) => size,
+        (None, FileType::Directory) => BLOCK_SIZE as u64,
+        (None, _) => 0,
     }
 }
 
+fn blocks(size: u64) -> u64 {
+    size.div_ceil(512)
+}
+
+// arm64 uses the generic struct stat, include/uapi/asm-generic/stat.h
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Stat {
     pub st_dev: u64,        // Device
     pub st_ino: u64,        // File serial number
@@ -48,70 +85,188 @@ pub struct Stat {
     pub __unused5: u32,     // Unused
 }
 
-unsafe impl UserCopyable for Stat {}
+impl Stat {
+    fn new(oid: Oid, value: &FileAttr) -> Self {
+        let time = |t: Option<Duration>| t.unwrap_or_default();
+        let size = size(value);
+        Self {
+            st_dev: dev(oid),
+            st_ino: ino(oid),
+            st_mode: value.mode().bits() as u32 | type_bits(value.file_type()),
+            st_nlink: value.nlinks(),
+            st_uid: value.uid().into(),
+            st_gid: value.gid().into(),
+            st_rdev: encode_dev(rdev(value.file_type())),
+            st_size: size as _,
+            st_blksize: BLOCK_SIZE as _,
+            st_blocks: blocks(size) as _,
+            st_atime: time(value.atime).as_secs() as _,
+            st_atime_nsec: time(value.atime).subsec_nanos() as _,
+            st_mtime: time(value.mtime).as_secs() as _,
+            st_mtime_nsec: time(value.mtime).subsec_nanos() as _,
+            st_ctime: time(value.ctime).as_secs() as _,
+            st_ctime_nsec: time(value.ctime).subsec_nanos() as _,
+            ..Default::default()
+        }
+    }
+}
 
-impl From<FileAttr> for Stat {
-    fn from(value: FileAttr) -> Self {
-        let type_val = match value.file_type {
-            FileType::Directory => 0o040000,
-            FileType::CharDevice(_) => 0o020000,
-            FileType::BlockDevice(_) => 0o060000,
-            FileType::File => 0o100000,
-            FileType::Fifo => 0o010000,
-            FileType::Symlink => 0o120000,
-            FileType::Socket => 0o140000,
-        };
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StatxTimestamp {
+    pub tv_sec: i64,
+    pub tv_nsec: u32,
+    pub __reserved: i32,
+}
+
+impl From<Duration> for StatxTimestamp {
+    fn from(d: Duration) -> Self {
+        StatxTimestamp { tv_sec: d.as_secs() as _, tv_nsec: d.subsec_nanos(), __reserved: 0 }
+    }
+}
 
+// include/uapi/linux/stat.h, 256 bytes whatever the kernel version
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Statx {
+    pub stx_mask: u32,
+    pub stx_blksize: u32,
+    pub stx_attributes: u64,
+    pub stx_nlink: u32,
+    pub stx_uid: u32,
+    pub stx_gid: u32,
+    pub stx_mode: u16,
+    pub __spare0: u16,
+    pub stx_ino: u64,
+    pub stx_size: u64,
+    pub stx_blocks: u64,
+    pub stx_attributes_mask: u64,
+    pub stx_atime: StatxTimestamp,
+    pub stx_btime: StatxTimestamp,
+    pub stx_ctime: StatxTimestamp,
+    pub stx_mtime: StatxTimestamp,
+    pub stx_rdev_major: u32,
+    pub stx_rdev_minor: u32,
+    pub stx_dev_major: u32,
+    pub stx_dev_minor: u32,
+    pub __spare2: [u64; 14],
+}
+
+pub const STATX_TYPE: u32 = 0x1;
+pub const STATX_MODE: u32 = 0x2;
+pub const STATX_NLINK: u32 = 0x4;
+pub const STATX_UID: u32 = 0x8;
+pub const STATX_GID: u32 = 0x10;
+pub const STATX_ATIME: u32 = 0x20;
+pub const STATX_MTIME: u32 = 0x40;
+pub const STATX_CTIME: u32 = 0x80;
+pub const STATX_INO: u32 = 0x100;
+pub const STATX_SIZE: u32 = 0x200;
+pub const STATX_BLOCKS: u32 = 0x400;
+// the reserved bit, which must be clear
+const STATX__RESERVED: u32 = 0x80000000;
+
+impl Statx {
+    // everything we know goes in whatever was asked for, which is allowed,
+    // but the mask only claims the times we actually have
+    fn new(oid: Oid, value: &FileAttr) -> Self {
+        let mut mask = STATX_TYPE | STATX_MODE | STATX_NLINK | STATX_UID | STATX_GID
+            | STATX_INO | STATX_SIZE | STATX_BLOCKS;
+        let mut time = |t: Option<Duration>, bit: u32| match t {
+            Some(t) => {
+                mask |= bit;
+                t.into()
+            }
+            None => StatxTimestamp::default(),
+        };
+        let stx_atime = time(value.atime, STATX_ATIME);
+        let stx_mtime = time(value.mtime, STATX_MTIME);
+        let stx_ctime = time(value.ctime, STATX_CTIME);
+        let size = size(value);
+        let (rdev_major, rdev_minor) = rdev(value.file_type());
+        let dev = dev(oid);
         Self {
-            st_dev: value.id,
-            st_ino: value.id,
-            st_mode: value.mode.bits() as u32 | type_val,
-            st_nlink: value.nlinks,
-            st_uid: value.uid.into(),
-            st_gid: value.gid.into(),
-            st_rdev: 0,
-            __pad1: 0,
-            st_size: value.size as _,
-            st_blksize: value.block_size as _,
-            __pad2: 0,
-            st_blocks: 0,
-            st_atime: value.atime.as_secs() as _,
-            st_atime_nsec: value.atime.subsec_nanos() as _,
-            st_mtime: value.mtime.as_secs() as _,
-            st_mtime_nsec: value.mtime.subsec_nanos() as _,
-            st_ctime: value.ctime.as_secs() as _,
-            st_ctime_nsec: value.ctime.subsec_nanos() as _,
-            __unused4: 0,
-            __unused5: 0,
+            stx_mask: mask,
+            stx_blksize: BLOCK_SIZE,
+            stx_nlink: value.nlinks(),
+            stx_uid: value.uid().into(),
+            stx_gid: value.gid().into(),
+            stx_mode: (value.mode().bits() as u32 | type_bits(value.file_type())) as u16,
+            stx_ino: ino(oid),
+            stx_size: size,
+            stx_blocks: blocks(size),
+            stx_atime,
+            stx_ctime,
+            stx_mtime,
+            stx_rdev_major: rdev_major,
+            stx_rdev_minor: rdev_minor,
+            stx_dev_major: (dev >> 32) as u32,
+            stx_dev_minor: dev as u32,
+            ..Default::default()
         }
     }
 }
 
-pub async fn sys_newfstatat(
-    _dirfd: Fd,
-    path: TUA<c_char>,
-    _statbuf: TUA<Stat>,
-    flags: i32,
-) -> Result<usize, KernelError> {
-    let mut buf = [0; 1024];
-
-    let _flags = AtFlags::from_bits_truncate(flags);
-    let _path = Path::new(UserCStr::from_ptr(path).copy_from_user(&mut buf).await?);
-    Ok(0)
+// both are repr(C) made of integers with no holes, so the bytes are the
+// layout userspace expects
+fn bytes<T: Copy>(x: &T) -> &[u8] {
+    unsafe { core::slice::from_raw_parts(x as *const T as *const u8, core::mem::size_of::<T>()) }
 }
 
-pub async fn sys_fstat(fd: Fd, statbuf: TUA<Stat>) -> Result<usize, KernelError> {
-    let fd = current_task()
-        .fd_table
-        .lock_save_irq()
-        .get(fd)
-        .ok_or(KernelError::BadFd)?;
+// what a stat call with this path and flags is about, and its attributes
+async fn lookup<R:Runtime>(t: &Task<R>, dirfd: Fd, path: AddressSpace, flags: AtFlags) -> Result<(Oid, FileAttr), Error> {
+    let path = copy_in_cstr(&t.process.kernel.runtime, path, PATH_MAX - 1)?;
+    let oid = if path.is_empty() && flags.contains(AtFlags::AT_EMPTY_PATH) {
+        // the dirfd itself, or the cwd
+        start(t, dirfd, &path)?
+    } else {
+        let r = resolve(t, dirfd, &path, !flags.contains(AtFlags::AT_SYMLINK_NOFOLLOW)).await?;
+        r.oid.ok_or(linuxerr!(ENOENT))?
+    };
+    Ok((oid, seen(t, load(&t.process.kernel.runtime, oid).await?)))
+}
 
-    let inode = fd.inode().ok_or(KernelError::BadFd)?;
+// the owner and group as the process knows them, whoever it can't see
+// shows up as the overflow id like in a user namespace
+fn seen<R:Runtime>(t: &Task<R>, mut attr: FileAttr) -> FileAttr {
+    let creds = t.process.creds.lock();
+    attr.uid = Some(creds.guest_uid(attr.uid()).unwrap_or(Uid::new(OVERFLOW_ID)));
+    attr.gid = Some(creds.guest_gid(attr.gid()).unwrap_or(Gid::new(OVERFLOW_ID)));
+    attr
+}
 
-    let attr = inode.getattr().await?;
+pub async fn sys_fstat<R:Runtime>(t: Task<R>, fd: Fd, statbuf: AddressSpace) -> Result<usize, Error> {
+    let oid = t.process.get_fd(fd)?.obj;
+    let attr = seen(&t, load(&t.process.kernel.runtime, oid).await?);
+    copy_out(&t.process.kernel.runtime, statbuf, bytes(&Stat::new(oid, &attr)))?;
+    Ok(0)
+}
 
-    copy_to_user(statbuf, attr.into()).await?;
+pub async fn sys_newfstatat<R:Runtime>(
+    t: Task<R>,
+    dirfd: Fd,
+    path: AddressSpace,
+    statbuf: AddressSpace,
+    flags: AtFlags,
+) -> Result<usize, Error> {
+    let (oid, attr) = lookup(&t, dirfd, path, flags).await?;
+    copy_out(&t.process.kernel.runtime, statbuf, bytes(&Stat::new(oid, &attr)))?;
+    Ok(0)
+}
 
+pub async fn sys_statx<R:Runtime>(
+    t: Task<R>,
+    dirfd: Fd,
+    path: AddressSpace,
+    flags: AtFlags,
+    mask: u32,
+    statxbuf: AddressSpace,
+) -> Result<usize, Error> {
+    if mask & STATX__RESERVED != 0
+        || flags.contains(AtFlags::AT_STATX_FORCE_SYNC | AtFlags::AT_STATX_DONT_SYNC) {
+        return Err(linuxerr!(EINVAL));
+    }
+    let (oid, attr) = lookup(&t, dirfd, path, flags).await?;
+    copy_out(&t.process.kernel.runtime, statxbuf, bytes(&Statx::new(oid, &attr)))?;
     Ok(0)
 }
@@ -0,0 +1,351 @@
+use alloc::boxed::Box;
+use async_trait::async_trait;
+use core::convert::Infallible;
+use core::sync::atomic::{AtomicU64, Ordering};
+use protocol::Error;
+use crate::{
+    AddressSpace, AtFlags, Fd, LinuxError, Pgid, Pid, Runtime, Syscall, Task, linuxerr,
+    access::{sys_faccessat, sys_faccessat2},
+    chdir::{sys_chdir, sys_chroot, sys_fchdir, sys_getcwd},
+    close::sys_close,
+    creds::{
+        sys_getegid, sys_geteuid, sys_getgid, sys_getgroups, sys_getresgid, sys_getresuid, sys_gettid,
+        sys_getuid, sys_setfsgid, sys_setfsuid, sys_setgid, sys_setgroups, sys_setregid, sys_setresgid,
+        sys_setresuid, sys_setreuid, sys_setuid,
+    },
+    dir::sys_getdents64,
+    exec::sys_execve,
+    exit::{sys_exit, sys_exit_group},
+    fallocate::sys_fallocate,
+    open::sys_openat,
+    readlink::{sys_readlinkat, sys_symlinkat},
+    link::sys_linkat,
+    mkdir::sys_mkdirat,
+    rename::{RenameFlags, sys_renameat, sys_renameat2},
+    unlink::sys_unlinkat,
+    iov::{sys_preadv, sys_preadv2, sys_pwritev, sys_pwritev2, sys_readv, sys_writev},
+    pid::{sys_getpgid, sys_getpid, sys_getppid, sys_setpgid},
+    process::sys_set_robust_list,
+    rsrc_lim::sys_prlimit64,
+    seek::sys_lseek,
+    rw::{sys_pread64, sys_pwrite64, sys_read, sys_write},
+    stat::{sys_fstat, sys_newfstatat, sys_statx},
+    truncate::{sys_ftruncate, sys_truncate},
+    utimensat::sys_utimensat,
+    xattr::{
+        XattrFlags, sys_fgetxattr, sys_flistxattr, sys_fremovexattr, sys_fsetxattr, sys_getxattr,
+        sys_lgetxattr, sys_listxattr, sys_llistxattr, sys_lremovexattr, sys_lsetxattr, sys_removexattr,
+        sys_setxattr,
+    },
+};
+
+// aarch64 uses the generic table, include/uapi/asm-generic/unistd.h
+pub const SYS_SETXATTR: u32 = 5;
+pub const SYS_LSETXATTR: u32 = 6;
+pub const SYS_FSETXATTR: u32 = 7;
+pub const SYS_GETXATTR: u32 = 8;
+pub const SYS_LGETXATTR: u32 = 9;
+pub const SYS_FGETXATTR: u32 = 10;
+pub const SYS_LISTXATTR: u32 = 11;
+pub const SYS_LLISTXATTR: u32 = 12;
+pub const SYS_FLISTXATTR: u32 = 13;
+pub const SYS_REMOVEXATTR: u32 = 14;
+pub const SYS_LREMOVEXATTR: u32 = 15;
+pub const SYS_FREMOVEXATTR: u32 = 16;
+pub const SYS_GETCWD: u32 = 17;
+pub const SYS_MKDIRAT: u32 = 34;
+pub const SYS_UNLINKAT: u32 = 35;
+pub const SYS_SYMLINKAT: u32 = 36;
+pub const SYS_LINKAT: u32 = 37;
+pub const SYS_RENAMEAT: u32 = 38;
+pub const SYS_TRUNCATE: u32 = 45;
+pub const SYS_FTRUNCATE: u32 = 46;
+pub const SYS_FALLOCATE: u32 = 47;
+pub const SYS_FACCESSAT: u32 = 48;
+pub const SYS_CHDIR: u32 = 49;
+pub const SYS_FCHDIR: u32 = 50;
+pub const SYS_CHROOT: u32 = 51;
+pub const SYS_OPENAT: u32 = 56;
+pub const SYS_CLOSE: u32 = 57;
+pub const SYS_GETDENTS64: u32 = 61;
+pub const SYS_LSEEK: u32 = 62;
+pub const SYS_READ: u32 = 63;
+pub const SYS_WRITE: u32 = 64;
+pub const SYS_READV: u32 = 65;
+pub const SYS_WRITEV: u32 = 66;
+pub const SYS_PREAD64: u32 = 67;
+pub const SYS_PWRITE64: u32 = 68;
+pub const SYS_PREADV: u32 = 69;
+pub const SYS_PWRITEV: u32 = 70;
+pub const SYS_READLINKAT: u32 = 78;
+pub const SYS_NEWFSTATAT: u32 = 79;
+pub const SYS_FSTAT: u32 = 80;
+pub const SYS_UTIMENSAT: u32 = 88;
+pub const SYS_EXIT: u32 = 93;
+pub const SYS_EXIT_GROUP: u32 = 94;
+pub const SYS_SET_ROBUST_LIST: u32 = 99;
+pub const SYS_SETREGID: u32 = 143;
+pub const SYS_SETGID: u32 = 144;
+pub const SYS_SETREUID: u32 = 145;
+pub const SYS_SETUID: u32 = 146;
+pub const SYS_SETRESUID: u32 = 147;
+pub const SYS_GETRESUID: u32 = 148;
+pub const SYS_SETRESGID: u32 = 149;
+pub const SYS_GETRESGID: u32 = 150;
+pub const SYS_SETFSUID: u32 = 151;
+pub const SYS_SETFSGID: u32 = 152;
+pub const SYS_SETPGID: u32 = 154;
+pub const SYS_GETPGID: u32 = 155;
+pub const SYS_GETGROUPS: u32 = 158;
+pub const SYS_SETGROUPS: u32 = 159;
+pub const SYS_GETPID: u32 = 172;
+pub const SYS_GETPPID: u32 = 173;
+pub const SYS_GETUID: u32 = 174;
+pub const SYS_GETEUID: u32 = 175;
+pub const SYS_GETGID: u32 = 176;
+pub const SYS_GETEGID: u32 = 177;
+pub const SYS_GETTID: u32 = 178;
+pub const SYS_EXECVE: u32 = 221;
+pub const SYS_PRLIMIT64: u32 = 261;
+pub const SYS_RENAMEAT2: u32 = 276;
+pub const SYS_PREADV2: u32 = 286;
+pub const SYS_PWRITEV2: u32 = 287;
+pub const SYS_STATX: u32 = 291;
+pub const SYS_FACCESSAT2: u32 = 439;
+
+// everything we answer, in number order. the counters are kept in the same
+// order. add to both the table and dispatch
+pub const SYSCALLS: [(u32, &str); 72] = [
+    (SYS_SETXATTR, "setxattr"),
+    (SYS_LSETXATTR, "lsetxattr"),
+    (SYS_FSETXATTR, "fsetxattr"),
+    (SYS_GETXATTR, "getxattr"),
+    (SYS_LGETXATTR, "lgetxattr"),
+    (SYS_FGETXATTR, "fgetxattr"),
+    (SYS_LISTXATTR, "listxattr"),
+    (SYS_LLISTXATTR, "llistxattr"),
+    (SYS_FLISTXATTR, "flistxattr"),
+    (SYS_REMOVEXATTR, "removexattr"),
+    (SYS_LREMOVEXATTR, "lremovexattr"),
+    (SYS_FREMOVEXATTR, "fremovexattr"),
+    (SYS_GETCWD, "getcwd"),
+    (SYS_MKDIRAT, "mkdirat"),
+    (SYS_UNLINKAT, "unlinkat"),
+    (SYS_SYMLINKAT, "symlinkat"),
+    (SYS_LINKAT, "linkat"),
+    (SYS_RENAMEAT, "renameat"),
+    (SYS_TRUNCATE, "truncate"),
+    (SYS_FTRUNCATE, "ftruncate"),
+    (SYS_FALLOCATE, "fallocate"),
+    (SYS_FACCESSAT, "faccessat"),
+    (SYS_CHDIR, "chdir"),
+    (SYS_FCHDIR, "fchdir"),
+    (SYS_CHROOT, "chroot"),
+    (SYS_OPENAT, "openat"),
+    (SYS_CLOSE, "close"),
+    (SYS_GETDENTS64, "getdents64"),
+    (SYS_LSEEK, "lseek"),
+    (SYS_READ, "read"),
+    (SYS_WRITE, "write"),
+    (SYS_READV, "readv"),
+    (SYS_WRITEV, "writev"),
+    (SYS_PREAD64, "pread64"),
+    (SYS_PWRITE64, "pwrite64"),
+    (SYS_PREADV, "preadv"),
+    (SYS_PWRITEV, "pwritev"),
+    (SYS_READLINKAT, "readlinkat"),
+    (SYS_NEWFSTATAT, "newfstatat"),
+    (SYS_FSTAT, "fstat"),
+    (SYS_UTIMENSAT, "utimensat"),
+    (SYS_EXIT, "exit"),
+    (SYS_EXIT_GROUP, "exit_group"),
+    (SYS_SET_ROBUST_LIST, "set_robust_list"),
+    (SYS_SETREGID, "setregid"),
+    (SYS_SETGID, "setgid"),
+    (SYS_SETREUID, "setreuid"),
+    (SYS_SETUID, "setuid"),
+    (SYS_SETRESUID, "setresuid"),
+    (SYS_GETRESUID, "getresuid"),
+    (SYS_SETRESGID, "setresgid"),
+    (SYS_GETRESGID, "getresgid"),
+    (SYS_SETFSUID, "setfsuid"),
+    (SYS_SETFSGID, "setfsgid"),
+    (SYS_SETPGID, "setpgid"),
+    (SYS_GETPGID, "getpgid"),
+    (SYS_GETGROUPS, "getgroups"),
+    (SYS_SETGROUPS, "setgroups"),
+    (SYS_GETPID, "getpid"),
+    (SYS_GETPPID, "getppid"),
+    (SYS_GETUID, "getuid"),
+    (SYS_GETEUID, "geteuid"),
+    (SYS_GETGID, "getgid"),
+    (SYS_GETEGID, "getegid"),
+    (SYS_GETTID, "gettid"),
+    (SYS_EXECVE, "execve"),
+    (SYS_PRLIMIT64, "prlimit64"),
+    (SYS_RENAMEAT2, "renameat2"),
+    (SYS_PREADV2, "preadv2"),
+    (SYS_PWRITEV2, "pwritev2"),
+    (SYS_STATX, "statx"),
+    (SYS_FACCESSAT2, "faccessat2"),
+];
+
+// argument decoding. registers come in as u64 whatever the C type was, so
+// narrower arguments are truncated the same way the kernel does
+fn fd(x: u64) -> Fd {
+    Fd::from(x)
+}
+
+fn user(x: u64) -> AddressSpace {
+    AddressSpace::User(x)
+}
+
+fn pid(x: u64) -> Pid {
+    Pid(x as u32)
+}
+
+// unknown bits are refused rather than dropped, so a program that depends
+// on a flag we don't implement finds out
+pub fn flags<F: bitflags::Flags<Bits = u32>>(x: u64) -> Result<F, Error> {
+    F::from_bits(x as u32).ok_or(linuxerr!(EINVAL))
+}
+
+fn infallible(r: Result<usize, Infallible>) -> Result<usize, Error> {
+    r.map_err(|e| match e {})
+}
+
+// what goes back in x0. anything that failed without an errno
+// is an EIO as far as the application is concerned
+pub fn to_x0(r: Result<usize, Error>) -> u64 {
+    match r {
+        Ok(n) => n as u64,
+        Err(e) => {
+            let errno = e.syserr.unwrap_or(LinuxError::EIO as u8);
+            (-(errno as i64)) as u64
+        }
+    }
+}
+
+// one per task, handed to Runtime::create_thread
+pub struct Dispatch<R:Runtime> {
+    task: Task<R>,
+    counts: [AtomicU64; SYSCALLS.len()],
+    unknown: AtomicU64,
+}
+
+impl<R:Runtime> Dispatch<R> {
+    pub fn new(task: Task<R>) -> Self {
+        Dispatch {
+            task,
+            counts: [const { AtomicU64::new(0) }; SYSCALLS.len()],
+            unknown: AtomicU64::new(0),
+        }
+    }
+
+    // (number, name, calls) for everything in the table
+    pub fn counts(&self) -> impl Iterator<Item = (u32, &'static str, u64)> + '_ {
+        SYSCALLS.iter().zip(self.counts.iter())
+            .map(|((num, name), c)| (*num, *name, c.load(Ordering::Relaxed)))
+    }
+
+    // calls that came back ENOSYS
+    pub fn unknown(&self) -> u64 {
+        self.unknown.load(Ordering::Relaxed)
+    }
+
+    fn count(&self, num: u32) {
+        match SYSCALLS.binary_search_by_key(&num, |(n, _)| *n) {
+            Ok(i) => self.counts[i].fetch_add(1, Ordering::Relaxed),
+            Err(_) => self.unknown.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    pub async fn dispatch(&self, num: u32, a: [u64; 6]) -> Result<usize, Error> {
+        self.count(num);
+        let t = self.task.clone();
+        match num {
+            SYS_SETXATTR => sys_setxattr(t, user(a[0]), user(a[1]), user(a[2]), a[3] as usize, flags::<XattrFlags>(a[4])?).await,
+            SYS_LSETXATTR => sys_lsetxattr(t, user(a[0]), user(a[1]), user(a[2]), a[3] as usize, flags::<XattrFlags>(a[4])?).await,
+            SYS_FSETXATTR => sys_fsetxattr(t, fd(a[0]), user(a[1]), user(a[2]), a[3] as usize, flags::<XattrFlags>(a[4])?).await,
+            SYS_GETXATTR => sys_getxattr(t, user(a[0]), user(a[1]), user(a[2]), a[3] as usize).await,
+            SYS_LGETXATTR => sys_lgetxattr(t, user(a[0]), user(a[1]), user(a[2]), a[3] as usize).await,
+            SYS_FGETXATTR => sys_fgetxattr(t, fd(a[0]), user(a[1]), user(a[2]), a[3] as usize).await,
+            SYS_LISTXATTR => sys_listxattr(t, user(a[0]), user(a[1]), a[2] as usize).await,
+            SYS_LLISTXATTR => sys_llistxattr(t, user(a[0]), user(a[1]), a[2] as usize).await,
+            SYS_FLISTXATTR => sys_flistxattr(t, fd(a[0]), user(a[1]), a[2] as usize).await,
+            SYS_REMOVEXATTR => sys_removexattr(t, user(a[0]), user(a[1])).await,
+            SYS_LREMOVEXATTR => sys_lremovexattr(t, user(a[0]), user(a[1])).await,
+            SYS_FREMOVEXATTR => sys_fremovexattr(t, fd(a[0]), user(a[1])).await,
+            SYS_MKDIRAT => sys_mkdirat(t, fd(a[0]), user(a[1]), a[2] as u32).await,
+            SYS_UNLINKAT => sys_unlinkat(t, fd(a[0]), user(a[1]), flags::<AtFlags>(a[2])?).await,
+            SYS_LINKAT => sys_linkat(t, fd(a[0]), user(a[1]), fd(a[2]), user(a[3]), flags::<AtFlags>(a[4])?).await,
+            SYS_RENAMEAT => sys_renameat(t, fd(a[0]), user(a[1]), fd(a[2]), user(a[3])).await,
+            SYS_RENAMEAT2 => sys_renameat2(t, fd(a[0]), user(a[1]), fd(a[2]), user(a[3]), flags::<RenameFlags>(a[4])?).await,
+            SYS_TRUNCATE => sys_truncate(t, user(a[0]), a[1] as i64).await,
+            SYS_FTRUNCATE => sys_ftruncate(t, fd(a[0]), a[1] as i64).await,
+            SYS_FALLOCATE => sys_fallocate(t, fd(a[0]), a[1] as u32, a[2] as i64, a[3] as i64).await,
+            SYS_GETCWD => sys_getcwd(t, user(a[0]), a[1] as usize).await,
+            SYS_CHDIR => sys_chdir(t, user(a[0])).await,
+            SYS_FCHDIR => sys_fchdir(t, fd(a[0])).await,
+            SYS_CHROOT => sys_chroot(t, user(a[0])).await,
+            SYS_FACCESSAT => sys_faccessat(t, fd(a[0]), user(a[1]), a[2] as u32).await,
+            SYS_FACCESSAT2 => sys_faccessat2(t, fd(a[0]), user(a[1]), a[2] as u32, flags::<AtFlags>(a[3])?).await,
+            SYS_SYMLINKAT => sys_symlinkat(t, user(a[0]), fd(a[1]), user(a[2])).await,
+            SYS_OPENAT => sys_openat(t, fd(a[0]), user(a[1]), a[2] as u32, a[3] as u32).await,
+            SYS_CLOSE => sys_close(t, fd(a[0])).await,
+            SYS_GETDENTS64 => sys_getdents64(t, fd(a[0]), user(a[1]), a[2] as u32).await,
+            SYS_LSEEK => sys_lseek(t, fd(a[0]), a[1] as i64, a[2] as u32).await,
+            SYS_READ => sys_read(t, fd(a[0]), user(a[1]), a[2] as usize).await,
+            SYS_WRITE => sys_write(t, fd(a[0]), user(a[1]), a[2] as usize).await,
+            SYS_READV => sys_readv(t, fd(a[0]), user(a[1]), a[2] as usize).await,
+            SYS_WRITEV => sys_writev(t, fd(a[0]), user(a[1]), a[2] as usize).await,
+            SYS_PREAD64 => sys_pread64(t, fd(a[0]), user(a[1]), a[2] as usize, a[3] as i64).await,
+            SYS_PWRITE64 => sys_pwrite64(t, fd(a[0]), user(a[1]), a[2] as usize, a[3] as i64).await,
+            // on a 64 bit kernel the whole offset is in pos_l, pos_h is ignored
+            SYS_PREADV => sys_preadv(t, fd(a[0]), user(a[1]), a[2] as usize, a[3] as i64).await,
+            SYS_PWRITEV => sys_pwritev(t, fd(a[0]), user(a[1]), a[2] as usize, a[3] as i64).await,
+            SYS_PREADV2 => sys_preadv2(t, fd(a[0]), user(a[1]), a[2] as usize, a[3] as i64, a[5]).await,
+            SYS_PWRITEV2 => sys_pwritev2(t, fd(a[0]), user(a[1]), a[2] as usize, a[3] as i64, a[5]).await,
+            SYS_READLINKAT => sys_readlinkat(t, fd(a[0]), user(a[1]), user(a[2]), a[3] as usize).await,
+            SYS_NEWFSTATAT => sys_newfstatat(t, fd(a[0]), user(a[1]), user(a[2]), flags::<AtFlags>(a[3])?).await,
+            SYS_FSTAT => sys_fstat(t, fd(a[0]), user(a[1])).await,
+            SYS_UTIMENSAT => sys_utimensat(t, fd(a[0]), user(a[1]), user(a[2]), flags::<AtFlags>(a[3])?).await,
+            SYS_EXECVE => sys_execve(t, user(a[0]), user(a[1]), user(a[2])).await,
+            SYS_EXIT => sys_exit(t, a[0] as usize).await,
+            SYS_EXIT_GROUP => sys_exit_group(t, a[0] as usize).await,
+            SYS_GETRESUID => sys_getresuid(t, user(a[0]), user(a[1]), user(a[2])),
+            SYS_GETRESGID => sys_getresgid(t, user(a[0]), user(a[1]), user(a[2])),
+            SYS_GETGROUPS => sys_getgroups(t, a[0] as i32, user(a[1])),
+            SYS_SETGROUPS => sys_setgroups(t, a[0] as usize, user(a[1])),
+            SYS_SETUID => sys_setuid(t, a[0] as u32),
+            SYS_SETGID => sys_setgid(t, a[0] as u32),
+            SYS_SETREUID => sys_setreuid(t, a[0] as u32, a[1] as u32),
+            SYS_SETREGID => sys_setregid(t, a[0] as u32, a[1] as u32),
+            SYS_SETRESUID => sys_setresuid(t, a[0] as u32, a[1] as u32, a[2] as u32),
+            SYS_SETRESGID => sys_setresgid(t, a[0] as u32, a[1] as u32, a[2] as u32),
+            SYS_SETFSUID => infallible(sys_setfsuid(t, a[0] as u32)),
+            SYS_SETFSGID => infallible(sys_setfsgid(t, a[0] as u32)),
+            SYS_STATX => sys_statx(t, fd(a[0]), user(a[1]), flags::<AtFlags>(a[2])?, a[3] as u32, user(a[4])).await,
+            // xxx - only the address is kept, nothing walks the list at exit
+            SYS_SET_ROBUST_LIST => sys_set_robust_list(t, user(a[0]), a[1] as usize).await,
+            SYS_PRLIMIT64 => sys_prlimit64(t, pid(a[0]), a[1] as u32, user(a[2]), user(a[3])).await,
+            SYS_SETPGID => sys_setpgid(t, pid(a[0]), Pgid(a[1] as u32)),
+            SYS_GETPGID => sys_getpgid(t, pid(a[0])),
+            SYS_GETPID => infallible(sys_getpid(t)),
+            SYS_GETPPID => infallible(sys_getppid(t)),
+            SYS_GETUID => infallible(sys_getuid(t)),
+            SYS_GETEUID => infallible(sys_geteuid(t)),
+            SYS_GETGID => infallible(sys_getgid(t)),
+            SYS_GETEGID => infallible(sys_getegid(t)),
+            SYS_GETTID => infallible(sys_gettid(t)),
+            _ => Err(linuxerr!(ENOSYS)),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<R:Runtime> Syscall for Dispatch<R> {
+    async fn on_syscall(&self, num:u32, args:[u64;6]) -> u64 {
+        to_x0(self.dispatch(num, args).await)
+    }
+}
@@ -2,6 +2,8 @@
 // we need to have a consistent idea of errnos at every location, so
 // we define them here despite that its not the best layering
 
+use protocol::Error;
+
 pub enum LinuxError {
     EPERM = 1,             // Operation not permitted
     ENOENT = 2,            // No such file or directory
@@ -144,8 +146,21 @@ macro_rules! linuxerr {
         Error {
             location: None,
             cause: "syscall".to_string(),
-            syserr: Some(crate::LinuxError::$code as u8),
+            syserr: Some($crate::LinuxError::$code as u8),
+            status: None,
         }
     }};
 }
 
+
+// a block that was abandoned on our behalf becomes what the application
+// expects from an interrupted or timed out call, anything else passes through
+pub fn interrupted(e: Error) -> Error {
+    if e.is_cancelled() {
+        linuxerr!(EINTR)
+    } else if e.is_timed_out() {
+        linuxerr!(EAGAIN)
+    } else {
+        e
+    }
+}
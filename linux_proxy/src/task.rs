@@ -1,13 +1,28 @@
 use crate::{
     Pid, Process, Runtime, Lockable,
 };
+use protocol::{Cancel, Command, DynStream, Error, Value};
 
-use alloc::{sync::Arc};
+use alloc::{sync::Arc, vec::Vec};
 
 pub struct Task<R:Runtime> {
     pub tid: Tid,
     pub process: Arc<Process<R>>,
     pub state: Arc<R::Lock<TaskState>>,
+    // the token of the last interruptible block we issued
+    pub interrupt: Arc<R::Lock<Option<Cancel>>>,
+}
+
+// by hand, derive would want R: Clone
+impl<R:Runtime> Clone for Task<R> {
+    fn clone(&self) -> Self {
+        Self {
+            tid: self.tid,
+            process: self.process.clone(),
+            state: self.state.clone(),
+            interrupt: self.interrupt.clone(),
+        }
+    }
 }
 
 impl<R:Runtime> Task<R> {
@@ -16,6 +31,22 @@ impl<R:Runtime> Task<R> {
             tid: Tid(1),
             process: p,
             state: Arc::new(R::Lock::new(TaskState::Runnable)),
+            interrupt: Arc::new(R::Lock::new(None)),
+        }
+    }
+
+    // blocks that a signal should be able to break out of (reads on pipes,
+    // waits..) go through here instead of runtime.execute. pass the result
+    // through interrupted() to get EINTR or EAGAIN back out
+    pub fn execute_interruptible(&self, block: Vec<Command>, cancel: Cancel) -> Result<DynStream<Vec<Value>>, Error> {
+        *self.interrupt.lock() = Some(cancel.clone());
+        self.process.kernel.runtime.execute_cancellable(block, cancel)
+    }
+
+    // from signal delivery. if the block already finished this does nothing
+    pub fn interrupt(&self) {
+        if let Some(c) = &*self.interrupt.lock() {
+            c.cancel();
         }
     }
 
@@ -0,0 +1,87 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+use crate::{Runtime, Task, stat::dev};
+use protocol::{Command, Error, FromValue, Oid, Value, Variable, attribute};
+
+// atime, mtime and ctime are kept as attributes of the entry, nanoseconds
+// since the epoch. mtime is the contents changing, ctime anything about the
+// entry changing including its contents, and atime reads, as far as the
+// mount's policy wants it kept
+
+// what reads do to atime, like the mount options of the same names. a
+// mount is a device here, the high half of the Oid, the same thing stat
+// reports as st_dev
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Atime {
+    Strict,
+    // only if it is older than the last change, or a day old, so that ls
+    // -lu and mail readers still get something useful for much less
+    #[default]
+    Relative,
+    Never,
+}
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Times {
+    pub atime: Option<Duration>,
+    pub mtime: Option<Duration>,
+    pub ctime: Option<Duration>,
+}
+
+impl Times {
+    // gets for the three into Unions from first on, so nothing breaks for an
+    // entry that never had them
+    pub fn get(block: &mut Vec<Command>, e: Value, first: Variable) {
+        for (i, a) in ["atime", "mtime", "ctime"].into_iter().enumerate() {
+            block.push(Command::Get(e.clone(), attribute!(a), Value::Union(first + i as Variable)));
+        }
+    }
+
+    pub fn from_row(row: &[Value]) -> Result<Times, Error> {
+        let time = |v: &Value| match v {
+            Value::Empty() => Ok(None),
+            v => Duration::from_value(v).map(Some),
+        };
+        Ok(Times { atime: time(&row[0])?, mtime: time(&row[1])?, ctime: time(&row[2])? })
+    }
+}
+
+pub fn now<R:Runtime>(t: &Task<R>) -> Duration {
+    t.process.kernel.runtime.now()
+}
+
+// the contents changed
+pub fn modified(e: Value, now: Duration) -> [Command; 2] {
+    [
+        Command::Set(e.clone(), attribute!("mtime"), now.into()),
+        Command::Set(e, attribute!("ctime"), now.into()),
+    ]
+}
+
+// the entry changed, but not what's in it
+pub fn changed(e: Value, now: Duration) -> Command {
+    Command::Set(e, attribute!("ctime"), now.into())
+}
+
+pub fn created(e: Value, now: Duration) -> [Command; 3] {
+    let [m, c] = modified(e.clone(), now);
+    [Command::Set(e, attribute!("atime"), now.into()), m, c]
+}
+
+// the set for atime after reading oid, if there should be one
+pub fn accessed<R:Runtime>(t: &Task<R>, oid: Oid, times: &Times) -> Option<Command> {
+    let now = now(t);
+    let update = match t.process.kernel.atime(dev(oid)) {
+        Atime::Never => false,
+        Atime::Strict => true,
+        Atime::Relative => match times.atime {
+            None => true,
+            Some(a) => times.mtime.is_some_and(|m| a <= m)
+                || times.ctime.is_some_and(|c| a <= c)
+                || now.saturating_sub(a) >= DAY,
+        },
+    };
+    update.then(|| Command::Set(Value::Oid(oid), attribute!("atime"), now.into()))
+}
@@ -1,25 +1,13 @@
 use core::time::Duration;
+use crate::{AddressSpace, Runtime, copy_in, linuxerr};
+use protocol::Error;
 
-use crate::{
-    error::KernelError,
-    memory::address::TUA,
-};
-
-use crate::memory::uaccess::{UserCopyable, copy_from_user};
-
+// struct timespec, both fields are longs on arm64
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
 pub struct TimeSpec {
-    tv_sec: i64,
-    tv_nsec: u64,
-}
-
-unsafe impl UserCopyable for TimeSpec {}
-
-impl From<TimeSpec> for Duration {
-    fn from(value: TimeSpec) -> Self {
-        Duration::new(value.tv_sec as _, value.tv_nsec as _)
-    }
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
 }
 
 impl From<Duration> for TimeSpec {
@@ -32,18 +20,19 @@ impl From<Duration> for TimeSpec {
 }
 
 impl TimeSpec {
-    pub async fn copy_from_user(src: TUA<Self>) -> Result<Self, KernelError> {
-        let timespec = copy_from_user(src).await?;
-
-        // Sanity checking.
-        if timespec.tv_nsec > 999_999_999 {
-            return Err(KernelError::InvalidValue);
-        }
+    pub fn copy_in<R:Runtime>(runtime: &R, from: AddressSpace) -> Result<Self, Error> {
+        let mut b = [0u8; 16];
+        copy_in(runtime, from, &mut b)?;
+        let word = |b: &[u8]| i64::from_le_bytes(b.try_into().unwrap());
+        Ok(TimeSpec { tv_sec: word(&b[..8]), tv_nsec: word(&b[8..]) })
+    }
 
-        if timespec.tv_sec < 0 {
-            return Err(KernelError::InvalidValue);
+    // times before the epoch can't be kept, so they are refused along with
+    // nanoseconds that aren't
+    pub fn duration(&self) -> Result<Duration, Error> {
+        if !(0..1_000_000_000).contains(&self.tv_nsec) || self.tv_sec < 0 {
+            return Err(linuxerr!(EINVAL));
         }
-
-        Ok(timespec)
+        Ok(Duration::new(self.tv_sec as u64, self.tv_nsec as u32))
     }
 }
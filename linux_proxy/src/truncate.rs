@@ -0,0 +1,88 @@
+use alloc::vec;
+use crate::{
+    AT_FDCWD, AccessMode, AddressSpace, Fd, FileType, PATH_MAX, Runtime, Task,
+    copy_in_cstr, linuxerr,
+    access::permission,
+    namei::{resolve, type_of},
+    rw::{Current, can_write, current, drain, forget_extents},
+    times::{modified, now},
+};
+use protocol::{Command, Error, Oid, Value, attribute};
+
+// sets the length of a file. whatever was past the end has to read as zeros
+// if it grows again, so the contents are cut along with it, by clearing
+// them and copying back what's kept in the same block. the contents are as
+// long as the size, see write_at, so that's all there to copy. growing is
+// a copy of nothing to the new end, which pads. either way the extents go,
+// a file without them is all data, see seek. without shrink a file that is
+// already longer is left alone
+pub async fn resize<R:Runtime>(t: &Task<R>, obj: Oid, length: u64, shrink: bool) -> Result<(), Error> {
+    if length > i64::MAX as u64 {
+        return Err(linuxerr!(EFBIG));
+    }
+    loop {
+        let Current { stored, size, extents, .. } = current(t, obj).await?;
+        if !shrink && size >= length {
+            return Ok(());
+        }
+        let mut block = vec![
+            Command::CompareAndSet(Value::Oid(obj), attribute!("size"), stored, Value::Unsigned(length)),
+        ];
+        block.extend(forget_extents(obj, &extents));
+        if length > size {
+            block.push(Command::Copy(
+                Value::Oid(obj), attribute!("contents"), Value::Unsigned(0),
+                Value::Oid(obj), attribute!("contents"), Value::Unsigned(length),
+                Value::Unsigned(0),
+            ));
+        } else if length < size {
+            block.push(Command::Set(Value::Oid(obj), attribute!("contents"), Value::Empty()));
+            if length > 0 {
+                block.push(Command::Copy(
+                    Value::Oid(obj), attribute!("contents"), Value::Unsigned(0),
+                    Value::Oid(obj), attribute!("contents"), Value::Unsigned(0),
+                    Value::Unsigned(length),
+                ));
+            }
+        }
+        block.extend(modified(Value::Oid(obj), now(t)));
+        match drain(t, block).await {
+            Ok(()) => return Ok(()),
+            Err(e) if e.is_guard_failure() => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn length(length: i64) -> Result<u64, Error> {
+    u64::try_from(length).map_err(|_| linuxerr!(EINVAL))
+}
+
+pub async fn sys_truncate<R:Runtime>(t: Task<R>, path: AddressSpace, len: i64) -> Result<usize, Error> {
+    let len = length(len)?;
+    let path = copy_in_cstr(&t.process.kernel.runtime, path, PATH_MAX - 1)?;
+    let r = resolve(&t, Fd(AT_FDCWD), &path, true).await?;
+    let oid = r.oid.ok_or(linuxerr!(ENOENT))?;
+    r.writable()?;
+    match r.file_type {
+        FileType::File if r.trailing_slash => return Err(linuxerr!(ENOTDIR)),
+        FileType::File => {}
+        FileType::Directory => return Err(linuxerr!(EISDIR)),
+        _ => return Err(linuxerr!(EINVAL)),
+    }
+    permission(&t, oid, AccessMode::W_OK).await?;
+    resize(&t, oid, len, true).await?;
+    Ok(0)
+}
+
+// the descriptor has to be open for writing, and on a regular file
+pub async fn sys_ftruncate<R:Runtime>(t: Task<R>, fd: Fd, len: i64) -> Result<usize, Error> {
+    let len = length(len)?;
+    let file = t.process.get_fd(fd)?;
+    can_write(&file).map_err(|_| linuxerr!(EINVAL))?;
+    if type_of(&t, file.obj).await? != FileType::File {
+        return Err(linuxerr!(EINVAL));
+    }
+    resize(&t, file.obj, len, true).await?;
+    Ok(0)
+}
@@ -0,0 +1,72 @@
+use crate::{
+    AddressSpace, AtFlags, Fd, FileType, Lockable, PATH_MAX, Runtime, Task,
+    copy_in_cstr, linuxerr,
+    namei::{Edit, failed_guard, nlinks, resolve},
+    times::now,
+};
+use protocol::Error;
+
+// the name goes, and with the last one the entry, unless someone still
+// has it open. an fd holds the Oid and not the name, so then it waits for
+// the last close, see close
+pub async fn sys_unlinkat<R:Runtime>(t: Task<R>, dirfd: Fd, path: AddressSpace, flags: AtFlags) -> Result<usize, Error> {
+    if !(flags - AtFlags::AT_REMOVEDIR).is_empty() {
+        return Err(linuxerr!(EINVAL));
+    }
+    let rmdir = flags.contains(AtFlags::AT_REMOVEDIR);
+    let path = copy_in_cstr(&t.process.kernel.runtime, path, PATH_MAX - 1)?;
+    loop {
+        let r = resolve(&t, dirfd, &path, false).await?;
+        let oid = r.oid.ok_or(linuxerr!(ENOENT))?;
+        let is_dir = r.file_type == FileType::Directory;
+        let Some(name) = r.name else {
+            // ".", ".." or "/"
+            return Err(match (rmdir, path.ends_with(b"..")) {
+                (false, _) => linuxerr!(EISDIR),
+                (true, true) => linuxerr!(ENOTEMPTY),
+                (true, false) if oid == *t.process.root.lock() => linuxerr!(EBUSY),
+                (true, false) => linuxerr!(EINVAL),
+            });
+        };
+        match (rmdir, is_dir) {
+            (true, false) => return Err(linuxerr!(ENOTDIR)),
+            (false, true) => return Err(linuxerr!(EISDIR)),
+            (false, false) if r.trailing_slash => return Err(linuxerr!(ENOTDIR)),
+            _ => {}
+        }
+        if r.read_only {
+            return Err(linuxerr!(EROFS));
+        }
+
+        let counts = nlinks(&t, &[oid, r.parent]).await?;
+        let mut e = Edit::default();
+        let names = e.names(r.parent);
+        e.expect(&names, &name, oid);
+        let (empty, left) = if rmdir {
+            // the parent loses the link from '..', and nothing is left
+            // pointing at the directory
+            let empty = e.empty(oid);
+            e.nlink(r.parent, &counts[1].0, counts[1].1.saturating_sub(1));
+            e.nlink(oid, &counts[0].0, 0);
+            (Some(empty), 0)
+        } else {
+            let left = counts[0].1.saturating_sub(1);
+            e.nlink(oid, &counts[0].0, left);
+            (None, left)
+        };
+        e.unlink(&names, &name);
+        let now = now(&t);
+        e.modified(r.parent, now);
+        e.changed(oid, now);
+        if left == 0 && !t.process.kernel.is_open(oid) {
+            e.destroy(oid);
+        }
+        match e.run(&t).await {
+            Ok(_) => return Ok(0),
+            Err(e) if empty.is_some() && failed_guard(&e) == empty => return Err(linuxerr!(ENOTEMPTY)),
+            // it moved or changed under us, look again
+            Err(e) if e.is_guard_failure() => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
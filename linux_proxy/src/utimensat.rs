@@ -0,0 +1,105 @@
+use alloc::vec::Vec;
+use core::time::Duration;
+use crate::{
+    AccessMode, AddressSpace, Capabilities, AtFlags, Fd, Lockable, PATH_MAX, Runtime, Task,
+    copy_in_cstr, linuxerr, load,
+    attr::FileAttr,
+    namei::{resolve, start},
+    rw::drain,
+    timespec::TimeSpec,
+    times::{changed, now},
+};
+use protocol::{Command, Error, Oid, Value, attribute};
+
+pub const UTIME_NOW: i64 = (1 << 30) - 1;
+pub const UTIME_OMIT: i64 = (1 << 30) - 2;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Change {
+    Now,
+    Omit,
+    To(Duration),
+}
+
+fn change(ts: TimeSpec) -> Result<Change, Error> {
+    match ts.tv_nsec {
+        UTIME_NOW => Ok(Change::Now),
+        UTIME_OMIT => Ok(Change::Omit),
+        _ => ts.duration().map(Change::To),
+    }
+}
+
+// with no path this is futimens, and dirfd is the file. that takes no
+// flags, there's nothing to follow or not
+async fn target<R:Runtime>(t: &Task<R>, dirfd: Fd, path: AddressSpace, flags: AtFlags) -> Result<Oid, Error> {
+    if let AddressSpace::User(0) = path {
+        if dirfd.is_atcwd() {
+            return Err(linuxerr!(EFAULT));
+        }
+        if !flags.is_empty() {
+            return Err(linuxerr!(EINVAL));
+        }
+        return Ok(t.process.get_fd(dirfd)?.obj);
+    }
+    let path = copy_in_cstr(&t.process.kernel.runtime, path, PATH_MAX - 1)?;
+    if path.is_empty() && flags.contains(AtFlags::AT_EMPTY_PATH) {
+        return start(t, dirfd, &path);
+    }
+    let r = resolve(t, dirfd, &path, !flags.contains(AtFlags::AT_SYMLINK_NOFOLLOW)).await?;
+    r.oid.ok_or(linuxerr!(ENOENT))
+}
+
+// times are [atime, mtime], no times at all is both now. setting either to
+// something other than now is for the owner, now is for anyone who could
+// have written the file to the same effect
+pub async fn sys_utimensat<R:Runtime>(
+    t: Task<R>,
+    dirfd: Fd,
+    path: AddressSpace,
+    times: AddressSpace,
+    flags: AtFlags,
+) -> Result<usize, Error> {
+    if !(flags - (AtFlags::AT_SYMLINK_NOFOLLOW | AtFlags::AT_EMPTY_PATH)).is_empty() {
+        return Err(linuxerr!(EINVAL));
+    }
+    let changes = match times {
+        AddressSpace::User(0) => [Change::Now, Change::Now],
+        AddressSpace::User(addr) => {
+            let runtime = &t.process.kernel.runtime;
+            [
+                change(TimeSpec::copy_in(runtime, times)?)?,
+                change(TimeSpec::copy_in(runtime, AddressSpace::User(addr + 16))?)?,
+            ]
+        }
+        _ => return Err(linuxerr!(EFAULT)),
+    };
+    if changes == [Change::Omit, Change::Omit] {
+        return Ok(0);
+    }
+
+    let oid = target(&t, dirfd, path, flags).await?;
+    let attr: FileAttr = load(&t.process.kernel.runtime, oid).await?;
+    let creds = t.process.creds.lock().clone();
+    let owner = creds.owns(attr.uid()) || creds.capable_on(Capabilities::CAP_FOWNER, attr.uid(), attr.gid());
+    if changes.iter().any(|c| matches!(c, Change::To(_))) {
+        if !owner {
+            return Err(linuxerr!(EPERM));
+        }
+    } else if !owner {
+        attr.check_access(&creds, AccessMode::W_OK)?;
+    }
+
+    let now = now(&t);
+    let mut block = Vec::new();
+    for (c, a) in changes.into_iter().zip(["atime", "mtime"]) {
+        let at = match c {
+            Change::Now => now,
+            Change::To(d) => d,
+            Change::Omit => continue,
+        };
+        block.push(Command::Set(Value::Oid(oid), attribute!(a), at.into()));
+    }
+    block.push(changed(Value::Oid(oid), now));
+    drain(&t, block).await?;
+    Ok(0)
+}
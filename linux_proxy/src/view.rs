@@ -0,0 +1,149 @@
+use alloc::{string::String, vec, vec::Vec};
+use protocol::{Command, Entity, Error, FromValue, Oid, Value, attribute, err};
+use crate::{Component, Lockable, Path, Runtime, Task, load, namei::directory_in};
+
+// what a process sees at a path, so that two programs can each have their
+// own /usr/lib without anything like a container. a view is an ordered list
+// of entries, each putting the directory root at prefix. several entries
+// on the same prefix are layered, a name is looked for in each in order
+// and the first one that has it wins, directories with the same name being
+// layered the same way all the way down. a path that isn't under any entry
+// is the process root's as usual. the directories above a prefix have to
+// exist for it to be reached, see manifest. a view comes with the
+// executable, see exec, and a forked process has a copy of its parent's,
+// see Process::fork
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct MountFlags: u64 {
+        // nothing under it can be changed, MS_RDONLY
+        const RDONLY = 1;
+    }
+}
+
+impl From<MountFlags> for Value {
+    fn from(value: MountFlags) -> Self {
+        Value::Unsigned(value.bits())
+    }
+}
+
+impl FromValue for MountFlags {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        Ok(MountFlags::from_bits_truncate(u64::from_value(v)?))
+    }
+}
+
+// an entry as an executable's manifest has it. order is where it goes
+// among the layers on the same prefix, lowest first, and entries that
+// don't say go after the ones that do in the order they're listed
+#[derive(Clone, Debug, Entity)]
+pub struct ViewEntry {
+    pub prefix: String,
+    pub root: Oid,
+    pub flags: Option<MountFlags>,
+    pub order: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mount {
+    pub prefix: Path,
+    pub root: Oid,
+    pub read_only: bool,
+    pub order: u32,
+}
+
+// one of the directories a place in the view is made of
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layer {
+    pub oid: Oid,
+    pub read_only: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct View {
+    mounts: Vec<Mount>,
+}
+
+impl View {
+    pub fn new(entries: Vec<ViewEntry>) -> Result<View, Error> {
+        let mut mounts = Vec::new();
+        for e in entries {
+            let prefix = Path::from_bytes(e.prefix.as_bytes())?.normalize();
+            if !prefix.is_absolute() {
+                return Err(err!("view prefix {} isn't absolute", e.prefix));
+            }
+            // "/usr/lib/" is "/usr/lib", and '..' from the root is gone
+            let prefix = Path::from_elements(true, prefix.components().filter_map(|c| match c {
+                Component::Name(name) => Some(name),
+                Component::Parent => None,
+            }));
+            mounts.push(Mount {
+                prefix,
+                root: e.root,
+                read_only: e.flags.unwrap_or(MountFlags::empty()).contains(MountFlags::RDONLY),
+                order: e.order.unwrap_or(u32::MAX),
+            });
+        }
+        // stable, so the listed order breaks ties
+        mounts.sort_by_key(|m| m.order);
+        Ok(View { mounts })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mounts.is_empty()
+    }
+
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    // the layers put right at path, the first is looked in first and is
+    // where anything new goes
+    pub fn at(&self, path: &Path) -> Vec<Layer> {
+        self.mounts.iter()
+            .filter(|m| m.prefix == *path)
+            .map(|m| Layer { oid: m.root, read_only: m.read_only })
+            .collect()
+    }
+}
+
+// the view in the manifest of an executable, its view attribute. the
+// manifest is an entity from the position of each entry to the entry
+pub async fn manifest<R:Runtime>(t: &Task<R>, exe: Oid) -> Result<Option<View>, Error> {
+    let block = vec![
+        Command::Get(Value::Oid(exe), attribute!("view"), Value::Variable(0)),
+        Command::Get(Value::Variable(0), Value::Variable(1), Value::Variable(2)),
+    ];
+    let mut st = t.process.kernel.runtime.execute(block)?;
+    let mut listed = Vec::new();
+    while let Some(row) = st.next().await? {
+        listed.push((u64::from_value(&row[1])?, Oid::from_value(&row[2])?));
+    }
+    if listed.is_empty() {
+        return Ok(None);
+    }
+    listed.sort();
+    let mut entries = Vec::new();
+    for (_, entry) in listed {
+        entries.push(load::<R, ViewEntry>(&t.process.kernel.runtime, entry).await?);
+    }
+    let view = View::new(entries)?;
+    // a prefix is only found by going through the directories above it, so
+    // those have to be there already, in the graph or put there by another
+    // entry. the prefix itself needn't be
+    for m in view.mounts() {
+        if let Some(up) = m.prefix.parent() {
+            directory_in(t, &view, &up).await?;
+        }
+    }
+    Ok(Some(view))
+}
+
+// what execve does once it has the executable. one that brings a view
+// replaces whatever the process had, one without keeps it
+pub async fn exec<R:Runtime>(t: &Task<R>, exe: Oid) -> Result<(), Error> {
+    if let Some(view) = manifest(t, exe).await? {
+        *t.process.view.lock() = view;
+    }
+    Ok(())
+}
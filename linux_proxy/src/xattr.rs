@@ -0,0 +1,237 @@
+use alloc::{format, string::String, vec, vec::Vec};
+use crate::{
+    AT_FDCWD, AccessMode, AddressSpace, Fd, FileType, LinuxError, Lockable, PATH_MAX, Runtime, Task,
+    copy_in, copy_in_cstr, copy_out, linuxerr, load,
+    attr::FileAttr,
+    namei::resolve,
+    times::{changed, now},
+};
+use protocol::{Command, Error, Oid, Value, attribute};
+
+// extended attributes are attributes of the entry itself, under their own
+// names. "user.x" is the attribute "user.x", so anything else that puts
+// attributes in that namespace shows up here too, and can be read with
+// getfattr. the other namespaces are for the kernel and security modules
+// we don't have, and are refused like on a filesystem without them
+
+pub const XATTR_NAME_MAX: usize = 255;
+pub const XATTR_SIZE_MAX: usize = 65536;
+pub const XATTR_LIST_MAX: usize = 65536;
+
+const NAMESPACE: &str = "user.";
+
+bitflags::bitflags! {
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct XattrFlags: u32 {
+        const XATTR_CREATE = 0x1;   // fail if it exists
+        const XATTR_REPLACE = 0x2;  // fail if it doesn't
+    }
+}
+
+// what the entry is reached by, a path following links or not, or an fd
+#[derive(Clone, Copy)]
+pub enum Target {
+    Path(AddressSpace, bool),
+    Fd(Fd),
+}
+
+async fn target<R:Runtime>(t: &Task<R>, target: Target) -> Result<Oid, Error> {
+    match target {
+        Target::Path(path, follow) => {
+            let path = copy_in_cstr(&t.process.kernel.runtime, path, PATH_MAX - 1)?;
+            resolve(t, Fd(AT_FDCWD), &path, follow).await?.oid.ok_or(linuxerr!(ENOENT))
+        }
+        Target::Fd(fd) => Ok(t.process.get_fd(fd)?.obj),
+    }
+}
+
+fn name<R:Runtime>(t: &Task<R>, name: AddressSpace) -> Result<String, Error> {
+    let name = match copy_in_cstr(&t.process.kernel.runtime, name, XATTR_NAME_MAX) {
+        Err(e) if e.syserr == Some(LinuxError::ENAMETOOLONG as u8) => return Err(linuxerr!(ERANGE)),
+        r => r?,
+    };
+    if name.is_empty() {
+        return Err(linuxerr!(ERANGE));
+    }
+    let name = String::from_utf8(name).map_err(|_| linuxerr!(EOPNOTSUPP))?;
+    if !name.starts_with(NAMESPACE) {
+        return Err(linuxerr!(EOPNOTSUPP));
+    }
+    Ok(name)
+}
+
+// user attributes are for the owner's data, so they follow the permissions
+// on it, and only files and directories have them. on anything else who
+// can write them would be up to whoever can write the device or the link
+async fn allowed<R:Runtime>(t: &Task<R>, oid: Oid, mode: AccessMode) -> Result<(), Error> {
+    let attr: FileAttr = load(&t.process.kernel.runtime, oid).await?;
+    if mode.contains(AccessMode::W_OK) && !matches!(attr.file_type(), FileType::File | FileType::Directory) {
+        return Err(linuxerr!(EPERM));
+    }
+    let creds = t.process.creds.lock().clone();
+    attr.check_access(&creds, mode)
+}
+
+// attributes put there by something other than setxattr needn't be bytes,
+// they read as text
+fn bytes(v: &Value) -> Result<Vec<u8>, Error> {
+    Ok(match v {
+        Value::Bytes(b) => b.clone(),
+        Value::Utf8String(s) => s.as_bytes().to_vec(),
+        Value::Unsigned(n) => format!("{}", n).into_bytes(),
+        Value::Signed(n) => format!("{}", n).into_bytes(),
+        Value::Oid(o) => format!("{:032x}", o.0).into_bytes(),
+        _ => return Err(linuxerr!(ENODATA)),
+    })
+}
+
+// with size 0 just how much there is, otherwise it has to fit
+fn give<R:Runtime>(t: &Task<R>, out: &[u8], buf: AddressSpace, size: usize) -> Result<usize, Error> {
+    if size == 0 {
+        return Ok(out.len());
+    }
+    if out.len() > size {
+        return Err(linuxerr!(ERANGE));
+    }
+    copy_out(&t.process.kernel.runtime, buf, out)?;
+    Ok(out.len())
+}
+
+pub async fn getxattr<R:Runtime>(t: Task<R>, on: Target, name: AddressSpace,
+                                 value: AddressSpace, size: usize) -> Result<usize, Error> {
+    let oid = target(&t, on).await?;
+    let name = self::name(&t, name)?;
+    allowed(&t, oid, AccessMode::R_OK).await?;
+    let block = vec![Command::Get(Value::Oid(oid), attribute!(name), Value::Union(0))];
+    let row = t.process.kernel.runtime.execute(block)?.next().await?.ok_or(linuxerr!(ENOENT))?;
+    if row[0] == Value::Empty() {
+        return Err(linuxerr!(ENODATA));
+    }
+    give(&t, &bytes(&row[0])?, value, size.min(XATTR_SIZE_MAX))
+}
+
+// CREATE is an absent in the same block as the set, REPLACE a get that has
+// to find something, so either holds against someone else doing the same
+pub async fn setxattr<R:Runtime>(t: Task<R>, on: Target, name: AddressSpace, value: AddressSpace,
+                                 size: usize, flags: XattrFlags) -> Result<usize, Error> {
+    if size > XATTR_SIZE_MAX {
+        return Err(linuxerr!(E2BIG));
+    }
+    let oid = target(&t, on).await?;
+    let name = self::name(&t, name)?;
+    let mut data = vec![0u8; size];
+    if size > 0 {
+        copy_in(&t.process.kernel.runtime, value, &mut data)?;
+    }
+    allowed(&t, oid, AccessMode::W_OK).await?;
+
+    let mut block = Vec::new();
+    if flags.contains(XattrFlags::XATTR_CREATE) {
+        block.push(Command::Absent(Value::Oid(oid), attribute!(name)));
+    }
+    if flags.contains(XattrFlags::XATTR_REPLACE) {
+        block.push(Command::Get(Value::Oid(oid), attribute!(name), Value::Variable(0)));
+    }
+    block.push(Command::Set(Value::Oid(oid), attribute!(name), Value::Bytes(data)));
+    block.push(changed(Value::Oid(oid), now(&t)));
+    let mut st = t.process.kernel.runtime.execute(block)?;
+    match st.next().await {
+        Ok(Some(_)) => Ok(0),
+        Ok(None) => Err(linuxerr!(ENODATA)),
+        Err(e) if e.is_guard_failure() => Err(linuxerr!(EEXIST)),
+        Err(e) => Err(e),
+    }
+}
+
+// every user attribute, each name followed by a nul
+pub async fn listxattr<R:Runtime>(t: Task<R>, on: Target, list: AddressSpace, size: usize) -> Result<usize, Error> {
+    let oid = target(&t, on).await?;
+    let block = vec![Command::Get(Value::Oid(oid), Value::Variable(0), Value::Variable(1))];
+    let mut st = t.process.kernel.runtime.execute(block)?;
+    let mut names = Vec::new();
+    while let Some(row) = st.next().await? {
+        if let Value::Utf8String(name) = &row[0]
+            && name.starts_with(NAMESPACE) {
+            names.push(name.clone());
+        }
+    }
+    names.sort();
+    let mut out = Vec::new();
+    for name in names {
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+    }
+    if out.len() > XATTR_LIST_MAX {
+        return Err(linuxerr!(E2BIG));
+    }
+    give(&t, &out, list, size.min(XATTR_LIST_MAX))
+}
+
+pub async fn removexattr<R:Runtime>(t: Task<R>, on: Target, name: AddressSpace) -> Result<usize, Error> {
+    let oid = target(&t, on).await?;
+    let name = self::name(&t, name)?;
+    allowed(&t, oid, AccessMode::W_OK).await?;
+    let block = vec![
+        Command::Get(Value::Oid(oid), attribute!(name), Value::Variable(0)),
+        Command::Set(Value::Oid(oid), attribute!(name), Value::Empty()),
+        changed(Value::Oid(oid), now(&t)),
+    ];
+    match t.process.kernel.runtime.execute(block)?.next().await? {
+        Some(_) => Ok(0),
+        None => Err(linuxerr!(ENODATA)),
+    }
+}
+
+pub async fn sys_getxattr<R:Runtime>(t: Task<R>, path: AddressSpace, name: AddressSpace,
+                                     value: AddressSpace, size: usize) -> Result<usize, Error> {
+    getxattr(t, Target::Path(path, true), name, value, size).await
+}
+
+pub async fn sys_lgetxattr<R:Runtime>(t: Task<R>, path: AddressSpace, name: AddressSpace,
+                                      value: AddressSpace, size: usize) -> Result<usize, Error> {
+    getxattr(t, Target::Path(path, false), name, value, size).await
+}
+
+pub async fn sys_fgetxattr<R:Runtime>(t: Task<R>, fd: Fd, name: AddressSpace,
+                                      value: AddressSpace, size: usize) -> Result<usize, Error> {
+    getxattr(t, Target::Fd(fd), name, value, size).await
+}
+
+pub async fn sys_setxattr<R:Runtime>(t: Task<R>, path: AddressSpace, name: AddressSpace, value: AddressSpace,
+                                     size: usize, flags: XattrFlags) -> Result<usize, Error> {
+    setxattr(t, Target::Path(path, true), name, value, size, flags).await
+}
+
+pub async fn sys_lsetxattr<R:Runtime>(t: Task<R>, path: AddressSpace, name: AddressSpace, value: AddressSpace,
+                                      size: usize, flags: XattrFlags) -> Result<usize, Error> {
+    setxattr(t, Target::Path(path, false), name, value, size, flags).await
+}
+
+pub async fn sys_fsetxattr<R:Runtime>(t: Task<R>, fd: Fd, name: AddressSpace, value: AddressSpace,
+                                      size: usize, flags: XattrFlags) -> Result<usize, Error> {
+    setxattr(t, Target::Fd(fd), name, value, size, flags).await
+}
+
+pub async fn sys_listxattr<R:Runtime>(t: Task<R>, path: AddressSpace, list: AddressSpace, size: usize) -> Result<usize, Error> {
+    listxattr(t, Target::Path(path, true), list, size).await
+}
+
+pub async fn sys_llistxattr<R:Runtime>(t: Task<R>, path: AddressSpace, list: AddressSpace, size: usize) -> Result<usize, Error> {
+    listxattr(t, Target::Path(path, false), list, size).await
+}
+
+pub async fn sys_flistxattr<R:Runtime>(t: Task<R>, fd: Fd, list: AddressSpace, size: usize) -> Result<usize, Error> {
+    listxattr(t, Target::Fd(fd), list, size).await
+}
+
+pub async fn sys_removexattr<R:Runtime>(t: Task<R>, path: AddressSpace, name: AddressSpace) -> Result<usize, Error> {
+    removexattr(t, Target::Path(path, true), name).await
+}
+
+pub async fn sys_lremovexattr<R:Runtime>(t: Task<R>, path: AddressSpace, name: AddressSpace) -> Result<usize, Error> {
+    removexattr(t, Target::Path(path, false), name).await
+}
+
+pub async fn sys_fremovexattr<R:Runtime>(t: Task<R>, fd: Fd, name: AddressSpace) -> Result<usize, Error> {
+    removexattr(t, Target::Fd(fd), name).await
+}
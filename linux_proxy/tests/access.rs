@@ -0,0 +1,115 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AddressSpace, AtFlags, Credentials, Fd, Gid, Lockable, OpenFlags, Task, Uid,
+    access::{sys_faccessat, sys_faccessat2},
+    exec::sys_execve,
+    open::sys_openat,
+};
+use protocol::{Store, Value};
+use std::{ffi::CString, sync::{Arc, Mutex}};
+
+fn access(t: &Task<Machine>, p: &str, mode: u32) -> Result<usize, u8> {
+    let (_c, path) = cstr(p);
+    errno(block_on(sys_faccessat(t.clone(), Fd(AT_FDCWD), path, mode)))
+}
+
+fn mode(s: &Arc<Mutex<Store>>, mode: u64) {
+    set(&mut s.lock().unwrap(), PASSWD, "mode", Value::Unsigned(mode));
+}
+
+// a NULL terminated array of pointers to the strings, and what it points at
+fn strings(v: &[&str]) -> (Vec<CString>, Vec<u64>) {
+    let s: Vec<CString> = v.iter().map(|s| CString::new(*s).unwrap()).collect();
+    let mut p: Vec<u64> = s.iter().map(|s| s.as_ptr() as u64).collect();
+    p.push(0);
+    (s, p)
+}
+
+fn execve(t: &Task<Machine>, p: &str, argv: &[&str], envp: &[&str]) -> Result<usize, u8> {
+    let (_c, path) = cstr(p);
+    let (_a, a) = strings(argv);
+    let (_e, e) = strings(envp);
+    let (a, e) = (AddressSpace::User(a.as_ptr() as u64), AddressSpace::User(e.as_ptr() as u64));
+    errno(block_on(sys_execve(t.clone(), path, a, e)))
+}
+
+fn become_user(t: &Task<Machine>) {
+    *t.process.creds.lock() = Credentials::new_user(Uid::new(1000), Gid::new(1000), vec![]);
+}
+
+#[test]
+fn root_access() {
+    let (t, s) = world();
+    assert_eq!(access(&t, "/etc/passwd", 0), Ok(0));
+    assert_eq!(access(&t, "/nope", 0), Err(2));
+    assert_eq!(access(&t, "/etc/passwd", 6), Ok(0));
+    // root only gets x where someone has it, a directory is always
+    // searchable
+    assert_eq!(access(&t, "/etc/passwd", 1), Err(13));
+    assert_eq!(access(&t, "/etc", 1), Ok(0));
+    mode(&s, 0o610);
+    assert_eq!(access(&t, "/etc/passwd", 1), Ok(0));
+    mode(&s, 0);
+    assert_eq!(access(&t, "/etc/passwd", 6), Ok(0));
+    assert_eq!(access(&t, "/etc/passwd", 8), Err(22));
+
+    let (_c, path) = cstr("/etc/passwd");
+    assert_eq!(errno(block_on(sys_faccessat2(t.clone(), Fd(AT_FDCWD), path, 4, AtFlags::AT_EACCESS))), Ok(0));
+    assert_eq!(errno(block_on(sys_faccessat2(t.clone(), Fd(AT_FDCWD), path, 4, AtFlags::AT_NO_AUTOMOUNT))), Err(22));
+}
+
+#[test]
+fn user_access() {
+    let (t, s) = world();
+    become_user(&t);
+    // other's bits, 0644
+    assert_eq!(access(&t, "/etc/passwd", 4), Ok(0));
+    assert_eq!(access(&t, "/etc/passwd", 2), Err(13));
+    mode(&s, 0o604);
+    set(&mut s.lock().unwrap(), PASSWD, "gid", Value::Unsigned(1000));
+    // the group's say wins over other's, even though other's is more
+    assert_eq!(access(&t, "/etc/passwd", 4), Err(13));
+}
+
+#[test]
+fn exec_needs_x() {
+    let (t, s) = world();
+    // even root needs an x bit somewhere, and a directory is never run
+    assert_eq!(execve(&t, "/etc/passwd", &["passwd"], &[]), Err(13));
+    assert_eq!(execve(&t, "/etc", &["etc"], &[]), Err(13));
+    assert_eq!(execve(&t, "/nope", &[], &[]), Err(2));
+    assert_eq!(execve(&t, "", &[], &[]), Err(2));
+    assert_eq!(execve(&t, "/etc/passwd/", &[], &[]), Err(20));
+    assert!(t.process.kernel.runtime.execs.lock().unwrap().is_empty());
+
+    mode(&s, 0o744);
+    assert_eq!(execve(&t, "/etc/passwd", &["passwd", "-l"], &["HOME=/"]), Ok(0));
+    let execs = t.process.kernel.runtime.execs.lock().unwrap().clone();
+    let argv = vec![b"passwd".to_vec(), b"-l".to_vec()];
+    assert_eq!(execs, vec![(PROCESS, PASSWD, argv, vec![b"HOME=/".to_vec()])]);
+
+    // someone else has only r from 0744
+    become_user(&t);
+    assert_eq!(execve(&t, "/etc/passwd", &["passwd"], &[]), Err(13));
+    mode(&s, 0o745);
+    assert_eq!(execve(&t, "/etc/passwd", &["passwd"], &[]), Ok(0));
+}
+
+#[test]
+fn exec_closes_cloexec() {
+    let (t, s) = world();
+    mode(&s, 0o755);
+    let open = |flags: OpenFlags| {
+        let (_c, path) = cstr("/etc/passwd");
+        Fd(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, flags.bits(), 0)).unwrap() as i32)
+    };
+    let kept = open(OpenFlags::O_RDONLY);
+    let closed = open(OpenFlags::O_RDONLY | OpenFlags::O_CLOEXEC);
+    assert_eq!(execve(&t, "/etc/passwd", &[], &[]), Ok(0));
+    assert!(t.process.get_fd(kept).is_ok());
+    assert!(t.process.get_fd(closed).is_err());
+    // and the slot it left is the next one handed out
+    assert_eq!(open(OpenFlags::O_RDONLY), closed);
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AtFlags, Credentials, Fd, Gid, Lockable, OpenFlags, Task, Uid,
+    chdir::{sys_chdir, sys_chroot, sys_fchdir, sys_getcwd},
+    open::sys_openat,
+    readlink::sys_symlinkat,
+    rename::{RenameFlags, sys_renameat2},
+    stat::{Stat, sys_newfstatat},
+    view::{View, ViewEntry},
+};
+use protocol::{Oid, Store, Value};
+use std::sync::{Arc, Mutex};
+
+fn cd(t: &Task<Machine>, p: &str) -> Result<usize, u8> {
+    let (_c, path) = cstr(p);
+    errno(block_on(sys_chdir(t.clone(), path)))
+}
+
+fn cwd(t: &Task<Machine>) -> String {
+    let mut buf = [0u8; 128];
+    let n = block_on(sys_getcwd(t.clone(), user(&mut buf), 128)).unwrap();
+    String::from_utf8(buf[..n - 1].to_vec()).unwrap()
+}
+
+fn ino(t: &Task<Machine>, p: &str) -> Result<u64, u8> {
+    let (_c, path) = cstr(p);
+    let mut st = Stat::default();
+    errno(block_on(sys_newfstatat(t.clone(), Fd(AT_FDCWD), path, user(&mut st), AtFlags::empty())))?;
+    Ok(st.st_ino)
+}
+
+fn open(t: &Task<Machine>, p: &str, flags: OpenFlags) -> Fd {
+    let (_c, path) = cstr(p);
+    Fd(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, flags.bits(), 0)).unwrap() as i32)
+}
+
+fn rename(t: &Task<Machine>, from: &str, to: &str) -> Result<usize, u8> {
+    let (_a, from) = cstr(from);
+    let (_b, to) = cstr(to);
+    errno(block_on(sys_renameat2(t.clone(), Fd(AT_FDCWD), from, Fd(AT_FDCWD), to, RenameFlags::empty())))
+}
+
+// /tmp/jail/etc/passwd, something to be chrooted into
+fn jail(s: &Arc<Mutex<Store>>) {
+    let mut s = s.lock().unwrap();
+    mkdir(&mut s, Oid(20), Some((TMP, "jail")));
+    mkdir(&mut s, Oid(21), Some((Oid(20), "etc")));
+    mkfile(&mut s, Oid(22), Oid(21), "passwd", b"jailed\n");
+}
+
+#[test]
+fn cwd_and_back() {
+    let (t, s) = world();
+    jail(&s);
+    assert_eq!(cwd(&t), "/");
+    assert_eq!(cd(&t, "/tmp/jail/etc"), Ok(0));
+    assert_eq!(cwd(&t), "/tmp/jail/etc");
+    assert_eq!(ino(&t, "passwd"), Ok(22));
+    assert_eq!(cd(&t, "../.."), Ok(0));
+    assert_eq!(cwd(&t), "/tmp");
+    assert_eq!(cd(&t, "/etc/passwd"), Err(20));
+    assert_eq!(cd(&t, "/nope"), Err(2));
+    assert_eq!(cd(&t, "/../../etc"), Ok(0));
+    assert_eq!(cwd(&t), "/etc");
+
+    // the nul has to fit too
+    let mut small = [0u8; 5];
+    assert_eq!(errno(block_on(sys_getcwd(t.clone(), user(&mut small), 4))), Err(34));
+    assert_eq!(errno(block_on(sys_getcwd(t.clone(), user(&mut small), 5))), Ok(5));
+    assert_eq!(&small, b"/etc\0");
+}
+
+#[test]
+fn by_descriptor() {
+    let (t, s) = world();
+    jail(&s);
+    let dir = open(&t, "/tmp/jail", OpenFlags::O_DIRECTORY);
+    assert_eq!(errno(block_on(sys_fchdir(t.clone(), dir))), Ok(0));
+    assert_eq!(cwd(&t), "/tmp/jail");
+    let file = open(&t, "/etc/passwd", OpenFlags::O_RDONLY);
+    assert_eq!(errno(block_on(sys_fchdir(t.clone(), file))), Err(20));
+    assert_eq!(errno(block_on(sys_fchdir(t.clone(), Fd(99)))), Err(9));
+
+    // somewhere we can't search isn't somewhere we can be
+    *t.process.creds.lock() = Credentials::new_user(Uid::new(5), Gid::new(5), vec![]);
+    set(&mut s.lock().unwrap(), Oid(21), "mode", Value::Unsigned(0o700));
+    assert_eq!(cd(&t, "etc"), Err(13));
+    assert_eq!(cwd(&t), "/tmp/jail");
+    let (_r, r) = cstr("/tmp/jail");
+    assert_eq!(errno(block_on(sys_chroot(t.clone(), r))), Err(1));
+}
+
+#[test]
+fn chrooted() {
+    let (t, s) = world();
+    jail(&s);
+    let (_r, r) = cstr("/tmp/jail");
+    assert_eq!(cd(&t, "/tmp"), Ok(0));
+    assert_eq!(errno(block_on(sys_chroot(t.clone(), r))), Ok(0));
+    // the cwd stays behind, outside
+    assert_eq!(cwd(&t), "(unreachable)/tmp");
+    assert_eq!(ino(&t, "/etc/passwd"), Ok(22));
+    assert_eq!(ino(&t, "/../../etc/passwd"), Ok(22));
+    assert_eq!(ino(&t, "/etc/../../../etc/passwd"), Ok(22));
+    assert_eq!(cd(&t, "/etc"), Ok(0));
+    assert_eq!(cwd(&t), "/etc");
+    assert_eq!(cd(&t, "../../.."), Ok(0));
+    assert_eq!(cwd(&t), "/");
+    assert_eq!(ino(&t, "etc/passwd"), Ok(22));
+    // a link to somewhere absolute lands inside too
+    let (_tg, target) = cstr("/etc/passwd");
+    let (_l, l) = cstr("/l");
+    block_on(sys_symlinkat(t.clone(), target, Fd(AT_FDCWD), l)).unwrap();
+    assert_eq!(ino(&t, "/l"), Ok(22));
+}
+
+#[test]
+fn renamed_above() {
+    let (t, s) = world();
+    jail(&s);
+    assert_eq!(cd(&t, "/tmp/jail/etc"), Ok(0));
+    assert_eq!(rename(&t, "/tmp/jail", "/tmp/cell"), Ok(0));
+    assert_eq!(cwd(&t), "/tmp/cell/etc");
+    assert_eq!(ino(&t, "passwd"), Ok(22));
+
+    // in a view the kept path is what relative lookups start from, so it
+    // has to follow too, even when its old name is taken again
+    *t.process.view.lock() = View::new(vec![ViewEntry {
+        prefix: "/opt".into(),
+        root: TMP,
+        flags: None,
+        order: None,
+    }]).unwrap();
+    assert_eq!(rename(&t, "/tmp/cell", "/tmp/jail"), Ok(0));
+    assert_eq!(ino(&t, "passwd"), Ok(22));
+    {
+        let mut s = s.lock().unwrap();
+        mkdir(&mut s, Oid(30), Some((TMP, "cell")));
+        mkdir(&mut s, Oid(31), Some((Oid(30), "etc")));
+        mkfile(&mut s, Oid(32), Oid(31), "passwd", b"someone else's\n");
+    }
+    assert_eq!(ino(&t, "passwd"), Ok(22));
+    assert_eq!(cwd(&t), "/tmp/jail/etc");
+    assert_eq!(ino(&t, "../../cell/etc/passwd"), Ok(32));
+}
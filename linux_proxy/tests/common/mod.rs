@@ -0,0 +1,310 @@
+#![allow(dead_code)]
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use linux_proxy::{
+    AccessMode, AddressSpace, Credentials, DynSyscall, FileType, Kernel, Lockable, Pid, Process, Runnable,
+    Runtime, Task,
+};
+use protocol::{
+    Cancel, Command, DynAllocator, DynEntityHandler, DynStream, Error, Oid, Resolver, Scope, SimpleAllocator,
+    Store, Stream, Value, attribute,
+};
+use std::ffi::CString;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+// a runtime over a Store in this process, enough to run syscalls against.
+// user memory is ours too, so a user address is a host pointer for copy,
+// and an offset into the vma attribute of the process for blocks that move
+// contents
+
+// nothing here actually waits on anything, so polling in a loop with a
+// waker that does nothing is enough
+pub fn block_on<F: Future>(f: F) -> F::Output {
+    fn noop(_: *const ()) {}
+    fn clone(p: *const ()) -> RawWaker {
+        RawWaker::new(p, &VTABLE)
+    }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    let waker = unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &VTABLE)) };
+    let mut cx = Context::from_waker(&waker);
+    let mut f = pin!(f);
+    loop {
+        if let Poll::Ready(x) = f.as_mut().poll(&mut cx) {
+            return x;
+        }
+    }
+}
+
+pub struct Lock<A>(Mutex<A>);
+
+impl<A> Lockable<A> for Lock<A> {
+    type Guard<'a>
+        = MutexGuard<'a, A>
+    where
+        A: 'a;
+    fn new(a: A) -> Self {
+        Lock(Mutex::new(a))
+    }
+    fn lock(&self) -> MutexGuard<'_, A> {
+        self.0.lock().unwrap()
+    }
+}
+
+pub struct Thread;
+
+impl Runnable for Thread {
+    fn run(&self) -> ! {
+        panic!("no threads in tests")
+    }
+}
+
+pub struct Shared(pub Arc<Mutex<Store>>);
+
+impl Resolver for Shared {
+    fn resolve(&self, v: Oid) -> Option<DynEntityHandler> {
+        self.0.lock().unwrap().resolve(v)
+    }
+}
+
+// (process, executable, argv, envp)
+pub type Exec = (Oid, Oid, Vec<Vec<u8>>, Vec<Vec<u8>>);
+
+pub struct Machine {
+    pub store: Arc<Mutex<Store>>,
+    pub allocator: DynAllocator,
+    // seconds since the epoch, moved along by tick
+    pub clock: Mutex<u64>,
+    // every block executed, in order
+    pub blocks: Mutex<Vec<Vec<Command>>>,
+    // a signal arrives while the next block moving contents is out, which
+    // is where a read would wait
+    pub interrupt: Mutex<bool>,
+    // another thread's doing, run when the next block moving contents
+    // goes out and before it is evaluated
+    pub meanwhile: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+    // each exec that got this far
+    pub execs: Mutex<Vec<Exec>>,
+}
+
+// the whole result, collected before the writes are committed, with the
+// error if there was one at the end
+struct Rows(std::vec::IntoIter<Vec<Value>>, Option<Error>);
+
+#[async_trait::async_trait]
+impl Stream<Vec<Value>> for Rows {
+    async fn next(&mut self) -> Result<Option<Vec<Value>>, Error> {
+        if let Some(r) = self.0.next() {
+            return Ok(Some(r));
+        }
+        match self.1.take() {
+            Some(e) => Err(e),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Runtime for Machine {
+    type Lock<A> = Lock<A>;
+    type Thread = Thread;
+
+    fn create_thread(&self, _: u64, _: u64, _: DynSyscall) -> Result<Thread, Error> {
+        Ok(Thread)
+    }
+
+    fn console(&self, s: String) {
+        print!("{}", s)
+    }
+
+    fn map(&self, _: AddressSpace, _: AddressSpace, _: AccessMode, _: usize) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn unmap(&self, _: AddressSpace, _: usize) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn copy(&self, to: AddressSpace, from: AddressSpace, length: usize) {
+        let a = |x| match x {
+            AddressSpace::User(a) | AddressSpace::Kernel(a) | AddressSpace::Physical(a) => a,
+        };
+        unsafe { std::ptr::copy(a(from) as *const u8, a(to) as *mut u8, length) }
+    }
+
+    fn now(&self) -> Duration {
+        Duration::from_secs(*self.clock.lock().unwrap())
+    }
+
+    fn execute_cancellable(&self, block: Vec<Command>, cancel: Cancel) -> Result<DynStream<Vec<Value>>, Error> {
+        self.blocks.lock().unwrap().push(block.clone());
+        let copies = block.iter().any(|c| matches!(c, Command::Copy(..)));
+        if copies && std::mem::take(&mut *self.interrupt.lock().unwrap()) {
+            cancel.cancel();
+        }
+        let meanwhile = if copies { self.meanwhile.lock().unwrap().take() } else { None };
+        if let Some(f) = meanwhile {
+            f();
+        }
+        let scope = Scope {
+            myself: Oid(1),
+            allocator: self.allocator.clone(),
+            resolver: Arc::new(Shared(self.store.clone())),
+            cancel,
+        };
+        let mut e = scope.evaluate(block)?;
+        let mut rows = vec![];
+        loop {
+            match block_on(e.next()) {
+                Ok(Some(r)) => rows.push(r),
+                Ok(None) => break,
+                Err(err) => return Ok(Box::new(Rows(rows.into_iter(), Some(err)))),
+            }
+        }
+        if let Err(err) = self.store.lock().unwrap().commit(e.writes()) {
+            return Ok(Box::new(Rows(vec![].into_iter(), Some(err))));
+        }
+        Ok(Box::new(Rows(rows.into_iter(), None)))
+    }
+
+    fn exec(&self, process: Oid, exe: Oid, argv: Vec<Vec<u8>>, envp: Vec<Vec<u8>>) -> Result<(), Error> {
+        self.execs.lock().unwrap().push((process, exe, argv, envp));
+        Ok(())
+    }
+}
+
+pub const ROOT: Oid = Oid(10);
+pub const ETC: Oid = Oid(11);
+pub const PASSWD: Oid = Oid(12);
+pub const TMP: Oid = Oid(13);
+pub const PROCESS: Oid = Oid(2);
+
+// where a directory keeps its names
+pub fn names(dir: Oid) -> Oid {
+    Oid(dir.0 + 5000)
+}
+
+pub fn set(s: &mut Store, e: Oid, a: &str, v: Value) {
+    s.commit(vec![Command::Set(Value::Oid(e), attribute!(a), v)]).unwrap()
+}
+
+fn owned(s: &mut Store, oid: Oid, file_type: FileType, mode: u64, nlink: u64) {
+    set(s, oid, "type", Value::from(file_type));
+    set(s, oid, "mode", Value::Unsigned(mode));
+    set(s, oid, "uid", Value::Unsigned(0));
+    set(s, oid, "gid", Value::Unsigned(0));
+    set(s, oid, "nlink", Value::Unsigned(nlink));
+}
+
+pub fn mkdir(s: &mut Store, oid: Oid, parent: Option<(Oid, &str)>) {
+    s.commit(vec![Command::Create(Value::Oid(oid)), Command::Create(Value::Oid(names(oid)))])
+        .unwrap();
+    set(s, oid, "children", Value::Oid(names(oid)));
+    owned(s, oid, FileType::Directory, 0o755, 2);
+    if let Some((p, n)) = parent {
+        set(s, oid, "parent", Value::Oid(p));
+        set(s, names(p), n, Value::Oid(oid));
+    }
+}
+
+pub fn mkfile(s: &mut Store, oid: Oid, parent: Oid, n: &str, data: &[u8]) {
+    s.commit(vec![Command::Create(Value::Oid(oid))]).unwrap();
+    owned(s, oid, FileType::File, 0o644, 1);
+    set(s, oid, "size", Value::Unsigned(data.len() as u64));
+    set(s, oid, "contents", Value::Bytes(data.to_vec()));
+    set(s, names(parent), n, Value::Oid(oid));
+}
+
+// a machine over whatever is in the store
+pub fn over(s: Store) -> (Machine, Arc<Mutex<Store>>) {
+    let store = Arc::new(Mutex::new(s));
+    let machine = Machine {
+        store: store.clone(),
+        allocator: SimpleAllocator::new(Oid(100000)),
+        clock: Mutex::new(1000),
+        blocks: Mutex::new(vec![]),
+        interrupt: Mutex::new(false),
+        meanwhile: Mutex::new(None),
+        execs: Mutex::new(vec![]),
+    };
+    (machine, store)
+}
+
+// /etc/passwd and an empty /tmp, and a root process with 4k of memory
+// sitting at the root
+pub fn machine() -> (Machine, Arc<Mutex<Store>>) {
+    let mut s = Store::new(Oid(1));
+    s.add_root(ROOT);
+    mkdir(&mut s, ROOT, None);
+    mkdir(&mut s, ETC, Some((ROOT, "etc")));
+    mkfile(&mut s, PASSWD, ETC, "passwd", b"root:x:0:0\n");
+    mkdir(&mut s, TMP, Some((ROOT, "tmp")));
+    s.add_root(PROCESS);
+    s.commit(vec![Command::Create(Value::Oid(PROCESS))]).unwrap();
+    set(&mut s, PROCESS, "vma", Value::Bytes(vec![0; 4096]));
+    over(s)
+}
+
+// root's first process on any runtime. the library wants an Arc whether
+// or not the runtime can cross threads
+#[allow(clippy::arc_with_non_send_sync)]
+pub fn boot<R: Runtime>(r: R) -> Task<R> {
+    let kernel = Arc::new(Kernel::new(r));
+    let p = Arc::new(Process::new(kernel, PROCESS, Pid(1), Credentials::new_root(), ROOT));
+    p.kernel.add_process(&p);
+    Task::new(p)
+}
+
+pub fn world() -> (Task<Machine>, Arc<Mutex<Store>>) {
+    let (m, s) = machine();
+    (boot(m), s)
+}
+
+// a path for the guest, the CString has to outlive the address
+pub fn cstr(p: &str) -> (CString, AddressSpace) {
+    let c = CString::new(p).unwrap();
+    let a = AddressSpace::User(c.as_ptr() as u64);
+    (c, a)
+}
+
+// somewhere for a syscall to put its answer
+pub fn user<T>(x: &mut T) -> AddressSpace {
+    AddressSpace::User(x as *mut T as u64)
+}
+
+// just the errno, which is what the tests care about
+pub fn errno<T>(r: Result<T, Error>) -> Result<T, u8> {
+    r.map_err(|e| e.syserr.unwrap_or(255))
+}
+
+pub fn attr(s: &Arc<Mutex<Store>>, o: Oid, a: &str) -> Option<Value> {
+    s.lock().unwrap().resolve(o)?.get(attribute!(a)).unwrap()
+}
+
+// the process's memory as blocks see it
+pub fn poke(s: &Arc<Mutex<Store>>, addr: usize, data: &[u8]) {
+    let Some(Value::Bytes(mut m)) = attr(s, PROCESS, "vma") else {
+        panic!("no vma")
+    };
+    m[addr..addr + data.len()].copy_from_slice(data);
+    set(&mut s.lock().unwrap(), PROCESS, "vma", Value::Bytes(m));
+}
+
+pub fn peek(s: &Arc<Mutex<Store>>, addr: usize, len: usize) -> Vec<u8> {
+    let Some(Value::Bytes(m)) = attr(s, PROCESS, "vma") else {
+        panic!("no vma")
+    };
+    m[addr..addr + len].to_vec()
+}
+
+pub fn tick(t: &Task<Machine>, secs: u64) {
+    *t.process.kernel.runtime.clock.lock().unwrap() += secs;
+}
+
+// a timestamp attribute, to the second
+pub fn secs(s: &Arc<Mutex<Store>>, o: Oid, a: &str) -> Option<u64> {
+    attr(s, o, a).map(|v| match v {
+        Value::Unsigned(n) => n / 1_000_000_000,
+        v => panic!("{:?}", v),
+    })
+}
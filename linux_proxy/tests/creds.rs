@@ -0,0 +1,143 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AddressSpace, AtFlags, Capabilities, Fd, Lockable, OpenFlags, Task, Uid,
+    access::{sys_faccessat, sys_faccessat2},
+    creds::{
+        sys_getgroups, sys_getresgid, sys_getresuid, sys_setfsuid, sys_setgid, sys_setgroups, sys_setregid,
+        sys_setresuid, sys_setreuid, sys_setuid,
+    },
+    open::sys_openat,
+};
+use protocol::{Store, Value};
+use std::sync::{Arc, Mutex};
+
+// -1, leave it alone
+const NONE: u32 = u32::MAX;
+
+fn resuid(t: &Task<Machine>) -> [u32; 3] {
+    let mut ids = [0u32; 3];
+    let a = ids.as_mut_ptr() as u64;
+    let at = |n: u64| AddressSpace::User(a + n * 4);
+    sys_getresuid(t.clone(), at(0), at(1), at(2)).unwrap();
+    ids
+}
+
+fn resgid(t: &Task<Machine>) -> [u32; 3] {
+    let mut ids = [0u32; 3];
+    let a = ids.as_mut_ptr() as u64;
+    let at = |n: u64| AddressSpace::User(a + n * 4);
+    sys_getresgid(t.clone(), at(0), at(1), at(2)).unwrap();
+    ids
+}
+
+fn access(t: &Task<Machine>, p: &str, mode: u32) -> Result<usize, u8> {
+    let (_c, path) = cstr(p);
+    errno(block_on(sys_faccessat(t.clone(), Fd(AT_FDCWD), path, mode)))
+}
+
+fn capable(t: &Task<Machine>, cap: Capabilities) -> bool {
+    t.process.creds.lock().capable(cap)
+}
+
+// passwd readable by the group gid and nobody else
+fn group_only(s: &Arc<Mutex<Store>>, gid: u64) {
+    let mut s = s.lock().unwrap();
+    set(&mut s, PASSWD, "mode", Value::Unsigned(0o640));
+    set(&mut s, PASSWD, "gid", Value::Unsigned(gid));
+}
+
+#[test]
+fn groups() {
+    let (t, s) = world();
+    group_only(&s, 42);
+    // sorted and without the repeat
+    let mut groups = [42u32, 7, 42];
+    assert_eq!(errno(sys_setgroups(t.clone(), 3, user(&mut groups))), Ok(0));
+    let mut out = [0u32; 4];
+    assert_eq!(errno(sys_getgroups(t.clone(), 0, AddressSpace::User(0))), Ok(2));
+    assert_eq!(errno(sys_getgroups(t.clone(), 1, user(&mut out))), Err(22));
+    assert_eq!(errno(sys_getgroups(t.clone(), 4, user(&mut out))), Ok(2));
+    assert_eq!(&out[..2], &[7, 42]);
+
+    // a supplementary group is as good as the primary one
+    assert_eq!(errno(sys_setresuid(t.clone(), 1000, 1000, NONE)), Ok(0));
+    assert_eq!(access(&t, "/etc/passwd", 4), Ok(0));
+    assert_eq!(access(&t, "/etc/passwd", 2), Err(13));
+    // and without root they can't be changed
+    assert_eq!(errno(sys_setgroups(t.clone(), 0, AddressSpace::User(0))), Err(1));
+    assert_eq!(access(&t, "/etc/passwd", 4), Ok(0));
+}
+
+#[test]
+fn saved_uids() {
+    let (t, s) = world();
+    group_only(&s, 42);
+    // drop the effective uid, keep root saved. the capabilities are only
+    // put away
+    assert_eq!(errno(sys_setresuid(t.clone(), 1000, 1000, NONE)), Ok(0));
+    assert_eq!(resuid(&t), [1000, 1000, 0]);
+    assert!(t.process.creds.lock().effective().is_empty());
+    assert!(!t.process.creds.lock().permitted().is_empty());
+    assert_eq!(access(&t, "/etc/passwd", 4), Err(13));
+
+    // and back
+    assert_eq!(errno(sys_setuid(t.clone(), 0)), Ok(0));
+    assert_eq!(resuid(&t), [1000, 0, 0]);
+    assert!(capable(&t, Capabilities::CAP_DAC_OVERRIDE));
+    // access asks about the real uid, who isn't root, unless told not to
+    let (_c, path) = cstr("/etc/passwd");
+    assert_eq!(access(&t, "/etc/passwd", 2), Err(13));
+    assert_eq!(errno(block_on(sys_faccessat2(t.clone(), Fd(AT_FDCWD), path, 2, AtFlags::AT_EACCESS))), Ok(0));
+
+    // setreuid, the saved one follows an effective one that isn't the real
+    assert_eq!(errno(sys_setreuid(t.clone(), NONE, 1000)), Ok(0));
+    assert_eq!(resuid(&t), [1000, 1000, 0]);
+    assert_eq!(errno(sys_setreuid(t.clone(), NONE, 0)), Ok(0));
+    assert_eq!(resuid(&t), [1000, 0, 0]);
+    assert_eq!(errno(sys_setuid(t.clone(), NONE)), Err(22));
+}
+
+#[test]
+fn fsuid() {
+    let (t, _s) = world();
+    assert_eq!(errno(sys_setresuid(t.clone(), 1000, NONE, NONE)), Ok(0));
+    // only the file capabilities go with it
+    assert_eq!(sys_setfsuid(t.clone(), 1000), Ok(0));
+    assert!(!capable(&t, Capabilities::CAP_DAC_OVERRIDE));
+    assert!(capable(&t, Capabilities::CAP_SETUID));
+    assert_eq!(sys_setfsuid(t.clone(), 0), Ok(1000));
+    assert!(capable(&t, Capabilities::CAP_DAC_OVERRIDE));
+    // something it isn't allowed is still answered with the old one
+    assert_eq!(errno(sys_setuid(t.clone(), 2000)), Ok(0));
+    assert_eq!(sys_setfsuid(t.clone(), 0), Ok(2000));
+    assert_eq!(t.process.creds.lock().fsuid(), Uid::new(2000));
+}
+
+#[test]
+fn dropped_for_good() {
+    let (t, s) = world();
+    group_only(&s, 0);
+    assert_eq!(errno(sys_setuid(t.clone(), 2000)), Ok(0));
+    assert_eq!(resuid(&t), [2000, 2000, 2000]);
+    assert!(t.process.creds.lock().permitted().is_empty());
+    assert_eq!(errno(sys_setuid(t.clone(), 0)), Err(1));
+    assert_eq!(errno(sys_setresuid(t.clone(), NONE, 1000, NONE)), Err(1));
+    assert_eq!(errno(sys_setgid(t.clone(), 5)), Err(1));
+    assert_eq!(errno(sys_setregid(t.clone(), NONE, 0)), Ok(0));
+    assert_eq!(resgid(&t), [0, 0, 0]);
+
+    // still in group 0 for now
+    assert_eq!(access(&t, "/etc/passwd", 4), Ok(0));
+    set(&mut s.lock().unwrap(), PASSWD, "gid", Value::Unsigned(43));
+    assert_eq!(access(&t, "/etc/passwd", 4), Err(13));
+    // opening it goes through the same check, and so does making
+    // something in /
+    let open = |p: &str, flags: OpenFlags| {
+        let (_c, path) = cstr(p);
+        errno(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, flags.bits(), 0o644)))
+    };
+    assert_eq!(open("/etc/passwd", OpenFlags::O_RDONLY), Err(13));
+    assert_eq!(open("/new", OpenFlags::O_CREAT), Err(13));
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AddressSpace, AtFlags, Fd, OpenFlags, Task,
+    dir::sys_getdents64,
+    open::sys_openat,
+    seek::sys_lseek,
+    unlink::sys_unlinkat,
+};
+
+fn open(t: &Task<Machine>, p: &str, flags: OpenFlags) -> Fd {
+    let (_c, path) = cstr(p);
+    Fd(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, flags.bits(), 0o644)).unwrap() as i32)
+}
+
+// (ino, d_off, d_type, name) for each record
+fn getdents(t: &Task<Machine>, fd: Fd, size: usize) -> Result<Vec<(u64, u64, u8, String)>, u8> {
+    let mut buf = vec![0u8; size];
+    let to = AddressSpace::User(buf.as_mut_ptr() as u64);
+    let n = errno(block_on(sys_getdents64(t.clone(), fd, to, size as u32)))?;
+    let mut out = vec![];
+    let mut i = 0;
+    while i < n {
+        let word = |at: usize| u64::from_le_bytes(buf[at..at + 8].try_into().unwrap());
+        let reclen = u16::from_le_bytes([buf[i + 16], buf[i + 17]]) as usize;
+        let name = buf[i + 19..i + reclen].split(|b| *b == 0).next().unwrap();
+        out.push((word(i), word(i + 8), buf[i + 18], String::from_utf8(name.to_vec()).unwrap()));
+        i += reclen;
+    }
+    Ok(out)
+}
+
+fn with_four(t: &Task<Machine>) {
+    for n in ["a", "b", "c", "d"] {
+        open(t, &format!("/tmp/{}", n), OpenFlags::O_CREAT);
+    }
+}
+
+#[test]
+fn lists_everything_once() {
+    let (t, _s) = world();
+    with_four(&t);
+    let fd = open(&t, "/tmp", OpenFlags::O_RDONLY);
+    let all = getdents(&t, fd, 4096).unwrap();
+    assert_eq!(all.len(), 6);
+    assert_eq!((all[0].0, all[0].2, all[0].3.as_str()), (13, 4, "."));
+    assert_eq!((all[1].0, all[1].3.as_str()), (10, ".."));
+    assert!(all[2..].iter().all(|e| e.2 == 8));
+    assert_eq!(getdents(&t, fd, 4096), Ok(vec![]));
+
+    // the root is its own parent
+    let root = getdents(&t, open(&t, "/", OpenFlags::O_RDONLY), 4096).unwrap();
+    assert_eq!(root[1].0, 10);
+    assert_eq!(getdents(&t, open(&t, "/etc/passwd", OpenFlags::O_RDONLY), 4096), Err(20));
+}
+
+#[test]
+fn resumes_in_small_buffers() {
+    let (t, _s) = world();
+    with_four(&t);
+    let all = getdents(&t, open(&t, "/tmp", OpenFlags::O_RDONLY), 4096).unwrap();
+    let fd = open(&t, "/tmp", OpenFlags::O_RDONLY);
+    // not even one fits
+    assert_eq!(getdents(&t, fd, 10), Err(22));
+    let mut got = vec![];
+    loop {
+        let some = getdents(&t, fd, 48).unwrap();
+        if some.is_empty() {
+            break;
+        }
+        assert!(some.len() <= 2);
+        got.extend(some);
+    }
+    assert_eq!(got, all);
+}
+
+#[test]
+fn resumes_across_changes() {
+    let (t, _s) = world();
+    with_four(&t);
+    let all = getdents(&t, open(&t, "/tmp", OpenFlags::O_RDONLY), 4096).unwrap();
+    let fd = open(&t, "/tmp", OpenFlags::O_RDONLY);
+    let first = getdents(&t, fd, 72).unwrap();
+    assert_eq!(first, all[..3]);
+
+    // taking away what was already read doesn't shift the rest
+    let (_c, gone) = cstr(&format!("/tmp/{}", first[2].3));
+    block_on(sys_unlinkat(t.clone(), Fd(AT_FDCWD), gone, AtFlags::empty())).unwrap();
+    assert_eq!(getdents(&t, fd, 4096).unwrap(), all[3..]);
+
+    // and a d_off is somewhere to come back to
+    assert_eq!(errno(block_on(sys_lseek(t.clone(), fd, all[3].1 as i64, 0))), Ok(all[3].1 as usize));
+    assert_eq!(getdents(&t, fd, 4096).unwrap(), all[4..]);
+}
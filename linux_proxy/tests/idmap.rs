@@ -0,0 +1,140 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AtFlags, Credentials, Fd, Gid, Kernel, Lockable, Pid, Process, Task, Uid,
+    access::{sys_faccessat, sys_faccessat2},
+    creds::{launched, sys_setfsuid, sys_setgroups, sys_setresgid, sys_setuid},
+    idmap::IdMap,
+    open::sys_openat,
+    stat::{Stat, sys_newfstatat},
+};
+use protocol::{FromValue, Store, Value};
+use std::sync::{Arc, Mutex};
+
+fn stat(t: &Task<Machine>, p: &str) -> (u32, u32) {
+    let (_c, path) = cstr(p);
+    let mut st = Stat::default();
+    block_on(sys_newfstatat(t.clone(), Fd(AT_FDCWD), path, user(&mut st), AtFlags::empty())).unwrap();
+    (st.st_uid, st.st_gid)
+}
+
+fn access(t: &Task<Machine>, p: &str, mode: u32) -> Result<usize, u8> {
+    let (_c, path) = cstr(p);
+    errno(block_on(sys_faccessat(t.clone(), Fd(AT_FDCWD), path, mode)))
+}
+
+fn create(t: &Task<Machine>, p: &str) -> Result<usize, u8> {
+    let (_c, path) = cstr(p);
+    errno(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, 0o101, 0o600)))
+}
+
+fn map(s: &str) -> IdMap {
+    IdMap::parse(s).unwrap()
+}
+
+// the first process the way the monitor starts it, whatever maps it was
+// given on the process entity and root inside
+#[allow(clippy::arc_with_non_send_sync)]
+fn launch(uid_map: Option<&str>, gid_map: Option<&str>) -> (Task<Machine>, Arc<Mutex<Store>>) {
+    let (m, s) = machine();
+    {
+        let mut s = s.lock().unwrap();
+        for (name, map) in [("uid_map", uid_map), ("gid_map", gid_map)] {
+            if let Some(map) = map {
+                set(&mut s, PROCESS, name, Value::Utf8String(map.to_string()));
+            }
+        }
+    }
+    let creds = block_on(launched(&m, PROCESS)).unwrap();
+    let p = Arc::new(Process::new(Arc::new(Kernel::new(m)), PROCESS, Pid(1), creds, ROOT));
+    p.kernel.add_process(&p);
+    (Task::new(p), s)
+}
+
+#[test]
+fn parse() {
+    let m = map("0 1000 1\n1 100000 65536\n");
+    assert_eq!(m.to_host(0), Some(1000));
+    assert_eq!(m.to_host(5), Some(100004));
+    assert_eq!(m.to_host(65537), None);
+    assert_eq!(m.to_guest(1000), Some(0));
+    assert_eq!(m.to_guest(0), None);
+    assert_eq!(IdMap::from_value(&Value::from(m.clone())).unwrap(), m);
+    // overlapping on either side, short, empty and past the end
+    assert!(IdMap::parse("0 1000 2\n1 2000 1").is_err());
+    assert!(IdMap::parse("0 1000 2\n5 1001 1").is_err());
+    assert!(IdMap::parse("0 1000").is_err());
+    assert!(IdMap::parse("0 1000 0").is_err());
+    assert!(IdMap::parse("4294967295 0 2").is_err());
+}
+
+#[test]
+fn from_the_monitor() {
+    let (t, s) = launch(Some("0 1000 1\n1 100000 10"), Some("0 1000 1"));
+    assert_eq!(t.process.creds.lock().uid_map(), Some(&map("0 1000 1\n1 100000 10")));
+    // something of ours outside is root's inside, and root's outside is
+    // nobody's
+    {
+        let mut s = s.lock().unwrap();
+        set(&mut s, PASSWD, "uid", Value::Unsigned(100003));
+        set(&mut s, PASSWD, "gid", Value::Unsigned(1000));
+    }
+    assert_eq!(stat(&t, "/etc/passwd"), (4, 0));
+    assert_eq!(stat(&t, "/etc"), (65534, 65534));
+
+    // without them the ids are the host's
+    let (t, _s) = launch(None, None);
+    assert_eq!(t.process.creds.lock().uid_map(), None);
+    assert_eq!(stat(&t, "/etc/passwd"), (0, 0));
+    // and either can be on its own
+    let (t, _s) = launch(None, Some("0 1000 1"));
+    assert_eq!(t.process.creds.lock().uid_map(), None);
+    assert_eq!(stat(&t, "/etc/passwd"), (0, 65534));
+}
+
+#[test]
+fn bad_maps_refused() {
+    let (m, s) = machine();
+    set(&mut s.lock().unwrap(), PROCESS, "uid_map", Value::Utf8String("0 1000".to_string()));
+    assert!(block_on(launched(&m, PROCESS)).is_err());
+}
+
+#[test]
+fn guest_root() {
+    let (t, s) = launch(Some("0 1000 1"), Some("0 1000 1"));
+    // nobody in here owns /etc/passwd, so root's capabilities don't count
+    assert_eq!(stat(&t, "/etc/passwd"), (65534, 65534));
+    assert_eq!(access(&t, "/etc/passwd", 4), Ok(0));
+    assert_eq!(access(&t, "/etc/passwd", 2), Err(13));
+
+    // but it can make things in a directory of its own, owned by 1000
+    // outside
+    {
+        let mut s = s.lock().unwrap();
+        set(&mut s, TMP, "uid", Value::Unsigned(1000));
+        set(&mut s, TMP, "mode", Value::Unsigned(0o700));
+    }
+    assert!(create(&t, "/tmp/mine").is_ok());
+    assert_eq!(stat(&t, "/tmp/mine"), (0, 0));
+    let Some(Value::Oid(made)) = attr(&s, names(TMP), "mine") else { panic!() };
+    assert_eq!(attr(&s, made, "uid"), Some(Value::Unsigned(1000)));
+    // and has its capabilities there
+    set(&mut s.lock().unwrap(), made, "mode", Value::Unsigned(0));
+    let (_c, path) = cstr("/tmp/mine");
+    assert_eq!(errno(block_on(sys_faccessat2(t.clone(), Fd(AT_FDCWD), path, 6, AtFlags::AT_EACCESS))), Ok(0));
+
+    // there is no one else to become
+    assert_eq!(errno(sys_setuid(t.clone(), 5)), Err(22));
+    assert_eq!(errno(sys_setresgid(t.clone(), 7, u32::MAX, u32::MAX)), Err(22));
+    let mut groups = [3u32];
+    assert_eq!(errno(sys_setgroups(t.clone(), 1, user(&mut groups))), Err(22));
+    assert_eq!(sys_setfsuid(t.clone(), 5), Ok(0));
+    assert_eq!(t.process.creds.lock().fsuid(), Uid::new(0));
+
+    // and an fs id that doesn't map can't make anything
+    *t.process.creds.lock() = Credentials::new_user(Uid::new(7), Gid::new(7), vec![])
+        .mapped(map("0 1000 1"), map("0 1000 1"));
+    set(&mut s.lock().unwrap(), TMP, "mode", Value::Unsigned(0o777));
+    assert_eq!(create(&t, "/tmp/other"), Err(75));
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::*;
+use linux_proxy::{AT_FDCWD, Fd, open::sys_openat, rw::{sys_pread64, sys_read}};
+
+#[test]
+fn read_interrupted() {
+    let (t, s) = world();
+    let (_c, path) = cstr("/etc/passwd");
+    let fd = Fd(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, 0, 0)).unwrap() as i32);
+    let buf = linux_proxy::AddressSpace::User(0);
+
+    *t.process.kernel.runtime.interrupt.lock().unwrap() = true;
+    assert_eq!(errno(block_on(sys_read(t.clone(), fd, buf, 4))), Err(4));
+    // nothing was read, so the position didn't move
+    assert_eq!(t.process.get_fd(fd).unwrap().pos, 0);
+    assert_eq!(peek(&s, 0, 4), vec![0; 4]);
+
+    assert_eq!(errno(block_on(sys_read(t.clone(), fd, buf, 4))), Ok(4));
+    assert_eq!(peek(&s, 0, 4), b"root");
+
+    *t.process.kernel.runtime.interrupt.lock().unwrap() = true;
+    assert_eq!(errno(block_on(sys_pread64(t.clone(), fd, buf, 4, 0))), Err(4));
+
+    // the signal came after, there is nothing left to interrupt
+    assert_eq!(errno(block_on(sys_read(t.clone(), fd, buf, 4))), Ok(4));
+    t.interrupt();
+    assert_eq!(t.process.get_fd(fd).unwrap().pos, 8);
+}
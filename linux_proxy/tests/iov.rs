@@ -0,0 +1,76 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AddressSpace, Fd, OpenFlags, Task,
+    iov::{IoVec, sys_preadv2, sys_pwritev2, sys_readv, sys_writev},
+    open::sys_openat,
+};
+use protocol::{Command, Value};
+
+fn open(t: &Task<Machine>, p: &str, flags: OpenFlags) -> Fd {
+    let (_c, path) = cstr(p);
+    Fd(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, flags.bits(), 0o644)).unwrap() as i32)
+}
+
+// the iovecs themselves are read with copy_in, so they live on our side
+fn iov(v: &[(u64, u64)]) -> (Vec<IoVec>, AddressSpace) {
+    let v: Vec<IoVec> = v.iter().map(|(b, l)| IoVec { iov_base: *b, iov_len: *l }).collect();
+    let a = AddressSpace::User(v.as_ptr() as u64);
+    (v, a)
+}
+
+#[test]
+fn gathers_and_scatters() {
+    let (t, s) = world();
+    poke(&s, 0, b"abcdefgh");
+    let w = open(&t, "/tmp/f", OpenFlags::O_CREAT | OpenFlags::O_RDWR);
+    let f = t.process.get_fd(w).unwrap().obj;
+    // an empty segment in the middle is skipped, and the rest go out
+    // together in the one block
+    let (_v, a) = iov(&[(0, 3), (100, 0), (3, 5)]);
+    t.process.kernel.runtime.blocks.lock().unwrap().clear();
+    assert_eq!(errno(block_on(sys_writev(t.clone(), w, a, 3))), Ok(8));
+    let copies: Vec<usize> = t.process.kernel.runtime.blocks.lock().unwrap().iter()
+        .map(|b| b.iter().filter(|c| matches!(c, Command::Copy(..))).count())
+        .filter(|n| *n > 0)
+        .collect();
+    assert_eq!(copies, vec![2]);
+    assert_eq!(attr(&s, f, "contents"), Some(Value::Bytes(b"abcdefgh".to_vec())));
+    assert_eq!(t.process.get_fd(w).unwrap().pos, 8);
+
+    // the last segment only part filled, and the position left alone
+    let (_v, a) = iov(&[(200, 2), (300, 4), (400, 10)]);
+    assert_eq!(errno(block_on(sys_preadv2(t.clone(), w, a, 3, 1, 0))), Ok(7));
+    assert_eq!(peek(&s, 200, 2), b"bc");
+    assert_eq!(peek(&s, 300, 4), b"defg");
+    assert_eq!(peek(&s, 400, 2), b"h\0");
+    assert_eq!(t.process.get_fd(w).unwrap().pos, 8);
+    // -1 is the file position, which is at the end
+    assert_eq!(errno(block_on(sys_preadv2(t.clone(), w, a, 3, -1, 0))), Ok(0));
+}
+
+#[test]
+fn flags_and_limits() {
+    let (t, s) = world();
+    poke(&s, 0, b"ab");
+    let w = open(&t, "/tmp/f", OpenFlags::O_CREAT | OpenFlags::O_RDWR);
+    let f = t.process.get_fd(w).unwrap().obj;
+    let (_v, a) = iov(&[(0, 2)]);
+    block_on(sys_writev(t.clone(), w, a, 1)).unwrap();
+
+    // RWF_APPEND wins over the offset, and the position stays put
+    assert_eq!(errno(block_on(sys_pwritev2(t.clone(), w, a, 1, 0, 0x10))), Ok(2));
+    assert_eq!(attr(&s, f, "contents"), Some(Value::Bytes(b"abab".to_vec())));
+    assert_eq!(t.process.get_fd(w).unwrap().pos, 2);
+    assert_eq!(errno(block_on(sys_pwritev2(t.clone(), w, a, 1, -1, 0))), Ok(2));
+    assert_eq!(t.process.get_fd(w).unwrap().pos, 4);
+
+    // flags we don't know, offsets below -1, too many segments, and a
+    // total that doesn't fit in what we return
+    assert_eq!(errno(block_on(sys_preadv2(t.clone(), w, a, 1, 0, 0x100))), Err(95));
+    assert_eq!(errno(block_on(sys_preadv2(t.clone(), w, a, 1, -2, 0))), Err(22));
+    assert_eq!(errno(block_on(sys_readv(t.clone(), w, a, 2000))), Err(22));
+    let (_v, a) = iov(&[(0, u64::MAX), (0, 2)]);
+    assert_eq!(errno(block_on(sys_writev(t.clone(), w, a, 2))), Err(22));
+}
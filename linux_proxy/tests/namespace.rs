@@ -0,0 +1,141 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AtFlags, Fd, OpenFlags, Task,
+    close::sys_close,
+    link::sys_linkat,
+    mkdir::sys_mkdirat,
+    open::sys_openat,
+    rename::{RenameFlags, sys_renameat2},
+    unlink::sys_unlinkat,
+};
+use protocol::{Oid, Store, Value};
+use std::sync::{Arc, Mutex};
+
+fn open(t: &Task<Machine>, p: &str) -> Result<Oid, u8> {
+    let (_c, path) = cstr(p);
+    let fd = errno(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, OpenFlags::O_RDONLY.bits(), 0)))?;
+    Ok(t.process.get_fd(Fd(fd as i32)).unwrap().obj)
+}
+
+fn mkdir(t: &Task<Machine>, p: &str) -> Result<usize, u8> {
+    let (_c, path) = cstr(p);
+    errno(block_on(sys_mkdirat(t.clone(), Fd(AT_FDCWD), path, 0o755)))
+}
+
+fn unlink(t: &Task<Machine>, p: &str, flags: AtFlags) -> Result<usize, u8> {
+    let (_c, path) = cstr(p);
+    errno(block_on(sys_unlinkat(t.clone(), Fd(AT_FDCWD), path, flags)))
+}
+
+fn link(t: &Task<Machine>, from: &str, to: &str) -> Result<usize, u8> {
+    let (_a, from) = cstr(from);
+    let (_b, to) = cstr(to);
+    errno(block_on(sys_linkat(t.clone(), Fd(AT_FDCWD), from, Fd(AT_FDCWD), to, AtFlags::empty())))
+}
+
+fn rename(t: &Task<Machine>, from: &str, to: &str, flags: RenameFlags) -> Result<usize, u8> {
+    let (_a, from) = cstr(from);
+    let (_b, to) = cstr(to);
+    errno(block_on(sys_renameat2(t.clone(), Fd(AT_FDCWD), from, Fd(AT_FDCWD), to, flags)))
+}
+
+fn nlink(s: &Arc<Mutex<Store>>, o: Oid) -> Option<Value> {
+    attr(s, o, "nlink")
+}
+
+#[test]
+fn directories() {
+    let (t, s) = world();
+    let rmdir = AtFlags::AT_REMOVEDIR;
+    assert_eq!(mkdir(&t, "/tmp/a"), Ok(0));
+    assert_eq!(mkdir(&t, "/tmp/a"), Err(17));
+    assert_eq!(mkdir(&t, "/tmp/x/y"), Err(2));
+    // the new one's .. counts against its parent
+    assert_eq!(nlink(&s, TMP), Some(Value::Unsigned(3)));
+    let a = open(&t, "/tmp/a").unwrap();
+    assert_eq!(attr(&s, a, "parent"), Some(Value::Oid(TMP)));
+
+    assert_eq!(mkdir(&t, "/tmp/a/b"), Ok(0));
+    assert_eq!(unlink(&t, "/tmp/a", rmdir), Err(39));
+    assert_eq!(unlink(&t, "/tmp/a", AtFlags::empty()), Err(21));
+    assert_eq!(unlink(&t, "/etc/passwd", rmdir), Err(20));
+    assert_eq!(unlink(&t, "/", rmdir), Err(16));
+    assert_eq!(unlink(&t, "/tmp/a/..", rmdir), Err(39));
+    assert_eq!(unlink(&t, "/tmp/a/b", rmdir), Ok(0));
+    assert_eq!(nlink(&s, a), Some(Value::Unsigned(2)));
+    assert_eq!(unlink(&t, "/tmp/a", rmdir), Ok(0));
+    assert_eq!(nlink(&s, TMP), Some(Value::Unsigned(2)));
+    assert_eq!(open(&t, "/tmp/a"), Err(2));
+}
+
+#[test]
+fn hard_links() {
+    let (t, s) = world();
+    assert_eq!(link(&t, "/etc/passwd", "/tmp/pw"), Ok(0));
+    assert_eq!(link(&t, "/etc/passwd", "/tmp/pw"), Err(17));
+    assert_eq!(link(&t, "/etc", "/tmp/e"), Err(1));
+    assert_eq!(nlink(&s, PASSWD), Some(Value::Unsigned(2)));
+    assert_eq!(open(&t, "/tmp/pw"), Ok(PASSWD));
+    assert_eq!(unlink(&t, "/tmp/pw", AtFlags::empty()), Ok(0));
+    assert_eq!(nlink(&s, PASSWD), Some(Value::Unsigned(1)));
+    assert_eq!(unlink(&t, "/tmp/pw", AtFlags::empty()), Err(2));
+}
+
+#[test]
+fn renames() {
+    let (t, s) = world();
+    mkdir(&t, "/tmp/d").unwrap();
+    mkdir(&t, "/tmp/d/sub").unwrap();
+    let d = open(&t, "/tmp/d").unwrap();
+    // not into itself
+    assert_eq!(rename(&t, "/tmp/d", "/tmp/d/sub/x", RenameFlags::empty()), Err(22));
+    assert_eq!(rename(&t, "/tmp/d", "/etc/d", RenameFlags::empty()), Ok(0));
+    assert_eq!(attr(&s, d, "parent"), Some(Value::Oid(ETC)));
+    assert_eq!(nlink(&s, TMP), Some(Value::Unsigned(2)));
+    assert_eq!(nlink(&s, ETC), Some(Value::Unsigned(3)));
+    assert_eq!(open(&t, "/etc/d"), Ok(d));
+
+    assert_eq!(rename(&t, "/etc/passwd", "/etc/d", RenameFlags::empty()), Err(21));
+    assert_eq!(rename(&t, "/etc/d", "/etc/passwd", RenameFlags::empty()), Err(20));
+    mkdir(&t, "/tmp/empty").unwrap();
+    assert_eq!(rename(&t, "/tmp/empty", "/etc/d", RenameFlags::empty()), Err(39));
+    assert_eq!(rename(&t, "/etc/passwd", "/tmp/p", RenameFlags::RENAME_NOREPLACE), Ok(0));
+    assert_eq!(rename(&t, "/tmp/p", "/tmp/empty", RenameFlags::RENAME_NOREPLACE), Err(17));
+
+    // two names for the one file, renaming one over the other does nothing
+    link(&t, "/tmp/p", "/tmp/q").unwrap();
+    assert_eq!(rename(&t, "/tmp/p", "/tmp/q", RenameFlags::empty()), Ok(0));
+    assert_eq!(open(&t, "/tmp/p"), Ok(PASSWD));
+    assert_eq!(rename(&t, "/", "/tmp/r", RenameFlags::empty()), Err(16));
+
+    // an empty directory can be replaced, and goes away once it's closed
+    let (_c, path) = cstr("/tmp/empty");
+    let fd = Fd(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, 0, 0)).unwrap() as i32);
+    let empty = t.process.get_fd(fd).unwrap().obj;
+    assert_eq!(rename(&t, "/etc/d/sub", "/tmp/empty", RenameFlags::empty()), Ok(0));
+    assert_eq!(nlink(&s, empty), Some(Value::Unsigned(0)));
+    block_on(sys_close(t.clone(), fd)).unwrap();
+    assert_eq!(nlink(&s, empty), None);
+    assert_eq!(nlink(&s, TMP), Some(Value::Unsigned(3)));
+    assert_eq!(nlink(&s, d), Some(Value::Unsigned(2)));
+}
+
+#[test]
+fn exchanges() {
+    let (t, s) = world();
+    mkdir(&t, "/tmp/d").unwrap();
+    let d = open(&t, "/tmp/d").unwrap();
+    // a file and a directory across parents, each parent's count follows
+    // the directory
+    assert_eq!(rename(&t, "/etc/passwd", "/tmp/d", RenameFlags::RENAME_EXCHANGE), Ok(0));
+    assert_eq!(open(&t, "/etc/passwd"), Ok(d));
+    assert_eq!(open(&t, "/tmp/d"), Ok(PASSWD));
+    assert_eq!(attr(&s, d, "parent"), Some(Value::Oid(ETC)));
+    assert_eq!(nlink(&s, ETC), Some(Value::Unsigned(3)));
+    assert_eq!(nlink(&s, TMP), Some(Value::Unsigned(2)));
+    let both = RenameFlags::RENAME_EXCHANGE | RenameFlags::RENAME_NOREPLACE;
+    assert_eq!(rename(&t, "/etc/passwd", "/tmp/d", both), Err(22));
+    assert_eq!(rename(&t, "/etc/passwd", "/tmp/none", RenameFlags::RENAME_EXCHANGE), Err(2));
+}
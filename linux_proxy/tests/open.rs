@@ -0,0 +1,70 @@
+mod common;
+
+use common::*;
+use linux_proxy::{AT_FDCWD, Fd, FdFlags, OpenFlags, Task, open::sys_openat};
+use protocol::Value;
+
+fn open(t: &Task<Machine>, dirfd: i32, p: &str, flags: OpenFlags, mode: u32) -> Result<usize, u8> {
+    let (_c, path) = cstr(p);
+    errno(block_on(sys_openat(t.clone(), Fd(dirfd), path, flags.bits(), mode)))
+}
+
+const NONE: OpenFlags = OpenFlags::O_RDONLY;
+
+#[test]
+fn resolution() {
+    let (t, _s) = world();
+    assert_eq!(open(&t, AT_FDCWD, "/etc/passwd", NONE, 0), Ok(0));
+    assert_eq!(open(&t, AT_FDCWD, "etc/../etc/./passwd", NONE, 0), Ok(1));
+    // .. at the root stays there
+    assert_eq!(open(&t, AT_FDCWD, "/../../etc/passwd", NONE, 0), Ok(2));
+    assert_eq!(open(&t, AT_FDCWD, "/etc/nope", NONE, 0), Err(2));
+    assert_eq!(open(&t, AT_FDCWD, "/nope/x", OpenFlags::O_CREAT, 0o644), Err(2));
+    assert_eq!(open(&t, AT_FDCWD, "/etc/passwd/x", NONE, 0), Err(20));
+    assert_eq!(open(&t, AT_FDCWD, "/etc/passwd/", NONE, 0), Err(20));
+    assert_eq!(open(&t, AT_FDCWD, "/etc/passwd/..", NONE, 0), Err(20));
+    assert_eq!(open(&t, AT_FDCWD, "/etc/passwd", OpenFlags::O_DIRECTORY, 0), Err(20));
+    assert_eq!(open(&t, AT_FDCWD, "/etc", OpenFlags::O_WRONLY, 0), Err(21));
+    assert_eq!(open(&t, AT_FDCWD, "/etc", OpenFlags::O_DIRECTORY, 0), Ok(3));
+    assert_eq!(open(&t, 3, "passwd", NONE, 0), Ok(4));
+    assert_eq!(open(&t, AT_FDCWD, "", NONE, 0), Err(2));
+    // the lowest free descriptor
+    t.process.remove_fd(Fd(1));
+    assert_eq!(open(&t, AT_FDCWD, "/", NONE, 0), Ok(1));
+}
+
+#[test]
+fn create() {
+    let (t, s) = world();
+    let excl = OpenFlags::O_CREAT | OpenFlags::O_EXCL;
+    assert_eq!(open(&t, AT_FDCWD, "/etc/passwd", excl, 0o644), Err(17));
+    assert_eq!(open(&t, AT_FDCWD, "/tmp/new", OpenFlags::O_CREAT | OpenFlags::O_WRONLY, 0o666), Ok(0));
+    assert_eq!(open(&t, AT_FDCWD, "/tmp/new", excl, 0o666), Err(17));
+    // not a way to make a directory
+    assert_eq!(open(&t, AT_FDCWD, "/tmp/dir", OpenFlags::O_CREAT | OpenFlags::O_DIRECTORY, 0o755), Err(22));
+    assert_eq!(attr(&s, names(TMP), "dir"), None);
+    assert_eq!(open(&t, AT_FDCWD, "/tmp/new", NONE, 0), Ok(1));
+    // less the umask
+    let f = t.process.get_fd(Fd(0)).unwrap().obj;
+    assert_eq!(attr(&s, f, "mode"), Some(Value::Unsigned(0o644)));
+}
+
+#[test]
+fn truncate() {
+    let (t, s) = world();
+    assert_eq!(open(&t, AT_FDCWD, "/etc/passwd", OpenFlags::O_WRONLY | OpenFlags::O_TRUNC, 0), Ok(0));
+    assert_eq!(attr(&s, PASSWD, "size"), Some(Value::Unsigned(0)));
+    set(&mut s.lock().unwrap(), PASSWD, "size", Value::Unsigned(3));
+    set(&mut s.lock().unwrap(), PASSWD, "contents", Value::Bytes(b"abc".to_vec()));
+    // read only truncates too, as linux does
+    assert_eq!(open(&t, AT_FDCWD, "/etc/passwd", OpenFlags::O_TRUNC, 0), Ok(1));
+    assert_eq!(attr(&s, PASSWD, "size"), Some(Value::Unsigned(0)));
+}
+
+#[test]
+fn truncate_without_a_descriptor() {
+    let (t, s) = world();
+    while t.process.insert_fd(ROOT, NONE, FdFlags::empty(), None).is_ok() {}
+    assert_eq!(open(&t, AT_FDCWD, "/etc/passwd", OpenFlags::O_WRONLY | OpenFlags::O_TRUNC, 0), Err(24));
+    assert_eq!(attr(&s, PASSWD, "size"), Some(Value::Unsigned(11)));
+}
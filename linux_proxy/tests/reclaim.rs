@@ -0,0 +1,131 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AddressSpace, AtFlags, Fd, Lockable, OpenFlags, Pid, ProcessState, Task,
+    close::sys_close,
+    exit::{ChildState, SIGCHLD, sys_exit, sys_exit_group},
+    mkdir::sys_mkdirat,
+    open::sys_openat,
+    rename::{RenameFlags, sys_renameat2},
+    rw::sys_read,
+    unlink::sys_unlinkat,
+};
+use protocol::{Oid, Resolver};
+use std::sync::{Arc, Mutex};
+
+fn open(t: &Task<Machine>, p: &str, flags: OpenFlags) -> Fd {
+    let (_c, path) = cstr(p);
+    Fd(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, flags.bits(), 0o644)).unwrap() as i32)
+}
+
+fn unlink(t: &Task<Machine>, p: &str, flags: AtFlags) -> Result<usize, u8> {
+    let (_c, path) = cstr(p);
+    errno(block_on(sys_unlinkat(t.clone(), Fd(AT_FDCWD), path, flags)))
+}
+
+fn exists(s: &Arc<Mutex<protocol::Store>>, o: Oid) -> bool {
+    s.lock().unwrap().resolve(o).is_some()
+}
+
+#[test]
+fn unlinked_and_closed() {
+    let (t, s) = world();
+    assert_eq!(unlink(&t, "/etc/passwd", AtFlags::empty()), Ok(0));
+    assert!(!exists(&s, PASSWD));
+
+    // still open, so it stays until the close
+    let fd = open(&t, "/tmp/f", OpenFlags::O_CREAT | OpenFlags::O_RDWR);
+    let f = t.process.get_fd(fd).unwrap().obj;
+    let again = open(&t, "/tmp/f", OpenFlags::O_RDONLY);
+    assert_eq!(unlink(&t, "/tmp/f", AtFlags::empty()), Ok(0));
+    assert!(exists(&s, f));
+    assert_eq!(errno(block_on(sys_close(t.clone(), fd))), Ok(0));
+    assert!(exists(&s, f));
+    assert_eq!(errno(block_on(sys_close(t.clone(), again))), Ok(0));
+    assert!(!exists(&s, f));
+    assert_eq!(errno(block_on(sys_close(t.clone(), again))), Err(9));
+
+    // closing something that still has a name leaves it alone
+    let fd = open(&t, "/etc", OpenFlags::O_RDONLY);
+    block_on(sys_close(t.clone(), fd)).unwrap();
+    assert!(exists(&s, ETC));
+}
+
+#[test]
+fn rmdir_takes_its_names() {
+    let (t, s) = world();
+    let (_c, path) = cstr("/tmp/d");
+    block_on(sys_mkdirat(t.clone(), Fd(AT_FDCWD), path, 0o755)).unwrap();
+    let d = attr(&s, names(TMP), "d").unwrap();
+    let protocol::Value::Oid(d) = d else { panic!() };
+    let protocol::Value::Oid(children) = attr(&s, d, "children").unwrap() else { panic!() };
+    assert_eq!(unlink(&t, "/tmp/d", AtFlags::AT_REMOVEDIR), Ok(0));
+    assert!(!exists(&s, d));
+    assert!(!exists(&s, children));
+    assert!(exists(&s, TMP));
+}
+
+#[test]
+fn replaced_by_rename() {
+    let (t, s) = world();
+    let fd = open(&t, "/tmp/f", OpenFlags::O_CREAT | OpenFlags::O_RDWR);
+    block_on(sys_close(t.clone(), fd)).unwrap();
+    let (_a, from) = cstr("/tmp/f");
+    let (_b, to) = cstr("/etc/passwd");
+    block_on(sys_renameat2(t.clone(), Fd(AT_FDCWD), from, Fd(AT_FDCWD), to, RenameFlags::empty())).unwrap();
+    assert!(!exists(&s, PASSWD));
+}
+
+#[test]
+fn forked_copies_hold_it_open() {
+    let (t, s) = world();
+    let fd = open(&t, "/etc/passwd", OpenFlags::O_RDONLY);
+    // in the same memory, like a vfork, so peek sees what it reads
+    let child = Task::new(t.process.fork(PROCESS, Pid(2)));
+    assert_eq!(unlink(&t, "/etc/passwd", AtFlags::empty()), Ok(0));
+    assert_eq!(errno(block_on(sys_close(t.clone(), fd))), Ok(0));
+    // the child's copy still has it
+    assert!(exists(&s, PASSWD));
+    assert_eq!(errno(block_on(sys_read(child.clone(), fd, AddressSpace::User(0), 16))), Ok(11));
+    assert_eq!(peek(&s, 0, 11), b"root:x:0:0\n");
+    assert_eq!(errno(block_on(sys_close(child.clone(), fd))), Ok(0));
+    assert!(!exists(&s, PASSWD));
+}
+
+#[test]
+fn exit_closes_everything() {
+    let (init, s) = world();
+    let t = Task::new(init.process.fork(Oid(3), Pid(2)));
+    let fd = open(&t, "/tmp/f", OpenFlags::O_CREAT | OpenFlags::O_RDWR);
+    let f = t.process.get_fd(fd).unwrap().obj;
+    open(&t, "/etc/passwd", OpenFlags::O_RDONLY);
+    assert_eq!(unlink(&t, "/tmp/f", AtFlags::empty()), Ok(0));
+    block_on(sys_exit_group(t.clone(), 0)).unwrap();
+    assert!(!exists(&s, f));
+    assert!(exists(&s, PASSWD));
+    assert!(t.process.get_fd(fd).is_err());
+    assert!(t.state.lock().is_finished());
+}
+
+#[test]
+fn exit_tells_the_parent() {
+    let (init, _s) = world();
+    let parent = Task::new(init.process.fork(Oid(3), Pid(2)));
+    let t = Task::new(parent.process.fork(Oid(4), Pid(3)));
+    let orphan = t.process.fork(Oid(5), Pid(4));
+    block_on(sys_exit(t.clone(), 3)).unwrap();
+    assert_eq!(*t.process.state.lock(), ProcessState::Exiting);
+    assert!(t.state.lock().is_finished());
+    // the code is kept for wait, and the parent gets a SIGCHLD
+    assert!(parent.process.children.lock().is_empty());
+    assert_eq!(parent.process.child_states.lock().get(&Pid(3)), Some(&ChildState::NormalExit { code: 3 }));
+    assert_eq!(*parent.process.pending.lock() & SIGCHLD, SIGCHLD);
+    // and what it started is init's now
+    assert_eq!(*orphan.parent.lock(), Pid(1));
+    assert!(init.process.children.lock().contains_key(&Pid(4)));
+
+    // a second exit finds it already on the way out
+    block_on(sys_exit_group(t.clone(), 5)).unwrap();
+    assert_eq!(parent.process.child_states.lock().get(&Pid(3)), Some(&ChildState::NormalExit { code: 3 }));
+}
@@ -0,0 +1,92 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, Fd, OpenFlags, Runtime, Task,
+    close::sys_close,
+    open::sys_openat,
+    record::{DynSink, Record, Recorder, Replay},
+    rw::{sys_read, sys_write},
+    stat::sys_fstat,
+};
+use protocol::{Buffer, Command, Encodable, Error, Oid, Resolver, Store, Value, attribute};
+use std::sync::{Arc, Mutex};
+
+// a sink into memory, standing in for the monitor's file
+fn tape() -> (DynSink, Arc<Mutex<Vec<u8>>>) {
+    let log = Arc::new(Mutex::new(vec![]));
+    let into = log.clone();
+    let sink = move |b: &[u8]| -> Result<(), Error> {
+        into.lock().unwrap().extend_from_slice(b);
+        Ok(())
+    };
+    (Arc::new(sink), log)
+}
+
+// the record's own timestamps, which would differ from run to run
+fn zero() -> protocol::DynClock {
+    Arc::new(|| 0)
+}
+
+// open, read, stat, write somewhere new, and close, with the answers and
+// whatever fstat filled in
+fn run<R: Runtime>(t: &Task<R>) -> (Vec<Result<usize, u8>>, [u8; 256]) {
+    let mut results = vec![];
+    let mut st = [0u8; 256];
+    let (_a, passwd) = cstr("/etc/passwd");
+    let (_b, f) = cstr("/tmp/f");
+    let buf = linux_proxy::AddressSpace::User(0);
+    let rd = Fd(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), passwd, 0, 0)).unwrap() as i32);
+    results.push(errno(block_on(sys_read(t.clone(), rd, buf, 4))));
+    results.push(errno(block_on(sys_fstat(t.clone(), rd, user(&mut st)))));
+    let flags = OpenFlags::O_CREAT | OpenFlags::O_WRONLY;
+    let wr = Fd(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), f, flags.bits(), 0o644)).unwrap() as i32);
+    results.push(errno(block_on(sys_write(t.clone(), wr, buf, 4))));
+    results.push(errno(block_on(sys_close(t.clone(), wr))));
+    results.push(errno(block_on(sys_close(t.clone(), rd))));
+    (results, st)
+}
+
+#[test]
+fn replays_a_run() {
+    let (m, s) = machine();
+    let (sink, recorded) = tape();
+    let t = boot(Recorder::new(m, sink, zero()));
+    let (results, st) = run(&t);
+    assert_eq!(results, vec![Ok(4), Ok(0), Ok(4), Ok(0), Ok(0)]);
+    assert_eq!(peek(&s, 0, 4), b"root");
+    let recorded = recorded.lock().unwrap().clone();
+
+    // nothing behind it, so every answer has to come from the recording,
+    // and recording the replay gives back the same log row for row
+    let (empty, nothing) = over(Store::new(Oid(1)));
+    *empty.clock.lock().unwrap() = 0;
+    let (sink, again) = tape();
+    let t = boot(Recorder::new(Replay::new(empty, &recorded).unwrap(), sink, zero()));
+    assert_eq!(run(&t), (results, st));
+    assert_eq!(*again.lock().unwrap(), recorded);
+    assert!(nothing.lock().unwrap().resolve(PASSWD).is_none());
+}
+
+#[test]
+fn times_follow_their_block() {
+    let block = vec![Command::Get(Value::Oid(ROOT), attribute!("mode"), Value::Variable(0))];
+    let mut b = Buffer::new();
+    for r in [
+        Record::Now { seq: 0, time: 0, now: 5 },
+        Record::Now { seq: 0, time: 0, now: 6 },
+        Record::Block { seq: 0, time: 0, block: block.clone() },
+        Record::End { seq: 0, time: 0, error: None },
+        Record::Now { seq: 1, time: 0, now: 9 },
+    ] {
+        r.encode(&mut b).unwrap();
+    }
+    let (empty, _) = over(Store::new(Oid(1)));
+    let p = Replay::new(empty, b.bytes()).unwrap();
+    // one read fewer before the block than when it was recorded, which
+    // doesn't move the one after it
+    assert_eq!(p.now().as_nanos(), 5);
+    assert_eq!(block_on(p.execute(block).unwrap().next()).unwrap(), None);
+    assert_eq!(p.now().as_nanos(), 9);
+    assert_eq!(p.now().as_nanos(), 0);
+}
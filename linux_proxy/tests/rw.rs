@@ -0,0 +1,114 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AddressSpace, Fd, OpenFlags, Task,
+    open::sys_openat,
+    rw::{MAX_FILE_SIZE, sys_pread64, sys_pwrite64, sys_read, sys_write},
+    seek::sys_lseek,
+};
+use protocol::Value;
+
+const SEEK_SET: u32 = 0;
+
+fn open(t: &Task<Machine>, p: &str, flags: OpenFlags) -> Fd {
+    let (_c, path) = cstr(p);
+    Fd(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, flags.bits(), 0o644)).unwrap() as i32)
+}
+
+fn at(addr: u64) -> AddressSpace {
+    AddressSpace::User(addr)
+}
+
+fn pos(t: &Task<Machine>, fd: Fd) -> u64 {
+    t.process.get_fd(fd).unwrap().pos
+}
+
+#[test]
+fn reads_move_along() {
+    let (t, s) = world();
+    let fd = open(&t, "/etc/passwd", OpenFlags::O_RDONLY);
+    assert_eq!(errno(block_on(sys_read(t.clone(), fd, at(0), 4))), Ok(4));
+    assert_eq!(peek(&s, 0, 4), b"root");
+    // short at the end, then nothing
+    assert_eq!(errno(block_on(sys_read(t.clone(), fd, at(0), 100))), Ok(7));
+    assert_eq!(peek(&s, 0, 7), b":x:0:0\n");
+    assert_eq!(errno(block_on(sys_read(t.clone(), fd, at(0), 100))), Ok(0));
+    assert_eq!(errno(block_on(sys_write(t.clone(), fd, at(0), 1))), Err(9));
+
+    // pread leaves the position alone
+    assert_eq!(errno(block_on(sys_pread64(t.clone(), fd, at(100), 2, 5))), Ok(2));
+    assert_eq!(peek(&s, 100, 2), b"x:");
+    assert_eq!(errno(block_on(sys_pread64(t.clone(), fd, at(100), 2, -1))), Err(22));
+    assert_eq!(pos(&t, fd), 11);
+
+    let d = open(&t, "/tmp", OpenFlags::O_RDONLY);
+    assert_eq!(errno(block_on(sys_read(t.clone(), d, at(0), 1))), Err(21));
+}
+
+#[test]
+fn writes_and_appends() {
+    let (t, s) = world();
+    poke(&s, 200, b"hello world");
+    let w = open(&t, "/tmp/f", OpenFlags::O_CREAT | OpenFlags::O_WRONLY);
+    assert_eq!(errno(block_on(sys_read(t.clone(), w, at(0), 1))), Err(9));
+    assert_eq!(errno(block_on(sys_write(t.clone(), w, at(200), 5))), Ok(5));
+    // a hole in between reads back as zeros
+    assert_eq!(errno(block_on(sys_pwrite64(t.clone(), w, at(205), 6, 10))), Ok(6));
+    assert_eq!(pos(&t, w), 5);
+    let f = t.process.get_fd(w).unwrap().obj;
+    assert_eq!(attr(&s, f, "size"), Some(Value::Unsigned(16)));
+    assert_eq!(attr(&s, f, "contents"), Some(Value::Bytes(b"hello\0\0\0\0\0 world".to_vec())));
+
+    // appends go on the end whatever the offset says
+    let a = open(&t, "/tmp/f", OpenFlags::O_APPEND | OpenFlags::O_WRONLY);
+    assert_eq!(errno(block_on(sys_write(t.clone(), a, at(200), 2))), Ok(2));
+    assert_eq!(errno(block_on(sys_pwrite64(t.clone(), a, at(200), 2, 0))), Ok(2));
+    assert_eq!(attr(&s, f, "size"), Some(Value::Unsigned(20)));
+    assert_eq!(pos(&t, a), 18);
+}
+
+#[test]
+fn nothing_past_the_largest_file() {
+    let (t, _s) = world();
+    let w = open(&t, "/tmp/f", OpenFlags::O_CREAT | OpenFlags::O_WRONLY);
+    let max = MAX_FILE_SIZE as i64;
+    assert_eq!(errno(block_on(sys_lseek(t.clone(), w, max, SEEK_SET))), Ok(max as usize));
+    assert_eq!(errno(block_on(sys_write(t.clone(), w, at(0), 4))), Err(27));
+    // the range it claimed is handed back
+    assert_eq!(pos(&t, w), MAX_FILE_SIZE);
+    assert_eq!(errno(block_on(sys_write(t.clone(), w, at(0), 0))), Ok(0));
+}
+
+#[test]
+fn read_races_a_seek() {
+    let (t, s) = world();
+    let fd = open(&t, "/etc/passwd", OpenFlags::O_RDONLY);
+    block_on(sys_read(t.clone(), fd, at(0), 5)).unwrap();
+    // someone seeks back to the start while the read is out, so it is read
+    // again from there rather than landing on top of the seek
+    let other = t.clone();
+    *t.process.kernel.runtime.meanwhile.lock().unwrap() = Some(Box::new(move || {
+        block_on(sys_lseek(other, fd, 0, SEEK_SET)).unwrap();
+    }));
+    assert_eq!(errno(block_on(sys_read(t.clone(), fd, at(0), 4))), Ok(4));
+    assert_eq!(peek(&s, 0, 4), b"root");
+    assert_eq!(pos(&t, fd), 4);
+}
+
+#[test]
+fn writes_race_each_other() {
+    let (t, s) = world();
+    poke(&s, 0, b"firstsecond");
+    let w = open(&t, "/tmp/f", OpenFlags::O_CREAT | OpenFlags::O_WRONLY);
+    // a second write through the same descriptor goes out while the first
+    // is still on its way, and takes the range after it
+    let other = t.clone();
+    *t.process.kernel.runtime.meanwhile.lock().unwrap() = Some(Box::new(move || {
+        assert_eq!(block_on(sys_write(other, w, at(5), 6)).unwrap(), 6);
+    }));
+    assert_eq!(errno(block_on(sys_write(t.clone(), w, at(0), 5))), Ok(5));
+    let f = t.process.get_fd(w).unwrap().obj;
+    assert_eq!(attr(&s, f, "contents"), Some(Value::Bytes(b"firstsecond".to_vec())));
+    assert_eq!(pos(&t, w), 11);
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AddressSpace, Fd, FileType, OpenFlags, Task,
+    open::sys_openat,
+    rw::sys_write,
+    seek::sys_lseek,
+};
+use protocol::{Command, Oid, Value};
+
+const SEEK_SET: u32 = 0;
+const SEEK_CUR: u32 = 1;
+const SEEK_END: u32 = 2;
+const SEEK_DATA: u32 = 3;
+const SEEK_HOLE: u32 = 4;
+
+fn open(t: &Task<Machine>, p: &str, flags: OpenFlags) -> Fd {
+    let (_c, path) = cstr(p);
+    Fd(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, flags.bits(), 0o644)).unwrap() as i32)
+}
+
+fn seek(t: &Task<Machine>, fd: Fd, offset: i64, whence: u32) -> Result<usize, u8> {
+    errno(block_on(sys_lseek(t.clone(), fd, offset, whence)))
+}
+
+#[test]
+fn set_cur_end() {
+    let (t, _s) = world();
+    let fd = open(&t, "/etc/passwd", OpenFlags::O_RDONLY);
+    assert_eq!(seek(&t, fd, 3, SEEK_SET), Ok(3));
+    assert_eq!(seek(&t, fd, 2, SEEK_CUR), Ok(5));
+    assert_eq!(seek(&t, fd, -6, SEEK_CUR), Err(22));
+    assert_eq!(seek(&t, fd, -1, SEEK_END), Ok(10));
+    assert_eq!(seek(&t, fd, -12, SEEK_END), Err(22));
+    assert_eq!(seek(&t, fd, -1, SEEK_SET), Err(22));
+    assert_eq!(seek(&t, fd, 0, 7), Err(22));
+    // none of the failures moved it
+    assert_eq!(t.process.get_fd(fd).unwrap().pos, 10);
+}
+
+#[test]
+fn data_and_holes() {
+    let (t, s) = world();
+    {
+        // data at [0, 10), [4096, 5096) and [8192, 8292) of 10000
+        let mut s = s.lock().unwrap();
+        mkfile(&mut s, Oid(20), TMP, "sparse", &[0u8; 10000]);
+        s.commit(vec![Command::Create(Value::Oid(Oid(21)))]).unwrap();
+        set(&mut s, Oid(20), "extents", Value::Oid(Oid(21)));
+        for (start, length) in [(4096, 1000), (8192, 100), (0, 10)] {
+            s.commit(vec![Command::Set(Value::Oid(Oid(21)), Value::Unsigned(start), Value::Unsigned(length))])
+                .unwrap();
+        }
+    }
+    // without extents it is all data, with the hole at the end
+    let fd = open(&t, "/etc/passwd", OpenFlags::O_RDONLY);
+    assert_eq!(seek(&t, fd, 0, SEEK_DATA), Ok(0));
+    assert_eq!(seek(&t, fd, 0, SEEK_HOLE), Ok(11));
+    assert_eq!(seek(&t, fd, 11, SEEK_DATA), Err(6));
+    assert_eq!(seek(&t, fd, -1, SEEK_DATA), Err(6));
+
+    let fd = open(&t, "/tmp/sparse", OpenFlags::O_RDWR);
+    assert_eq!(seek(&t, fd, 0, SEEK_DATA), Ok(0));
+    assert_eq!(seek(&t, fd, 0, SEEK_HOLE), Ok(10));
+    assert_eq!(seek(&t, fd, 10, SEEK_DATA), Ok(4096));
+    assert_eq!(t.process.get_fd(fd).unwrap().pos, 4096);
+    assert_eq!(seek(&t, fd, 4100, SEEK_HOLE), Ok(5096));
+    assert_eq!(seek(&t, fd, 5096, SEEK_DATA), Ok(8192));
+    assert_eq!(seek(&t, fd, 8300, SEEK_DATA), Err(6));
+    assert_eq!(seek(&t, fd, 8300, SEEK_HOLE), Ok(8300));
+    assert_eq!(seek(&t, fd, 8200, SEEK_HOLE), Ok(8292));
+
+    // a write forgets the extents, so it's all data again
+    seek(&t, fd, 20, SEEK_SET).unwrap();
+    block_on(sys_write(t.clone(), fd, AddressSpace::User(0), 1)).unwrap();
+    assert_eq!(seek(&t, fd, 10, SEEK_DATA), Ok(10));
+}
+
+#[test]
+fn what_can_seek() {
+    let (t, s) = world();
+    {
+        let mut s = s.lock().unwrap();
+        mkfile(&mut s, Oid(22), TMP, "fifo", b"");
+        set(&mut s, Oid(22), "type", Value::from(FileType::Fifo));
+    }
+    let fd = open(&t, "/tmp/fifo", OpenFlags::O_RDONLY);
+    assert_eq!(seek(&t, fd, 0, SEEK_SET), Err(29));
+    // a directory has cookies rather than an end
+    let fd = open(&t, "/tmp", OpenFlags::O_RDONLY);
+    assert_eq!(seek(&t, fd, 0, SEEK_END), Err(22));
+    assert_eq!(seek(&t, fd, 0, SEEK_DATA), Err(22));
+    assert_eq!(seek(&t, fd, 0, SEEK_SET), Ok(0));
+}
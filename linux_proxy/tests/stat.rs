@@ -0,0 +1,79 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AtFlags, Fd, Task,
+    access::sys_faccessat,
+    open::sys_openat,
+    readlink::sys_symlinkat,
+    stat::{Stat, Statx, sys_fstat, sys_newfstatat, sys_statx},
+};
+use protocol::{Command, Oid, Value};
+
+fn stat(t: &Task<Machine>, dirfd: i32, p: &str, flags: AtFlags) -> Result<Stat, u8> {
+    let (_c, path) = cstr(p);
+    let mut st = Stat::default();
+    errno(block_on(sys_newfstatat(t.clone(), Fd(dirfd), path, user(&mut st), flags)))?;
+    Ok(st)
+}
+
+fn open(t: &Task<Machine>, p: &str) -> Fd {
+    let (_c, path) = cstr(p);
+    Fd(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, 0, 0)).unwrap() as i32)
+}
+
+#[test]
+fn from_attributes() {
+    let (t, _s) = world();
+    let s = stat(&t, AT_FDCWD, "/etc/passwd", AtFlags::empty()).unwrap();
+    assert_eq!((s.st_ino, s.st_mode, s.st_size, s.st_nlink, s.st_blocks), (12, 0o100644, 11, 1, 1));
+    // a directory has no size of its own
+    let s = stat(&t, AT_FDCWD, "/etc", AtFlags::empty()).unwrap();
+    assert_eq!((s.st_ino, s.st_mode, s.st_size, s.st_nlink), (11, 0o40755, 4096, 2));
+    assert_eq!(stat(&t, AT_FDCWD, "/nope", AtFlags::empty()).err(), Some(2));
+    assert_eq!(stat(&t, AT_FDCWD, "", AtFlags::empty()).err(), Some(2));
+    assert_eq!(stat(&t, AT_FDCWD, "", AtFlags::AT_EMPTY_PATH).unwrap().st_ino, 10);
+    assert_eq!(std::mem::size_of::<Stat>(), 128);
+}
+
+#[test]
+fn links_and_descriptors() {
+    let (t, _s) = world();
+    let (_a, target) = cstr("/etc/passwd");
+    let (_b, link) = cstr("/tmp/l");
+    block_on(sys_symlinkat(t.clone(), target, Fd(AT_FDCWD), link)).unwrap();
+    assert_eq!(stat(&t, AT_FDCWD, "/tmp/l", AtFlags::empty()).unwrap().st_ino, 12);
+    let s = stat(&t, AT_FDCWD, "/tmp/l", AtFlags::AT_SYMLINK_NOFOLLOW).unwrap();
+    assert_eq!((s.st_mode, s.st_size), (0o120777, 11));
+
+    let fd = open(&t, "/etc/passwd");
+    let mut s = Stat::default();
+    assert_eq!(errno(block_on(sys_fstat(t.clone(), fd, user(&mut s)))), Ok(0));
+    assert_eq!(s.st_ino, 12);
+    assert_eq!(stat(&t, fd.0, "", AtFlags::AT_EMPTY_PATH).unwrap().st_ino, 12);
+
+    let etc = open(&t, "/etc");
+    let (_p, passwd) = cstr("passwd");
+    let mut x = Statx::default();
+    block_on(sys_statx(t.clone(), etc, passwd, AtFlags::empty(), 0x7ff, user(&mut x))).unwrap();
+    assert_eq!((x.stx_ino, x.stx_size, x.stx_mode, x.stx_mask), (12, 11, 0o100644, 0x71f));
+    assert_eq!(std::mem::size_of::<Statx>(), 256);
+}
+
+#[test]
+fn bare_entity() {
+    // nothing but contents, the way something put there from outside the
+    // proxy looks. it reads as namei sees it, a file with the one link
+    let (t, s) = world();
+    let bare = Oid(20);
+    {
+        let mut s = s.lock().unwrap();
+        s.commit(vec![Command::Create(Value::Oid(bare))]).unwrap();
+        set(&mut s, bare, "contents", Value::Bytes(b"hi".to_vec()));
+        set(&mut s, names(TMP), "bare", Value::Oid(bare));
+    }
+    let st = stat(&t, AT_FDCWD, "/tmp/bare", AtFlags::empty()).unwrap();
+    assert_eq!((st.st_ino, st.st_mode, st.st_nlink, st.st_uid, st.st_gid), (20, 0o100000, 1, 0, 0));
+    let (_c, path) = cstr("/tmp/bare");
+    assert_eq!(errno(block_on(sys_faccessat(t.clone(), Fd(AT_FDCWD), path, 4))), Ok(0));
+}
@@ -0,0 +1,74 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AddressSpace, Fd, OpenFlags, Task,
+    open::sys_openat,
+    readlink::{sys_readlinkat, sys_symlinkat},
+};
+
+fn open(t: &Task<Machine>, p: &str, flags: OpenFlags) -> Result<protocol::Oid, u8> {
+    let (_c, path) = cstr(p);
+    let fd = errno(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, flags.bits(), 0o644)))?;
+    Ok(t.process.get_fd(Fd(fd as i32)).unwrap().obj)
+}
+
+fn symlink(t: &Task<Machine>, target: &str, p: &str) -> Result<usize, u8> {
+    let (_a, target) = cstr(target);
+    let (_b, path) = cstr(p);
+    errno(block_on(sys_symlinkat(t.clone(), target, Fd(AT_FDCWD), path)))
+}
+
+fn readlink(t: &Task<Machine>, p: &str, size: usize) -> Result<Vec<u8>, u8> {
+    let (_c, path) = cstr(p);
+    let mut buf = vec![0u8; 64];
+    let to = AddressSpace::User(buf.as_mut_ptr() as u64);
+    let n = errno(block_on(sys_readlinkat(t.clone(), Fd(AT_FDCWD), path, to, size)))?;
+    buf.truncate(n);
+    Ok(buf)
+}
+
+#[test]
+fn made_and_read_back() {
+    let (t, _s) = world();
+    assert_eq!(symlink(&t, "/etc/passwd", "/tmp/abs"), Ok(0));
+    assert_eq!(symlink(&t, "x", "/tmp/abs"), Err(17));
+    assert_eq!(readlink(&t, "/tmp/abs", 64), Ok(b"/etc/passwd".to_vec()));
+    // cut short without a nul, the way linux does it
+    assert_eq!(readlink(&t, "/tmp/abs", 4), Ok(b"/etc".to_vec()));
+    assert_eq!(readlink(&t, "/tmp/abs", 0), Err(22));
+    assert_eq!(readlink(&t, "/etc/passwd", 64), Err(22));
+}
+
+#[test]
+fn followed_on_lookup() {
+    let (t, _s) = world();
+    symlink(&t, "/etc/passwd", "/tmp/abs").unwrap();
+    symlink(&t, "../etc", "/tmp/rel").unwrap();
+    symlink(&t, "dangling", "/tmp/d").unwrap();
+    assert_eq!(open(&t, "/tmp/abs", OpenFlags::O_RDONLY), Ok(PASSWD));
+    // relative to the directory the link is in, not the cwd
+    assert_eq!(open(&t, "/tmp/rel/passwd", OpenFlags::O_RDONLY), Ok(PASSWD));
+    assert_eq!(open(&t, "/tmp/rel/", OpenFlags::O_DIRECTORY), Ok(ETC));
+    assert_eq!(readlink(&t, "/tmp/rel/passwd", 64), Err(22));
+    assert_eq!(open(&t, "/tmp/abs", OpenFlags::O_NOFOLLOW), Err(40));
+    assert_eq!(open(&t, "/tmp/abs", OpenFlags::O_CREAT | OpenFlags::O_EXCL), Err(17));
+    assert_eq!(open(&t, "/tmp/d", OpenFlags::O_RDONLY), Err(2));
+}
+
+#[test]
+fn loops() {
+    let (t, _s) = world();
+    symlink(&t, "/tmp/loop", "/tmp/loop").unwrap();
+    assert_eq!(open(&t, "/tmp/loop", OpenFlags::O_RDONLY), Err(40));
+    assert_eq!(open(&t, "/tmp/loop/x", OpenFlags::O_RDONLY), Err(40));
+    assert_eq!(readlink(&t, "/tmp/loop", 64), Ok(b"/tmp/loop".to_vec()));
+
+    // a chain of 40 is fine, 41 is not
+    for i in 0..41 {
+        symlink(&t, &format!("l{}", i + 1), &format!("/tmp/l{}", i)).unwrap();
+    }
+    symlink(&t, "/etc/passwd", "/tmp/l41").unwrap();
+    assert_eq!(open(&t, "/tmp/l2", OpenFlags::O_RDONLY), Ok(PASSWD));
+    assert_eq!(open(&t, "/tmp/l1", OpenFlags::O_RDONLY), Err(40));
+}
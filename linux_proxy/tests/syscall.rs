@@ -0,0 +1,76 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    Credentials, Gid, Lockable, Uid, RLIM_INFINITY, RLimit, RlimitId,
+    syscall::{Dispatch, SYS_GETGROUPS, SYS_PRLIMIT64, SYS_SET_ROBUST_LIST, to_x0},
+};
+
+fn call(d: &Dispatch<Machine>, num: u32, a: [u64; 6]) -> i64 {
+    to_x0(block_on(d.dispatch(num, a))) as i64
+}
+
+fn at<T>(x: &mut T) -> u64 {
+    x as *mut T as u64
+}
+
+#[test]
+fn unknown_is_enosys() {
+    let (t, _s) = world();
+    let d = Dispatch::new(t);
+    assert_eq!(call(&d, 4000, [0; 6]), -38);
+    assert_eq!(d.unknown(), 1);
+    let getgroups = d.counts().find(|(n, ..)| *n == SYS_GETGROUPS).unwrap();
+    assert_eq!(getgroups.2, 0);
+}
+
+#[test]
+fn getgroups_negative_size() {
+    let (t, _s) = world();
+    *t.process.creds.lock() = Credentials::new_user(Uid::new(1000), Gid::new(1000), vec![Gid::new(5)]);
+    let d = Dispatch::new(t);
+    let mut g = [0u32; 4];
+    assert_eq!(call(&d, SYS_GETGROUPS, [(-1i64) as u64, at(&mut g), 0, 0, 0, 0]), -22);
+    assert_eq!(g, [0; 4]);
+    assert_eq!(call(&d, SYS_GETGROUPS, [0, 0, 0, 0, 0, 0]), 1);
+    assert_eq!(call(&d, SYS_GETGROUPS, [4, at(&mut g), 0, 0, 0, 0]), 1);
+    assert_eq!(g[0], 5);
+}
+
+#[test]
+fn prlimit_round_trips() {
+    let (t, _s) = world();
+    let d = Dispatch::new(t.clone());
+    let nofile = RlimitId::NOFILE as u64;
+    let mut old = RLimit { rlim_cur: 0, rlim_max: 0 };
+    assert_eq!(call(&d, SYS_PRLIMIT64, [0, nofile, 0, at(&mut old), 0, 0]), 0);
+    assert_eq!(old, RLimit { rlim_cur: 1024, rlim_max: 4096 });
+
+    let mut new = RLimit { rlim_cur: 2048, rlim_max: 4096 };
+    assert_eq!(call(&d, SYS_PRLIMIT64, [1, nofile, at(&mut new), at(&mut old), 0, 0]), 0);
+    assert_eq!(old.rlim_cur, 1024);
+    assert_eq!(call(&d, SYS_PRLIMIT64, [0, nofile, 0, at(&mut old), 0, 0]), 0);
+    assert_eq!(old.rlim_cur, 2048);
+
+    // soft above hard, someone else, and no such limit
+    let mut bad = RLimit { rlim_cur: 5000, rlim_max: 4096 };
+    assert_eq!(call(&d, SYS_PRLIMIT64, [0, nofile, at(&mut bad), 0, 0, 0]), -22);
+    assert_eq!(call(&d, SYS_PRLIMIT64, [7, nofile, 0, at(&mut old), 0, 0]), -3);
+    assert_eq!(call(&d, SYS_PRLIMIT64, [0, 16, 0, at(&mut old), 0, 0]), -22);
+
+    // raising the hard limit takes CAP_SYS_RESOURCE
+    *t.process.creds.lock() = Credentials::new_user(Uid::new(1000), Gid::new(1000), vec![]);
+    let mut up = RLimit { rlim_cur: 1024, rlim_max: RLIM_INFINITY };
+    let stack = RlimitId::STACK as u64;
+    assert_eq!(call(&d, SYS_PRLIMIT64, [0, nofile, at(&mut up), 0, 0, 0]), -1);
+    assert_eq!(call(&d, SYS_PRLIMIT64, [0, stack, at(&mut up), 0, 0, 0]), 0);
+}
+
+#[test]
+fn robust_list_is_only_kept() {
+    let (t, _s) = world();
+    let d = Dispatch::new(t.clone());
+    assert_eq!(call(&d, SYS_SET_ROBUST_LIST, [0x1000, 8, 0, 0, 0, 0]), -22);
+    assert_eq!(call(&d, SYS_SET_ROBUST_LIST, [0x1000, 24, 0, 0, 0, 0]), 0);
+    assert!(matches!(*t.process.robust_list.lock(), Some(linux_proxy::AddressSpace::User(0x1000))));
+}
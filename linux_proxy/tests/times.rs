@@ -0,0 +1,243 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AddressSpace, AtFlags, Fd, OpenFlags, Task,
+    dir::sys_getdents64,
+    fallocate::sys_fallocate,
+    mkdir::sys_mkdirat,
+    open::sys_openat,
+    rw::{sys_pread64, sys_read, sys_write},
+    seek::sys_lseek,
+    stat::dev,
+    times::Atime,
+    truncate::{sys_ftruncate, sys_truncate},
+    utimensat::{UTIME_OMIT, sys_utimensat},
+};
+use protocol::{Command, Oid, Resolver, Store, Value, attribute};
+use std::sync::{Arc, Mutex};
+
+const SEEK_DATA: u32 = 3;
+const SEEK_HOLE: u32 = 4;
+const KEEP_SIZE: u32 = 1;
+const PUNCH: u32 = 3;
+
+fn open(t: &Task<Machine>, p: &str, flags: OpenFlags) -> Fd {
+    let (_c, path) = cstr(p);
+    Fd(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, flags.bits(), 0o644)).unwrap() as i32)
+}
+
+fn at(addr: u64) -> AddressSpace {
+    AddressSpace::User(addr)
+}
+
+fn contents(s: &Arc<Mutex<Store>>, o: Oid) -> Vec<u8> {
+    match attr(s, o, "contents") {
+        Some(Value::Bytes(b)) => b,
+        None => vec![],
+        v => panic!("{:?}", v),
+    }
+}
+
+// the length of the extent starting at start
+fn extent(s: &Arc<Mutex<Store>>, extents: Oid, start: u64) -> Option<Value> {
+    s.lock().unwrap().resolve(extents).unwrap().get(Value::Unsigned(start)).unwrap()
+}
+
+fn fallocate(t: &Task<Machine>, fd: Fd, mode: u32, offset: i64, len: i64) -> Result<usize, u8> {
+    errno(block_on(sys_fallocate(t.clone(), fd, mode, offset, len)))
+}
+
+// nothing on the way asked for the contents themselves
+fn never_read(t: &Task<Machine>) {
+    let blocks = t.process.kernel.runtime.blocks.lock().unwrap();
+    assert!(!blocks.iter().flatten().any(|c| matches!(c, Command::Get(_, a, _) if *a == attribute!("contents"))));
+}
+
+#[test]
+fn truncation() {
+    let (t, s) = world();
+    poke(&s, 200, b"hello world");
+    let w = open(&t, "/tmp/f", OpenFlags::O_CREAT | OpenFlags::O_RDWR);
+    let f = t.process.get_fd(w).unwrap().obj;
+    assert_eq!(secs(&s, f, "mtime"), Some(1000));
+    assert_eq!(secs(&s, TMP, "mtime"), Some(1000));
+    tick(&t, 5);
+    block_on(sys_write(t.clone(), w, at(200), 11)).unwrap();
+    assert_eq!(secs(&s, f, "mtime"), Some(1005));
+    assert_eq!(secs(&s, f, "ctime"), Some(1005));
+
+    tick(&t, 5);
+    t.process.kernel.runtime.blocks.lock().unwrap().clear();
+    assert_eq!(errno(block_on(sys_ftruncate(t.clone(), w, 5))), Ok(0));
+    never_read(&t);
+    assert_eq!(attr(&s, f, "size"), Some(Value::Unsigned(5)));
+    assert_eq!(contents(&s, f), b"hello");
+    assert_eq!(secs(&s, f, "mtime"), Some(1010));
+    // what was cut doesn't come back
+    assert_eq!(errno(block_on(sys_ftruncate(t.clone(), w, 8))), Ok(0));
+    assert_eq!(contents(&s, f), b"hello\0\0\0");
+    let (_c, p) = cstr("/tmp/f");
+    assert_eq!(errno(block_on(sys_truncate(t.clone(), p, 0))), Ok(0));
+    assert_eq!(contents(&s, f), b"");
+    assert_eq!(errno(block_on(sys_truncate(t.clone(), p, 3))), Ok(0));
+    assert_eq!(contents(&s, f), b"\0\0\0");
+
+    assert_eq!(errno(block_on(sys_truncate(t.clone(), p, -1))), Err(22));
+    let (_d, dir) = cstr("/tmp");
+    assert_eq!(errno(block_on(sys_truncate(t.clone(), dir, 0))), Err(21));
+    let r = open(&t, "/tmp/f", OpenFlags::O_RDONLY);
+    assert_eq!(errno(block_on(sys_ftruncate(t.clone(), r, 0))), Err(22));
+}
+
+#[test]
+fn growing_from_nothing() {
+    let (t, s) = world();
+    let w = open(&t, "/tmp/g", OpenFlags::O_CREAT | OpenFlags::O_RDWR);
+    let g = t.process.get_fd(w).unwrap().obj;
+    assert_eq!(errno(block_on(sys_ftruncate(t.clone(), w, 4))), Ok(0));
+    assert_eq!(contents(&s, g), b"\0\0\0\0");
+    let r = open(&t, "/tmp/g", OpenFlags::O_RDONLY);
+    assert_eq!(errno(block_on(sys_read(t.clone(), r, at(300), 10))), Ok(4));
+    tick(&t, 5);
+    open(&t, "/tmp/g", OpenFlags::O_TRUNC | OpenFlags::O_WRONLY);
+    assert_eq!(attr(&s, g, "size"), Some(Value::Unsigned(0)));
+    assert_eq!(secs(&s, g, "mtime"), Some(1005));
+}
+
+#[test]
+fn fallocation() {
+    let (t, s) = world();
+    poke(&s, 200, b"0123456789");
+    let w = open(&t, "/tmp/f", OpenFlags::O_CREAT | OpenFlags::O_RDWR);
+    let f = t.process.get_fd(w).unwrap().obj;
+    block_on(sys_write(t.clone(), w, at(200), 10)).unwrap();
+    // inside the file, or KEEP_SIZE, there's nothing to do
+    assert_eq!(fallocate(&t, w, KEEP_SIZE, 0, 100), Ok(0));
+    assert_eq!(fallocate(&t, w, 0, 4, 2), Ok(0));
+    assert_eq!(attr(&s, f, "size"), Some(Value::Unsigned(10)));
+    assert_eq!(fallocate(&t, w, 0, 10, 6), Ok(0));
+    assert_eq!(contents(&s, f), b"0123456789\0\0\0\0\0\0");
+
+    assert_eq!(fallocate(&t, w, 2, 2, 3), Err(95));
+    assert_eq!(fallocate(&t, w, 0x10, 2, 3), Err(95));
+    assert_eq!(fallocate(&t, w, 0x1000, 2, 3), Err(95));
+    assert_eq!(fallocate(&t, w, 0, 2, 0), Err(22));
+    let r = open(&t, "/tmp/f", OpenFlags::O_RDONLY);
+    assert_eq!(fallocate(&t, r, 0, 0, 1), Err(9));
+}
+
+#[test]
+fn punching_holes() {
+    let (t, s) = world();
+    poke(&s, 200, b"0123456789");
+    let w = open(&t, "/tmp/f", OpenFlags::O_CREAT | OpenFlags::O_RDWR);
+    let f = t.process.get_fd(w).unwrap().obj;
+    block_on(sys_write(t.clone(), w, at(200), 10)).unwrap();
+    block_on(sys_write(t.clone(), w, at(0), 6)).unwrap();
+
+    t.process.kernel.runtime.blocks.lock().unwrap().clear();
+    assert_eq!(fallocate(&t, w, PUNCH, 2, 3), Ok(0));
+    never_read(&t);
+    assert_eq!(contents(&s, f), b"01\0\0\x0056789\0\0\0\0\0\0");
+    assert_eq!(attr(&s, f, "size"), Some(Value::Unsigned(16)));
+    assert_eq!(errno(block_on(sys_lseek(t.clone(), w, 0, SEEK_HOLE))), Ok(2));
+    assert_eq!(errno(block_on(sys_lseek(t.clone(), w, 2, SEEK_DATA))), Ok(5));
+
+    // the second one cuts the extents that are there rather than make new
+    let Some(Value::Oid(extents)) = attr(&s, f, "extents") else {
+        panic!("no extents")
+    };
+    assert_eq!(fallocate(&t, w, PUNCH, 8, 100), Ok(0));
+    assert_eq!(attr(&s, f, "extents"), Some(Value::Oid(extents)));
+    assert_eq!(extent(&s, extents, 0), Some(Value::Unsigned(2)));
+    assert_eq!(extent(&s, extents, 5), Some(Value::Unsigned(3)));
+    assert_eq!(extent(&s, extents, 8), None);
+    assert_eq!(errno(block_on(sys_lseek(t.clone(), w, 6, SEEK_HOLE))), Ok(8));
+    assert_eq!(errno(block_on(sys_lseek(t.clone(), w, 8, SEEK_DATA))), Err(6));
+    assert_eq!(contents(&s, f), b"01\0\0\x00567\0\0\0\0\0\0\0\0");
+    // one out of the middle leaves a piece either side
+    assert_eq!(fallocate(&t, w, PUNCH, 6, 1), Ok(0));
+    assert_eq!(extent(&s, extents, 5), Some(Value::Unsigned(1)));
+    assert_eq!(extent(&s, extents, 7), Some(Value::Unsigned(1)));
+    assert_eq!(contents(&s, f), b"01\0\0\x005\x007\0\0\0\0\0\0\0\0");
+
+    // and a write that drops them takes the entity with it
+    block_on(sys_write(t.clone(), w, at(200), 1)).unwrap();
+    assert_eq!(attr(&s, f, "extents"), None);
+    assert!(s.lock().unwrap().resolve(extents).is_none());
+}
+
+#[test]
+fn utimes() {
+    let (t, s) = world();
+    let w = open(&t, "/tmp/f", OpenFlags::O_CREAT | OpenFlags::O_RDWR);
+    let f = t.process.get_fd(w).unwrap().obj;
+    let (_c, p) = cstr("/tmp/f");
+    tick(&t, 100);
+    let mut ts: [i64; 4] = [5, 0, 7, UTIME_OMIT];
+    assert_eq!(errno(block_on(sys_utimensat(t.clone(), Fd(AT_FDCWD), p, user(&mut ts), AtFlags::empty()))), Ok(0));
+    assert_eq!(secs(&s, f, "atime"), Some(5));
+    assert_eq!(secs(&s, f, "mtime"), Some(1000));
+    assert_eq!(secs(&s, f, "ctime"), Some(1100));
+
+    // futimens, both now
+    tick(&t, 1);
+    assert_eq!(errno(block_on(sys_utimensat(t.clone(), w, at(0), at(0), AtFlags::empty()))), Ok(0));
+    assert_eq!(secs(&s, f, "atime"), Some(1101));
+    assert_eq!(secs(&s, f, "mtime"), Some(1101));
+    let mut bad: [i64; 4] = [5, 1_000_000_000, 7, 0];
+    assert_eq!(errno(block_on(sys_utimensat(t.clone(), w, at(0), user(&mut bad), AtFlags::empty()))), Err(22));
+    assert_eq!(errno(block_on(sys_utimensat(t.clone(), Fd(AT_FDCWD), at(0), at(0), AtFlags::empty()))), Err(14));
+    // with no path there's no link to not follow
+    let nofollow = AtFlags::AT_SYMLINK_NOFOLLOW;
+    assert_eq!(errno(block_on(sys_utimensat(t.clone(), w, at(0), at(0), nofollow))), Err(22));
+
+    // omitting both doesn't even change ctime
+    let mut omit: [i64; 4] = [0, UTIME_OMIT, 0, UTIME_OMIT];
+    tick(&t, 1);
+    assert_eq!(errno(block_on(sys_utimensat(t.clone(), w, at(0), user(&mut omit), AtFlags::empty()))), Ok(0));
+    assert_eq!(secs(&s, f, "ctime"), Some(1101));
+}
+
+#[test]
+fn atime() {
+    let (t, s) = world();
+    poke(&s, 200, b"0123456789");
+    let w = open(&t, "/tmp/f", OpenFlags::O_CREAT | OpenFlags::O_RDWR);
+    let f = t.process.get_fd(w).unwrap().obj;
+    block_on(sys_write(t.clone(), w, at(200), 10)).unwrap();
+    let read = || block_on(sys_pread64(t.clone(), w, at(300), 1, 0)).unwrap();
+
+    // relatime, atime isn't newer than mtime so it moves, then not again
+    // until a day has gone by
+    tick(&t, 10);
+    read();
+    assert_eq!(secs(&s, f, "atime"), Some(1010));
+    tick(&t, 10);
+    read();
+    assert_eq!(secs(&s, f, "atime"), Some(1010));
+    tick(&t, 86400);
+    read();
+    assert_eq!(secs(&s, f, "atime"), Some(87420));
+    t.process.kernel.set_atime(dev(f), Atime::Strict);
+    tick(&t, 1);
+    read();
+    assert_eq!(secs(&s, f, "atime"), Some(87421));
+    t.process.kernel.set_atime(dev(f), Atime::Never);
+    tick(&t, 1);
+    read();
+    assert_eq!(secs(&s, f, "atime"), Some(87421));
+
+    // directories too, by listing them
+    t.process.kernel.set_atime(dev(f), Atime::Relative);
+    let (_c, p) = cstr("/tmp/d");
+    block_on(sys_mkdirat(t.clone(), Fd(AT_FDCWD), p, 0o755)).unwrap();
+    let d = open(&t, "/tmp/d", OpenFlags::O_DIRECTORY);
+    let dir = t.process.get_fd(d).unwrap().obj;
+    assert_eq!(secs(&s, TMP, "mtime"), Some(87422));
+    tick(&t, 1);
+    let mut buf = vec![0u8; 1024];
+    block_on(sys_getdents64(t.clone(), d, at(buf.as_mut_ptr() as u64), 1024)).unwrap();
+    assert_eq!(secs(&s, dir, "atime"), Some(87423));
+}
@@ -0,0 +1,227 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AtFlags, Fd, Lockable, OpenFlags, Pid, Task,
+    chdir::{sys_chdir, sys_fchdir, sys_getcwd},
+    mkdir::sys_mkdirat,
+    open::sys_openat,
+    readlink::sys_symlinkat,
+    stat::{Stat, sys_newfstatat},
+    unlink::sys_unlinkat,
+    view::{self, MountFlags, View, ViewEntry},
+};
+use protocol::{Command, Oid, Store, Value};
+use std::sync::{Arc, Mutex};
+
+fn ino_at(t: &Task<Machine>, dirfd: Fd, p: &str) -> Result<u64, u8> {
+    let (_c, path) = cstr(p);
+    let mut st = Stat::default();
+    errno(block_on(sys_newfstatat(t.clone(), dirfd, path, user(&mut st), AtFlags::empty())))?;
+    Ok(st.st_ino)
+}
+
+fn ino(t: &Task<Machine>, p: &str) -> Result<u64, u8> {
+    ino_at(t, Fd(AT_FDCWD), p)
+}
+
+fn open(t: &Task<Machine>, p: &str, flags: OpenFlags) -> Result<Fd, u8> {
+    let (_c, path) = cstr(p);
+    errno(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, flags.bits(), 0o644))).map(|fd| Fd(fd as i32))
+}
+
+fn cd(t: &Task<Machine>, p: &str) -> Result<usize, u8> {
+    let (_c, path) = cstr(p);
+    errno(block_on(sys_chdir(t.clone(), path)))
+}
+
+fn cwd(t: &Task<Machine>) -> String {
+    let mut buf = [0u8; 128];
+    let n = block_on(sys_getcwd(t.clone(), user(&mut buf), 128)).unwrap();
+    String::from_utf8(buf[..n - 1].to_vec()).unwrap()
+}
+
+fn entry(prefix: &str, root: Oid, flags: Option<MountFlags>, order: Option<u32>) -> ViewEntry {
+    ViewEntry { prefix: prefix.into(), root, flags, order }
+}
+
+fn exec(t: &Task<Machine>, exe: Oid) -> Result<(), u8> {
+    errno(block_on(view::exec(t, exe)))
+}
+
+// an executable in /tmp whose manifest has entries, each a prefix, a root
+// and its flags. the manifest is at exe + 1 and its entries after it
+fn app(s: &mut Store, exe: Oid, name: &str, entries: &[(&str, Oid, u64)]) {
+    mkfile(s, exe, TMP, name, b"");
+    let manifest = Oid(exe.0 + 1);
+    s.commit(vec![Command::Create(Value::Oid(manifest))]).unwrap();
+    set(s, exe, "view", Value::Oid(manifest));
+    for (i, (prefix, root, flags)) in entries.iter().enumerate() {
+        let e = Oid(exe.0 + 2 + i as u128);
+        s.commit(vec![Command::Create(Value::Oid(e))]).unwrap();
+        s.commit(vec![Command::Set(Value::Oid(manifest), Value::Unsigned(i as u64), Value::Oid(e))]).unwrap();
+        set(s, e, "prefix", Value::Utf8String(prefix.to_string()));
+        set(s, e, "root", Value::Oid(*root));
+        set(s, e, "flags", Value::Unsigned(*flags));
+    }
+}
+
+// /usr, and two trees for /usr/lib. the app's own is writable, with a read
+// only base under it
+fn layered(s: &Arc<Mutex<Store>>) {
+    let mut s = s.lock().unwrap();
+    mkdir(&mut s, Oid(20), Some((ROOT, "usr")));
+    mkdir(&mut s, Oid(30), None);
+    mkfile(&mut s, Oid(32), Oid(30), "libfoo", b"app");
+    mkdir(&mut s, Oid(35), Some((Oid(30), "sub")));
+    mkfile(&mut s, Oid(36), Oid(35), "x", b"");
+    mkdir(&mut s, Oid(31), None);
+    mkfile(&mut s, Oid(34), Oid(31), "libfoo", b"base");
+    mkfile(&mut s, Oid(33), Oid(31), "libbar", b"base");
+    mkdir(&mut s, Oid(37), Some((Oid(31), "sub")));
+    mkfile(&mut s, Oid(38), Oid(37), "y", b"");
+}
+
+fn layers_on_lib(t: &Task<Machine>) {
+    *t.process.view.lock() = View::new(vec![
+        entry("/usr/lib", Oid(31), Some(MountFlags::RDONLY), None),
+        entry("/usr/./lib/", Oid(30), None, Some(1)),
+    ]).unwrap();
+}
+
+#[test]
+fn layers() {
+    let (t, s) = world();
+    layered(&s);
+    assert_eq!(ino(&t, "/usr/lib/libfoo"), Err(2));
+    layers_on_lib(&t);
+    assert!(View::new(vec![entry("usr", Oid(31), None, None)]).is_err());
+    // the first layer that has a name wins, directories are merged
+    assert_eq!(ino(&t, "/usr/lib/libfoo"), Ok(32));
+    assert_eq!(ino(&t, "/usr/lib/libbar"), Ok(33));
+    assert_eq!(ino(&t, "/usr/lib/sub/x"), Ok(36));
+    assert_eq!(ino(&t, "/usr/lib/sub/y"), Ok(38));
+    assert_eq!(ino(&t, "/usr/lib"), Ok(30));
+    assert_eq!(ino(&t, "/usr/lib/.."), Ok(20));
+    assert_eq!(ino(&t, "/usr/lib/sub/../libbar"), Ok(33));
+    assert_eq!(ino(&t, "/etc/passwd"), Ok(12));
+    assert_eq!(ino(&t, "/usr/lib/nope"), Err(2));
+}
+
+#[test]
+fn read_only_layers() {
+    let (t, s) = world();
+    layered(&s);
+    layers_on_lib(&t);
+    assert_eq!(open(&t, "/usr/lib/libbar", OpenFlags::O_WRONLY), Err(30));
+    assert!(open(&t, "/usr/lib/libbar", OpenFlags::O_RDONLY).is_ok());
+    assert!(open(&t, "/usr/lib/libfoo", OpenFlags::O_WRONLY).is_ok());
+    // anything new goes in the top layer
+    assert!(open(&t, "/usr/lib/new", OpenFlags::O_WRONLY | OpenFlags::O_CREAT).is_ok());
+    assert!(attr(&s, names(Oid(30)), "new").is_some());
+    assert!(attr(&s, names(Oid(31)), "new").is_none());
+    assert_eq!(open(&t, "/usr/lib/sub/y", OpenFlags::O_WRONLY), Err(30));
+    let (_d, d) = cstr("/usr/lib/sub/z");
+    assert_eq!(errno(block_on(sys_mkdirat(t.clone(), Fd(AT_FDCWD), d, 0o755))), Ok(0));
+    assert!(attr(&s, names(Oid(35)), "z").is_some());
+    let (_u, u) = cstr("/usr/lib/libbar");
+    assert_eq!(errno(block_on(sys_unlinkat(t.clone(), Fd(AT_FDCWD), u, AtFlags::empty()))), Err(30));
+}
+
+#[test]
+fn relative() {
+    let (t, s) = world();
+    layered(&s);
+    layers_on_lib(&t);
+    assert_eq!(cd(&t, "/usr/lib/sub"), Ok(0));
+    assert_eq!(cwd(&t), "/usr/lib/sub");
+    assert_eq!(ino(&t, "y"), Ok(38));
+    assert_eq!(ino(&t, "../libbar"), Ok(33));
+    assert_eq!(ino(&t, "../../lib/libfoo"), Ok(32));
+    assert_eq!(ino(&t, "../../../../etc/passwd"), Ok(12));
+    let fd = open(&t, "/usr/lib", OpenFlags::O_DIRECTORY).unwrap();
+    assert_eq!(ino_at(&t, fd, "libbar"), Ok(33));
+    assert_eq!(ino_at(&t, fd, "sub/y"), Ok(38));
+    assert_eq!(errno(block_on(sys_fchdir(t.clone(), fd))), Ok(0));
+    assert_eq!(cwd(&t), "/usr/lib");
+
+    // a link in a layer is followed in the view
+    let (_tg, target) = cstr("sub/y");
+    let (_l, l) = cstr("/usr/lib/l");
+    block_on(sys_symlinkat(t.clone(), target, Fd(AT_FDCWD), l)).unwrap();
+    assert_eq!(ino(&t, "/usr/lib/l"), Ok(38));
+}
+
+#[test]
+fn manifest() {
+    let (t, s) = world();
+    {
+        let mut s = s.lock().unwrap();
+        mkdir(&mut s, Oid(43), None);
+        mkfile(&mut s, Oid(44), Oid(43), "passwd", b"app\n");
+        app(&mut s, Oid(40), "app", &[("/etc", Oid(43), 1)]);
+        mkfile(&mut s, Oid(45), TMP, "plain", b"");
+    }
+    assert_eq!(exec(&t, Oid(45)), Ok(()));
+    assert!(t.process.view.lock().is_empty());
+    assert_eq!(exec(&t, Oid(40)), Ok(()));
+    assert_eq!(t.process.view.lock().mounts().len(), 1);
+    assert_eq!(ino(&t, "/etc/passwd"), Ok(44));
+    assert_eq!(open(&t, "/etc/passwd", OpenFlags::O_RDWR), Err(30));
+    assert_eq!(ino(&t, "/tmp/app"), Ok(40));
+    // one without a view keeps the one there is
+    assert_eq!(exec(&t, Oid(45)), Ok(()));
+    assert_eq!(ino(&t, "/etc/passwd"), Ok(44));
+}
+
+#[test]
+fn mount_points() {
+    let (t, s) = world();
+    {
+        let mut s = s.lock().unwrap();
+        mkdir(&mut s, Oid(30), None);
+        mkdir(&mut s, Oid(31), Some((Oid(30), "lib")));
+        // nothing at /usr to find /usr/lib through
+        app(&mut s, Oid(50), "lost", &[("/usr/lib", Oid(30), 0)]);
+        // or a file in the way
+        app(&mut s, Oid(60), "blocked", &[("/etc/passwd/lib", Oid(30), 0)]);
+        // unless another entry puts it there, the prefix itself needn't be
+        app(&mut s, Oid(70), "found", &[("/usr/lib/x", Oid(31), 0), ("/usr", Oid(30), 0)]);
+    }
+    assert_eq!(exec(&t, Oid(50)), Err(2));
+    assert_eq!(exec(&t, Oid(60)), Err(20));
+    assert!(t.process.view.lock().is_empty());
+    assert_eq!(exec(&t, Oid(70)), Ok(()));
+    assert_eq!(ino(&t, "/usr/lib"), Ok(31));
+    assert_eq!(ino(&t, "/usr/lib/x"), Ok(31));
+}
+
+#[test]
+fn forked() {
+    let (t, s) = world();
+    {
+        let mut s = s.lock().unwrap();
+        mkdir(&mut s, Oid(43), None);
+        mkfile(&mut s, Oid(44), Oid(43), "passwd", b"app\n");
+        app(&mut s, Oid(40), "app", &[("/etc", Oid(43), 0)]);
+        mkdir(&mut s, Oid(53), None);
+        mkfile(&mut s, Oid(54), Oid(53), "passwd", b"other\n");
+        app(&mut s, Oid(50), "other", &[("/etc", Oid(53), 0)]);
+    }
+    assert_eq!(exec(&t, Oid(40)), Ok(()));
+    assert_eq!(cd(&t, "/etc"), Ok(0));
+    let child = Task::new(t.process.fork(Oid(3), Pid(2)));
+    assert_eq!(*child.process.parent.lock(), Pid(1));
+    assert!(t.process.children.lock().contains_key(&Pid(2)));
+    // what the parent sees, from where it was
+    assert_eq!(ino(&child, "/etc/passwd"), Ok(44));
+    assert_eq!(ino(&child, "passwd"), Ok(44));
+    assert_eq!(cwd(&child), "/etc");
+
+    // and from then on each its own
+    assert_eq!(exec(&child, Oid(50)), Ok(()));
+    assert_eq!(ino(&child, "/etc/passwd"), Ok(54));
+    assert_eq!(ino(&t, "/etc/passwd"), Ok(44));
+    assert_eq!(cd(&child, "/tmp"), Ok(0));
+    assert_eq!(cwd(&t), "/etc");
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use common::*;
+use linux_proxy::{
+    AT_FDCWD, AddressSpace, Credentials, Fd, Gid, Lockable, Task, Uid,
+    open::sys_openat,
+    readlink::sys_symlinkat,
+    xattr::{
+        XattrFlags, sys_fgetxattr, sys_flistxattr, sys_fsetxattr, sys_getxattr, sys_listxattr,
+        sys_lsetxattr, sys_removexattr, sys_setxattr,
+    },
+};
+use protocol::Value;
+
+fn set(t: &Task<Machine>, p: &str, n: &str, v: &[u8], flags: XattrFlags) -> Result<usize, u8> {
+    let (_p, path) = cstr(p);
+    let (_n, name) = cstr(n);
+    errno(block_on(sys_setxattr(t.clone(), path, name, AddressSpace::User(v.as_ptr() as u64), v.len(), flags)))
+}
+
+fn get(t: &Task<Machine>, p: &str, n: &str, size: usize) -> Result<Vec<u8>, u8> {
+    let (_p, path) = cstr(p);
+    let (_n, name) = cstr(n);
+    let mut buf = vec![0u8; size];
+    let to = AddressSpace::User(buf.as_mut_ptr() as u64);
+    let len = errno(block_on(sys_getxattr(t.clone(), path, name, to, size)))?;
+    buf.resize(len, 0);
+    Ok(buf)
+}
+
+fn list(t: &Task<Machine>, p: &str, size: usize) -> Result<Vec<u8>, u8> {
+    let (_p, path) = cstr(p);
+    let mut buf = vec![0u8; size];
+    let len = errno(block_on(sys_listxattr(t.clone(), path, AddressSpace::User(buf.as_mut_ptr() as u64), size)))?;
+    buf.resize(len, 0);
+    Ok(buf)
+}
+
+#[test]
+fn create_and_replace() {
+    let (t, s) = world();
+    let none = XattrFlags::empty();
+    assert_eq!(set(&t, "/etc/passwd", "user.origin", b"built by ci", XattrFlags::XATTR_REPLACE), Err(61));
+    assert_eq!(set(&t, "/etc/passwd", "user.origin", b"built by ci", XattrFlags::XATTR_CREATE), Ok(0));
+    assert_eq!(set(&t, "/etc/passwd", "user.origin", b"built by ci", XattrFlags::XATTR_CREATE), Err(17));
+    assert_eq!(set(&t, "/etc/passwd", "user.origin", b"built", XattrFlags::XATTR_REPLACE), Ok(0));
+    assert_eq!(attr(&s, PASSWD, "user.origin"), Some(Value::Bytes(b"built".to_vec())));
+
+    // size 0 asks how big it is, anything else has to fit
+    assert_eq!(get(&t, "/etc/passwd", "user.origin", 0).map(|b| b.len()), Ok(5));
+    assert_eq!(get(&t, "/etc/passwd", "user.origin", 2), Err(34));
+    assert_eq!(get(&t, "/etc/passwd", "user.origin", 64), Ok(b"built".to_vec()));
+
+    assert_eq!(set(&t, "/etc/passwd", "user.empty", b"", none), Ok(0));
+    assert_eq!(get(&t, "/etc/passwd", "user.empty", 64), Ok(vec![]));
+    let (_p, path) = cstr("/etc/passwd");
+    let (_n, name) = cstr("user.origin");
+    assert_eq!(errno(block_on(sys_removexattr(t.clone(), path, name))), Ok(0));
+    assert_eq!(errno(block_on(sys_removexattr(t.clone(), path, name))), Err(61));
+    assert_eq!(get(&t, "/etc/passwd", "user.origin", 64), Err(61));
+    assert_eq!(get(&t, "/nope", "user.origin", 64), Err(2));
+}
+
+#[test]
+fn listed_and_read_as_text() {
+    let (t, s) = world();
+    set(&t, "/etc/passwd", "user.origin", b"built", XattrFlags::empty()).unwrap();
+    // something else's metadata, and only the user ones are listed
+    {
+        let mut s = s.lock().unwrap();
+        common::set(&mut s, PASSWD, "user.policy", Value::Unsigned(42));
+    }
+    assert_eq!(get(&t, "/etc/passwd", "user.policy", 64), Ok(b"42".to_vec()));
+    assert_eq!(list(&t, "/etc/passwd", 0).map(|b| b.len()), Ok(24));
+    assert_eq!(list(&t, "/etc/passwd", 64), Ok(b"user.origin\0user.policy\0".to_vec()));
+    assert_eq!(list(&t, "/etc/passwd", 5), Err(34));
+}
+
+#[test]
+fn names_and_sizes() {
+    let (t, _s) = world();
+    let none = XattrFlags::empty();
+    assert_eq!(set(&t, "/etc/passwd", "trusted.x", b"1", none), Err(95));
+    assert_eq!(set(&t, "/etc/passwd", "", b"1", none), Err(34));
+    let long = format!("user.{}", "a".repeat(300));
+    assert_eq!(set(&t, "/etc/passwd", &long, b"1", none), Err(34));
+    let big = vec![0u8; 65537];
+    assert_eq!(set(&t, "/etc/passwd", "user.big", &big, none), Err(7));
+    assert_eq!(set(&t, "/etc/passwd", "user.big", &big[..65536], none), Ok(0));
+}
+
+#[test]
+fn descriptors_links_and_owners() {
+    let (t, _s) = world();
+    let (_p, path) = cstr("/etc/passwd");
+    let (_n, name) = cstr("user.origin");
+    let v = b"abc";
+    let va = AddressSpace::User(v.as_ptr() as u64);
+    let fd = Fd(block_on(sys_openat(t.clone(), Fd(AT_FDCWD), path, 0, 0)).unwrap() as i32);
+    assert_eq!(errno(block_on(sys_fsetxattr(t.clone(), fd, name, va, 3, XattrFlags::empty()))), Ok(0));
+    let mut buf = vec![0u8; 64];
+    let ba = AddressSpace::User(buf.as_mut_ptr() as u64);
+    assert_eq!(errno(block_on(sys_fgetxattr(t.clone(), fd, name, ba, 64))), Ok(3));
+    assert_eq!(&buf[..3], b"abc");
+    assert_eq!(errno(block_on(sys_flistxattr(t.clone(), fd, ba, 5))), Err(34));
+
+    // a link itself has none, the file it points at does
+    let (_l, link) = cstr("/tmp/pw");
+    block_on(sys_symlinkat(t.clone(), path, Fd(AT_FDCWD), link)).unwrap();
+    assert_eq!(errno(block_on(sys_lsetxattr(t.clone(), link, name, va, 3, XattrFlags::empty()))), Err(1));
+    assert_eq!(get(&t, "/tmp/pw", "user.origin", 64), Ok(b"abc".to_vec()));
+
+    // passwd is 0644 root's, someone else can read but not write
+    *t.process.creds.lock() = Credentials::new_user(Uid::new(1000), Gid::new(1000), vec![]);
+    assert_eq!(get(&t, "/etc/passwd", "user.origin", 64), Ok(b"abc".to_vec()));
+    assert_eq!(set(&t, "/etc/passwd", "user.origin", b"x", XattrFlags::empty()), Err(13));
+}
@@ -2,8 +2,9 @@
 use std::alloc::{Layout, alloc};
 use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::Read;
+use std::io::{Read, Write};
 use std::slice;
+use protocol::Encodable;
 use xhypervisor::*;
 
 const PAGESIZE: usize = 65536;
@@ -89,8 +90,79 @@ impl VM {
     }
 }
 
-fn vm_create() {
-    let kernel = load_kernel_aligned(std::env::args().nth(1).expect("rag")).expect("kernel");
+// what to run and who as. the id maps are in the format of
+// /proc/pid/uid_map, "inside outside count" a line, with commas standing
+// in for newlines on the command line. they become the uid_map and gid_map
+// attributes of the first process's credentials, where linux_proxy::idmap
+// reads them. without them the guest ids are the host ones. --record
+// names a file for linux_proxy::record to write into, see RECORD
+struct Launch {
+    kernel: String,
+    uid_map: Option<String>,
+    gid_map: Option<String>,
+    record: Option<String>,
+}
+
+impl Launch {
+    // monitor kernel [--uid-map MAP] [--gid-map MAP] [--record FILE]
+    fn from_args(mut args: impl Iterator<Item = String>) -> Result<Launch, String> {
+        let mut kernel = None;
+        let mut uid_map = None;
+        let mut gid_map = None;
+        let mut record = None;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--uid-map" => uid_map = Some(args.next().ok_or("--uid-map needs a map")?),
+                "--gid-map" => gid_map = Some(args.next().ok_or("--gid-map needs a map")?),
+                "--record" => record = Some(args.next().ok_or("--record needs a file")?),
+                _ if kernel.is_none() => kernel = Some(arg),
+                _ => return Err(format!("unexpected argument {}", arg)),
+            }
+        }
+        Ok(Launch {
+            kernel: kernel.ok_or("no kernel")?,
+            uid_map,
+            gid_map,
+            record,
+        })
+    }
+
+    // the attributes to set on the first process before it runs, handed
+    // to the guest on LAUNCH
+    fn credentials(&self, process: protocol::Oid) -> Vec<protocol::Command> {
+        [("uid_map", &self.uid_map), ("gid_map", &self.gid_map)]
+            .into_iter()
+            .filter_map(|(name, map)| {
+                let map = map.as_ref()?;
+                Some(protocol::Command::Set(
+                    protocol::Value::Oid(process),
+                    protocol::attribute!(name),
+                    protocol::Value::Utf8String(map.replace(',', "\n")),
+                ))
+            })
+            .collect()
+    }
+}
+
+// the hvc immediates, with the buffer in x0 and its length in x1. a
+// record is one whole linux_proxy::record::Record, appended as it comes so
+// a guest that dies partway leaves a log that reads up to there
+const CONSOLE: u64 = 5;
+const RECORD: u64 = 6;
+// x2 is the first process, the buffer gets the block of Launch::credentials
+// for it and x0 comes back as its length. the guest runs the block before
+// linux_proxy::creds::launched reads the maps back for the process
+const LAUNCH: u64 = 7;
+
+fn vm_create(launch: &Launch) {
+    let kernel = load_kernel_aligned(launch.kernel.clone()).expect("kernel");
+    let mut record = launch.record.as_ref().map(|path| {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .expect("record")
+    });
     // from elf
     const EL1_USER_PAYLOAD_ADDRESS: u64 = 0x10000000;
     let mut vm = VM::new();
@@ -118,15 +190,35 @@ fn vm_create() {
                 let ec = (exception.syndrome >> 26) & 0x3f;
 
                 if ec == 0x16 {
-                    unsafe {
-                        let n = vm
-                            .guest_to_host(vcpu.read_register(Register::X0).unwrap())
-                            .expect("translate");
-                        let s = slice::from_raw_parts(
-                            n as *const u8,
-                            vcpu.read_register(Register::X1).unwrap() as usize,
-                        );
-                        println!("{}", str::from_utf8(s).expect("Invalid UTF-8"));
+                    let n = vm
+                        .guest_to_host(vcpu.read_register(Register::X0).unwrap())
+                        .expect("translate");
+                    let len = vcpu.read_register(Register::X1).unwrap() as usize;
+                    let s = unsafe { slice::from_raw_parts(n as *const u8, len) };
+                    match exception.syndrome & 0xffff {
+                        CONSOLE => println!("{}", str::from_utf8(s).expect("Invalid UTF-8")),
+                        // without --record the guest shouldn't be recording,
+                        // but there's no harm in dropping it
+                        RECORD => {
+                            if let Some(f) = record.as_mut() {
+                                f.write_all(s).expect("record");
+                            }
+                        }
+                        // as much of the block as fits, and how long all
+                        // of it is, so a guest with too small a buffer can
+                        // ask again
+                        LAUNCH => {
+                            let process = protocol::Oid(vcpu.read_register(Register::X2).unwrap());
+                            let mut b = protocol::Buffer::new();
+                            for c in launch.credentials(process) {
+                                c.encode(&mut b).expect("encode");
+                            }
+                            let out = unsafe { slice::from_raw_parts_mut(n as *mut u8, len) };
+                            let fits = b.len().min(len);
+                            out[..fits].copy_from_slice(&b.bytes()[..fits]);
+                            vcpu.write_register(Register::X0, b.len() as u64).unwrap();
+                        }
+                        imm => println!("Unknown hvc #{}", imm),
                     }
                     continue;
                 //		    break;
@@ -149,5 +241,6 @@ fn vm_create() {
 }
 
 fn main() {
-    vm_create();
+    let launch = Launch::from_args(std::env::args().skip(1)).expect("arguments");
+    vm_create(&launch);
 }
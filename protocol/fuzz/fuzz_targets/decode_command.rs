@@ -0,0 +1,15 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use protocol::{Buffer, Encodable};
+
+// anything that decodes has to encode back to exactly the bytes it came from
+fuzz_target!(|data: &[u8]| {
+    let mut b = Buffer::from_bytes(data);
+    if let Ok(block) = b.decode() {
+        let mut out = Buffer::new();
+        for c in &block {
+            c.encode(&mut out).unwrap();
+        }
+        assert_eq!(out.bytes(), data);
+    }
+});
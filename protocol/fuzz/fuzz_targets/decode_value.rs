@@ -0,0 +1,13 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use protocol::{Buffer, Encodable, Value};
+
+fuzz_target!(|data: &[u8]| {
+    let mut b = Buffer::from_bytes(data);
+    if let Ok(v) = Value::decode(&mut b) {
+        let used = data.len() - b.len();
+        let mut out = Buffer::new();
+        v.encode(&mut out).unwrap();
+        assert_eq!(out.bytes(), &data[..used]);
+    }
+});
@@ -0,0 +1,64 @@
+#![no_main]
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use libfuzzer_sys::fuzz_target;
+use protocol::{Buffer, Cancel, Command, Oid, Scope, SimpleAllocator, Store, Stream, Value, attribute};
+use std::sync::Arc;
+
+fn block_on<F: Future>(f: F) -> F::Output {
+    fn noop(_: *const ()) {}
+    fn clone(p: *const ()) -> RawWaker {
+        RawWaker::new(p, &VTABLE)
+    }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    let waker = unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &VTABLE)) };
+    let mut cx = Context::from_waker(&waker);
+    let mut f = pin!(f);
+    loop {
+        if let Poll::Ready(x) = f.as_mut().poll(&mut cx) {
+            return x;
+        }
+    }
+}
+
+fn store() -> Store {
+    let mut s = Store::new(Oid(1));
+    s.add_root(Oid(100));
+    s.commit(vec![
+        Command::Create(Value::Oid(Oid(100))),
+        Command::Create(Value::Oid(Oid(101))),
+        Command::Set(Value::Oid(Oid(100)), attribute!("a"), Value::Oid(Oid(101))),
+        Command::Set(Value::Oid(Oid(101)), attribute!("contents"), Value::Bytes(b"hello".to_vec())),
+        Command::Set(Value::Oid(Oid(101)), attribute!("size"), Value::Unsigned(5)),
+    ]).unwrap();
+    s
+}
+
+// whatever the block is, evaluating it and committing the result should
+// come back with an error or not at all, never a panic
+fuzz_target!(|data: &[u8]| {
+    let Ok(block) = Buffer::from_bytes(data).decode() else { return };
+    let resolver = Arc::new(store());
+    let scope = Scope {
+        myself: Oid(1),
+        allocator: SimpleAllocator::new(Oid(1000)),
+        resolver: resolver.clone(),
+        cancel: Cancel::new(),
+    };
+    let Ok(mut e) = scope.evaluate(block) else { return };
+    // a cross product of key enumerations can get big, it's not what we're looking for
+    for _ in 0..1024 {
+        match block_on(e.next()) {
+            Ok(Some(_)) => {}
+            Ok(None) => break,
+            Err(_) => return,
+        }
+    }
+    let writes = e.writes();
+    drop(scope);
+    if let Ok(mut s) = Arc::try_unwrap(resolver) {
+        let _ = s.commit(writes);
+        s.collect();
+    }
+});
@@ -1,4 +1,4 @@
-use crate::{Command, Error};
+use crate::{Command, Error, err};
 use alloc::vec::Vec;
 
 pub struct Buffer {
@@ -14,6 +14,12 @@ pub trait Encodable {
         Self: Sized;
 }
 
+impl Default for Buffer {
+    fn default() -> Self {
+        Buffer::new()
+    }
+}
+
 impl Buffer {
     pub fn new() -> Buffer {
         Buffer {
@@ -23,42 +29,86 @@ impl Buffer {
         }
     }
 
+    // everything that arrives off a channel is untrusted, so nothing in
+    // here gets to panic on a short or malformed input
+    pub fn from_bytes(b: &[u8]) -> Buffer {
+        Buffer {
+            read: 0,
+            write: b.len(),
+            body: b.to_vec(),
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.write - self.read
     }
-    
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // the unread part
+    pub fn bytes(&self) -> &[u8] {
+        &self.body[self.read..self.write]
+    }
+
     pub fn read(&mut self, count: usize) -> Result<&[u8], Error> {
+        if count > self.len() {
+            return Err(err!("short buffer, wanted {} bytes and have {}", count, self.len()));
+        }
         let start = self.read;
-        self.read = self.read + count;
-        Ok(&self.body[self.read..self.read + start])
+        self.read += count;
+        Ok(&self.body[start..self.read])
     }
 
     pub fn write(&mut self, b: &[u8]) -> Result<(), Error>{
-        let len = b.len();
-        self.body.reserve(len);
-        self.body[self.write..self.write + len].copy_from_slice(b);
-        self.write = self.write + len;
+        self.body.truncate(self.write);
+        self.body.extend_from_slice(b);
+        self.write += b.len();
         Ok(())
     }
 
+    // leb128, low bits first
     pub fn write_varint(&mut self, i: u64) -> Result<(), Error>{
         let mut current = i;
-        while current < 0x80 {
-            let mut val: u8 = (current & 0x7f) as u8;
-            if val > 0x7f {
-                val |= 0x80;
+        loop {
+            let val = (current & 0x7f) as u8;
+            current >>= 7;
+            if current == 0 {
+                return self.write(&[val]);
             }
-            self.write(&[val])?;
-            current = current>>7;
+            self.write(&[val | 0x80])?;
         }
-        Ok(())
     }
 
-    pub fn read_varint(&self) -> Result<u64, Error> {
-        Ok(0)
+    pub fn read_varint(&mut self) -> Result<u64, Error> {
+        let mut result: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let b = self.read(1)?[0];
+            if shift == 63 && b > 1 {
+                return Err(err!("varint overflows 64 bits"));
+            }
+            result |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                // only one encoding per number, a trailing zero byte is padding
+                if b == 0 && shift > 0 {
+                    return Err(err!("overlong varint"));
+                }
+                return Ok(result);
+            }
+            shift += 7;
+            if shift > 63 {
+                return Err(err!("varint overflows 64 bits"));
+            }
+        }
     }
 
-    pub fn decode(&self) -> Result<Vec<Command>, Error> {
-        loop {}
+    pub fn decode(&mut self) -> Result<Vec<Command>, Error> {
+        let mut block = Vec::new();
+        while !self.is_empty() {
+            block.push(Command::decode(self)?);
+        }
+        Ok(block)
     }
 }
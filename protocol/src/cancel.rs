@@ -0,0 +1,103 @@
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::{Error, Oid};
+
+// nanoseconds, on whatever clock the deadlines in frames are written against
+pub type DynClock = Arc<dyn Fn() -> u64 + Send + Sync>;
+
+struct CancelState {
+    cancelled: AtomicBool,
+    deadline: Option<(u64, DynClock)>,
+}
+
+// shared by everyone working on one block, the caller holds a clone to
+// pull the plug and every handler in the chain checks it before producing
+// another row. a deadline is just a cancellation nobody has to remember
+// to send
+#[derive(Clone)]
+pub struct Cancel {
+    state: Arc<CancelState>,
+}
+
+impl Default for Cancel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cancel {
+    pub fn new() -> Self {
+        Cancel {
+            state: Arc::new(CancelState {
+                cancelled: AtomicBool::new(false),
+                deadline: None,
+            }),
+        }
+    }
+
+    pub fn with_deadline(deadline: u64, clock: DynClock) -> Self {
+        Cancel {
+            state: Arc::new(CancelState {
+                cancelled: AtomicBool::new(false),
+                deadline: Some((deadline, clock)),
+            }),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn deadline(&self) -> Option<u64> {
+        self.state.deadline.as_ref().map(|(d, _)| *d)
+    }
+
+    pub fn check(&self, location: Oid) -> Result<(), Error> {
+        if self.state.cancelled.load(Ordering::Acquire) {
+            return Err(Error::cancelled(location));
+        }
+        if let Some((deadline, clock)) = &self.state.deadline && clock() >= *deadline {
+            return Err(Error::timed_out(location));
+        }
+        Ok(())
+    }
+}
+
+// the transport side, blocks that arrived in a Frame::Request and haven't
+// finished yet, so a Frame::Cancel from the peer can find them
+pub struct Inflight {
+    requests: BTreeMap<u64, Cancel>,
+    clock: DynClock,
+}
+
+impl Inflight {
+    pub fn new(clock: DynClock) -> Self {
+        Inflight {
+            requests: BTreeMap::new(),
+            clock,
+        }
+    }
+
+    pub fn begin(&mut self, id: u64, deadline: Option<u64>) -> Cancel {
+        let c = match deadline {
+            Some(d) => Cancel::with_deadline(d, self.clock.clone()),
+            None => Cancel::new(),
+        };
+        self.requests.insert(id, c.clone());
+        c
+    }
+
+    // false if the request already finished, which is a normal race
+    pub fn cancel(&mut self, id: u64) -> bool {
+        if let Some(c) = self.requests.remove(&id) {
+            c.cancel();
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn finish(&mut self, id: u64) {
+        self.requests.remove(&id);
+    }
+}
@@ -1,4 +1,4 @@
-use alloc::string::String;
+use alloc::{string::String, vec, vec::Vec};
 
 use crate::{
     err,
@@ -10,10 +10,99 @@ use crate::{
     Value,
 };
 
+#[derive(Debug, Clone, PartialEq, Eq)]
 #[repr(u8)]
 pub enum Status {
     Success = 1,
     Error(String) = 2,
+    // index of the command in the block that didn't hold
+    GuardFailed(u32) = 3,
+    Cancelled = 4,
+    TimedOut = 5,
+}
+
+// comparisons for Guard. equality works on any pair of values, the
+// ordering comparisons only between two unsigned or two signed values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Predicate {
+    Equal = 1,
+    NotEqual = 2,
+    Less = 3,
+    LessEqual = 4,
+    Greater = 5,
+    GreaterEqual = 6,
+}
+
+impl Predicate {
+    pub fn holds(&self, left: &Value, right: &Value) -> Result<bool, Error> {
+        let ordering = match (left, right) {
+            (Value::Unsigned(l), Value::Unsigned(r)) => Some(l.cmp(r)),
+            (Value::Signed(l), Value::Signed(r)) => Some(l.cmp(r)),
+            _ => None,
+        };
+        match (self, ordering) {
+            (Predicate::Equal, _) => Ok(left == right),
+            (Predicate::NotEqual, _) => Ok(left != right),
+            (Predicate::Less, Some(o)) => Ok(o.is_lt()),
+            (Predicate::LessEqual, Some(o)) => Ok(o.is_le()),
+            (Predicate::Greater, Some(o)) => Ok(o.is_gt()),
+            (Predicate::GreaterEqual, Some(o)) => Ok(o.is_ge()),
+            (_, None) => Err(err!("{:?} can't compare {:?} and {:?}", self, left, right)),
+        }
+    }
+}
+
+impl Encodable for Predicate {
+    fn decode(source: &mut Buffer) -> Result<Self, Error> {
+        match source.read(1)?[0] {
+            1 => Ok(Predicate::Equal),
+            2 => Ok(Predicate::NotEqual),
+            3 => Ok(Predicate::Less),
+            4 => Ok(Predicate::LessEqual),
+            5 => Ok(Predicate::Greater),
+            6 => Ok(Predicate::GreaterEqual),
+            x => Err(err!("invalid predicate code {}", x)),
+        }
+    }
+
+    fn encode(&self, b: &mut Buffer) -> Result<(), Error> {
+        b.write(&[*self as u8])
+    }
+}
+
+impl Encodable for Status {
+    fn decode(source: &mut Buffer) -> Result<Self, Error> {
+        match source.read(1)?[0] {
+            1 => Ok(Status::Success),
+            2 => {
+                let length = source.read_varint()?;
+                let body = source.read(length as usize)?;
+                Ok(Status::Error(String::from_utf8(body.to_vec()).map_err(|_| err!("invalid utf8 contents"))?))
+            }
+            3 => Ok(Status::GuardFailed(u32::try_from(source.read_varint()?).map_err(|_| err!("guard index out of range"))?)),
+            4 => Ok(Status::Cancelled),
+            5 => Ok(Status::TimedOut),
+            x => Err(err!("invalid status code {}", x)),
+        }
+    }
+
+    fn encode(&self, b: &mut Buffer) -> Result<(), Error> {
+        match self {
+            Status::Success => b.write(&[1]),
+            Status::Error(s) => {
+                b.write(&[2])?;
+                b.write_varint(s.len() as u64)?;
+                b.write(s.as_bytes())
+            }
+            Status::GuardFailed(i) => {
+                b.write(&[3])?;
+                b.write_varint(*i as u64)
+            }
+            Status::Cancelled => b.write(&[4]),
+            Status::TimedOut => b.write(&[5]),
+        }
+    }
 }
 
 
@@ -28,7 +117,18 @@ pub enum Status {
 // the last argument of copy is an unsigned or variable
 // i know dependent types are supposed to be the answer, but i dont think
 // this would require such a large hammer
-#[derive(Debug)]
+//
+// guard, absent and compare-and-set don't bind anything, if they don't hold
+// the whole block is abandoned with Status::GuardFailed before anything is
+// committed. thats what lets O_EXCL or a lock acquisition be a single block.
+// compare-and-set with an expected value of Empty means 'only if absent'.
+// absent with a variable that isn't bound yet means the entity has no
+// attributes at all
+//
+// setting an attribute to Empty removes it rather than storing anything,
+// and destroy removes the entity outright. entities that nothing refers
+// to any more are left for the collector in Store
+#[derive(Debug, Clone, PartialEq)]
 #[repr(u8)]
 pub enum Command {
     Get(Entity, Attribute, Value) = 1,
@@ -37,10 +137,49 @@ pub enum Command {
          Entity, Attribute, Value,
          Value) = 3,
     Create(Value) = 4,
+    Guard(Value, Predicate, Value) = 5,
+    Absent(Entity, Attribute) = 6,
+    CompareAndSet(Entity, Attribute, Value, Value) = 7,
+    Destroy(Entity) = 8,
+}
+
+impl Command {
+    // see Value::codepoint
+    pub fn codepoint(&self) -> u8 {
+        unsafe { *(self as *const Self as *const u8) }
+    }
+
+    // every value it carries, entities and attributes included
+    pub fn values(&self) -> Vec<&Value> {
+        match self {
+            Command::Get(e, a, v) | Command::Set(e, a, v) => vec![e, a, v],
+            Command::Copy(se, sa, so, de, da, dof, n) => vec![se, sa, so, de, da, dof, n],
+            Command::Create(v) | Command::Destroy(v) => vec![v],
+            Command::Guard(l, _, r) => vec![l, r],
+            Command::Absent(e, a) => vec![e, a],
+            Command::CompareAndSet(e, a, old, new) => vec![e, a, old, new],
+        }
+    }
+
+    // one of every variant, for check_codepoints. add new ones here
+    pub fn samples() -> Vec<Command> {
+        let v = || Value::Variable(0);
+        vec![
+            Command::Get(v(), v(), v()),
+            Command::Set(v(), v(), v()),
+            Command::Copy(v(), v(), v(), v(), v(), v(), v()),
+            Command::Create(v()),
+            Command::Guard(v(), Predicate::Equal, v()),
+            Command::Absent(v(), v()),
+            Command::CompareAndSet(v(), v(), v(), v()),
+            Command::Destroy(v()),
+        ]
+    }
 }
 
 // xxx - discriminant issues resulting in an unforuntate duplication that
-// requires manual consistency for the codepoint
+// requires manual consistency for the codepoint. encode takes it from the
+// discriminant, decode is checked by check_codepoints at handshake
 impl Encodable for Command {
     fn decode(source: &mut Buffer) -> Result<Self, Error>
     where
@@ -61,26 +200,41 @@ impl Encodable for Command {
                 Value::decode(source)?,
                 Attribute::decode(source)?,
                 Value::decode(source)?,                
-                Attribute::decode(source)?,
                 Value::decode(source)?,
+                Attribute::decode(source)?,
                 Value::decode(source)?,                
-                Value::Unsigned(u64::from_be_bytes(source.read(8)?.try_into().expect("word"))),
+                Value::decode(source)?,
             )),
             4 => Ok(Command::Create(Value::decode(source)?)),
-            x => Err(err!("invalid protocol command code {}", x as u8)),
+            5 => Ok(Command::Guard(
+                Value::decode(source)?,
+                Predicate::decode(source)?,
+                Value::decode(source)?,
+            )),
+            6 => Ok(Command::Absent(
+                Value::decode(source)?,
+                Attribute::decode(source)?,
+            )),
+            7 => Ok(Command::CompareAndSet(
+                Value::decode(source)?,
+                Attribute::decode(source)?,
+                Value::decode(source)?,
+                Value::decode(source)?,
+            )),
+            8 => Ok(Command::Destroy(Value::decode(source)?)),
+            x => Err(err!("invalid protocol command code {}", x)),
         }
     }
 
     fn encode(&self, b: &mut Buffer) -> Result<(), Error> {
+        b.write(&[self.codepoint()])?;
         match &self {
             Command::Get(e, a, v) => {
-                b.write(&[1])?;
                 e.encode(b)?;
                 a.encode(b)?;
                 v.encode(b)?;
             }
             Command::Set(e, a, v) => {
-                b.write(&[2])?;
                 e.encode(b)?;
                 a.encode(b)?;
                 v.encode(b)?;
@@ -88,7 +242,6 @@ impl Encodable for Command {
             Command::Copy(source_entity, source_attribute, source_offset,
                           dest_entity, dest_attribute, dest_offset,
                           length) => {
-                b.write(&[3])?;
                 source_entity.encode(b)?;
                 source_attribute.encode(b)?;
                 source_offset.encode(b)?;                
@@ -98,9 +251,26 @@ impl Encodable for Command {
                 length.encode(b)?;
             }
             Command::Create(dest) => {
-                b.write(&[4])?;
                 dest.encode(b)?;
             }
+            Command::Guard(left, p, right) => {
+                left.encode(b)?;
+                p.encode(b)?;
+                right.encode(b)?;
+            }
+            Command::Absent(e, a) => {
+                e.encode(b)?;
+                a.encode(b)?;
+            }
+            Command::CompareAndSet(e, a, expected, new) => {
+                e.encode(b)?;
+                a.encode(b)?;
+                expected.encode(b)?;
+                new.encode(b)?;
+            }
+            Command::Destroy(e) => {
+                e.encode(b)?;
+            }
         }
         Ok(())
     }
@@ -1,4 +1,4 @@
-use crate::{Oid, DynEntityHandler, Memory};
+use crate::{Oid, DynEntityHandler, Memory, Status, new_object};
 use alloc::{string::String, sync::Arc};
 
 // maybe this should be dynentity 
@@ -9,27 +9,64 @@ pub struct Error {
     pub location: Option<Oid>,
     pub cause: String,
     pub syserr: Option<u8>,
+    // set when the block was abandoned rather than failing, a guard or
+    // compare-and-set that didn't hold, or a cancellation or deadline. so the
+    // caller can tell a lost race (retry, EEXIST) or an EINTR from something
+    // actually going wrong
+    pub status: Option<Status>,
     // file and line can we do?
 }
 
 impl Error {
+    fn abandoned(location: Oid, cause: &str, status: Status) -> Error {
+        Error {
+            location: Some(location),
+            cause: String::from(cause),
+            syserr: None,
+            status: Some(status),
+        }
+    }
+
+    pub fn guard_failed(location: Oid, index: u32) -> Error {
+        Error::abandoned(location, "guard failed", Status::GuardFailed(index))
+    }
+
+    pub fn cancelled(location: Oid) -> Error {
+        Error::abandoned(location, "cancelled", Status::Cancelled)
+    }
+
+    pub fn timed_out(location: Oid) -> Error {
+        Error::abandoned(location, "deadline passed", Status::TimedOut)
+    }
+
+    pub fn is_guard_failure(&self) -> bool {
+        matches!(self.status, Some(Status::GuardFailed(_)))
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.status, Some(Status::Cancelled))
+    }
+
+    pub fn is_timed_out(&self) -> bool {
+        matches!(self.status, Some(Status::TimedOut))
+    }
+
     fn to_object() -> DynEntityHandler {
         // its odd, that this has an oid, but we kinda need one
-        let out = Arc::new(Memory::new());
-        out
+        Arc::new(Memory::new(new_object()))
     }
 }
 
 #[macro_export]
 macro_rules! err {
     ($($arg:tt)*) => {{
-        crate::Error{cause:alloc::format!($($arg)*), location:None, syserr: None}
+        $crate::Error{cause:$crate::format!($($arg)*), location:None, syserr: None, status: None}
     }}
 }
 
 #[macro_export]
 macro_rules! locerr {
     ($oid:expr, $($arg:tt)*) => {{
-        crate::Error{cause:alloc::format!($($arg)*), location:Some($oid), syserr: None}
+        $crate::Error{cause:$crate::format!($($arg)*), location:Some($oid), syserr: None, status: None}
     }}
 }
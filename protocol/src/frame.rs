@@ -0,0 +1,81 @@
+use alloc::vec::Vec;
+use crate::{Buffer, Capabilities, Command, Encodable, Error, err};
+
+// what actually goes over a channel between peers. the id is chosen by the
+// sender and is only meaningful on that channel. a deadline of zero on the
+// wire means none. each side sends a hello first, and nothing else until
+// it has the other's
+#[derive(Debug)]
+#[repr(u8)]
+pub enum Frame {
+    Request { id: u64, deadline: Option<u64>, block: Vec<Command> } = 1,
+    Cancel { id: u64 } = 2,
+    Hello(Capabilities) = 3,
+}
+
+impl Frame {
+    // one whole frame off a channel after the handshake, source holding
+    // just it. anything bigger than was agreed, or asking for something the
+    // peer said it wouldn't, is refused before it gets anywhere
+    pub fn receive(source: &mut Buffer, agreed: &Capabilities) -> Result<Frame, Error> {
+        if source.len() as u64 > agreed.max_frame {
+            return Err(err!("frame of {} bytes, more than the {} agreed", source.len(), agreed.max_frame));
+        }
+        let frame = Frame::decode(source)?;
+        if !source.is_empty() {
+            return Err(err!("{} bytes after the frame", source.len()));
+        }
+        if let Frame::Request { block, .. } = &frame {
+            for c in block {
+                agreed.permits(c)?;
+            }
+        }
+        Ok(frame)
+    }
+}
+
+impl Encodable for Frame {
+    fn decode(source: &mut Buffer) -> Result<Self, Error> {
+        match source.read(1)?[0] {
+            1 => {
+                let id = source.read_varint()?;
+                let deadline = match source.read_varint()? {
+                    0 => None,
+                    d => Some(d),
+                };
+                let count = source.read_varint()?;
+                let mut block = Vec::new();
+                for _ in 0..count {
+                    block.push(Command::decode(source)?);
+                }
+                Ok(Frame::Request { id, deadline, block })
+            }
+            2 => Ok(Frame::Cancel { id: source.read_varint()? }),
+            3 => Ok(Frame::Hello(Capabilities::decode(source)?)),
+            x => Err(err!("invalid frame code {}", x)),
+        }
+    }
+
+    fn encode(&self, b: &mut Buffer) -> Result<(), Error> {
+        match self {
+            Frame::Request { id, deadline, block } => {
+                b.write(&[1])?;
+                b.write_varint(*id)?;
+                b.write_varint(deadline.unwrap_or(0))?;
+                b.write_varint(block.len() as u64)?;
+                for c in block {
+                    c.encode(b)?;
+                }
+            }
+            Frame::Cancel { id } => {
+                b.write(&[2])?;
+                b.write_varint(*id)?;
+            }
+            Frame::Hello(c) => {
+                b.write(&[3])?;
+                c.encode(b)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+use alloc::vec::Vec;
+use crate::{Buffer, Command, Encodable, Error, Value, err};
+
+// bump when a codepoint changes meaning, adding one doesn't need it since
+// the command and value lists are exchanged explicitly
+pub const PROTOCOL_VERSION: u32 = 1;
+pub const DEFAULT_MAX_FRAME: u64 = 1 << 20;
+
+// nobody can do anything useful without these, a peer that doesn't
+// speak them is refused rather than degraded
+const REQUIRED_COMMANDS: [u8; 4] = [1, 2, 3, 4];
+const REQUIRED_VALUES: [u8; 5] = [1, 2, 3, 4, 6];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Features(pub u32);
+
+impl Features {
+    pub const SIGNATURES: Features = Features(1);
+    pub const COMPRESSION: Features = Features(2);
+    pub const WATCH: Features = Features(4);
+
+    pub fn empty() -> Features {
+        Features(0)
+    }
+
+    pub fn contains(&self, f: Features) -> bool {
+        self.0 & f.0 == f.0
+    }
+
+    pub fn intersection(&self, f: Features) -> Features {
+        Features(self.0 & f.0)
+    }
+}
+
+// what one side speaks, sent in Frame::Hello as the first thing on a new
+// channel. each side negotiates the other's hello against its own and
+// they arrive at the same answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub version: u32,
+    pub commands: Vec<u8>,
+    pub values: Vec<u8>,
+    pub max_frame: u64,
+    pub features: Features,
+}
+
+// encode takes codepoints from the discriminants, decode has them written
+// out by hand. make sure the two agree for every variant, otherwise we'd
+// advertise something we can't read back
+pub fn check_codepoints() -> Result<(), Error> {
+    for c in Command::samples() {
+        let mut b = Buffer::new();
+        c.encode(&mut b)?;
+        let d = Command::decode(&mut b)?;
+        if d.codepoint() != c.codepoint() || !b.is_empty() {
+            return Err(err!("command codepoint {} doesn't round trip, decoded as {}",
+                            c.codepoint(), d.codepoint()));
+        }
+    }
+    for v in Value::samples() {
+        let mut b = Buffer::new();
+        v.encode(&mut b)?;
+        let d = Value::decode(&mut b)?;
+        if d != v || !b.is_empty() {
+            return Err(err!("value codepoint {} doesn't round trip, decoded as {:?}",
+                            v.codepoint(), d));
+        }
+    }
+    Ok(())
+}
+
+fn intersect(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().filter(|x| b.contains(x)).copied().collect()
+}
+
+impl Capabilities {
+    pub fn local(features: Features) -> Result<Capabilities, Error> {
+        check_codepoints()?;
+        Ok(Capabilities {
+            version: PROTOCOL_VERSION,
+            commands: Command::samples().iter().map(|c| c.codepoint()).collect(),
+            values: Value::samples().iter().map(|v| v.codepoint()).collect(),
+            max_frame: DEFAULT_MAX_FRAME,
+            features,
+        })
+    }
+
+    // what both sides can use. refuses on a version mismatch or a peer
+    // missing the basics, otherwise drops down to the common subset
+    pub fn negotiate(&self, peer: &Capabilities) -> Result<Capabilities, Error> {
+        if peer.version != self.version {
+            return Err(err!("protocol version mismatch, we speak {} and peer speaks {}",
+                            self.version, peer.version));
+        }
+        if let Some(c) = REQUIRED_COMMANDS.iter().find(|c| !peer.commands.contains(c)) {
+            return Err(err!("peer doesn't support required command {}", c));
+        }
+        if let Some(v) = REQUIRED_VALUES.iter().find(|v| !peer.values.contains(v)) {
+            return Err(err!("peer doesn't support required value type {}", v));
+        }
+        Ok(Capabilities {
+            version: self.version,
+            commands: intersect(&self.commands, &peer.commands),
+            values: intersect(&self.values, &peer.values),
+            max_frame: self.max_frame.min(peer.max_frame),
+            features: self.features.intersection(peer.features),
+        })
+    }
+
+    // for the sender, before a block goes out under negotiated capabilities,
+    // and for the receiver when it comes in. the values in it count as much
+    // as the command itself
+    pub fn permits(&self, c: &Command) -> Result<(), Error> {
+        if !self.commands.contains(&c.codepoint()) {
+            return Err(err!("peer doesn't support command {:?}", c));
+        }
+        if let Some(v) = c.values().into_iter().find(|v| !self.values.contains(&v.codepoint())) {
+            return Err(err!("peer doesn't support value {:?} in {:?}", v, c));
+        }
+        Ok(())
+    }
+}
+
+impl Encodable for Capabilities {
+    fn decode(source: &mut Buffer) -> Result<Self, Error> {
+        let version = source.read_varint()? as u32;
+        let count = source.read_varint()?;
+        let commands = source.read(count as usize)?.to_vec();
+        let count = source.read_varint()?;
+        let values = source.read(count as usize)?.to_vec();
+        let max_frame = source.read_varint()?;
+        let features = Features(source.read_varint()? as u32);
+        Ok(Capabilities { version, commands, values, max_frame, features })
+    }
+
+    fn encode(&self, b: &mut Buffer) -> Result<(), Error> {
+        b.write_varint(self.version as u64)?;
+        b.write_varint(self.commands.len() as u64)?;
+        b.write(&self.commands)?;
+        b.write_varint(self.values.len() as u64)?;
+        b.write(&self.values)?;
+        b.write_varint(self.max_frame)?;
+        b.write_varint(self.features.0 as u64)
+    }
+}
@@ -1,16 +1,20 @@
-use alloc::{collections::BTreeMap, boxed::Box, sync::Arc, vec::Vec};
+use alloc::{collections::{BTreeMap, BTreeSet}, boxed::Box, vec::Vec};
 use async_trait::async_trait;
 use crate::{Attribute,
             Bindings,
-            DynAllocator,            
+            Cancel,
+            Command,
+            DynAllocator,
             DynEntityHandler,
             DynResolver,
             DynStream,
             Entity,
             Error,
             Oid,
+            Predicate,
             Stream,
             Value,
+            Variable,
             locerr,
             read_stream_with_err};
 
@@ -22,10 +26,15 @@ pub struct Scope {
     pub myself: Oid,
     pub allocator: DynAllocator,
     pub resolver: DynResolver,
-    pub write_commands: Arc<Vec<SetHandler>>,
+    pub cancel: Cancel,
 }
 
 impl Scope {
+    // the node scope is shared, each block evaluated under it gets its own token
+    pub fn for_block(&self, cancel: Cancel) -> Scope {
+        Scope { cancel, ..self.clone() }
+    }
+
     fn resolve(&self, v:Value) -> Result<DynEntityHandler, Error> {
         if let Value::Oid(oid)  = v {
             if let Some(e) = self.resolver.resolve(oid) {
@@ -39,11 +48,27 @@ impl Scope {
         }
 
     }
+
+    fn oid(&self, v:Value) -> Result<Oid, Error> {
+        if let Value::Oid(oid) = v {
+            Ok(oid)
+        } else {
+            Err(locerr!(self.myself, "non-oid in entity position {:?}", v))
+        }
+    }
+
+    fn unsigned(&self, v:Value) -> Result<usize, Error> {
+        if let Value::Unsigned(u) = v {
+            Ok(u as usize)
+        } else {
+            Err(locerr!(self.myself, "expected an unsigned offset or length, got {:?}", v))
+        }
+    }
 }
 
 // this is currently single threaded, which should be fine for short programs.
 // otherwise it would require the runtime from linux_proxy
- 
+
 // a resover maps an Oid to an ip address (?)
 // a translator maps an address from one space into another
 
@@ -51,19 +76,19 @@ impl Scope {
 
 struct EvalRoot {
     first: bool,
-} 
+}
 
 #[async_trait]
 impl Stream<Bindings> for EvalRoot {
     async fn next(&mut self) -> Result<Option<Bindings>, Error> {
         if self.first  {
             self.first = false;
-            Ok(Some(Bindings{b:BTreeMap::new()}))
+            Ok(Some(Bindings{b:BTreeMap::new(), writes:Vec::new()}))
         } else {
             // this shouldn't happen more than once
             Ok(None)
         }
-    }    
+    }
 }
 
 struct NewHandler {
@@ -75,21 +100,38 @@ struct NewHandler {
 #[async_trait]
 impl Stream<Bindings> for NewHandler {
     async fn next(&mut self) -> Result<Option<Bindings>, Error> {
-        read_stream_with_err!(self.prev, bindings, {        
+        read_stream_with_err!(self.scope, self.prev, bindings, {
             let oid = self.scope.allocator.new();
             // assumed that the scheduler is correct
-            bindings.assert(self.slot, Value::Oid(oid));
+            bindings.assert(self.slot.clone(), Value::Oid(oid));
+            bindings.writes.push(Command::Create(Value::Oid(oid)));
             Ok(Some(bindings))
         })
     }
 }
 
 struct SetHandler {
+    prev: DynStream<Bindings>,
+    scope: Scope,
     e: Entity,
     a: Attribute,
     v: Value,
 }
 
+#[async_trait]
+impl Stream<Bindings> for SetHandler {
+    async fn next(&mut self) -> Result<Option<Bindings>, Error> {
+        read_stream_with_err!(self.scope, self.prev, bindings, {
+            // not resolved, it may well have been created earlier in this block
+            let e = self.scope.oid(bindings.get(self.e.clone()).unwrap())?;
+            let a = bindings.get(self.a.clone()).unwrap();
+            let v = bindings.get(self.v.clone()).unwrap();
+            bindings.writes.push(Command::Set(Value::Oid(e), a, v));
+            Ok(Some(bindings))
+        })
+    }
+}
+
 struct GetHandler {
     prev: DynStream<Bindings>,
     scope: Scope,
@@ -101,39 +143,51 @@ struct GetHandler {
 #[async_trait]
 impl Stream<Bindings> for GetHandler {
     async fn next(&mut self) -> Result<Option<Bindings>, Error> {
-        read_stream_with_err!(self.prev, bindings, {
-            // we assume the scheduler has done its job, so these must succeed
-            let e = self.scope.resolve(bindings.get(self.entity).unwrap())?;
-            let a = bindings.get(self.attribute).unwrap();
-            
-            if let Some(v) = e.get(a)? {
-                bindings.assert(self.out, v);
+        loop {
+            return read_stream_with_err!(self.scope, self.prev, bindings, {
+                // we assume the scheduler has done its job, so these must succeed
+                let e = self.scope.resolve(bindings.get(self.entity.clone()).unwrap())?;
+                let a = bindings.get(self.attribute.clone()).unwrap();
+
+                // a union output is optional, a missing attribute binds it to
+                // Empty rather than eliminating the row
+                let (out, v) = match (self.out.clone(), e.get(a)?) {
+                    (Value::Union(n), None) => (Value::Variable(n), Value::Empty()),
+                    (Value::Union(n), Some(v)) => (Value::Variable(n), v),
+                    (out, Some(v)) => (out, v),
+                    (_, None) => continue,
+                };
+                if !bindings.assert(out, v) {
+                    continue
+                }
                 Ok(Some(bindings))
-            } else {
-                Ok(None)
-            }
-        })
+            })
+        }
     }
 }
 
 struct GetKeysHandler {
     scope: Scope,
     prev: DynStream<Bindings>,
-    keys: Option<(DynStream<Attribute>, Bindings)>,    
-    entity: Value,    
-    dest: Value, 
+    keys: Option<(DynStream<Attribute>, Bindings)>,
+    entity: Value,
+    dest: Value,
 }
 
 #[async_trait]
 impl Stream<Bindings> for GetKeysHandler {
     async fn next(&mut self) -> Result<Option<Bindings>, Error> {
-        if let Some((existing, bindings)) = self.keys &&
+        // the keys of one entity go by without pulling on prev, so without
+        // this a cancel wouldn't be seen until they ran out
+        self.scope.cancel.check(self.scope.myself)?;
+        if let Some((existing, bindings)) = &mut self.keys &&
             let Some(k) = existing.next().await? {
-                bindings.assert(self.dest, k);
+                let mut bindings = bindings.clone();
+                bindings.assert(self.dest.clone(), k);
                 return Ok(Some(bindings))
            }
-        read_stream_with_err!(self.prev, bindings, {
-            let e = self.scope.resolve(bindings.get(self.entity).unwrap())?;
+        read_stream_with_err!(self.scope, self.prev, bindings, {
+            let e = self.scope.resolve(bindings.get(self.entity.clone()).unwrap())?;
             self.keys = Some((e.keys(), bindings));
             self.next().await
         })
@@ -150,39 +204,344 @@ struct CopyHandler {
     length:Value,
 }
 
+// copies are turned into a set of the whole destination value. a copy
+// into a value an earlier copy in the block already wrote starts from that
+// write rather than the stored value, so that scattering into one value a
+// piece at a time (writev) keeps all the pieces
 #[async_trait]
 impl Stream<Bindings> for CopyHandler {
     async fn next(&mut self) -> Result<Option<Bindings>, Error> {
-        read_stream_with_err!(self.prev, bindings, {
+        read_stream_with_err!(self.scope, self.prev, bindings, {
+            let se = self.scope.resolve(bindings.get(self.se.clone()).unwrap())?;
+            let soffset = self.scope.unsigned(bindings.get(self.soffset.clone()).unwrap())?;
+            let doffset = self.scope.unsigned(bindings.get(self.doffset.clone()).unwrap())?;
+            let length = self.scope.unsigned(bindings.get(self.length.clone()).unwrap())?;
+            // nothing there reads as empty, the same as on the destination
+            // side, so a copy of nothing out of it is still fine
+            let source = match se.get(bindings.get(self.sa.clone()).unwrap())? {
+                Some(Value::Bytes(b)) => b,
+                None => Vec::new(),
+                x => return Err(locerr!(self.scope.myself, "attempt to copy from a non-byte value {:?}", x)),
+            };
+            let send = soffset.checked_add(length).filter(|end| *end <= source.len())
+                .ok_or_else(|| locerr!(self.scope.myself, "copy source out of range"))?;
+
+            let de = bindings.get(self.de.clone()).unwrap();
+            let da = bindings.get(self.da.clone()).unwrap();
+            let pending = bindings.writes.iter().rev().find_map(|w| match w {
+                Command::Set(e, a, v) if *e == de && *a == da => Some(v.clone()),
+                _ => None,
+            });
+            let current = match pending {
+                Some(v) => Some(v),
+                None => self.scope.resolve(de.clone())?.get(da.clone())?,
+            };
+            // a set to Empty earlier in the block cleared it, so copies after
+            // it build up a new value, reading from the stored one
+            let mut dest = match current {
+                Some(Value::Bytes(b)) => b,
+                None | Some(Value::Empty()) => Vec::new(),
+                x => return Err(locerr!(self.scope.myself, "attempt to copy into a non-byte value {:?}", x)),
+            };
+            let dend = doffset.checked_add(length)
+                .ok_or_else(|| locerr!(self.scope.myself, "copy destination out of range"))?;
+            if dest.len() < dend {
+                dest.resize(dend, 0);
+            }
+            dest[doffset..dend].copy_from_slice(&source[soffset..send]);
+            bindings.writes.push(Command::Set(de, da, Value::Bytes(dest)));
             Ok(Some(bindings))
         })
     }
 }
 
+// guards run for every row. one row failing abandons the block, since
+// the point is that none of the block's writes happen unless they all hold
+struct GuardHandler {
+    prev: DynStream<Bindings>,
+    scope: Scope,
+    index: u32,
+    left: Value,
+    predicate: Predicate,
+    right: Value,
+}
+
+#[async_trait]
+impl Stream<Bindings> for GuardHandler {
+    async fn next(&mut self) -> Result<Option<Bindings>, Error> {
+        read_stream_with_err!(self.scope, self.prev, bindings, {
+            let left = bindings.get(self.left.clone()).unwrap();
+            let right = bindings.get(self.right.clone()).unwrap();
+            if self.predicate.holds(&left, &right)? {
+                Ok(Some(bindings))
+            } else {
+                Err(Error::guard_failed(self.scope.myself, self.index))
+            }
+        })
+    }
+}
+
+// None for the attribute is an absent with a variable nothing has bound,
+// which asks for the entity to have no attributes at all (an empty
+// directory)
+struct AbsentHandler {
+    prev: DynStream<Bindings>,
+    scope: Scope,
+    index: u32,
+    entity: Entity,
+    attribute: Option<Attribute>,
+}
+
+#[async_trait]
+impl Stream<Bindings> for AbsentHandler {
+    async fn next(&mut self) -> Result<Option<Bindings>, Error> {
+        read_stream_with_err!(self.scope, self.prev, bindings, {
+            let e = self.scope.resolve(bindings.get(self.entity.clone()).unwrap())?;
+            let absent = match &self.attribute {
+                Some(a) => matches!(e.get(bindings.get(a.clone()).unwrap())?, None | Some(Value::Empty())),
+                None => e.keys().next().await?.is_none(),
+            };
+            if absent {
+                Ok(Some(bindings))
+            } else {
+                Err(Error::guard_failed(self.scope.myself, self.index))
+            }
+        })
+    }
+}
+
+struct CompareAndSetHandler {
+    prev: DynStream<Bindings>,
+    scope: Scope,
+    index: u32,
+    entity: Entity,
+    attribute: Attribute,
+    expected: Value,
+    new: Value,
+}
+
+// checked here so a block that has already lost fails early, but its the
+// recheck when the write is committed that makes it atomic
+#[async_trait]
+impl Stream<Bindings> for CompareAndSetHandler {
+    async fn next(&mut self) -> Result<Option<Bindings>, Error> {
+        read_stream_with_err!(self.scope, self.prev, bindings, {
+            let ev = bindings.get(self.entity.clone()).unwrap();
+            let a = bindings.get(self.attribute.clone()).unwrap();
+            let expected = bindings.get(self.expected.clone()).unwrap();
+            let new = bindings.get(self.new.clone()).unwrap();
+            let current = self.scope.resolve(ev.clone())?.get(a.clone())?.unwrap_or(Value::Empty());
+            if current != expected {
+                return Err(Error::guard_failed(self.scope.myself, self.index));
+            }
+            bindings.writes.push(Command::CompareAndSet(ev, a, expected, new));
+            Ok(Some(bindings))
+        })
+    }
+}
+
+struct DestroyHandler {
+    prev: DynStream<Bindings>,
+    scope: Scope,
+    entity: Entity,
+}
+
+#[async_trait]
+impl Stream<Bindings> for DestroyHandler {
+    async fn next(&mut self) -> Result<Option<Bindings>, Error> {
+        read_stream_with_err!(self.scope, self.prev, bindings, {
+            let oid = self.scope.oid(bindings.get(self.entity.clone()).unwrap())?;
+            bindings.writes.push(Command::Destroy(Value::Oid(oid)));
+            Ok(Some(bindings))
+        })
+    }
+}
+
+// the result of a block. rows are indexed by variable number, with
+// anything unbound in that row as Empty. the writes are collected as
+// rows go by, and are only complete once the stream has run out
+pub struct Evaluation {
+    rows: DynStream<Bindings>,
+    width: usize,
+    writes: Vec<Command>,
+}
+
+impl Evaluation {
+    pub fn writes(self) -> Vec<Command> {
+        self.writes
+    }
+}
+
+#[async_trait]
+impl Stream<Vec<Value>> for Evaluation {
+    async fn next(&mut self) -> Result<Option<Vec<Value>>, Error> {
+        match self.rows.next().await? {
+            Some(b) => {
+                self.writes.extend(b.writes);
+                Ok(Some((0..self.width)
+                        .map(|i| b.b.get(&(i as Variable)).cloned().unwrap_or(Value::Empty()))
+                        .collect()))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+// the 'scheduler'. walks the block once to make sure that every variable
+// is bound before anything reads it, which is what lets the handlers
+// unwrap their bindings
+struct Schedule {
+    myself: Oid,
+    bound: BTreeSet<Variable>,
+    width: usize,
+}
+
+impl Schedule {
+    fn see(&mut self, v: &Value) {
+        if let Value::Variable(n) | Value::Union(n) = v {
+            self.width = self.width.max(*n as usize + 1);
+        }
+    }
+
+    fn input(&mut self, index: usize, v: &Value) -> Result<(), Error> {
+        self.see(v);
+        match v {
+            Value::Variable(n) | Value::Union(n) if !self.bound.contains(n) =>
+                Err(locerr!(self.myself, "variable {} used before it is bound in command {}", n, index)),
+            _ => Ok(()),
+        }
+    }
+
+    fn output(&mut self, v: &Value) {
+        self.see(v);
+        if let Value::Variable(n) | Value::Union(n) = v {
+            self.bound.insert(*n);
+        }
+    }
+
+    fn is_free(&self, v: &Value) -> bool {
+        matches!(v, Value::Variable(n) if !self.bound.contains(n))
+    }
+}
+
 impl Scope {
-    
+    pub fn evaluate(&self, block: Vec<Command>) -> Result<Evaluation, Error> {
+        let mut s = Schedule{myself: self.myself, bound: BTreeSet::new(), width: 0};
+        let mut prev: DynStream<Bindings> = Box::new(EvalRoot{first: true});
+        for (i, c) in block.into_iter().enumerate() {
+            prev = match c {
+                Command::Get(e, a, v) => {
+                    s.input(i, &e)?;
+                    if s.is_free(&a) {
+                        s.output(&a);
+                        prev = self.build_get_keys(e.clone(), a.clone(), prev)?;
+                    } else {
+                        s.input(i, &a)?;
+                    }
+                    s.output(&v);
+                    self.build_get(e, a, v, prev)?
+                }
+                Command::Set(e, a, v) => {
+                    for x in [&e, &a, &v] {
+                        s.input(i, x)?;
+                    }
+                    self.build_set(e, a, v, prev)?
+                }
+                Command::Copy(se, sa, so, de, da, doff, length) => {
+                    for x in [&se, &sa, &so, &de, &da, &doff, &length] {
+                        s.input(i, x)?;
+                    }
+                    self.build_copy(se, sa, so, de, da, doff, length, prev)?
+                }
+                Command::Create(v) => {
+                    if !s.is_free(&v) {
+                        return Err(locerr!(self.myself, "create needs an unbound variable in command {}", i));
+                    }
+                    s.output(&v);
+                    self.build_new(v, prev)?
+                }
+                Command::Guard(l, p, r) => {
+                    s.input(i, &l)?;
+                    s.input(i, &r)?;
+                    self.build_guard(i as u32, l, p, r, prev)?
+                }
+                Command::Absent(e, a) => {
+                    s.input(i, &e)?;
+                    // a free variable stays free, it isn't bound to anything
+                    let a = if s.is_free(&a) {
+                        s.see(&a);
+                        None
+                    } else {
+                        s.input(i, &a)?;
+                        Some(a)
+                    };
+                    self.build_absent(i as u32, e, a, prev)?
+                }
+                Command::CompareAndSet(e, a, x, n) => {
+                    for v in [&e, &a, &x, &n] {
+                        s.input(i, v)?;
+                    }
+                    self.build_compare_and_set(i as u32, e, a, x, n, prev)?
+                }
+                Command::Destroy(e) => {
+                    s.input(i, &e)?;
+                    self.build_destroy(e, prev)?
+                }
+            };
+        }
+        Ok(Evaluation{rows: prev, width: s.width, writes: Vec::new()})
+    }
+
     // the fact that I can't use enum cases as subtypes is pretty annoying
     fn build_get(&self, entity: Entity, attribute: Attribute, out:Value, prev: DynStream<Bindings>) -> Result<DynStream<Bindings>, Error> {
-        Ok(Arc::new(GetHandler{prev, scope:self.clone(), entity, attribute, out}))
+        Ok(Box::new(GetHandler{prev, scope:self.clone(), entity, attribute, out}))
     }
-    
+
+    fn build_get_keys(&self, entity: Entity, dest: Value, prev: DynStream<Bindings>) -> Result<DynStream<Bindings>, Error> {
+        Ok(Box::new(GetKeysHandler{prev, scope:self.clone(), keys:None, entity, dest}))
+    }
+
+    fn build_set(&self, e: Entity, a: Attribute, v: Value, prev: DynStream<Bindings>) -> Result<DynStream<Bindings>, Error> {
+        Ok(Box::new(SetHandler{prev, scope:self.clone(), e, a, v}))
+    }
+
     fn build_new(&self, out: Value, prev: DynStream<Bindings>) -> Result<DynStream<Bindings>, Error> {
         if let Value::Variable(_) = out {
-            Ok(Arc::new(NewHandler{prev, scope:self.clone(), slot:out}))
+            Ok(Box::new(NewHandler{prev, scope:self.clone(), slot:out}))
         } else {
             // it would be nice if this included source information, wouldn't it?
             Err(locerr!(self.myself, "new argument must be variable"))
         }
-        
+
     }
-    
+
+    #[allow(clippy::too_many_arguments)]
     fn build_copy(&self,
                   se: Entity, sa: Attribute, soffset:Value,
                   de: Entity, da: Attribute, doffset:Value,
                   length: Value,
                   prev: DynStream<Bindings>) -> Result<DynStream<Bindings>, Error> {
         // validate length, entity, attribute
-        Ok(Arc::new(CopyHandler{se, sa, soffset, de, da, doffset, length, prev, scope:self.clone()}))
+        Ok(Box::new(CopyHandler{se, sa, soffset, de, da, doffset, length, prev, scope:self.clone()}))
     }
-}
 
+    fn build_guard(&self, index: u32, left: Value, predicate: Predicate, right: Value,
+                   prev: DynStream<Bindings>) -> Result<DynStream<Bindings>, Error> {
+        Ok(Box::new(GuardHandler{prev, scope:self.clone(), index, left, predicate, right}))
+    }
+
+    fn build_absent(&self, index: u32, entity: Entity, attribute: Option<Attribute>,
+                    prev: DynStream<Bindings>) -> Result<DynStream<Bindings>, Error> {
+        Ok(Box::new(AbsentHandler{prev, scope:self.clone(), index, entity, attribute}))
+    }
+
+    fn build_compare_and_set(&self, index: u32, entity: Entity, attribute: Attribute,
+                             expected: Value, new: Value,
+                             prev: DynStream<Bindings>) -> Result<DynStream<Bindings>, Error> {
+        Ok(Box::new(CompareAndSetHandler{prev, scope:self.clone(), index, entity, attribute, expected, new}))
+    }
+
+    fn build_destroy(&self, entity: Entity, prev: DynStream<Bindings>) -> Result<DynStream<Bindings>, Error> {
+        Ok(Box::new(DestroyHandler{prev, scope:self.clone(), entity}))
+    }
+}
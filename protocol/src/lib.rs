@@ -1,33 +1,47 @@
 #![no_std]
 #![allow(dead_code)]
 extern crate alloc;
+// so that code generated by protocol-derive can name ::protocol from in here too
+extern crate self as protocol;
 pub use alloc::{boxed::Box, format, string::String, sync::Arc, collections::BTreeMap, vec::Vec};
 use core::sync::atomic::{AtomicU64, Ordering};
 use async_trait::async_trait;
 
 mod buffer;
+mod cancel;
 mod command;
 mod error;
+mod frame;
+mod handshake;
 mod memory;
+mod query;
+mod session;
+mod store;
 mod value;
 pub mod interpreter;
 
 pub use buffer::*;
+pub use cancel::*;
 pub use command::*;
 pub use error::*;
+pub use frame::*;
+pub use handshake::*;
 pub use value::*;
 pub use memory::*;
+pub use query::*;
+pub use session::*;
+pub use store::*;
 pub use interpreter::*;
+pub use protocol_derive::Entity;
 
 #[macro_export]
 macro_rules! attribute {
-    ($sattr:expr) => {{
-        use alloc::string::ToString;
-        Attribute($sattr.to_string())
-    }};
+    ($sattr:expr) => {
+        $crate::Value::Utf8String($crate::format!("{}", $sattr))
+    };
 }
 
-type DynResolver = Arc<dyn Resolver + Sync + Send>;
+pub type DynResolver = Arc<dyn Resolver + Sync + Send>;
 pub trait Resolver {
     fn resolve(&self, v: Oid) -> Option<DynEntityHandler>;
 }
@@ -40,7 +54,7 @@ pub fn new_object() -> Oid {
 type ChangeSet = alloc::vec::Vec<(Attribute, Value)>;
 
 // we may need to add a method to sort of the target of a copy operation (in or out)
-pub type DynEntityHandler = Arc<dyn EntityHandler>;
+pub type DynEntityHandler = Arc<dyn EntityHandler + Send + Sync>;
 pub trait EntityHandler  {
     fn keys(&self) -> DynStream<Attribute>;
     fn get(&self, a: Attribute) -> Result<Option<Value>, Error>;
@@ -48,25 +62,29 @@ pub trait EntityHandler  {
     fn copyout(&self,
                source_attribute:Attribute,
                source_offset:usize,
-               dest:&[u8]) -> Result<(), Error>;    
+               dest:&mut [u8]) -> Result<(), Error>;
 }
 
 
-struct Bindings {
+// writes ride along with the row that produced them, and are only handed
+// to the store once the whole block has been evaluated
+#[derive(Clone)]
+pub struct Bindings {
     b:BTreeMap<Variable, Value>,
+    writes:Vec<Command>,
 }
 
 impl Bindings {
     fn get(&self, key:Value) -> Option<Value> {
         match key {
-            Value::Variable(k) => self.b.get(&k).map(|x|x.clone()),
+            Value::Variable(k) => self.b.get(&k).cloned(),
             _ => Some(key)
         }
     }
     
     fn assert(&mut self, key:Value, value:Value) -> bool {
         if let Value::Variable(v) = key {
-            match self.get(key) {
+            match self.get(key.clone()) {
                 Some(x) => x == value,
                 None => {
                     self.b.insert(v, value);
@@ -79,14 +97,18 @@ impl Bindings {
     }
 }
 
+// every handler passes its scope, so a cancelled or expired block stops
+// at whichever handler happens to be pulling next
 #[macro_export]
 macro_rules! read_stream_with_err {
-    ($stream:expr, $bindings:ident, $body:expr) => {{
-        let x = $stream.next().await;
-        match x {
-            Err(e) => x,
-            Ok(None) => x,
-            Ok(Some($bindings)) => $body,            
+    ($scope:expr, $stream:expr, $bindings:ident, $body:expr) => {{
+        $scope.cancel.check($scope.myself)?;
+        match $stream.next().await {
+            Err(e) => Err(e),
+            Ok(None) => Ok(None),
+            // not every handler adds to the row
+            #[allow(unused_mut)]
+            Ok(Some(mut $bindings)) => $body,
         }
     }}
 }
@@ -95,7 +117,7 @@ macro_rules! read_stream_with_err {
 // contained and not the container, but that eliminates our ability
 // to use '?', which is the only thing really keeping going here. I hate
 // the asymmetry of <A> and the return type of next
-pub type DynStream<A> = Arc<dyn Stream<A> + Send + Sync>;
+pub type DynStream<A> = Box<dyn Stream<A> + Send + Sync>;
 #[async_trait]
 pub trait Stream<A> {
     async fn next(&mut self) -> Result<Option<A>, Error>;
@@ -103,17 +125,20 @@ pub trait Stream<A> {
 
 
 pub type DynAllocator = Arc<dyn Allocator + Send + Sync>;
-trait Allocator {
+// new as in a new oid, not a new allocator
+pub trait Allocator {
+    #[allow(clippy::new_ret_no_self, clippy::wrong_self_convention)]
     fn new(&self) -> Oid;
 }
 
-struct SimpleAllocator {
+pub struct SimpleAllocator {
     base:Oid,
     count: AtomicU64,
 }
 
 impl SimpleAllocator {
-    fn new(base:Oid) -> DynAllocator {
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(base:Oid) -> DynAllocator {
         Arc::new(SimpleAllocator{base, count:AtomicU64::new(0)})
     }
 }
@@ -1,54 +1,69 @@
 use crate::{ Attribute,
  Error,
+ Oid,
  Value,
  EntityHandler,
  DynStream,
  Stream,
  locerr,
- Command,
- Scope};
-use alloc::{vec::Vec, collections::BTreeMap, boxed::Box, sync::Arc, vec};
+ Command};
+use alloc::{vec::Vec, collections::{BTreeMap, BTreeSet}, boxed::Box};
 use async_trait::async_trait;
 
 // we keep a separate set of keys so that we can do async iteration without plumbing a runtime or a lifetime
 // through the entire codebase. maybe there is a better answer? its not clear
 #[derive(Clone)]
 pub struct Memory {
+    myself: Oid,
     attributes: Vec<Attribute>,
     values: BTreeMap<Attribute, Value>,
 }
 
 impl Memory {
-    pub fn new()->Self {
+    pub fn new(myself: Oid)->Self {
         Memory{
+            myself,
             attributes:Vec::new(),
             values:BTreeMap::new(),
         }
     }
     
-    fn copyin(&self,
-              scope: Scope,
-              dest_attribute:Attribute, 
-              dest_offset: usize,
-              source: &[u8]) -> Result<(), Error> {
-        if let Some(x) = self.get(dest_attribute)? {
-            if let Value::Bytes(v) = x {
-                let end = dest_offset + source.len();
-                let first_end = core::cmp::min(end, v.len() as usize);
-                v.as_slice()[dest_offset..].copy_from_slice(&source[..first_end]);
-                if first_end < end {
-                    v.extend(&source[first_end..end])                    
+    // the write side of commit. Set to Empty is a removal, not a stored value
+    pub fn apply(&mut self, c: Command) -> Result<(), Error> {
+        match c {
+            Command::Set(_, a, Value::Empty()) => {
+                if self.values.remove(&a).is_some() {
+                    self.attributes.retain(|x| *x != a);
                 }
-                Ok(())
-            } else {
-                Err(locerr!(scope.myself, "attempt to copy into a non-byte value"))
             }
-        } else {
-            let mut out = vec![0; dest_offset];
-            out.extend_from_slice(source);
-            scope.write_commands.push(Command::Set(self.myself, dest_attribute, Value::Bytes(out)));
-            Ok(())
+            Command::Set(_, a, v) => {
+                if self.values.insert(a.clone(), v).is_none() {
+                    self.attributes.push(a);
+                }
+            }
+            c => return Err(locerr!(self.myself, "cant apply {:?} to an attribute set", c)),
         }
+        Ok(())
+    }
+
+    pub fn oid(&self) -> Oid {
+        self.myself
+    }
+
+    // drop every attribute naming or holding one of these, they're gone
+    pub fn forget(&mut self, gone: &BTreeSet<Oid>) {
+        let dead = |x: &Value| matches!(x, Value::Oid(oid) if gone.contains(oid));
+        self.values.retain(|a, v| !dead(a) && !dead(v));
+        let values = &self.values;
+        self.attributes.retain(|a| values.contains_key(a));
+    }
+
+    // every entity this one points at, through either an attribute or a value
+    pub fn references(&self) -> impl Iterator<Item = Oid> + '_ {
+        self.values.iter().flat_map(|(a, v)| [a, v]).filter_map(|x| match x {
+            Value::Oid(oid) => Some(*oid),
+            _ => None,
+        })
     }
 }
 
@@ -60,8 +75,9 @@ pub struct KeysIter {
 #[async_trait]
 impl Stream<Attribute> for KeysIter {
     async fn next(&mut self) -> Result<Option<Attribute>, Error> {
-        if self.index < self.m.values.len() {
-            Ok(Some(self.m.attributes[self.index].clone()))
+        if self.index < self.m.attributes.len() {
+            self.index += 1;
+            Ok(Some(self.m.attributes[self.index - 1].clone()))
         } else  {
             Ok(None)
         }
@@ -69,9 +85,9 @@ impl Stream<Attribute> for KeysIter {
 }
 
 
-impl<'a> EntityHandler for Memory {
+impl EntityHandler for Memory {
     fn keys(&self) -> DynStream<Attribute>  {
-        Arc::new(KeysIter{index: 0, m:self.clone()})
+        Box::new(KeysIter{index: 0, m:self.clone()})
     }
 
     fn get(&self, a: Attribute) -> Result<Option<Value>, Error> {
@@ -91,14 +107,16 @@ impl<'a> EntityHandler for Memory {
     fn copyout(&self,
                source_attribute:Attribute,
                source_offset:usize,
-               dest:&[u8]) -> Result<(), Error> {
-        if let Some(x) = self.get(source_attribute)? {
-            if let Value::Bytes(v) = x {
-                dest.copy_from_slice(v.as_slice()[source_offset..source_offset+dest.len()]);
+               dest:&mut [u8]) -> Result<(), Error> {
+        match self.get(source_attribute)? {
+            Some(Value::Bytes(v)) => {
+                let source = source_offset.checked_add(dest.len())
+                    .and_then(|end| v.get(source_offset..end))
+                    .ok_or_else(|| locerr!(self.myself, "copy source out of range"))?;
+                dest.copy_from_slice(source);
                 Ok(())
-            } else {
-                Err(locerr!(self.myself, "attempt to copy from a non-byte value"))
             }
+            _ => Err(locerr!(self.myself, "attempt to copy from a non-byte value")),
         }
-    }   
+    }
 }
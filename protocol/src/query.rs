@@ -0,0 +1,253 @@
+use alloc::{string::String, vec::Vec};
+use core::time::Duration;
+use crate::{Command, Error, Oid, Value, Variable, err};
+
+// the typed side of a query block. execute! names block variables with rust
+// bindings (name:Type), this keeps track of which variable number each
+// name was given, and what rust type the caller expects to come out of it
+
+pub trait FromValue: Sized {
+    fn from_value(v: &Value) -> Result<Self, Error>;
+}
+
+impl FromValue for Value {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        Ok(v.clone())
+    }
+}
+
+impl FromValue for Oid {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        match v {
+            Value::Oid(oid) => Ok(*oid),
+            _ => Err(err!("expected oid, got {:?}", v)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        match v {
+            Value::Utf8String(s) => Ok(s.clone()),
+            _ => Err(err!("expected string, got {:?}", v)),
+        }
+    }
+}
+
+impl FromValue for Vec<u8> {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        match v {
+            Value::Bytes(b) => Ok(b.clone()),
+            _ => Err(err!("expected bytes, got {:?}", v)),
+        }
+    }
+}
+
+impl FromValue for u64 {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        match v {
+            Value::Unsigned(u) => Ok(*u),
+            _ => Err(err!("expected unsigned, got {:?}", v)),
+        }
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        match v {
+            Value::Signed(i) => Ok(*i),
+            _ => Err(err!("expected signed, got {:?}", v)),
+        }
+    }
+}
+
+impl FromValue for u32 {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        u64::from_value(v)?.try_into().map_err(|_| err!("{:?} out of range for u32", v))
+    }
+}
+
+// what a Union binds to when the attribute isn't there
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        match v {
+            Value::Empty() => Ok(None),
+            v => T::from_value(v).map(Some),
+        }
+    }
+}
+
+// times are carried as unsigned nanoseconds
+impl FromValue for Duration {
+    fn from_value(v: &Value) -> Result<Self, Error> {
+        Ok(Duration::from_nanos(u64::from_value(v)?))
+    }
+}
+
+impl From<Oid> for Value {
+    fn from(oid: Oid) -> Self {
+        Value::Oid(oid)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(u: u64) -> Self {
+        Value::Unsigned(u)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(u: u32) -> Self {
+        Value::Unsigned(u as u64)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Utf8String(s)
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(b: Vec<u8>) -> Self {
+        Value::Bytes(b)
+    }
+}
+
+impl From<Duration> for Value {
+    fn from(d: Duration) -> Self {
+        Value::Unsigned(d.as_nanos() as u64)
+    }
+}
+
+pub type Block = Vec<Command>;
+
+// a rust struct whose fields are the attributes of one entity, normally
+// from #[derive(Entity)]. load is the block that fetches them, and from_row
+// takes the one row it returns
+pub trait Schema: Sized {
+    fn load(oid: Oid) -> Block;
+    fn store(&self, oid: Oid) -> Block;
+    fn from_row(row: &[Value]) -> Result<Self, Error>;
+}
+
+pub struct Query {
+    // indexed by variable number, (rust name, rust type)
+    variables: Vec<(&'static str, &'static str)>,
+    pub commands: Vec<Command>,
+}
+
+impl Default for Query {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Query {
+            variables: Vec::new(),
+            commands: Vec::new(),
+        }
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.variables.iter().position(|(n, _)| *n == name)
+    }
+
+    // allocate a variable the first time a name is seen, and hand back the same
+    // one for every later use. the same name with two different types is
+    // almost certainly a typo, so we refuse to build the block
+    pub fn var(&mut self, name: &'static str, ty: &'static str) -> Result<Value, Error> {
+        Ok(Value::Variable(self.slot(name, ty)?))
+    }
+
+    // the same, for a name:Option<Type>. the row has Empty for it when
+    // the attribute is missing, instead of no row at all
+    pub fn union(&mut self, name: &'static str, ty: &'static str) -> Result<Value, Error> {
+        Ok(Value::Union(self.slot(name, ty)?))
+    }
+
+    fn slot(&mut self, name: &'static str, ty: &'static str) -> Result<Variable, Error> {
+        if let Some(i) = self.position(name) {
+            let (_, existing) = self.variables[i];
+            if existing != ty {
+                return Err(err!("query variable {} used as both {} and {}", name, existing, ty));
+            }
+            Ok(i as Variable)
+        } else {
+            self.variables.push((name, ty));
+            Ok((self.variables.len() - 1) as Variable)
+        }
+    }
+
+    // result rows are indexed by variable number
+    pub fn bind<T: FromValue>(&self, row: &[Value], name: &'static str) -> Result<T, Error> {
+        let i = self.position(name).ok_or_else(|| err!("unknown query variable {}", name))?;
+        let v = row.get(i).ok_or_else(|| err!("result row missing variable {}", name))?;
+        T::from_value(v).map_err(|e| err!("query variable {}: {}", name, e.cause))
+    }
+}
+
+// execute!(runtime, [Get(file.obj, attribute!("children"), dir:Oid),
+//                    Get(dir:Oid, name:String, child:Oid)] {
+//     ... dir, name and child are bound here, once per result row
+// });
+//
+// commands are named by their Command variant. an argument of the form
+// name:Type is a block variable, anything else is an expression that is
+// converted into a constant Value. a variable carries its type at every
+// use, since a bare name would be taken as a rust expression. a
+// name:Option<Type> is a Union, None where the attribute is missing. has to
+// be used in an async fn that returns Result<_, protocol::Error>
+#[macro_export]
+macro_rules! execute {
+    ($runtime:expr, [$($cmd:ident ( $($arg:tt)* )),* $(,)?] $body:block) => {{
+        use $crate::Stream as _;
+        let mut __query = $crate::Query::new();
+        $(
+            let __command = $crate::__query_terms!(__query, $cmd, [], $($arg)*,);
+            __query.commands.push(__command);
+        )*
+        let mut __stream = $runtime.execute(core::mem::take(&mut __query.commands))?;
+        while let Some(__row) = __stream.next().await? {
+            $( $crate::__query_bindings!(__query, __row, $($arg)*,); )*
+            $body
+        }
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __query_terms {
+    ($q:ident, $cmd:ident, [$($acc:expr),*], ) => {
+        $crate::Command::$cmd($($acc),*)
+    };
+    ($q:ident, $cmd:ident, [$($acc:expr),*], $name:ident : Option<$ty:ty>, $($rest:tt)*) => {
+        $crate::__query_terms!($q, $cmd,
+                               [$($acc,)* $q.union(stringify!($name), core::any::type_name::<Option<$ty>>())?],
+                               $($rest)*)
+    };
+    ($q:ident, $cmd:ident, [$($acc:expr),*], $name:ident : $ty:ty, $($rest:tt)*) => {
+        $crate::__query_terms!($q, $cmd,
+                               [$($acc,)* $q.var(stringify!($name), core::any::type_name::<$ty>())?],
+                               $($rest)*)
+    };
+    ($q:ident, $cmd:ident, [$($acc:expr),*], $term:expr, $($rest:tt)*) => {
+        $crate::__query_terms!($q, $cmd, [$($acc,)* $crate::Value::from($term)], $($rest)*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __query_bindings {
+    ($q:ident, $row:ident, ) => {};
+    ($q:ident, $row:ident, $name:ident : $ty:ty, $($rest:tt)*) => {
+        #[allow(unused_variables)]
+        let $name: $ty = $q.bind(&$row, stringify!($name))?;
+        $crate::__query_bindings!($q, $row, $($rest)*);
+    };
+    ($q:ident, $row:ident, $term:expr, $($rest:tt)*) => {
+        $crate::__query_bindings!($q, $row, $($rest)*);
+    };
+}
@@ -0,0 +1,45 @@
+use crate::{DynClock, Error, Evaluation, Frame, Inflight, Scope, err};
+
+// the receiving end of a channel, once the hellos are out of the way (see
+// handshake). each request is evaluated under its own cancel, which is how
+// a later Frame::Cancel or the request's deadline reaches it. the caller
+// drives the evaluation it gets back, sends the rows, and calls finish
+pub struct Session {
+    scope: Scope,
+    inflight: Inflight,
+}
+
+impl Session {
+    pub fn new(scope: Scope, clock: DynClock) -> Self {
+        Session {
+            scope,
+            inflight: Inflight::new(clock),
+        }
+    }
+
+    // a request comes back with its id, anything else is handled here
+    pub fn receive(&mut self, frame: Frame) -> Result<Option<(u64, Evaluation)>, Error> {
+        match frame {
+            Frame::Request { id, deadline, block } => {
+                let cancel = self.inflight.begin(id, deadline);
+                match self.scope.for_block(cancel).evaluate(block) {
+                    Ok(e) => Ok(Some((id, e))),
+                    Err(e) => {
+                        self.inflight.finish(id);
+                        Err(e)
+                    }
+                }
+            }
+            // one that already finished is nothing to worry about
+            Frame::Cancel { id } => {
+                self.inflight.cancel(id);
+                Ok(None)
+            }
+            Frame::Hello(_) => Err(err!("hello after the handshake")),
+        }
+    }
+
+    pub fn finish(&mut self, id: u64) {
+        self.inflight.finish(id);
+    }
+}
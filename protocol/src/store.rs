@@ -0,0 +1,167 @@
+use alloc::{collections::{BTreeMap, BTreeSet}, sync::Arc, vec::Vec};
+use crate::{Command, DynEntityHandler, EntityHandler, Error, Memory, Oid, Resolver, Value, locerr};
+
+// a whole graph of Memory entities, for a node that actually holds state
+// rather than proxying it. nothing is reference counted, the graph has
+// cycles everywhere (directory '..', process parent/children), so instead
+// entities that can't be reached from the roots are swept by collect()
+pub struct Store {
+    myself: Oid,
+    entities: BTreeMap<Oid, Memory>,
+    roots: BTreeSet<Oid>,
+}
+
+impl Store {
+    pub fn new(myself: Oid) -> Self {
+        Store {
+            myself,
+            entities: BTreeMap::new(),
+            roots: BTreeSet::new(),
+        }
+    }
+
+    pub fn add_root(&mut self, oid: Oid) {
+        self.roots.insert(oid);
+    }
+
+    pub fn remove_root(&mut self, oid: Oid) {
+        self.roots.remove(&oid);
+    }
+
+    fn oid(&self, e: &Value) -> Result<Oid, Error> {
+        match e {
+            Value::Oid(oid) => Ok(*oid),
+            e => Err(locerr!(self.myself, "unbound entity in commit {:?}", e)),
+        }
+    }
+
+    // writes only. by the time a block gets here the interpreter has bound
+    // all the variables and checked the guards. compare-and-sets were checked
+    // against a snapshot though, so they are checked again here. all of it
+    // is checked before anything is applied, a block goes in whole or not
+    // at all
+    pub fn commit(&mut self, block: Vec<Command>) -> Result<(), Error> {
+        self.check(&block)?;
+        let mut destroyed = Vec::new();
+        for c in block {
+            match c {
+                Command::Create(Value::Oid(oid)) => {
+                    self.entities.entry(oid).or_insert_with(|| Memory::new(oid));
+                }
+                Command::Set(Value::Oid(oid), a, v) | Command::CompareAndSet(Value::Oid(oid), a, _, v) => {
+                    if let Some(m) = self.entities.get_mut(&oid) {
+                        m.apply(Command::Set(Value::Oid(oid), a, v))?;
+                    }
+                }
+                Command::Destroy(Value::Oid(oid)) => {
+                    self.roots.remove(&oid);
+                    if let Some(m) = self.entities.remove(&oid) {
+                        destroyed.push(m);
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !destroyed.is_empty() {
+            self.destroyed(destroyed);
+        }
+        Ok(())
+    }
+
+    // what each command would find, walking the block without changing
+    // anything. entities created earlier in the block are there, destroyed
+    // ones aren't
+    fn check(&self, block: &[Command]) -> Result<(), Error> {
+        let mut created = BTreeSet::new();
+        let mut destroyed = BTreeSet::new();
+        let exists = |oid: &Oid, created: &BTreeSet<Oid>, destroyed: &BTreeSet<Oid>| {
+            !destroyed.contains(oid) && (created.contains(oid) || self.entities.contains_key(oid))
+        };
+        for (i, c) in block.iter().enumerate() {
+            match c {
+                Command::Create(e) => {
+                    let oid = self.oid(e)?;
+                    destroyed.remove(&oid);
+                    created.insert(oid);
+                }
+                Command::Set(e, _, _) | Command::CompareAndSet(e, _, _, _) => {
+                    let oid = self.oid(e)?;
+                    if !exists(&oid, &created, &destroyed) {
+                        return Err(locerr!(self.myself, "unknown object {:?}", oid));
+                    }
+                    if let Command::CompareAndSet(_, a, expected, _) = c {
+                        let current = match self.entities.get(&oid) {
+                            Some(m) => m.get(a.clone())?.unwrap_or(Value::Empty()),
+                            None => Value::Empty(),
+                        };
+                        if current != *expected {
+                            return Err(Error::guard_failed(self.myself, i as u32));
+                        }
+                    }
+                }
+                Command::Destroy(e) => {
+                    let oid = self.oid(e)?;
+                    if !exists(&oid, &created, &destroyed) {
+                        return Err(locerr!(self.myself, "destroy of unknown object {:?}", oid));
+                    }
+                    created.remove(&oid);
+                    destroyed.insert(oid);
+                }
+                c => return Err(locerr!(self.myself, "cant commit {:?}", c)),
+            }
+        }
+        Ok(())
+    }
+
+    // nothing is left pointing at what was destroyed, and whatever only it
+    // kept reachable goes too. only those are swept, not everything
+    // unreachable, someone may be holding an entity by its Oid alone
+    fn destroyed(&mut self, gone: Vec<Memory>) {
+        let oids: BTreeSet<Oid> = gone.iter().map(|m| m.oid()).collect();
+        for m in self.entities.values_mut() {
+            m.forget(&oids);
+        }
+        let mut candidates = BTreeSet::new();
+        let mut pending: Vec<Oid> = gone.iter().flat_map(|m| m.references()).collect();
+        while let Some(oid) = pending.pop() {
+            if let Some(m) = self.entities.get(&oid) && candidates.insert(oid) {
+                pending.extend(m.references().filter(|r| !candidates.contains(r)));
+            }
+        }
+        self.sweep(candidates);
+    }
+
+    // mark from the roots and sweep everything else. references to entities
+    // that aren't here are someone elses problem and just aren't followed.
+    // returns what was reclaimed
+    pub fn collect(&mut self) -> Vec<Oid> {
+        let all = self.entities.keys().copied().collect();
+        self.sweep(all)
+    }
+
+    // reclaim whichever of the candidates can't be reached from the roots
+    fn sweep(&mut self, candidates: BTreeSet<Oid>) -> Vec<Oid> {
+        if candidates.is_empty() {
+            return Vec::new();
+        }
+        let mut marked = BTreeSet::new();
+        let mut pending: Vec<Oid> = self.roots.iter().copied().collect();
+        while let Some(oid) = pending.pop() {
+            if let Some(m) = self.entities.get(&oid) && marked.insert(oid) {
+                pending.extend(m.references().filter(|r| !marked.contains(r)));
+            }
+        }
+        let dead: Vec<Oid> = candidates.into_iter().filter(|k| !marked.contains(k)).collect();
+        for oid in &dead {
+            self.entities.remove(oid);
+        }
+        dead
+    }
+}
+
+// readers get a snapshot of the entity as of resolution
+impl Resolver for Store {
+    fn resolve(&self, v: Oid) -> Option<DynEntityHandler> {
+        self.entities.get(&v).map(|m| Arc::new(m.clone()) as DynEntityHandler)
+    }
+}
@@ -1,6 +1,6 @@
-use crate::{Buffer, DynEntityHandler, DynStream, Error, err, Encodable};
-use alloc::string::{String, ToString};
-use alloc::vec::Vec;
+use crate::{Buffer, DynEntityHandler, DynStream, Error, err, Encodable, FromValue};
+use alloc::string::String;
+use alloc::{vec, vec::Vec};
 
 #[derive(PartialEq, Eq, Ord, PartialOrd, Clone, Copy, Debug)]
 pub struct Oid(pub u128);
@@ -19,7 +19,7 @@ pub enum Value {
     Unsigned(u64) = 4,
     Signed(i64) = 5,
     Variable(Variable) = 6, 
-    Empty() = 7,            // used for deletia
+    Empty() = 7,            // used for deletia, Set to Empty removes the attribute
     Union(Variable) = 8, 
 }
 
@@ -28,10 +28,10 @@ impl Encodable for Oid {
         dest.write(&self.0.to_be_bytes())
     }
     fn decode(source: &mut Buffer) -> Result<Self, Error> {
-        // there is a compiler constant
-        Ok(Oid(u128::from_be_bytes(
-            source.read(16)?.try_into().expect("cast"),
-        )))
+        // read has already checked the length, so this can't fail
+        let mut b = [0u8; 16];
+        b.copy_from_slice(source.read(16)?);
+        Ok(Oid(u128::from_be_bytes(b)))
     }
 }
 
@@ -40,83 +40,107 @@ impl Encodable for Variable {
         dest.write(&self.to_be_bytes())
     }
     fn decode(source: &mut Buffer) -> Result<Self, Error> {
-        // there is a compiler constant
-        Ok(u32::from_be_bytes(
-            source.read(4)?.try_into().expect("cast"),
-        ))
+        let mut b = [0u8; 4];
+        b.copy_from_slice(source.read(4)?);
+        Ok(u32::from_be_bytes(b))
     }
 }
 
+impl Value {
+    // the discriminant, which for a repr(u8) enum is its first byte. encode
+    // uses this so that only decode has to be kept in step by hand, and the
+    // handshake checks that it has been
+    pub fn codepoint(&self) -> u8 {
+        unsafe { *(self as *const Self as *const u8) }
+    }
+
+    // one of every variant, for check_codepoints. add new ones here
+    pub fn samples() -> Vec<Value> {
+        vec![
+            Value::Oid(Oid(0)),
+            Value::Utf8String(String::new()),
+            Value::Bytes(Vec::new()),
+            Value::Unsigned(0),
+            Value::Signed(0),
+            Value::Variable(0),
+            Value::Empty(),
+            Value::Union(0),
+        ]
+    }
+}
+
+fn read_variable(source: &mut Buffer) -> Result<Variable, Error> {
+    let v = source.read_varint()?;
+    Variable::try_from(v).map_err(|_| err!("variable number {} out of range", v))
+}
+
 // discrimimant issues
 // since the discriminant is so small we could use the rest of those bits for something
 impl Encodable for Value {
     fn encode(&self, dest: &mut Buffer) -> Result<(), Error> {
+        dest.write(&[self.codepoint()])?;
         match self {
-            Value::Oid(oid) => {
-                dest.write(&[1])?;
-            }
+            Value::Oid(oid) => oid.encode(dest)?,
             Value::Utf8String(string) => {
-                dest.write(&[2])?;
+                dest.write_varint(string.len() as u64)?;
+                dest.write(string.as_bytes())?;
             }
             Value::Bytes(v) => {
-                dest.write(&[3])?;
                 dest.write_varint(v.len() as u64)?;
                 dest.write(v)?;                
             }
-           Value::Unsigned(u) => {
-                dest.write(&[4])?;
-                dest.write_varint(*u)?;                
-            }
-            Value::Signed(i) => {
-                panic!("no signed");
-            }
-            Value::Variable(v) => {
-                dest.write(&[6])?;
-                dest.write_varint(*v as u64)?;
-            }
-            Value::Empty() => {
-                dest.write(&[7])?;
-            }
-            Value::Union(v) => {
-                dest.write(&[8])?;
-                dest.write_varint(*v as u64)?;                
-            }
+            Value::Unsigned(u) => dest.write_varint(*u)?,
+            // zigzag, so small negative numbers stay small
+            Value::Signed(i) => dest.write_varint(((*i << 1) ^ (*i >> 63)) as u64)?,
+            Value::Variable(v) => dest.write_varint(*v as u64)?,
+            Value::Empty() => {}
+            Value::Union(v) => dest.write_varint(*v as u64)?,
         }
         Ok(())
     }
 
     fn decode(source: &mut Buffer) -> Result<Value, Error> {
         match source.read(1)?[0] {
-            x if x == 1 => Ok(Value::Oid(Oid::decode(source)?)),
-            x if x == 2 => {
+            1 => Ok(Value::Oid(Oid::decode(source)?)),
+            2 => {
                 let length = source.read_varint()?;
                 let body = source.read(length as usize)?;
                 let string = String::from_utf8(body.to_vec()).map_err(|_|err!("invalid utf8 contents"))?;
                 Ok(Value::Utf8String(string))
             }
-            x if x == 3 => {
+            3 => {
                 let length = source.read_varint()?;
                 Ok(Value::Bytes(source.read(length as usize)?.to_vec()))
             }
-            x if x == 4 => Ok(Value::Unsigned(source.read_varint()?)),
-            x if x == 5 => panic!("no signed values"),
-            x if x == 6 => Ok(Value::Variable(source.read_varint()? as Variable)),
-            x if x == 7 => Ok(Value::Empty()),
-            x if x == 8 => Ok(Value::Union(source.read_varint()? as Variable)),            
+            4 => Ok(Value::Unsigned(source.read_varint()?)),
+            5 => {
+                let z = source.read_varint()?;
+                Ok(Value::Signed(((z >> 1) as i64) ^ -((z & 1) as i64)))
+            }
+            6 => Ok(Value::Variable(read_variable(source)?)),
+            7 => Ok(Value::Empty()),
+            8 => Ok(Value::Union(read_variable(source)?)),            
             x => Err(err!("invalid Value codepoint {}", x)),
         }
     }
 }
 
 // oid or dyn?
-pub fn get_u64(_e: DynEntityHandler, _a: Attribute) -> Result<u64, Error> {
-    Ok(1)
+pub fn get_typed<T: FromValue>(e: DynEntityHandler, a: Attribute) -> Result<T, Error> {
+    match e.get(a.clone())? {
+        Some(v) => T::from_value(&v),
+        None => Err(err!("missing attribute {:?}", a)),
+    }
+}
+
+pub fn get_u64(e: DynEntityHandler, a: Attribute) -> Result<u64, Error> {
+    get_typed(e, a)
 }
 
-pub fn get_string(_e: DynEntityHandler, _a: Attribute) -> Result<String, Error> {
-    Ok("just kidding".to_string())
+pub fn get_string(e: DynEntityHandler, a: Attribute) -> Result<String, Error> {
+    get_typed(e, a)
 }
 
-pub fn get_attributes(_e: DynEntityHandler) -> Result<DynStream<Attribute>, Error> {
-    Err(err!("foo"))
+pub fn get_attributes(e: DynEntityHandler) -> Result<DynStream<Attribute>, Error> {
+    Ok(e.keys())
 }
@@ -0,0 +1,76 @@
+mod common;
+
+use common::{block_on, scope, store};
+use protocol::{Capabilities, Command, DynClock, Features, Frame, Oid, Session, Stream, Value, attribute};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+fn session() -> (Session, Arc<AtomicU64>) {
+    let now = Arc::new(AtomicU64::new(0));
+    let clock = now.clone();
+    let clock: DynClock = Arc::new(move || clock.load(Ordering::Relaxed));
+    (Session::new(scope(Arc::new(store())), clock), now)
+}
+
+// every attribute of the root, two rows
+fn keys(id: u64, deadline: Option<u64>) -> Frame {
+    Frame::Request {
+        id,
+        deadline,
+        block: vec![Command::Get(Value::Oid(Oid(100)), Value::Variable(0), Value::Variable(1))],
+    }
+}
+
+#[test]
+fn cancelled_between_rows() {
+    let (mut s, _) = session();
+    let (id, mut e) = s.receive(keys(7, None)).unwrap().unwrap();
+    assert_eq!(id, 7);
+    assert_eq!(block_on(e.next()).unwrap(), Some(vec![attribute!("a"), Value::Oid(Oid(101))]));
+    assert!(s.receive(Frame::Cancel { id: 7 }).unwrap().is_none());
+    assert!(block_on(e.next()).unwrap_err().is_cancelled());
+    // too late is fine
+    assert!(s.receive(Frame::Cancel { id: 7 }).unwrap().is_none());
+}
+
+#[test]
+fn finished_before_cancel() {
+    let (mut s, _) = session();
+    let (id, mut e) = s.receive(keys(1, None)).unwrap().unwrap();
+    while block_on(e.next()).unwrap().is_some() {}
+    s.finish(id);
+    assert!(s.receive(Frame::Cancel { id }).unwrap().is_none());
+}
+
+#[test]
+fn deadline_fires() {
+    let (mut s, now) = session();
+    let (_, mut e) = s.receive(keys(1, Some(100))).unwrap().unwrap();
+    now.store(50, Ordering::Relaxed);
+    assert!(block_on(e.next()).unwrap().is_some());
+    now.store(100, Ordering::Relaxed);
+    let err = block_on(e.next()).unwrap_err();
+    assert!(err.is_timed_out());
+    assert!(!err.is_cancelled());
+
+    // one that is already past never produces anything
+    let (_, mut e) = s.receive(keys(2, Some(10))).unwrap().unwrap();
+    assert!(block_on(e.next()).unwrap_err().is_timed_out());
+}
+
+#[test]
+fn one_request_cancelled_not_another() {
+    let (mut s, _) = session();
+    let (_, mut a) = s.receive(keys(1, None)).unwrap().unwrap();
+    let (_, mut b) = s.receive(keys(2, None)).unwrap().unwrap();
+    s.receive(Frame::Cancel { id: 1 }).unwrap();
+    assert!(block_on(a.next()).unwrap_err().is_cancelled());
+    assert!(block_on(b.next()).unwrap().is_some());
+}
+
+#[test]
+fn hello_only_once() {
+    let (mut s, _) = session();
+    let hello = Frame::Hello(Capabilities::local(Features::empty()).unwrap());
+    assert!(s.receive(hello).is_err());
+}
@@ -0,0 +1,64 @@
+#![allow(dead_code)]
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use protocol::{Cancel, Command, Error, Oid, Scope, SimpleAllocator, Store, Stream, Value, attribute};
+use std::sync::Arc;
+
+// nothing in the interpreter actually waits on anything, so polling in a
+// loop with a waker that does nothing is enough
+pub fn block_on<F: Future>(f: F) -> F::Output {
+    fn noop(_: *const ()) {}
+    fn clone(p: *const ()) -> RawWaker {
+        RawWaker::new(p, &VTABLE)
+    }
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    let waker = unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &VTABLE)) };
+    let mut cx = Context::from_waker(&waker);
+    let mut f = pin!(f);
+    loop {
+        if let Poll::Ready(x) = f.as_mut().poll(&mut cx) {
+            return x;
+        }
+    }
+}
+
+pub const MYSELF: Oid = Oid(1);
+
+// a little directory tree, enough for gets to have something to join over
+pub fn store() -> Store {
+    let mut s = Store::new(MYSELF);
+    let (root, a, b) = (Oid(100), Oid(101), Oid(102));
+    s.add_root(root);
+    s.commit(vec![
+        Command::Create(Value::Oid(root)),
+        Command::Create(Value::Oid(a)),
+        Command::Create(Value::Oid(b)),
+        Command::Set(Value::Oid(root), attribute!("a"), Value::Oid(a)),
+        Command::Set(Value::Oid(root), attribute!("b"), Value::Oid(b)),
+        Command::Set(Value::Oid(a), attribute!("contents"), Value::Bytes(b"hello".to_vec())),
+        Command::Set(Value::Oid(a), attribute!("size"), Value::Unsigned(5)),
+        Command::Set(Value::Oid(b), attribute!("size"), Value::Unsigned(0)),
+        Command::Set(Value::Oid(b), attribute!("parent"), Value::Oid(root)),
+    ]).unwrap();
+    s
+}
+
+pub fn scope(store: Arc<Store>) -> Scope {
+    Scope {
+        myself: MYSELF,
+        allocator: SimpleAllocator::new(Oid(1000)),
+        resolver: store,
+        cancel: Cancel::new(),
+    }
+}
+
+// every row and the writes, or the error
+pub fn evaluate(scope: &Scope, block: Vec<Command>) -> Result<(Vec<Vec<Value>>, Vec<Command>), Error> {
+    let mut e = scope.evaluate(block)?;
+    let mut rows = Vec::new();
+    while let Some(row) = block_on(e.next())? {
+        rows.push(row);
+    }
+    Ok((rows, e.writes()))
+}
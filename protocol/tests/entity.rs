@@ -0,0 +1,57 @@
+mod common;
+
+use common::{evaluate, scope, store};
+use protocol::{Entity, Oid, Resolver, Schema, Store, Value, attribute};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Entity)]
+struct File {
+    size: u64,
+    #[entity(rename = "contents")]
+    data: Vec<u8>,
+    owner: Option<u64>,
+    #[entity(skip)]
+    cached: u32,
+}
+
+// the block's writes, committed afterwards the way a runtime does
+fn run(s: Store, block: Vec<protocol::Command>) -> (Store, Vec<Vec<Value>>) {
+    let store = Arc::new(s);
+    let (rows, writes) = evaluate(&scope(store.clone()), block).unwrap();
+    let mut s = Arc::try_unwrap(store).ok().unwrap();
+    s.commit(writes).unwrap();
+    (s, rows)
+}
+
+#[test]
+fn loads_renamed_and_missing_fields() {
+    // common's 101 has size and contents, but no owner
+    let (_, rows) = run(store(), File::load(Oid(101)));
+    let f = File::from_row(&rows[0]).unwrap();
+    assert_eq!(f, File { size: 5, data: b"hello".to_vec(), owner: None, cached: 0 });
+}
+
+#[test]
+fn round_trips() {
+    let f = File { size: 3, data: b"abc".to_vec(), owner: Some(7), cached: 9 };
+    let (s, _) = run(store(), f.store(Oid(101)));
+    let (s, rows) = run(s, File::load(Oid(101)));
+    // skipped fields come back as their default
+    assert_eq!(File::from_row(&rows[0]).unwrap(), File { cached: 0, ..f.clone() });
+    assert_eq!(s.resolve(Oid(101)).unwrap().get(attribute!("contents")).unwrap(),
+               Some(Value::Bytes(b"abc".to_vec())));
+    assert_eq!(s.resolve(Oid(101)).unwrap().get(attribute!("data")).unwrap(), None);
+
+    // None takes the attribute away again
+    let (s, _) = run(s, File { owner: None, ..f.clone() }.store(Oid(101)));
+    assert_eq!(s.resolve(Oid(101)).unwrap().get(attribute!("owner")).unwrap(), None);
+    let (_, rows) = run(s, File::load(Oid(101)));
+    assert_eq!(File::from_row(&rows[0]).unwrap().owner, None);
+}
+
+#[test]
+fn required_field_missing() {
+    // 102 has a size but no contents, so there is no row at all
+    let (_, rows) = run(store(), File::load(Oid(102)));
+    assert!(rows.is_empty());
+}
@@ -0,0 +1,99 @@
+use protocol::{Buffer, Capabilities, Command, Encodable, Features, Frame, Oid, Value, attribute};
+
+fn local() -> Capabilities {
+    Capabilities::local(Features(Features::SIGNATURES.0 | Features::WATCH.0)).unwrap()
+}
+
+// what the peer said, as it comes off the wire
+fn hello(c: &Capabilities) -> Capabilities {
+    let mut b = Buffer::new();
+    Frame::Hello(c.clone()).encode(&mut b).unwrap();
+    match Frame::decode(&mut b).unwrap() {
+        Frame::Hello(c) => c,
+        f => panic!("{:?}", f),
+    }
+}
+
+#[test]
+fn both_sides_agree() {
+    let ours = local();
+    let theirs = Capabilities { max_frame: 4096, features: Features::WATCH, ..local() };
+    let a = ours.negotiate(&hello(&theirs)).unwrap();
+    let b = theirs.negotiate(&hello(&ours)).unwrap();
+    assert_eq!(a, b);
+    assert_eq!(a.max_frame, 4096);
+    assert_eq!(a.features, Features::WATCH);
+}
+
+#[test]
+fn version_mismatch() {
+    let theirs = Capabilities { version: local().version + 1, ..local() };
+    assert!(local().negotiate(&hello(&theirs)).unwrap_err().cause.contains("version"));
+}
+
+// a peer without Get can't be talked to at all
+#[test]
+fn missing_required_codepoint() {
+    let mut theirs = local();
+    theirs.commands.retain(|c| *c != 1);
+    assert!(local().negotiate(&hello(&theirs)).is_err());
+    let mut theirs = local();
+    theirs.values.retain(|v| *v != 1);
+    assert!(local().negotiate(&hello(&theirs)).is_err());
+}
+
+// one that only lacks something optional is dropped down to what both
+// speak, and we don't send it what it can't read
+#[test]
+fn mismatched_codepoints_dropped() {
+    let cas = Command::CompareAndSet(Value::Oid(Oid(1)), attribute!("a"), Value::Empty(), Value::Unsigned(1));
+    let mut theirs = local();
+    theirs.commands.retain(|c| *c != cas.codepoint());
+    // and some codepoint from the future we don't know
+    theirs.commands.push(200);
+    let n = local().negotiate(&hello(&theirs)).unwrap();
+    assert!(!n.commands.contains(&200));
+    assert!(n.permits(&cas).is_err());
+    assert!(n.permits(&Command::Create(Value::Variable(0))).is_ok());
+}
+
+// a value type the peer lacks is as bad as the command
+#[test]
+fn mismatched_values_dropped() {
+    let mut theirs = local();
+    theirs.values.retain(|v| *v != Value::Signed(0).codepoint());
+    let n = local().negotiate(&hello(&theirs)).unwrap();
+    assert!(n.permits(&Command::Set(Value::Oid(Oid(1)), attribute!("a"), Value::Signed(-1))).is_err());
+    assert!(n.permits(&Command::Set(Value::Oid(Oid(1)), attribute!("a"), Value::Unsigned(1))).is_ok());
+}
+
+fn request(block: Vec<Command>) -> Buffer {
+    let mut b = Buffer::new();
+    Frame::Request { id: 1, deadline: None, block }.encode(&mut b).unwrap();
+    b
+}
+
+#[test]
+fn received_under_the_agreement() {
+    let set = |v: Value| Command::Set(Value::Oid(Oid(1)), attribute!("a"), v);
+    let theirs = Capabilities { max_frame: 64, ..local() };
+    let n = local().negotiate(&hello(&theirs)).unwrap();
+    assert!(matches!(Frame::receive(&mut request(vec![set(Value::Unsigned(1))]), &n), Ok(Frame::Request { .. })));
+    // exactly the agreed size, and one byte more
+    let room = 64 - request(vec![set(Value::Bytes(vec![]))]).len();
+    let mut fits = request(vec![set(Value::Bytes(vec![0; room]))]);
+    assert_eq!(fits.len(), 64);
+    assert!(Frame::receive(&mut fits, &n).is_ok());
+    let mut over = request(vec![set(Value::Bytes(vec![0; room + 1]))]);
+    assert!(Frame::receive(&mut over, &n).unwrap_err().cause.contains("agreed"));
+
+    // what it said it wouldn't send
+    let mut theirs = local();
+    theirs.values.retain(|v| *v != Value::Signed(0).codepoint());
+    let n = local().negotiate(&hello(&theirs)).unwrap();
+    assert!(Frame::receive(&mut request(vec![set(Value::Signed(1))]), &n).is_err());
+    // and trailing junk
+    let mut b = request(vec![]);
+    b.write(&[0]).unwrap();
+    assert!(Frame::receive(&mut b, &local()).is_err());
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::{block_on, scope, store};
+use protocol::{Command, Error, Evaluation, Oid, Scope, Value, attribute, execute};
+use std::sync::Arc;
+
+// execute! only needs something with an execute that gives back a stream
+struct Local(Scope);
+
+impl Local {
+    fn execute(&self, block: Vec<Command>) -> Result<Evaluation, Error> {
+        self.0.evaluate(block)
+    }
+}
+
+#[test]
+fn binds_typed_variables() {
+    let local = Local(scope(Arc::new(store())));
+    let root = Oid(100);
+    let found = block_on(async {
+        let mut found = Vec::new();
+        execute!(local, [Get(root, name:Value, child:Oid),
+                         Get(child:Oid, attribute!("size"), size:u64)] {
+            found.push((name, child, size));
+        });
+        Ok::<_, Error>(found)
+    }).unwrap();
+    assert_eq!(found, vec![
+        (attribute!("a"), Oid(101), 5),
+        (attribute!("b"), Oid(102), 0),
+    ]);
+}
+
+#[test]
+fn binds_missing_as_none() {
+    let local = Local(scope(Arc::new(store())));
+    let found = block_on(async {
+        let mut found = Vec::new();
+        execute!(local, [Get(Oid(100), name:Value, child:Oid),
+                         Get(child:Oid, attribute!("parent"), parent:Option<Oid>)] {
+            found.push((name, parent));
+        });
+        Ok::<_, Error>(found)
+    }).unwrap();
+    // a has no parent, and still has its row
+    assert_eq!(found, vec![
+        (attribute!("a"), None),
+        (attribute!("b"), Some(Oid(100))),
+    ]);
+}
+
+#[test]
+fn rejects_wrong_type() {
+    let local = Local(scope(Arc::new(store())));
+    let e = block_on(async {
+        execute!(local, [Get(Oid(101), attribute!("contents"), size:u64)] {
+            let _ = size;
+        });
+        Ok::<_, Error>(())
+    }).unwrap_err();
+    assert!(e.cause.contains("size"));
+}
+
+#[test]
+fn rejects_one_name_two_types() {
+    let local = Local(scope(Arc::new(store())));
+    let e = block_on(async {
+        execute!(local, [Get(Oid(100), attribute!("a"), child:Oid),
+                         Get(child:u64, attribute!("size"), size:u64)] {
+            let _ = size;
+        });
+        Ok::<_, Error>(())
+    }).unwrap_err();
+    assert!(e.cause.contains("child"));
+}
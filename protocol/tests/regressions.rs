@@ -0,0 +1,267 @@
+// inputs that used to panic or hang, found by the fuzz targets or on the
+// way to writing them
+mod common;
+
+use common::{evaluate, scope, store};
+use protocol::{Buffer, Command, Encodable, Oid, Predicate, Value, attribute};
+use std::sync::Arc;
+
+fn decode_value(b: &[u8]) -> bool {
+    Value::decode(&mut Buffer::from_bytes(b)).is_ok()
+}
+
+fn decode_block(b: &[u8]) -> bool {
+    Buffer::from_bytes(b).decode().is_ok()
+}
+
+#[test]
+fn empty_input() {
+    assert!(!decode_value(&[]));
+    assert!(decode_block(&[]));
+    assert!(!decode_block(&[1]));
+}
+
+#[test]
+fn truncated_oid() {
+    assert!(!decode_value(&[1, 0, 0, 0]));
+}
+
+#[test]
+fn length_past_end() {
+    assert!(!decode_value(&[3, 0xff, 0xff, 0xff, 0xff, 0x0f]));
+    assert!(!decode_value(&[2, 5, b'a']));
+}
+
+#[test]
+fn bad_utf8() {
+    assert!(!decode_value(&[2, 2, 0xc3, 0x28]));
+}
+
+#[test]
+fn varint_overflow() {
+    assert!(!decode_value(&[4, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x02]));
+    assert!(!decode_value(&[4, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x80, 0x01]));
+    assert!(decode_value(&[4, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]));
+}
+
+// these decoded, but to something that encodes differently
+#[test]
+fn overlong_varint() {
+    assert!(!decode_block(&[8, 4, 0xff, 0xfc, 0x00]));
+    assert!(!decode_value(&[8, 0xfd, 0x00]));
+    assert!(!decode_value(&[4, 0x80, 0x00]));
+    assert!(decode_value(&[4, 0x00]));
+}
+
+#[test]
+fn variable_out_of_range() {
+    assert!(!decode_value(&[6, 0x80, 0x80, 0x80, 0x80, 0x10]));
+}
+
+#[test]
+fn bad_codepoints() {
+    assert!(!decode_value(&[0]));
+    assert!(!decode_value(&[9]));
+    assert!(!decode_block(&[0]));
+    assert!(!decode_block(&[9, 7]));
+}
+
+#[test]
+fn signed_extremes() {
+    for i in [i64::MIN, -1, 0, 1, i64::MAX] {
+        let mut b = Buffer::new();
+        Value::Signed(i).encode(&mut b).unwrap();
+        assert_eq!(Value::decode(&mut b).unwrap(), Value::Signed(i));
+    }
+}
+
+// copy used to read back fewer values than it wrote
+#[test]
+fn copy_round_trips() {
+    let c = Command::Copy(Value::Variable(0), attribute!("a"), Value::Unsigned(1),
+                          Value::Variable(1), attribute!("b"), Value::Unsigned(2),
+                          Value::Unsigned(3));
+    let mut b = Buffer::new();
+    c.encode(&mut b).unwrap();
+    c.encode(&mut b).unwrap();
+    assert_eq!(b.decode().unwrap(), vec![c.clone(), c]);
+}
+
+#[test]
+fn unbound_variable() {
+    let s = scope(Arc::new(store()));
+    assert!(evaluate(&s, vec![Command::Get(Value::Variable(0), attribute!("a"), Value::Variable(1))]).is_err());
+    assert!(evaluate(&s, vec![Command::Set(Value::Oid(Oid(100)), attribute!("a"), Value::Variable(1))]).is_err());
+}
+
+// a missing attribute drops the row rather than binding nothing
+#[test]
+fn get_missing() {
+    let s = scope(Arc::new(store()));
+    let (rows, _) = evaluate(&s, vec![
+        Command::Get(Value::Oid(Oid(100)), attribute!("missing"), Value::Variable(0)),
+    ]).unwrap();
+    assert!(rows.is_empty());
+
+    let (rows, _) = evaluate(&s, vec![
+        Command::Get(Value::Oid(Oid(100)), attribute!("missing"), Value::Union(0)),
+    ]).unwrap();
+    assert_eq!(rows, vec![vec![Value::Empty()]]);
+}
+
+// the keys stream never advanced
+#[test]
+fn get_keys_terminates() {
+    let s = scope(Arc::new(store()));
+    let (rows, _) = evaluate(&s, vec![
+        Command::Get(Value::Oid(Oid(100)), Value::Variable(0), Value::Variable(1)),
+        Command::Get(Value::Variable(1), attribute!("size"), Value::Variable(2)),
+    ]).unwrap();
+    assert_eq!(rows, vec![
+        vec![attribute!("a"), Value::Oid(Oid(101)), Value::Unsigned(5)],
+        vec![attribute!("b"), Value::Oid(Oid(102)), Value::Unsigned(0)],
+    ]);
+}
+
+#[test]
+fn guard_fails_block() {
+    let s = scope(Arc::new(store()));
+    let e = evaluate(&s, vec![
+        Command::Get(Value::Oid(Oid(101)), attribute!("size"), Value::Variable(0)),
+        Command::Guard(Value::Variable(0), Predicate::Greater, Value::Unsigned(10)),
+    ]).unwrap_err();
+    assert!(e.is_guard_failure());
+}
+
+// absent with a variable nothing has bound is about every attribute
+#[test]
+fn absent_anything() {
+    let s = scope(Arc::new(store()));
+    let e = evaluate(&s, vec![Command::Absent(Value::Oid(Oid(100)), Value::Variable(0))]).unwrap_err();
+    assert!(e.is_guard_failure());
+    let mut st = store();
+    st.commit(vec![Command::Create(Value::Oid(Oid(103)))]).unwrap();
+    let s = scope(Arc::new(st));
+    let (rows, _) = evaluate(&s, vec![Command::Absent(Value::Oid(Oid(103)), Value::Variable(0))]).unwrap();
+    assert_eq!(rows.len(), 1);
+}
+
+#[test]
+fn copy_into_new_attribute() {
+    let mut st = store();
+    let s = scope(Arc::new(store()));
+    let (_, writes) = evaluate(&s, vec![
+        Command::Copy(Value::Oid(Oid(101)), attribute!("contents"), Value::Unsigned(1),
+                      Value::Oid(Oid(102)), attribute!("contents"), Value::Unsigned(2),
+                      Value::Unsigned(3)),
+    ]).unwrap();
+    st.commit(writes).unwrap();
+    let s = scope(Arc::new(st));
+    let (rows, _) = evaluate(&s, vec![
+        Command::Get(Value::Oid(Oid(102)), attribute!("contents"), Value::Variable(0)),
+    ]).unwrap();
+    assert_eq!(rows, vec![vec![Value::Bytes(b"\0\0ell".to_vec())]]);
+}
+
+// two copies into one value used to each set the whole value starting
+// from what was stored, so the second one lost the first
+#[test]
+fn copies_into_one_value() {
+    let mut st = store();
+    let s = scope(Arc::new(store()));
+    let (_, writes) = evaluate(&s, vec![
+        Command::Copy(Value::Oid(Oid(101)), attribute!("contents"), Value::Unsigned(0),
+                      Value::Oid(Oid(102)), attribute!("contents"), Value::Unsigned(0),
+                      Value::Unsigned(2)),
+        Command::Copy(Value::Oid(Oid(101)), attribute!("contents"), Value::Unsigned(3),
+                      Value::Oid(Oid(102)), attribute!("contents"), Value::Unsigned(2),
+                      Value::Unsigned(2)),
+    ]).unwrap();
+    st.commit(writes).unwrap();
+    let s = scope(Arc::new(st));
+    let (rows, _) = evaluate(&s, vec![
+        Command::Get(Value::Oid(Oid(102)), attribute!("contents"), Value::Variable(0)),
+    ]).unwrap();
+    assert_eq!(rows, vec![vec![Value::Bytes(b"helo".to_vec())]]);
+}
+
+// a zero length copy from a value that isn't there pads the destination
+// out with zeros, which is how a file grows without being written
+#[test]
+fn copy_from_nothing() {
+    let s = scope(Arc::new(store()));
+    let (_, writes) = evaluate(&s, vec![
+        Command::Copy(Value::Oid(Oid(101)), attribute!("missing"), Value::Unsigned(0),
+                      Value::Oid(Oid(102)), attribute!("contents"), Value::Unsigned(4),
+                      Value::Unsigned(0)),
+    ]).unwrap();
+    assert_eq!(writes, vec![Command::Set(Value::Oid(Oid(102)), attribute!("contents"), Value::Bytes(vec![0; 4]))]);
+    assert!(evaluate(&s, vec![
+        Command::Copy(Value::Oid(Oid(101)), attribute!("missing"), Value::Unsigned(0),
+                      Value::Oid(Oid(102)), attribute!("contents"), Value::Unsigned(0),
+                      Value::Unsigned(1)),
+    ]).is_err());
+}
+
+// clearing a value and copying back the parts of it to keep cuts it, or
+// zeros what's between them, without anyone reading it out first
+#[test]
+fn copies_after_clearing() {
+    let mut st = store();
+    let s = scope(Arc::new(store()));
+    let (_, writes) = evaluate(&s, vec![
+        Command::Set(Value::Oid(Oid(101)), attribute!("contents"), Value::Empty()),
+        Command::Copy(Value::Oid(Oid(101)), attribute!("contents"), Value::Unsigned(0),
+                      Value::Oid(Oid(101)), attribute!("contents"), Value::Unsigned(0),
+                      Value::Unsigned(2)),
+        Command::Copy(Value::Oid(Oid(101)), attribute!("contents"), Value::Unsigned(3),
+                      Value::Oid(Oid(101)), attribute!("contents"), Value::Unsigned(3),
+                      Value::Unsigned(2)),
+    ]).unwrap();
+    st.commit(writes).unwrap();
+    let s = scope(Arc::new(st));
+    let (rows, _) = evaluate(&s, vec![
+        Command::Get(Value::Oid(Oid(101)), attribute!("contents"), Value::Variable(0)),
+    ]).unwrap();
+    assert_eq!(rows, vec![vec![Value::Bytes(b"he\0lo".to_vec())]]);
+}
+
+#[test]
+fn copy_out_of_range() {
+    let s = scope(Arc::new(store()));
+    assert!(evaluate(&s, vec![
+        Command::Copy(Value::Oid(Oid(101)), attribute!("contents"), Value::Unsigned(u64::MAX),
+                      Value::Oid(Oid(102)), attribute!("contents"), Value::Unsigned(0),
+                      Value::Unsigned(2)),
+    ]).is_err());
+}
+
+// two blocks that both read the same value and both try to move it on,
+// only the first to commit wins
+#[test]
+fn compare_and_set_rechecked_at_commit() {
+    let mut st = store();
+    let block = vec![
+        Command::Get(Value::Oid(Oid(101)), attribute!("size"), Value::Variable(0)),
+        Command::CompareAndSet(Value::Oid(Oid(101)), attribute!("size"), Value::Variable(0), Value::Unsigned(6)),
+    ];
+    let snapshot = Arc::new(store());
+    let (_, first) = evaluate(&scope(snapshot.clone()), block.clone()).unwrap();
+    let (_, second) = evaluate(&scope(snapshot), block).unwrap();
+    st.commit(first).unwrap();
+    assert!(st.commit(second).unwrap_err().is_guard_failure());
+}
+
+#[test]
+fn create_and_set() {
+    let mut st = store();
+    let s = scope(Arc::new(store()));
+    let (rows, writes) = evaluate(&s, vec![
+        Command::Create(Value::Variable(0)),
+        Command::Set(Value::Variable(0), attribute!("size"), Value::Unsigned(1)),
+        Command::Set(Value::Oid(Oid(100)), attribute!("c"), Value::Variable(0)),
+    ]).unwrap();
+    assert_eq!(rows, vec![vec![Value::Oid(Oid(1000))]]);
+    st.commit(writes).unwrap();
+    assert!(st.collect().is_empty());
+}
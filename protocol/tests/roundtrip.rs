@@ -0,0 +1,90 @@
+mod common;
+
+use common::{evaluate, scope, store};
+use proptest::prelude::*;
+use protocol::{Buffer, Command, Encodable, Oid, Predicate, Value, attribute};
+use std::sync::Arc;
+
+fn value() -> impl Strategy<Value = Value> + Clone {
+    prop_oneof![
+        any::<u128>().prop_map(|o| Value::Oid(Oid(o))),
+        ".*".prop_map(Value::Utf8String),
+        proptest::collection::vec(any::<u8>(), 0..64).prop_map(Value::Bytes),
+        any::<u64>().prop_map(Value::Unsigned),
+        any::<i64>().prop_map(Value::Signed),
+        any::<u32>().prop_map(Value::Variable),
+        Just(Value::Empty()),
+        any::<u32>().prop_map(Value::Union),
+    ]
+}
+
+fn predicate() -> impl Strategy<Value = Predicate> {
+    prop_oneof![
+        Just(Predicate::Equal),
+        Just(Predicate::NotEqual),
+        Just(Predicate::Less),
+        Just(Predicate::LessEqual),
+        Just(Predicate::Greater),
+        Just(Predicate::GreaterEqual),
+    ]
+}
+
+fn command(v: impl Strategy<Value = Value> + Clone) -> impl Strategy<Value = Command> {
+    prop_oneof![
+        (v.clone(), v.clone(), v.clone()).prop_map(|(e, a, x)| Command::Get(e, a, x)),
+        (v.clone(), v.clone(), v.clone()).prop_map(|(e, a, x)| Command::Set(e, a, x)),
+        (v.clone(), v.clone(), v.clone(), v.clone(), v.clone(), v.clone(), v.clone())
+            .prop_map(|(a, b, c, d, e, f, g)| Command::Copy(a, b, c, d, e, f, g)),
+        v.clone().prop_map(Command::Create),
+        (v.clone(), predicate(), v.clone()).prop_map(|(l, p, r)| Command::Guard(l, p, r)),
+        (v.clone(), v.clone()).prop_map(|(e, a)| Command::Absent(e, a)),
+        (v.clone(), v.clone(), v.clone(), v.clone())
+            .prop_map(|(e, a, x, n)| Command::CompareAndSet(e, a, x, n)),
+        v.prop_map(Command::Destroy),
+    ]
+}
+
+// values that mean something against common::store, so that evaluation
+// gets past the scheduler and into the handlers some of the time
+fn local_value() -> impl Strategy<Value = Value> + Clone {
+    prop_oneof![
+        (100u128..104).prop_map(|o| Value::Oid(Oid(o))),
+        prop_oneof![Just("a"), Just("b"), Just("size"), Just("contents"), Just("parent")]
+            .prop_map(|a| attribute!(a)),
+        (0u64..8).prop_map(Value::Unsigned),
+        (0u32..4).prop_map(Value::Variable),
+        (0u32..4).prop_map(Value::Union),
+        Just(Value::Empty()),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn value_round_trips(v in value()) {
+        let mut b = Buffer::new();
+        v.encode(&mut b).unwrap();
+        prop_assert_eq!(Value::decode(&mut b).unwrap(), v);
+        prop_assert!(b.is_empty());
+    }
+
+    #[test]
+    fn block_round_trips(block in proptest::collection::vec(command(value()), 0..8)) {
+        let mut b = Buffer::new();
+        for c in &block {
+            c.encode(&mut b).unwrap();
+        }
+        prop_assert_eq!(b.decode().unwrap(), block);
+    }
+
+    #[test]
+    fn evaluation_is_deterministic(block in proptest::collection::vec(command(local_value()), 0..6)) {
+        let store = Arc::new(store());
+        let first = evaluate(&scope(store.clone()), block.clone());
+        let second = evaluate(&scope(store), block);
+        match (first, second) {
+            (Ok(a), Ok(b)) => prop_assert_eq!(a, b),
+            (Err(a), Err(b)) => prop_assert_eq!(format!("{:?}", a), format!("{:?}", b)),
+            (a, b) => prop_assert!(false, "{:?} then {:?}", a.is_ok(), b.is_ok()),
+        }
+    }
+}
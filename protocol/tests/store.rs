@@ -0,0 +1,84 @@
+mod common;
+
+use common::store;
+use protocol::{Command, Oid, Resolver, Value, attribute};
+
+fn get(st: &protocol::Store, o: u128, a: &str) -> Option<Value> {
+    st.resolve(Oid(o)).unwrap().get(attribute!(a)).unwrap()
+}
+
+// the third command fails, so neither of the others happen
+#[test]
+fn commit_is_all_or_nothing() {
+    let mut st = store();
+    let e = st.commit(vec![
+        Command::Set(Value::Oid(Oid(101)), attribute!("size"), Value::Unsigned(9)),
+        Command::Destroy(Value::Oid(Oid(102))),
+        Command::Set(Value::Oid(Oid(999)), attribute!("size"), Value::Unsigned(1)),
+    ]);
+    assert!(e.is_err());
+    assert_eq!(get(&st, 101, "size"), Some(Value::Unsigned(5)));
+    assert!(st.resolve(Oid(102)).is_some());
+
+    // a set after the destroy is as bad as one on something unknown
+    assert!(st.commit(vec![
+        Command::Destroy(Value::Oid(Oid(102))),
+        Command::Set(Value::Oid(Oid(102)), attribute!("size"), Value::Unsigned(1)),
+    ]).is_err());
+    assert!(st.resolve(Oid(102)).is_some());
+
+    // and a failed compare-and-set anywhere stops all of it
+    let e = st.commit(vec![
+        Command::Set(Value::Oid(Oid(102)), attribute!("size"), Value::Unsigned(1)),
+        Command::CompareAndSet(Value::Oid(Oid(101)), attribute!("size"), Value::Unsigned(4), Value::Unsigned(6)),
+    ]).unwrap_err();
+    assert!(e.is_guard_failure());
+    assert_eq!(get(&st, 102, "size"), Some(Value::Unsigned(0)));
+}
+
+#[test]
+fn created_then_used() {
+    let mut st = store();
+    st.commit(vec![
+        Command::Create(Value::Oid(Oid(200))),
+        Command::Set(Value::Oid(Oid(200)), attribute!("size"), Value::Unsigned(1)),
+        Command::Set(Value::Oid(Oid(100)), attribute!("c"), Value::Oid(Oid(200))),
+    ]).unwrap();
+    assert_eq!(get(&st, 200, "size"), Some(Value::Unsigned(1)));
+}
+
+// nothing is left pointing at it, and what hung off it alone goes too
+#[test]
+fn destroy_scrubs_and_reclaims() {
+    let mut st = store();
+    st.commit(vec![
+        Command::Create(Value::Oid(Oid(200))),
+        Command::Set(Value::Oid(Oid(101)), attribute!("extents"), Value::Oid(Oid(200))),
+        // 102 is still named by the root, it stays
+        Command::Set(Value::Oid(Oid(101)), attribute!("sibling"), Value::Oid(Oid(102))),
+    ]).unwrap();
+    st.commit(vec![Command::Destroy(Value::Oid(Oid(101)))]).unwrap();
+    assert!(st.resolve(Oid(101)).is_none());
+    assert!(st.resolve(Oid(200)).is_none());
+    assert!(st.resolve(Oid(102)).is_some());
+    assert_eq!(get(&st, 100, "a"), None);
+    assert_eq!(get(&st, 100, "b"), Some(Value::Oid(Oid(102))));
+}
+
+#[test]
+fn collect_reclaims_detached() {
+    let mut st = store();
+    st.commit(vec![
+        Command::Create(Value::Oid(Oid(200))),
+        Command::Set(Value::Oid(Oid(101)), attribute!("next"), Value::Oid(Oid(200))),
+        Command::Set(Value::Oid(Oid(200)), attribute!("next"), Value::Oid(Oid(101))),
+    ]).unwrap();
+    assert!(st.collect().is_empty());
+    // the cycle is all that holds them up now
+    st.commit(vec![Command::Set(Value::Oid(Oid(100)), attribute!("a"), Value::Empty())]).unwrap();
+    let mut dead = st.collect();
+    dead.sort();
+    assert_eq!(dead, vec![Oid(101), Oid(200)]);
+    assert!(st.resolve(Oid(101)).is_none());
+    assert!(st.resolve(Oid(102)).is_some());
+}
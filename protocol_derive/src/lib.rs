@@ -0,0 +1,170 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    Data, DeriveInput, Fields, GenericArgument, LitStr, PathArguments, Type,
+    parse_macro_input, spanned::Spanned,
+};
+
+// #[derive(Entity)] maps the named fields of a struct onto attributes of a
+// single entity. load() is a block that reads every field into a variable
+// (numbered in field order), from_row() decodes the resulting row, and
+// store() is the block that writes them all back.
+//
+//    #[entity(rename = "nlink")]  use a different attribute name
+//    #[entity(skip)]              not stored, filled in with Default
+//
+// an Option<T> field is optional, it is read through a union variable so a
+// missing attribute doesn't eliminate the row, and None is stored as Empty
+#[proc_macro_derive(Entity, attributes(entity))]
+pub fn derive_entity(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(t) => t.into(),
+        Err(e) => e.to_compile_error().into(),
+    }
+}
+
+struct Field {
+    ident: syn::Ident,
+    attribute: String,
+    optional: bool,
+    skip: bool,
+}
+
+fn option_inner(ty: &Type) -> Option<&Type> {
+    if let Type::Path(p) = ty
+        && p.qself.is_none()
+        && let Some(last) = p.path.segments.last()
+        && last.ident == "Option"
+        && let PathArguments::AngleBracketed(args) = &last.arguments
+        && let Some(GenericArgument::Type(inner)) = args.args.first()
+    {
+        Some(inner)
+    } else {
+        None
+    }
+}
+
+fn parse_field(f: &syn::Field) -> syn::Result<Field> {
+    let ident = f.ident.clone().ok_or_else(|| syn::Error::new(f.span(), "entity fields must be named"))?;
+    let mut field = Field {
+        attribute: ident.to_string(),
+        ident,
+        optional: option_inner(&f.ty).is_some(),
+        skip: false,
+    };
+    for attr in f.attrs.iter().filter(|a| a.path().is_ident("entity")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                field.attribute = meta.value()?.parse::<LitStr>()?.value();
+                Ok(())
+            } else if meta.path.is_ident("skip") {
+                field.skip = true;
+                Ok(())
+            } else {
+                Err(meta.error("unknown entity attribute, expected rename or skip"))
+            }
+        })?;
+    }
+    Ok(field)
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let named = match &input.data {
+        Data::Struct(s) => match &s.fields {
+            Fields::Named(n) => &n.named,
+            _ => return Err(syn::Error::new(input.span(), "Entity can only be derived for structs with named fields")),
+        },
+        _ => return Err(syn::Error::new(input.span(), "Entity can only be derived for structs")),
+    };
+
+    let fields = named.iter().map(parse_field).collect::<syn::Result<Vec<_>>>()?;
+    let stored: Vec<&Field> = fields.iter().filter(|f| !f.skip).collect();
+
+    let gets = stored.iter().enumerate().map(|(i, f)| {
+        let attribute = &f.attribute;
+        let i = i as u32;
+        let out = if f.optional {
+            quote!(::protocol::Value::Union(#i))
+        } else {
+            quote!(::protocol::Value::Variable(#i))
+        };
+        quote! {
+            ::protocol::Command::Get(::protocol::Value::Oid(oid),
+                                     ::protocol::attribute!(#attribute),
+                                     #out)
+        }
+    });
+
+    let sets = stored.iter().map(|f| {
+        let attribute = &f.attribute;
+        let ident = &f.ident;
+        let value = if f.optional {
+            quote! {
+                match &self.#ident {
+                    Some(v) => ::protocol::Value::from(v.clone()),
+                    None => ::protocol::Value::Empty(),
+                }
+            }
+        } else {
+            quote!(::protocol::Value::from(self.#ident.clone()))
+        };
+        quote! {
+            ::protocol::Command::Set(::protocol::Value::Oid(oid),
+                                     ::protocol::attribute!(#attribute),
+                                     #value)
+        }
+    });
+
+    let mut index = 0usize;
+    let decodes = fields.iter().map(|f| {
+        let ident = &f.ident;
+        if f.skip {
+            return quote!(#ident: ::core::default::Default::default());
+        }
+        let i = index;
+        index += 1;
+        let attribute = &f.attribute;
+        if f.optional {
+            quote! {
+                #ident: match row.get(#i) {
+                    None | Some(::protocol::Value::Empty()) => None,
+                    Some(v) => Some(::protocol::FromValue::from_value(v).map_err(|e| {
+                        ::protocol::err!("attribute {}: {}", #attribute, e.cause)
+                    })?),
+                }
+            }
+        } else {
+            quote! {
+                #ident: {
+                    let v = row.get(#i).ok_or_else(|| ::protocol::err!("missing attribute {}", #attribute))?;
+                    ::protocol::FromValue::from_value(v).map_err(|e| {
+                        ::protocol::err!("attribute {}: {}", #attribute, e.cause)
+                    })?
+                }
+            }
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::protocol::Schema for #name #ty_generics #where_clause {
+            fn load(oid: ::protocol::Oid) -> ::protocol::Block {
+                ::protocol::Vec::from([#(#gets),*])
+            }
+
+            fn store(&self, oid: ::protocol::Oid) -> ::protocol::Block {
+                ::protocol::Vec::from([#(#sets),*])
+            }
+
+            fn from_row(row: &[::protocol::Value]) -> ::core::result::Result<Self, ::protocol::Error> {
+                Ok(#name {
+                    #(#decodes),*
+                })
+            }
+        }
+    })
+}